@@ -1,14 +1,22 @@
 use crate::apu::dmc::DMC;
+use crate::apu::frame_counter::FrameCounter;
 use crate::apu::noise::Noise;
 use crate::apu::pulse::Pulse;
+use crate::apu::recorder::AudioRecorder;
 use crate::apu::triangle::Triangle;
-use crate::cpu::{CoreMemory, MemoryListener};
+use crate::cpu::{CoreMemory, MemoryListener, CPU};
+use crate::mapper::Mapper;
 use crate::processor::Processor;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use crate::simulator::Speed;
+#[cfg(feature = "audio")]
 use rodio::{ChannelCount, OutputStream, SampleRate, Sink, Source};
 use std::cell::RefCell;
 use std::collections::VecDeque;
+use std::io;
 use std::rc::Rc;
 use std::sync::{Arc, RwLock};
+#[cfg(feature = "audio")]
 use std::time::Duration;
 /* TODO This is a little bit faster than the theoretical rate that we should be sampling at,
  * but it seems to be the best rate for keeping the sample queue from backing up;
@@ -16,35 +24,61 @@ use std::time::Duration;
  */
 
 pub struct APU {
-    apu_counter: u16,
-    _output_stream: Option<OutputStream>, /* can't remove this--if it's collected, sound won't play */
-    _sink: Option<Sink>,                  /* ditto--confusingly, since OutputStream should have a ref */
-    pulse1: Pulse,                        /* to it through the Mixer? */
+    frame_counter: FrameCounter,
+    on_apu_cycle: bool, /* whether the current CPU cycle is also an APU cycle */
+    #[cfg(feature = "audio")]
+    output: Option<AudioOutput>, /* None when there's no audio device, or when silent */
+    pulse1: Pulse,
     pulse2: Pulse,
     triangle: Triangle,
     noise: Noise,
     dmc: DMC,
     status: u8,
     queue: Arc<RwLock<VecDeque<f32>>>,
+    sample_period: Option<f64>, /* CPU cycles per sample, or None to make no sound at all */
+    sample_clock: f64,          /* CPU cycles since the last sample */
+    muted: bool,
+    recorder: Option<AudioRecorder>,
+    recording_clock: f64, /* CPU cycles since the last recorded sample */
+    cartridge: Option<Rc<RefCell<Box<dyn Mapper>>>>, /* for its expansion audio, if any */
 }
 
 const PULSE_1_FIRST_ADDR: u16 = 0x4000;
 const PULSE_2_FIRST_ADDR: u16 = 0x4004;
+/* mono samples per second of audio output */
+pub const SAMPLE_RATE: u32 = 44744;
+/* CPU cycles per sample at full speed, giving the audio device its SAMPLE_RATE */
+const CYCLES_PER_SAMPLE: f64 = 40.0;
+/* about 90ms of audio; beyond that, the oldest samples are dropped rather than lag further */
+const MAX_QUEUED_SAMPLES: usize = 4096;
+/* pulse 1, pulse 2, triangle, noise, DMC, and the cartridge's expansion audio */
+pub(crate) const CHANNELS: usize = 6;
+
+/* keeps the audio device open; if either part is collected, sound won't play */
+#[cfg(feature = "audio")]
+struct AudioOutput {
+    _stream: OutputStream,
+    _sink: Sink,
+}
 
 impl APU {
+    /* plays through the default audio device, or runs silently if there isn't one */
+    #[cfg(feature = "audio")]
     pub fn new() -> Rc<RefCell<APU>> {
-        let queue = Arc::new(RwLock::new(VecDeque::new()));
-
-        let (output_stream, sink) =
-            match rodio::OutputStreamBuilder::open_default_stream() {
-                Ok(stream_handle) => {
-                    let sink = Sink::connect_new(&stream_handle.mixer());
-                    sink.append(BufferedMixedSource::new(queue.clone()));
-                    (Some(stream_handle), Some(sink))
-                }
-                Err(_) => (None, None), /* headless: APU runs silently */
-            };
+        let apu = Self::silent();
+        if let Ok(stream) = rodio::OutputStreamBuilder::open_default_stream() {
+            let sink = Sink::connect_new(stream.mixer());
+            sink.append(BufferedMixedSource::new(apu.borrow().queue.clone()));
+            apu.borrow_mut().output = Some(AudioOutput {
+                _stream: stream,
+                _sink: sink,
+            });
+        }
+        apu
+    }
 
+    /* never opens an audio device, e.g. when running headless; samples are mixed but dropped */
+    pub fn silent() -> Rc<RefCell<APU>> {
         let pulse1 = Pulse::new(PULSE_1_FIRST_ADDR, true);
         let pulse2 = Pulse::new(PULSE_2_FIRST_ADDR, false);
         let triangle = Triangle::new();
@@ -52,48 +86,211 @@ impl APU {
         let dmc = DMC::new();
 
         Rc::new(RefCell::new(APU {
-            apu_counter: 0,
-            _output_stream: output_stream,
+            frame_counter: FrameCounter::new(),
+            on_apu_cycle: false,
+            #[cfg(feature = "audio")]
+            output: None,
             pulse1,
             pulse2,
             triangle,
             noise,
             dmc,
-            queue,
-            _sink: sink,
+            queue: Arc::new(RwLock::new(VecDeque::new())),
             status: 0,
+            sample_period: Some(CYCLES_PER_SAMPLE),
+            sample_clock: 0.0,
+            muted: false,
+            recorder: None,
+            recording_clock: 0.0,
+            cartridge: None,
         }))
     }
 
-    pub fn apu_tick(&mut self) {
-        self.apu_counter = (self.apu_counter + 1) % 14915;
+    /* mixes in the sound channels the cartridge's mapper has, if any */
+    pub fn connect_cartridge(&mut self, mapper: Rc<RefCell<Box<dyn Mapper>>>) {
+        self.cartridge = Some(mapper);
+    }
 
-        self.pulse1.tick(self.apu_counter);
-        self.pulse2.tick(self.apu_counter);
-        self.triangle.tick(self.apu_counter);
-        self.noise.tick(self.apu_counter);
-        self.dmc.tick(self.apu_counter);
+    /* runs one CPU cycle; the channels' timers only run on every other one, the APU cycles */
+    pub fn tick(&mut self, cpu: &mut CPU) {
+        let clocks = self.frame_counter.clock();
+        if clocks.quarter {
+            self.pulse1.clock_quarter_frame();
+            self.pulse2.clock_quarter_frame();
+            self.triangle.clock_quarter_frame();
+            self.noise.clock_quarter_frame();
+        }
+        if clocks.half {
+            self.pulse1.clock_half_frame();
+            self.pulse2.clock_half_frame();
+            self.triangle.clock_half_frame();
+            self.noise.clock_half_frame();
+        }
+
+        self.on_apu_cycle = cpu.cycles().is_multiple_of(2);
+        if self.on_apu_cycle {
+            self.pulse1.tick();
+            self.pulse2.tick();
+            self.triangle.tick();
+            self.noise.tick();
+            self.dmc.tick(cpu);
+        }
+        cpu.set_apu_irq(self.frame_counter.irq_pending() || self.dmc.irq_pending());
 
         /* TODO find a better way to sync this up */
-        if self.apu_counter % 20 == 0 /* TODO */ && self.queue.read().unwrap().len() < 50000 {
-            self.queue.write().unwrap().push_back(self.mix());
+        if let Some(period) = self.sample_period.filter(|_| !self.muted) {
+            self.sample_clock += 1.0;
+            if self.sample_clock >= period {
+                self.sample_clock -= period;
+                self.push_sample();
+            }
+        }
+
+        /* recordings are sampled at SAMPLE_RATE in emulated time whatever the speed, so they
+         * sound as the game would at full speed; rewinding isn't recorded
+         */
+        if self.recorder.is_some() && !self.muted {
+            self.recording_clock += 1.0;
+            if self.recording_clock >= CYCLES_PER_SAMPLE {
+                self.recording_clock -= CYCLES_PER_SAMPLE;
+                let levels = self.levels();
+                if let Some(recorder) = &mut self.recorder {
+                    recorder.record(levels);
+                }
+            }
+        }
+    }
+
+    fn push_sample(&mut self) {
+        let sample = mix(self.levels());
+        let mut queue = self.queue.write().unwrap();
+        if queue.len() >= MAX_QUEUED_SAMPLES {
+            let excess = queue.len() - MAX_QUEUED_SAMPLES / 2;
+            queue.drain(..excess);
+        }
+        queue.push_back(sample);
+    }
+
+    /**
+     * Matches sampling to the emulation speed, so the audio device still gets its 44744 samples
+     * per second of real time and the queue neither backs up nor runs dry. Like a tape, sound
+     * goes up in pitch when fast-forwarding and down in slow motion. Unthrottled, there's no
+     * keeping up, so it goes quiet.
+     */
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.sample_period = speed
+            .multiplier()
+            .map(|multiplier| CYCLES_PER_SAMPLE * multiplier);
+        self.sample_clock = 0.0;
+        if self.sample_period.is_none() {
+            self.queue.write().unwrap().clear();
+        }
+    }
+
+    /* stops making sound, dropping anything still waiting to play, e.g. while rewinding */
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+        if muted {
+            self.queue.write().unwrap().clear();
+        }
+    }
+
+    /* takes the samples queued since the last call, when there's no audio device to take them */
+    pub fn take_samples(&mut self) -> Vec<f32> {
+        self.queue.write().unwrap().drain(..).collect()
+    }
+
+    /* starts recording what the APU puts out, finishing any earlier recording */
+    pub fn start_recording(&mut self, recorder: AudioRecorder) -> io::Result<()> {
+        let finished = self.stop_recording();
+        self.recorder = Some(recorder);
+        self.recording_clock = 0.0;
+        finished
+    }
+
+    /* completes the recording, if there is one */
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
         }
     }
 
-    fn mix(&self) -> f32 {
-        let pulse1_vol = self.pulse1.amplitude();
-        let pulse2_vol = self.pulse2.amplitude();
-        let triangle_vol = self.triangle.amplitude();
-        let noise_vol = self.noise.amplitude();
-        let dmc_vol = self.dmc.amplitude();
+    #[cfg(test)]
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    #[cfg(test)]
+    pub fn queued_samples(&self) -> usize {
+        self.queue.read().unwrap().len()
+    }
+
+    /* reset silences every channel, as if 0 were written to 0x4015, and restarts the frame
+     * counter in whichever mode it was in
+     */
+    pub fn reset(&mut self) {
+        self.write_status(0);
+        self.frame_counter.reset();
+    }
 
-        /* formulae from https://www.nesdev.org/wiki/APU_Mixer */
-        let pulse_out = 95.88 / (8128.0 / (pulse1_vol + pulse2_vol) + 100.0);
-        let tnd_out = 159.79
-            / (1.0 / (triangle_vol / 8227.0 + noise_vol / 12241.0 + dmc_vol / 22638.0) + 100.0);
+    fn write_status(&mut self, value: u8) {
+        self.status = value;
+        self.pulse1.set_enabled(value & 0x1 != 0);
+        self.pulse2.set_enabled(value & 0x2 != 0);
+        self.triangle.set_enabled(value & 0x4 != 0);
+        self.noise.set_enabled(value & 0x8 != 0);
+        self.dmc.set_enabled(value & 0x10 != 0);
+    }
 
-        pulse_out + tnd_out
+    /* what a read of 0x4015 sees: which channels are still playing, and the IRQ flags; reading
+     * it acknowledges the frame IRQ
+     */
+    fn read_status(&mut self, open_bus: u8) -> u8 {
+        let status = (self.pulse1.is_active() as u8)
+            | (self.pulse2.is_active() as u8) << 1
+            | (self.triangle.is_active() as u8) << 2
+            | (self.noise.is_active() as u8) << 3
+            | (self.dmc.is_active() as u8) << 4
+            | (open_bus & 0x20)
+            | (self.frame_counter.irq_pending() as u8) << 6
+            | (self.dmc.irq_pending() as u8) << 7;
+        self.frame_counter.clear_irq();
+        status
     }
+
+    /* each channel's output level, in CHANNELS order */
+    fn levels(&self) -> [f32; CHANNELS] {
+        [
+            self.pulse1.amplitude(),
+            self.pulse2.amplitude(),
+            self.triangle.amplitude(),
+            self.noise.amplitude(),
+            self.dmc.amplitude(),
+            self.cartridge
+                .as_ref()
+                .map_or(0.0, |mapper| mapper.borrow().expansion_audio()),
+        ]
+    }
+}
+
+/* mixes the channels' levels into a sample from 0 to about 1; expansion audio comes already
+ * mixed, and is just added on
+ */
+pub(crate) fn mix(levels: [f32; CHANNELS]) -> f32 {
+    let [pulse1_vol, pulse2_vol, triangle_vol, noise_vol, dmc_vol, expansion] = levels;
+    mix_pulses(pulse1_vol + pulse2_vol) + mix_tnd(triangle_vol, noise_vol, dmc_vol) + expansion
+}
+
+/* formulae from https://www.nesdev.org/wiki/APU_Mixer; mappers whose channels imitate the
+ * APU's mix them with these too
+ */
+pub(crate) fn mix_pulses(pulses: f32) -> f32 {
+    95.88 / (8128.0 / pulses + 100.0)
+}
+
+pub(crate) fn mix_tnd(triangle: f32, noise: f32, dmc: f32) -> f32 {
+    159.79 / (1.0 / (triangle / 8227.0 + noise / 12241.0 + dmc / 22638.0) + 100.0)
 }
 
 impl Processor for APU {
@@ -115,8 +312,19 @@ impl MemoryListener for APU {
         .to_vec() /* apu control regs */
     }
 
-    fn read(&mut self, memory: &CoreMemory, _address: u16) -> u8 {
-        memory.open_bus()
+    /* reading 0x4017 reads the second controller */
+    fn get_read_addresses(&self) -> Vec<u16> {
+        let mut addresses = self.get_addresses();
+        addresses.retain(|&address| address != 0x4017);
+        addresses
+    }
+
+    fn read(&mut self, memory: &CoreMemory, address: u16) -> u8 {
+        if address == 0x4015 {
+            self.read_status(memory.open_bus())
+        } else {
+            memory.open_bus()
+        }
     }
 
     fn write(&mut self, memory: &CoreMemory, address: u16, value: u8) {
@@ -131,30 +339,53 @@ impl MemoryListener for APU {
             0x0c => self.noise.write(memory, address, value),
             /* dmc:      xxx1 00xx */
             0x10 => self.dmc.write(memory, address, value),
-            _ => {
-                if address == 0x4015 {
-                    self.status = value;
-                    self.pulse1.set_enabled(value & 0x1 != 0);
-                    self.pulse2.set_enabled(value & 0x2 != 0);
-                    self.triangle.set_enabled(value & 0x4 != 0);
-                    self.noise.set_enabled(value & 0x8 != 0);
-                    self.dmc.set_enabled(value & 0x10 != 0);
-                }
-            }
+            _ => match address {
+                0x4015 => self.write_status(value),
+                0x4017 => self.frame_counter.write(value, self.on_apu_cycle),
+                _ => {}
+            },
         }
     }
 }
 
+/* samples already queued for the audio device aren't part of the machine's state */
+impl Savestate for APU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.frame_counter.save_state(writer);
+        writer.write_bool(self.on_apu_cycle);
+        writer.write_u8(self.status);
+        self.pulse1.save_state(writer);
+        self.pulse2.save_state(writer);
+        self.triangle.save_state(writer);
+        self.noise.save_state(writer);
+        self.dmc.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.frame_counter.load_state(reader)?;
+        self.on_apu_cycle = reader.read_bool()?;
+        self.status = reader.read_u8()?;
+        self.pulse1.load_state(reader)?;
+        self.pulse2.load_state(reader)?;
+        self.triangle.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        self.dmc.load_state(reader)
+    }
+}
+
+#[cfg(feature = "audio")]
 impl BufferedMixedSource {
     fn new(queue: Arc<RwLock<VecDeque<f32>>>) -> BufferedMixedSource {
         BufferedMixedSource { queue }
     }
 }
 
+#[cfg(feature = "audio")]
 pub struct BufferedMixedSource {
     queue: Arc<RwLock<VecDeque<f32>>>,
 }
 
+#[cfg(feature = "audio")]
 impl Iterator for BufferedMixedSource {
     type Item = f32;
 
@@ -167,6 +398,7 @@ impl Iterator for BufferedMixedSource {
     }
 }
 
+#[cfg(feature = "audio")]
 impl Source for BufferedMixedSource {
     fn current_span_len(&self) -> Option<usize> {
         None
@@ -177,7 +409,7 @@ impl Source for BufferedMixedSource {
     }
 
     fn sample_rate(&self) -> SampleRate {
-        44744
+        SAMPLE_RATE
     }
 
     fn total_duration(&self) -> Option<Duration> {
@@ -1,13 +1,19 @@
 use crate::apu::timer::Timer;
-use crate::cpu::{CoreMemory, MemoryListener};
-use std::cell::RefCell;
-use std::rc::Rc;
+use crate::cpu::{CoreMemory, MemoryListener, CPU};
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
 
-/* different for PAL */
+/* in CPU cycles per output bit; different for PAL */
 const NTSC_RATE_MAP: [u16; 16] = [
     428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
 ];
 
+/**
+ * The delta modulation channel, which plays 1-bit delta-encoded samples from PRG memory. The
+ * memory reader fetches sample bytes by DMA, stealing cycles from the CPU; the output unit shifts
+ * them out a bit at a time, moving the output level up or down by 2 for each bit.
+ * See https://www.nesdev.org/wiki/APU_DMC
+ */
 pub struct DMC {
     timer: Timer,
     bits_remaining: u8,
@@ -16,98 +22,134 @@ pub struct DMC {
     current_address: u16,
     sample_length: u16,
     sample_bytes_remaining: u16,
+    fetching: bool, /* a DMA has been requested, and the byte hasn't arrived yet */
     silence_flag: bool,
     shift_register: u8,
-    volume: u8,
+    volume: u8, /* the 7-bit output level */
     irq_enabled: bool,
+    irq_flag: bool,
     loop_flag: bool,
     rate_index: u16,
-    enabled: bool,
 }
 
 impl DMC {
-    #[allow(dead_code)]
-    pub fn initialize(memory: &Rc<RefCell<CoreMemory>>) -> Rc<RefCell<DMC>> {
-        let dmc_ref = Rc::new(RefCell::new(DMC::new()));
-        memory.borrow_mut().register_listener(dmc_ref.clone());
-
-        dmc_ref
-    }
-
     pub fn new() -> DMC {
-        let timer = Timer::new();
+        let mut timer = Timer::new();
+        timer.set_period(Self::timer_period(0));
 
         DMC {
             timer,
-            bits_remaining: 0,
+            bits_remaining: 8,
             sample_buffer: None,
-            sample_address: 0,
-            current_address: 0,
-            sample_length: 0,
+            sample_address: 0xc000,
+            current_address: 0xc000,
+            sample_length: 1,
             sample_bytes_remaining: 0,
+            fetching: false,
             silence_flag: true,
             shift_register: 0,
             volume: 0,
             irq_enabled: false,
+            irq_flag: false,
             loop_flag: false,
             rate_index: 0,
-            enabled: false,
         }
     }
 
-    pub fn tick(&mut self, _apu_counter: u16) {
-        if !self.enabled {
-            return;
+    /* the timer is clocked every APU cycle, i.e. every other CPU cycle, and reloads after
+     * period + 1 clocks
+     */
+    fn timer_period(rate_index: u16) -> u16 {
+        NTSC_RATE_MAP[rate_index as usize] / 2 - 1
+    }
+
+    pub fn tick(&mut self, cpu: &mut CPU) {
+        if let Some(sample) = cpu.take_dmc_sample() {
+            self.load_sample(sample);
         }
 
-        if self.sample_buffer.is_none() && self.sample_bytes_remaining > 0 {
-            /* TODO fix */
-            // self.sample_buffer = Some(self.memory.borrow_mut().read(self.current_address));
-            if self.current_address == 0xffff {
-                self.current_address = 0x8000;
-            } else {
-                self.current_address += 1;
-            }
-            self.sample_bytes_remaining -= 1;
-            if self.sample_bytes_remaining == 0 && self.loop_flag {
-                self.current_address = self.sample_address;
-                self.sample_bytes_remaining = self.sample_length;
-            }
-            /* TODO IRQ interrupt */
+        /* the memory reader refills the sample buffer as soon as it empties */
+        if self.sample_buffer.is_none() && self.sample_bytes_remaining > 0 && !self.fetching {
+            cpu.request_dmc_dma(self.current_address);
+            self.fetching = true;
         }
 
         if self.timer.clock() {
-            if !self.silence_flag {
-                if self.shift_register & 1 != 0 {
-                    if self.volume < 125 {
-                        self.volume += 2;
-                    }
-                } else if self.volume > 1 {
-                    self.volume -= 2;
+            self.clock_output();
+        }
+    }
+
+    fn load_sample(&mut self, sample: u8) {
+        self.fetching = false;
+        self.sample_buffer = Some(sample);
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        /* the channel may have been disabled while the fetch was in flight */
+        if self.sample_bytes_remaining == 0 {
+            return;
+        }
+        self.sample_bytes_remaining -= 1;
+        if self.sample_bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq_flag = true;
+            }
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.sample_bytes_remaining = self.sample_length;
+    }
+
+    fn clock_output(&mut self) {
+        if !self.silence_flag {
+            if self.shift_register & 1 != 0 {
+                if self.volume <= 125 {
+                    self.volume += 2;
                 }
+            } else if self.volume >= 2 {
+                self.volume -= 2;
             }
-            /* TODO clock shift register */
-            self.shift_register >>= 1;
-            self.bits_remaining -= 1;
-            if self.bits_remaining == 0 {
-                if self.sample_buffer.is_none() {
-                    self.silence_flag = true;
-                } else {
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            /* start a new output cycle */
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(sample) => {
                     self.silence_flag = false;
-                    self.shift_register = self.sample_buffer.unwrap();
-                    self.sample_buffer = None;
+                    self.shift_register = sample;
                 }
+                None => self.silence_flag = true,
             }
         }
     }
 
     pub fn amplitude(&self) -> f32 {
-        self.volume as f32 / 15.0
+        self.volume as f32
     }
 
+    /* a write to 0x4015: disabling stops the sample, enabling restarts it if it had finished;
+     * either way, the IRQ flag is cleared
+     */
     pub fn set_enabled(&mut self, enabled: bool) {
-        /* TODO: DMC behavior for being disabled is more complex than others */
-        self.enabled = enabled;
+        self.irq_flag = false;
+        if !enabled {
+            self.sample_bytes_remaining = 0;
+        } else if self.sample_bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    /* whether there are still sample bytes to play, as read from 0x4015 */
+    pub fn is_active(&self) -> bool {
+        self.sample_bytes_remaining > 0
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.irq_flag
     }
 }
 
@@ -116,34 +158,76 @@ impl MemoryListener for DMC {
         [0x4010, 0x4011, 0x4012, 0x4013].to_vec()
     }
 
-    fn read(&mut self, memory: &CoreMemory, address: u16) -> u8 {
-        memory.read(address)
+    fn read(&mut self, memory: &CoreMemory, _address: u16) -> u8 {
+        memory.open_bus()
     }
 
     fn write(&mut self, _memory: &CoreMemory, address: u16, value: u8) {
         match address {
             0x4010 => {
-                self.irq_enabled = value & 0x80 != 0; /* TODO: clear interrupt flag when cleared */
+                self.irq_enabled = value & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq_flag = false;
+                }
                 self.loop_flag = value & 0x40 != 0;
-                /* map from number of CPU cycles to number of APU cycles */
-                self.rate_index = NTSC_RATE_MAP[(value & 0x0f) as usize] >> 2;
-                self.timer.set_period(self.rate_index);
+                self.rate_index = (value & 0x0f) as u16;
+                self.timer.set_period(Self::timer_period(self.rate_index));
             }
             0x4011 => {
-                /* writes value directly, but usually load a sample instead */
-                self.volume = value;
+                /* writes the output level directly, but usually load a sample instead */
+                self.volume = value & 0x7f;
             }
             0x4012 => {
                 /* memory value is offset from 0xc000, aligned to 64 byte chunks */
                 self.sample_address = 0xc000 | ((value as u16) << 6);
-                self.current_address = self.sample_address;
             }
             0x4013 => {
                 /* actual length is (sample_length * 16) + 1 bytes */
                 self.sample_length = ((value as u16) << 4) | 1;
-                self.sample_bytes_remaining = self.sample_length;
             }
             _ => unreachable!(),
         }
     }
 }
+
+impl Savestate for DMC {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.timer.save_state(writer);
+        writer.write_u8(self.bits_remaining);
+        writer.write_bool(self.sample_buffer.is_some());
+        writer.write_u8(self.sample_buffer.unwrap_or(0));
+        writer.write_u16(self.sample_address);
+        writer.write_u16(self.current_address);
+        writer.write_u16(self.sample_length);
+        writer.write_u16(self.sample_bytes_remaining);
+        writer.write_bool(self.fetching);
+        writer.write_bool(self.silence_flag);
+        writer.write_u8(self.shift_register);
+        writer.write_u8(self.volume);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_flag);
+        writer.write_bool(self.loop_flag);
+        writer.write_u16(self.rate_index);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.timer.load_state(reader)?;
+        self.bits_remaining = reader.read_u8()?.clamp(1, 8);
+        let has_sample = reader.read_bool()?;
+        let sample = reader.read_u8()?;
+        self.sample_buffer = if has_sample { Some(sample) } else { None };
+        self.sample_address = reader.read_u16()?;
+        self.current_address = reader.read_u16()?;
+        self.sample_length = reader.read_u16()?;
+        self.sample_bytes_remaining = reader.read_u16()?;
+        self.fetching = reader.read_bool()?;
+        self.silence_flag = reader.read_bool()?;
+        self.shift_register = reader.read_u8()?;
+        self.volume = reader.read_u8()? & 0x7f;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_flag = reader.read_bool()?;
+        self.loop_flag = reader.read_bool()?;
+        self.rate_index = reader.read_u16()? & 0x0f;
+        Ok(())
+    }
+}
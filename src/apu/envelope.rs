@@ -1,3 +1,6 @@
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
 pub struct Envelope {
     decay_level: u8,
     period_or_volume: u8,
@@ -65,3 +68,24 @@ impl Envelope {
         self.decay_level = 0;
     }
 }
+
+impl Savestate for Envelope {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.decay_level);
+        writer.write_u8(self.period_or_volume);
+        writer.write_u8(self.divider);
+        writer.write_bool(self.constant_volume);
+        writer.write_bool(self.start_flag);
+        writer.write_bool(self.loop_flag);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.decay_level = reader.read_u8()?;
+        self.period_or_volume = reader.read_u8()?;
+        self.divider = reader.read_u8()?;
+        self.constant_volume = reader.read_bool()?;
+        self.start_flag = reader.read_bool()?;
+        self.loop_flag = reader.read_bool()?;
+        Ok(())
+    }
+}
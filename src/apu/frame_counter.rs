@@ -0,0 +1,165 @@
+use crate::savestate::{invalid_data, Savestate, StateReader, StateWriter};
+use std::io;
+
+/* when each step lands, in CPU cycles since the sequence (re)started; different for PAL */
+const STEP_1: u32 = 7457;
+const STEP_2: u32 = 14913;
+const STEP_3: u32 = 22371;
+const FOUR_STEP_IRQ: u32 = 29828; /* the IRQ flag is set on this cycle and the two after it */
+const FOUR_STEP_4: u32 = 29829;
+const FOUR_STEP_PERIOD: u32 = 29830;
+const FIVE_STEP_5: u32 = 37281;
+const FIVE_STEP_PERIOD: u32 = 37282;
+
+/* which of the channels' units the frame counter clocks on a given cycle */
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct FrameClocks {
+    pub quarter: bool, /* envelopes and the triangle's linear counter */
+    pub half: bool,    /* length counters and sweep units */
+}
+
+impl FrameClocks {
+    pub const NONE: FrameClocks = FrameClocks {
+        quarter: false,
+        half: false,
+    };
+    pub const QUARTER: FrameClocks = FrameClocks {
+        quarter: true,
+        half: false,
+    };
+    pub const HALF: FrameClocks = FrameClocks {
+        quarter: true,
+        half: true,
+    };
+}
+
+/**
+ * The frame counter, set up by writes to 0x4017. It runs a 4-step or 5-step sequence that
+ * clocks the channels' envelopes, length counters, and sweeps, and in 4-step mode it raises
+ * the frame IRQ at the end of every sequence unless that's inhibited.
+ * See https://www.nesdev.org/wiki/APU_Frame_Counter
+ */
+pub struct FrameCounter {
+    cycle: u32,
+    five_step: bool,
+    irq_inhibit: bool,
+    irq_flag: bool,
+    pending_write: Option<(u8, u8)>, /* a 0x4017 write and the cycles until it takes effect */
+    last_write: u8,
+}
+
+impl FrameCounter {
+    pub fn new() -> FrameCounter {
+        FrameCounter {
+            cycle: 0,
+            five_step: false,
+            irq_inhibit: false,
+            irq_flag: false,
+            pending_write: None,
+            last_write: 0,
+        }
+    }
+
+    /**
+     * Runs one CPU cycle, returning what to clock on it. A 0x4017 write takes effect 3 cycles
+     * after it's made if it landed on an APU cycle, or 4 if it landed between them; the sequence
+     * then restarts, and entering 5-step mode clocks everything straight away.
+     */
+    pub fn clock(&mut self) -> FrameClocks {
+        if let Some((value, delay)) = self.pending_write {
+            if delay > 1 {
+                self.pending_write = Some((value, delay - 1));
+            } else {
+                self.pending_write = None;
+                self.five_step = value & 0x80 != 0;
+                self.cycle = 0;
+                return if self.five_step {
+                    FrameClocks::HALF
+                } else {
+                    FrameClocks::NONE
+                };
+            }
+        }
+
+        self.cycle += 1;
+        let clocks = match self.cycle {
+            STEP_1 | STEP_3 => FrameClocks::QUARTER,
+            STEP_2 => FrameClocks::HALF,
+            FOUR_STEP_4 if !self.five_step => FrameClocks::HALF,
+            FIVE_STEP_5 if self.five_step => FrameClocks::HALF,
+            _ => FrameClocks::NONE,
+        };
+
+        if !self.five_step {
+            if (FOUR_STEP_IRQ..=FOUR_STEP_PERIOD).contains(&self.cycle) && !self.irq_inhibit {
+                self.irq_flag = true;
+            }
+            if self.cycle == FOUR_STEP_PERIOD {
+                self.cycle = 0;
+            }
+        } else if self.cycle == FIVE_STEP_PERIOD {
+            self.cycle = 0;
+        }
+
+        clocks
+    }
+
+    /* a write to 0x4017; on_apu_cycle is whether it landed on an APU cycle */
+    pub fn write(&mut self, value: u8, on_apu_cycle: bool) {
+        self.last_write = value;
+        self.irq_inhibit = value & 0x40 != 0;
+        if self.irq_inhibit {
+            self.irq_flag = false;
+        }
+        self.pending_write = Some((value, if on_apu_cycle { 3 } else { 4 }));
+    }
+
+    /* reset leaves the mode as it was, but restarts the sequence as if 0x4017 were rewritten */
+    pub fn reset(&mut self) {
+        self.irq_flag = false;
+        self.write(self.last_write, false);
+    }
+
+    pub fn irq_pending(&self) -> bool {
+        self.irq_flag
+    }
+
+    /* reading 0x4015 acknowledges the frame IRQ */
+    pub fn clear_irq(&mut self) {
+        self.irq_flag = false;
+    }
+}
+
+impl Savestate for FrameCounter {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.cycle);
+        writer.write_bool(self.five_step);
+        writer.write_bool(self.irq_inhibit);
+        writer.write_bool(self.irq_flag);
+        writer.write_bool(self.pending_write.is_some());
+        let (value, delay) = self.pending_write.unwrap_or((0, 0));
+        writer.write_u8(value);
+        writer.write_u8(delay);
+        writer.write_u8(self.last_write);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.cycle = reader.read_u32()?;
+        if self.cycle >= FIVE_STEP_PERIOD {
+            return Err(invalid_data("frame counter cycle out of range"));
+        }
+        self.five_step = reader.read_bool()?;
+        self.irq_inhibit = reader.read_bool()?;
+        self.irq_flag = reader.read_bool()?;
+        let has_write = reader.read_bool()?;
+        let value = reader.read_u8()?;
+        let delay = reader.read_u8()?;
+        self.pending_write = if has_write {
+            Some((value, delay))
+        } else {
+            None
+        };
+        self.last_write = reader.read_u8()?;
+        Ok(())
+    }
+}
@@ -1,3 +1,6 @@
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
 const LENGTH_COUNTER_LOOKUP: [u8; 32] = [
     10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
     192, 24, 72, 26, 16, 28, 32, 30,
@@ -49,3 +52,18 @@ impl LengthCounter {
         self.count = self.period;
     }
 }
+
+impl Savestate for LengthCounter {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.period);
+        writer.write_u8(self.count);
+        writer.write_bool(self.halt);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.period = reader.read_u8()?;
+        self.count = reader.read_u8()?;
+        self.halt = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -1,11 +1,21 @@
 mod apu;
 mod dmc;
 mod envelope;
+mod frame_counter;
 mod length_counter;
 mod noise;
 mod pulse;
+mod recorder;
 mod sweep;
 mod timer;
 mod triangle;
 
-pub use apu::APU;
+#[cfg(test)]
+mod tests;
+
+pub(crate) use apu::{mix_pulses, mix_tnd};
+pub use apu::{APU, SAMPLE_RATE};
+pub(crate) use pulse::Pulse;
+pub use recorder::{AudioRecorder, WavFormat};
+#[cfg(test)]
+pub(crate) use recorder::{WavWriter, STEM_NAMES};
@@ -2,7 +2,9 @@ use crate::apu::envelope::Envelope;
 use crate::apu::length_counter::LengthCounter;
 use crate::apu::timer::Timer;
 use crate::cpu::{CoreMemory, MemoryListener};
+use crate::savestate::{Savestate, StateReader, StateWriter};
 use std::cell::RefCell;
+use std::io;
 use std::rc::Rc;
 
 /* these values are different on PAL */
@@ -40,7 +42,7 @@ impl Noise {
         }
     }
 
-    pub fn tick(&mut self, apu_counter: u16) {
+    pub fn tick(&mut self) {
         if !self.enabled {
             return;
         }
@@ -50,17 +52,14 @@ impl Noise {
             self.shift_register >>= 1;
             self.shift_register |= feedback << 14;
         }
+    }
 
-        let is_half_frame = apu_counter == 7456 || apu_counter == 14914;
-        let is_quarter_frame = is_half_frame || apu_counter == 3728 || apu_counter == 11185;
-
-        if is_quarter_frame {
-            self.envelope.clock();
-        }
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
 
-        if is_half_frame || is_quarter_frame {
-            self.length_counter.clock();
-        }
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
     }
 
     pub fn amplitude(&self) -> f32 {
@@ -75,6 +74,11 @@ impl Noise {
         }
         self.enabled = enabled;
     }
+
+    /* whether the length counter is still running, as read from 0x4015 */
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
 }
 
 impl MemoryListener for Noise {
@@ -109,3 +113,24 @@ impl MemoryListener for Noise {
         }
     }
 }
+
+impl Savestate for Noise {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.envelope.save_state(writer);
+        self.length_counter.save_state(writer);
+        self.timer.save_state(writer);
+        writer.write_u16(self.shift_register);
+        writer.write_bool(self.mode_flag);
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.envelope.load_state(reader)?;
+        self.length_counter.load_state(reader)?;
+        self.timer.load_state(reader)?;
+        self.shift_register = reader.read_u16()?;
+        self.mode_flag = reader.read_bool()?;
+        self.enabled = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -3,7 +3,9 @@ use crate::apu::length_counter::LengthCounter;
 use crate::apu::sweep::Sweep;
 use crate::apu::timer::Timer;
 use crate::cpu::{CoreMemory, MemoryListener};
+use crate::savestate::{invalid_data, Savestate, StateReader, StateWriter};
 use std::cell::RefCell;
+use std::io;
 use std::rc::Rc;
 
 /* waveform descriptions from https://www.nesdev.org/wiki/APU_Pulse */
@@ -51,24 +53,21 @@ impl Pulse {
         }
     }
 
-    pub(crate) fn tick(&mut self, apu_counter: u16) {
+    pub(crate) fn tick(&mut self) {
         if !self.enabled {
             return;
         }
         /* on every tick, clock sequencer timer  */
         self.sequencer.clock();
+    }
 
-        let is_half_frame = apu_counter == 7456 || apu_counter == 14914;
-        let is_quarter_frame = is_half_frame || apu_counter == 3728 || apu_counter == 11185;
-
-        if is_quarter_frame {
-            self.envelope.clock();
+    pub(crate) fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
 
-            if is_half_frame {
-                self.length_counter.clock();
-                self.sweep.clock(&mut self.sequencer.timer);
-            }
-        }
+    pub(crate) fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+        self.sweep.clock(&mut self.sequencer.timer);
     }
 
     pub fn set_enabled(&mut self, enabled: bool) {
@@ -78,6 +77,11 @@ impl Pulse {
         self.enabled = enabled;
     }
 
+    /* whether the length counter is still running, as read from 0x4015 */
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
+
     fn set_duty_envelope(&mut self, byte0: u8) {
         self.sequencer.duty = ((byte0 & 0xc0) >> 6) as usize; /* NB: does not change duty_index */
         self.envelope.set_envelope(byte0);
@@ -93,6 +97,16 @@ impl Pulse {
         self.envelope.start();
     }
 
+    /* writes one of the four registers, numbered from 0, e.g. for 0x4000-0x4003 */
+    pub(crate) fn write_register(&mut self, register: u16, value: u8) {
+        match register {
+            0 => self.set_duty_envelope(value),
+            1 => self.sweep.set_sweep(value),
+            2 => self.sequencer.timer.set_timer_lo(value),
+            _ => self.set_lc_timer_hi(value),
+        }
+    }
+
     pub fn amplitude(&self) -> f32 {
         self.length_counter.amplitude()
             * self.envelope.amplitude()
@@ -139,25 +153,38 @@ impl MemoryListener for Pulse {
     }
 
     fn write(&mut self, _memory: &CoreMemory, address: u16, value: u8) {
-        match address - self.first_address {
-            0 => {
-                self.set_duty_envelope(value);
-            }
-            1 => {
-                self.sweep.set_sweep(value);
-            }
-            2 => {
-                self.sequencer.timer.set_timer_lo(value);
-            }
-            3 => {
-                self.set_lc_timer_hi(value);
-            }
-            _ => {
-                panic!(
-                    "APU instrument passed invalid memory address 0x{:x}",
-                    address
-                );
-            }
+        if address - self.first_address > 3 {
+            panic!(
+                "APU instrument passed invalid memory address 0x{:x}",
+                address
+            );
+        }
+        self.write_register(address - self.first_address, value);
+    }
+}
+
+impl Savestate for Pulse {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.envelope.save_state(writer);
+        self.length_counter.save_state(writer);
+        self.sweep.save_state(writer);
+        self.sequencer.timer.save_state(writer);
+        writer.write_u8(self.sequencer.duty as u8);
+        writer.write_u8(self.sequencer.duty_index as u8);
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.envelope.load_state(reader)?;
+        self.length_counter.load_state(reader)?;
+        self.sweep.load_state(reader)?;
+        self.sequencer.timer.load_state(reader)?;
+        self.sequencer.duty = reader.read_u8()? as usize;
+        self.sequencer.duty_index = reader.read_u8()? as usize;
+        if self.sequencer.duty >= 4 || self.sequencer.duty_index >= 8 {
+            return Err(invalid_data("Invalid pulse duty"));
         }
+        self.enabled = reader.read_bool()?;
+        Ok(())
     }
 }
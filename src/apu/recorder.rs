@@ -0,0 +1,210 @@
+use crate::apu::apu::{mix, CHANNELS, SAMPLE_RATE};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/* the channels' names, in the order the APU gives their levels; stems are named after them */
+pub const STEM_NAMES: [&str; CHANNELS] =
+    ["pulse1", "pulse2", "triangle", "noise", "dmc", "expansion"];
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_IEEE_FLOAT: u16 = 3;
+
+/**
+ * How samples are stored in a WAV file: as 16-bit integers, which everything can play, or as
+ * 32-bit floats, which keep exactly what the mixer made.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WavFormat {
+    #[default]
+    Pcm16,
+    Float,
+}
+
+impl WavFormat {
+    /* the name FromStr takes */
+    pub fn name(self) -> &'static str {
+        match self {
+            WavFormat::Pcm16 => "pcm16",
+            WavFormat::Float => "float",
+        }
+    }
+
+    fn bytes_per_sample(self) -> u32 {
+        match self {
+            WavFormat::Pcm16 => 2,
+            WavFormat::Float => 4,
+        }
+    }
+}
+
+impl FromStr for WavFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pcm16" => Ok(WavFormat::Pcm16),
+            "float" => Ok(WavFormat::Float),
+            _ => Err(format!("expected pcm16 or float, not '{}'", s)),
+        }
+    }
+}
+
+/**
+ * Writes mono samples out as a WAV file. The header's lengths aren't known until the end, so
+ * it's written with the count so far, then written again by finish.
+ */
+pub struct WavWriter<W: Write + Seek> {
+    output: W,
+    format: WavFormat,
+    sample_rate: u32,
+    samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(output: W, format: WavFormat, sample_rate: u32) -> io::Result<WavWriter<W>> {
+        let mut writer = WavWriter {
+            output,
+            format,
+            sample_rate,
+            samples: 0,
+        };
+        writer.write_header()?;
+        Ok(writer)
+    }
+
+    /* full scale is -1 to 1; 16-bit samples beyond it are clipped */
+    pub fn write_sample(&mut self, sample: f32) -> io::Result<()> {
+        match self.format {
+            WavFormat::Pcm16 => {
+                let sample = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+                self.output.write_all(&sample.to_le_bytes())?;
+            }
+            WavFormat::Float => self.output.write_all(&sample.to_le_bytes())?,
+        }
+        self.samples += 1;
+        Ok(())
+    }
+
+    /* fills in the header's lengths, returning the output */
+    pub fn finish(mut self) -> io::Result<W> {
+        self.output.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.output.seek(SeekFrom::End(0))?;
+        self.output.flush()?;
+        Ok(self.output)
+    }
+
+    /**
+     * RIFF, then the fmt chunk, then the data chunk. Float files also need the fmt chunk's
+     * extension size, and a fact chunk with the number of samples.
+     */
+    fn write_header(&mut self) -> io::Result<()> {
+        let bytes_per_sample = self.format.bytes_per_sample();
+        let data_size = self.samples * bytes_per_sample;
+        let (format_tag, fmt_size, fact_size) = match self.format {
+            WavFormat::Pcm16 => (FORMAT_PCM, 16, 0),
+            WavFormat::Float => (FORMAT_IEEE_FLOAT, 18, 12),
+        };
+
+        let mut header = Vec::new();
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(4 + 8 + fmt_size + fact_size + 8 + data_size).to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&fmt_size.to_le_bytes());
+        header.extend_from_slice(&format_tag.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes()); /* mono */
+        header.extend_from_slice(&self.sample_rate.to_le_bytes());
+        header.extend_from_slice(&(self.sample_rate * bytes_per_sample).to_le_bytes());
+        header.extend_from_slice(&(bytes_per_sample as u16).to_le_bytes()); /* block align */
+        header.extend_from_slice(&(bytes_per_sample as u16 * 8).to_le_bytes());
+        if self.format == WavFormat::Float {
+            header.extend_from_slice(&0u16.to_le_bytes()); /* no extension */
+            header.extend_from_slice(b"fact");
+            header.extend_from_slice(&4u32.to_le_bytes());
+            header.extend_from_slice(&self.samples.to_le_bytes());
+        }
+
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&data_size.to_le_bytes());
+        self.output.write_all(&header)
+    }
+}
+
+/**
+ * Records what the APU puts out to a WAV file, and optionally each channel on its own to a
+ * stem beside it. A stem is the channel as the mixer would put it out if the others were
+ * silent; since the mixer isn't linear, the stems don't quite add up to the mix.
+ */
+pub struct AudioRecorder {
+    mix: WavWriter<BufWriter<File>>,
+    stems: Vec<WavWriter<BufWriter<File>>>, /* in STEM_NAMES order, or none */
+    error: Option<io::Error>,               /* the first write to fail; nothing is written after */
+}
+
+impl AudioRecorder {
+    /* creates the WAV file at path and, with stems, one for each channel named by stem_path;
+     * everything is recorded at SAMPLE_RATE
+     */
+    pub fn create(path: &Path, format: WavFormat, stems: bool) -> io::Result<AudioRecorder> {
+        let create =
+            |path: &Path| WavWriter::new(BufWriter::new(File::create(path)?), format, SAMPLE_RATE);
+        let stems = match stems {
+            true => STEM_NAMES
+                .iter()
+                .map(|name| create(&Self::stem_path(path, name)))
+                .collect::<io::Result<_>>()?,
+            false => Vec::new(),
+        };
+        Ok(AudioRecorder {
+            mix: create(path)?,
+            stems,
+            error: None,
+        })
+    }
+
+    /* where a channel's stem goes: beside the mix, e.g. game.pulse1.wav for game.wav */
+    pub fn stem_path(path: &Path, stem: &str) -> PathBuf {
+        let name = match path.file_stem() {
+            Some(name) => format!("{}.{stem}.wav", name.to_string_lossy()),
+            None => format!("{stem}.wav"),
+        };
+        path.with_file_name(name)
+    }
+
+    /* takes a sample of each channel's level, in STEM_NAMES order */
+    pub(crate) fn record(&mut self, levels: [f32; CHANNELS]) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = self.write(levels) {
+            self.error = Some(e);
+        }
+    }
+
+    fn write(&mut self, levels: [f32; CHANNELS]) -> io::Result<()> {
+        self.mix.write_sample(mix(levels))?;
+        for (channel, stem) in self.stems.iter_mut().enumerate() {
+            let mut solo = [0.0; CHANNELS];
+            solo[channel] = levels[channel];
+            stem.write_sample(mix(solo))?;
+        }
+        Ok(())
+    }
+
+    /* completes the files, or reports the first thing that went wrong writing them */
+    pub fn finish(self) -> io::Result<()> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.mix.finish()?;
+        for stem in self.stems {
+            stem.finish()?;
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,6 @@
 use crate::apu::timer::Timer;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
 
 pub struct Sweep {
     divider: u8,
@@ -69,3 +71,27 @@ impl Sweep {
         self.reload = true;
     }
 }
+
+/* ones_complement is fixed by which pulse channel this is, so it isn't saved */
+impl Savestate for Sweep {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.divider);
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.negate);
+        writer.write_u8(self.period);
+        writer.write_bool(self.reload);
+        writer.write_u8(self.shift);
+        writer.write_bool(self.muting);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.divider = reader.read_u8()?;
+        self.enabled = reader.read_bool()?;
+        self.negate = reader.read_bool()?;
+        self.period = reader.read_u8()?;
+        self.reload = reader.read_bool()?;
+        self.shift = reader.read_u8()?;
+        self.muting = reader.read_bool()?;
+        Ok(())
+    }
+}
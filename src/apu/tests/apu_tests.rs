@@ -0,0 +1,125 @@
+use crate::apu::{mix_tnd, AudioRecorder, WavFormat, APU};
+use crate::cpu::tests::flat_memory::FlatMemory;
+use crate::cpu::{CoreMemory, CPU};
+use crate::rom::{Rom, RomHeader};
+use crate::simulator::Speed;
+use std::cell::RefCell;
+use std::fs;
+use std::rc::Rc;
+
+fn idle_cpu() -> Box<CPU> {
+    let memory = CoreMemory::new_flat(Box::new(FlatMemory::new(&[], 0)));
+    CPU::new(Box::new(memory))
+}
+
+/* how many samples the APU queues over the given number of CPU cycles */
+fn samples_over(apu: &mut APU, cpu: &mut CPU, cycles: usize) -> usize {
+    let before = apu.queued_samples();
+    for _ in 0..cycles {
+        apu.tick(cpu);
+        cpu.idle_cycle();
+    }
+    apu.queued_samples() - before
+}
+
+#[test]
+fn samples_every_40_cycles_at_normal_speed() {
+    let apu = APU::silent();
+    let mut apu = apu.borrow_mut();
+    let cpu = &mut idle_cpu();
+    assert_eq!(samples_over(&mut apu, cpu, 4000), 100);
+}
+
+#[test]
+fn sample_rate_follows_speed() {
+    let apu = APU::silent();
+    let mut apu = apu.borrow_mut();
+    let cpu = &mut idle_cpu();
+
+    /* four times as fast, so a quarter of the samples per emulated cycle */
+    apu.set_speed(Speed::Percent(400));
+    assert_eq!(samples_over(&mut apu, cpu, 16_000), 100);
+
+    apu.set_speed(Speed::Percent(25));
+    assert_eq!(samples_over(&mut apu, cpu, 1000), 100);
+
+    apu.set_speed(Speed::NORMAL);
+    assert_eq!(samples_over(&mut apu, cpu, 4000), 100);
+}
+
+#[test]
+fn unthrottled_is_silent() {
+    let apu = APU::silent();
+    let mut apu = apu.borrow_mut();
+    let cpu = &mut idle_cpu();
+    samples_over(&mut apu, cpu, 4000);
+
+    apu.set_speed(Speed::Unthrottled);
+    assert_eq!(apu.queued_samples(), 0);
+    assert_eq!(samples_over(&mut apu, cpu, 4000), 0);
+}
+
+#[test]
+fn queue_drops_old_samples_instead_of_backing_up() {
+    let apu = APU::silent();
+    let mut apu = apu.borrow_mut();
+    let cpu = &mut idle_cpu();
+    for _ in 0..40 * 10_000 {
+        apu.tick(cpu);
+        cpu.idle_cycle();
+        assert!(apu.queued_samples() <= 4096);
+    }
+    assert!(apu.queued_samples() > 2048);
+}
+
+#[test]
+fn recording_is_at_the_normal_rate_whatever_the_speed() {
+    let dir = std::env::temp_dir().join(format!("patina-apu-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("speed.wav");
+    let apu = APU::silent();
+    let mut apu = apu.borrow_mut();
+    let cpu = &mut idle_cpu();
+
+    let recorder = AudioRecorder::create(&path, WavFormat::Pcm16, false).unwrap();
+    apu.start_recording(recorder).unwrap();
+    assert!(apu.is_recording());
+    apu.set_speed(Speed::Percent(400));
+    samples_over(&mut apu, cpu, 4000);
+    apu.set_speed(Speed::Unthrottled);
+    samples_over(&mut apu, cpu, 4000);
+    /* nothing is recorded while it's muted for rewinding */
+    apu.set_muted(true);
+    samples_over(&mut apu, cpu, 4000);
+    apu.set_muted(false);
+    apu.stop_recording().unwrap();
+    assert!(!apu.is_recording());
+
+    /* the 44-byte header, then 100 16-bit samples every 4000 cycles */
+    assert_eq!(fs::metadata(&path).unwrap().len(), 44 + 200 * 2);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cartridge_audio_is_mixed_in() {
+    let rom = Rom {
+        header: RomHeader {
+            mapper: 5,
+            ..RomHeader::default()
+        },
+        prg_data: vec![0; 1 << 15],
+        chr_data: vec![0; 1 << 13],
+        _trainer: vec![],
+    };
+    let mapper = Rc::new(RefCell::new(rom.initialize_mapper().unwrap()));
+    /* MMC5's PCM channel at full scale; everything else is silent */
+    mapper.borrow_mut().write_prg(0x5011, 0xff);
+
+    let apu = APU::silent();
+    let mut apu = apu.borrow_mut();
+    let cpu = &mut idle_cpu();
+    apu.connect_cartridge(mapper);
+    samples_over(&mut apu, cpu, 400);
+    let samples = apu.take_samples();
+    assert_eq!(samples, vec![mix_tnd(0.0, 0.0, 127.5); 10]);
+}
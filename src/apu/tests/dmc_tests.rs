@@ -0,0 +1,191 @@
+use crate::apu::dmc::DMC;
+use crate::apu::APU;
+use crate::cpu::tests::flat_memory::FlatMemory;
+use crate::cpu::{CoreMemory, MemoryListener, CPU};
+
+/* a CPU on flat memory with samples at sample_address, for the DMC to fetch from */
+fn cpu_with_samples(samples: &[u8], sample_address: u16) -> Box<CPU> {
+    let mut image = vec![0xea; 0x10000]; /* NOPs */
+    let start = sample_address as usize;
+    image[start..start + samples.len()].copy_from_slice(samples);
+    image[0xfffc] = 0x00;
+    image[0xfffd] = 0x04;
+    let memory = CoreMemory::new_flat(Box::new(FlatMemory::new(&image, 0)));
+    CPU::new(Box::new(memory))
+}
+
+/* the DMC ignores the memory it's handed; this is just something to pass to it */
+fn register_memory() -> CoreMemory {
+    CoreMemory::new_flat(Box::new(FlatMemory::new(&[], 0)))
+}
+
+/* runs the DMC for the given number of APU cycles, two CPU cycles each */
+fn run(dmc: &mut DMC, cpu: &mut CPU, apu_cycles: usize) {
+    for _ in 0..apu_cycles {
+        dmc.tick(cpu);
+        cpu.idle_cycle();
+        cpu.idle_cycle();
+    }
+}
+
+/* sets up a sample at 0xc000 of length * 16 + 1 bytes, at the fastest rate */
+fn dmc_with_sample(flags: u8, length: u8) -> DMC {
+    let memory = register_memory();
+    let mut dmc = DMC::new();
+    dmc.write(&memory, 0x4010, flags | 0x0f);
+    dmc.write(&memory, 0x4012, 0x00);
+    dmc.write(&memory, 0x4013, length);
+    dmc
+}
+
+#[test]
+fn fetching_a_sample_byte_steals_cpu_cycles() {
+    let cpu = &mut cpu_with_samples(&[0x55], 0xc000);
+    let mut dmc = dmc_with_sample(0, 0);
+    dmc.set_enabled(true);
+    assert!(dmc.is_active());
+
+    dmc.tick(cpu);
+    let start = cpu.cycles();
+    cpu.idle_cycle();
+    let stolen = cpu.cycles() - start - 1;
+    assert!(stolen == 3 || stolen == 4, "stole {} cycles", stolen);
+
+    /* the DMC picks the byte up on its next cycle; that was the whole (1 byte) sample */
+    dmc.tick(cpu);
+    assert!(!dmc.is_active());
+}
+
+#[test]
+fn only_one_fetch_is_in_flight_at_a_time() {
+    let cpu = &mut cpu_with_samples(&[0x55; 17], 0xc000);
+    let mut dmc = dmc_with_sample(0, 1);
+    dmc.set_enabled(true);
+
+    /* the buffer fills once, then the next byte waits until the output unit empties it */
+    run(&mut dmc, cpu, 4);
+    let start = cpu.cycles();
+    run(&mut dmc, cpu, 4);
+    assert_eq!(cpu.cycles() - start, 8);
+}
+
+#[test]
+fn output_level_follows_sample_bits() {
+    let cpu = &mut cpu_with_samples(&[0xff; 17], 0xc000);
+    let mut dmc = dmc_with_sample(0, 1);
+    dmc.write(&register_memory(), 0x4011, 0x40);
+    dmc.set_enabled(true);
+    assert_eq!(dmc.amplitude(), 64.0);
+
+    /* rate 15 is 54 CPU cycles, or 27 APU cycles, per bit; the first output cycle is silent */
+    run(&mut dmc, cpu, 27 * 8 + 27 * 4);
+    assert_eq!(dmc.amplitude(), 72.0);
+}
+
+#[test]
+fn output_level_saturates() {
+    let cpu = &mut cpu_with_samples(&[0x00; 17], 0xc000);
+    let mut dmc = dmc_with_sample(0x40, 1);
+    dmc.write(&register_memory(), 0x4011, 0x03);
+    dmc.set_enabled(true);
+
+    run(&mut dmc, cpu, 27 * 32);
+    assert_eq!(dmc.amplitude(), 1.0);
+}
+
+#[test]
+fn end_of_sample_raises_irq_when_enabled() {
+    let cpu = &mut cpu_with_samples(&[0x00], 0xc000);
+    let mut dmc = dmc_with_sample(0x80, 0);
+    dmc.set_enabled(true);
+
+    run(&mut dmc, cpu, 4);
+    assert!(!dmc.is_active());
+    assert!(dmc.irq_pending());
+
+    /* writing 0x4015 acknowledges it */
+    dmc.set_enabled(false);
+    assert!(!dmc.irq_pending());
+}
+
+#[test]
+fn disabling_irq_in_4010_clears_the_flag() {
+    let cpu = &mut cpu_with_samples(&[0x00], 0xc000);
+    let mut dmc = dmc_with_sample(0x80, 0);
+    dmc.set_enabled(true);
+    run(&mut dmc, cpu, 4);
+    assert!(dmc.irq_pending());
+
+    dmc.write(&register_memory(), 0x4010, 0x0f);
+    assert!(!dmc.irq_pending());
+}
+
+#[test]
+fn looping_sample_restarts_without_irq() {
+    let cpu = &mut cpu_with_samples(&[0x00], 0xc000);
+    let mut dmc = dmc_with_sample(0xc0, 0);
+    dmc.set_enabled(true);
+
+    run(&mut dmc, cpu, 27 * 8 * 4);
+    assert!(dmc.is_active());
+    assert!(!dmc.irq_pending());
+}
+
+#[test]
+fn disabling_stops_the_sample_and_enabling_restarts_it() {
+    let cpu = &mut cpu_with_samples(&[0x00; 17], 0xc000);
+    let mut dmc = dmc_with_sample(0, 1);
+    dmc.set_enabled(true);
+    run(&mut dmc, cpu, 4);
+
+    dmc.set_enabled(false);
+    assert!(!dmc.is_active());
+    run(&mut dmc, cpu, 4);
+    assert!(!dmc.is_active());
+
+    dmc.set_enabled(true);
+    assert!(dmc.is_active());
+}
+
+#[test]
+fn sample_address_wraps_to_0x8000() {
+    /* start at 0xffc0, 0x41 bytes: the last one comes from 0x8000 */
+    let cpu = &mut cpu_with_samples(&[0x00; 0x40], 0xffc0);
+    cpu.write_mem(0x8000, 0xff);
+    let memory = register_memory();
+    let mut dmc = DMC::new();
+    dmc.write(&memory, 0x4010, 0x0f);
+    dmc.write(&memory, 0x4011, 0x00);
+    dmc.write(&memory, 0x4012, 0xff);
+    dmc.write(&memory, 0x4013, 0x04);
+    dmc.set_enabled(true);
+
+    /* 0x41 bytes of 8 bits each, plus the silent first output cycle and one to play the last */
+    run(&mut dmc, cpu, 27 * 8 * 0x42);
+    assert!(!dmc.is_active());
+    assert_eq!(dmc.amplitude(), 16.0);
+}
+
+#[test]
+fn status_register_reports_dmc_activity_and_irq() {
+    let apu = APU::silent();
+    let memory = register_memory();
+    let cpu = &mut cpu_with_samples(&[0x00], 0xc000);
+    let mut apu = apu.borrow_mut();
+    apu.write(&memory, 0x4010, 0x8f);
+    apu.write(&memory, 0x4013, 0x00);
+    apu.write(&memory, 0x4015, 0x10);
+    assert_eq!(apu.read(&memory, 0x4015) & 0x90, 0x10);
+
+    for _ in 0..8 {
+        apu.tick(cpu);
+        cpu.idle_cycle();
+    }
+    assert_eq!(apu.read(&memory, 0x4015) & 0x90, 0x80);
+    assert!(cpu.irq_set());
+
+    apu.write(&memory, 0x4015, 0x00);
+    apu.tick(cpu);
+    assert_eq!(apu.read(&memory, 0x4015) & 0x90, 0x00);
+    assert!(!cpu.irq_set());
+}
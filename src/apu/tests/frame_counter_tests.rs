@@ -0,0 +1,199 @@
+use crate::apu::frame_counter::{FrameClocks, FrameCounter};
+use crate::apu::APU;
+use crate::cpu::tests::flat_memory::FlatMemory;
+use crate::cpu::{CoreMemory, MemoryListener, CPU};
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+/* runs the frame counter until the next cycle that clocks something, returning how many
+ * cycles that took and what it clocked
+ */
+fn next_clocks(counter: &mut FrameCounter) -> (u32, FrameClocks) {
+    for cycle in 1..=40_000 {
+        let clocks = counter.clock();
+        if clocks.quarter || clocks.half {
+            return (cycle, clocks);
+        }
+    }
+    panic!("frame counter never clocked anything");
+}
+
+/* cycles after a 0x4017 write until the IRQ flag is set */
+fn cycles_until_irq(counter: &mut FrameCounter) -> u32 {
+    for cycle in 1..=40_000 {
+        counter.clock();
+        if counter.irq_pending() {
+            return cycle;
+        }
+    }
+    panic!("frame IRQ never came");
+}
+
+const QUARTER: FrameClocks = FrameClocks::QUARTER;
+const HALF: FrameClocks = FrameClocks::HALF;
+
+#[test]
+fn four_step_sequence() {
+    let mut counter = FrameCounter::new();
+    counter.write(0x40, true);
+
+    /* the write takes effect 3 cycles later, then the steps are 7457, 14913, 22371, 29829 */
+    assert_eq!(next_clocks(&mut counter), (3 + 7457, QUARTER));
+    assert_eq!(next_clocks(&mut counter), (14913 - 7457, HALF));
+    assert_eq!(next_clocks(&mut counter), (22371 - 14913, QUARTER));
+    assert_eq!(next_clocks(&mut counter), (29829 - 22371, HALF));
+    /* and the sequence is 29830 cycles long */
+    assert_eq!(next_clocks(&mut counter), (29830 - 29829 + 7457, QUARTER));
+    assert!(!counter.irq_pending());
+}
+
+#[test]
+fn five_step_sequence_clocks_immediately() {
+    let mut counter = FrameCounter::new();
+    counter.write(0x80, true);
+
+    assert_eq!(next_clocks(&mut counter), (3, HALF));
+    assert_eq!(next_clocks(&mut counter), (7457, QUARTER));
+    assert_eq!(next_clocks(&mut counter), (14913 - 7457, HALF));
+    assert_eq!(next_clocks(&mut counter), (22371 - 14913, QUARTER));
+    assert_eq!(next_clocks(&mut counter), (37281 - 22371, HALF));
+    /* the sequence is 37282 cycles long, and never raises an IRQ */
+    assert_eq!(next_clocks(&mut counter), (37282 - 37281 + 7457, QUARTER));
+    assert!(!counter.irq_pending());
+}
+
+#[test]
+fn write_between_apu_cycles_takes_a_cycle_longer() {
+    let mut counter = FrameCounter::new();
+    counter.write(0x80, false);
+    assert_eq!(next_clocks(&mut counter), (4, HALF));
+}
+
+#[test]
+fn irq_flag_is_set_29831_cycles_after_write() {
+    let mut counter = FrameCounter::new();
+    counter.write(0x00, true);
+    assert_eq!(cycles_until_irq(&mut counter), 29831);
+
+    /* it's set on three cycles in a row, so acknowledging it on the first two doesn't stick */
+    counter.clear_irq();
+    counter.clock();
+    assert!(counter.irq_pending());
+    counter.clear_irq();
+    counter.clock();
+    assert!(counter.irq_pending());
+    counter.clear_irq();
+    counter.clock();
+    assert!(!counter.irq_pending());
+
+    /* and then again every 29830 cycles */
+    assert_eq!(cycles_until_irq(&mut counter), 29830 - 3);
+}
+
+#[test]
+fn jitter_delays_irq_by_a_cycle() {
+    let mut counter = FrameCounter::new();
+    counter.write(0x00, false);
+    assert_eq!(cycles_until_irq(&mut counter), 29832);
+}
+
+#[test]
+fn inhibiting_irq_clears_the_flag() {
+    let mut counter = FrameCounter::new();
+    counter.write(0x00, true);
+    cycles_until_irq(&mut counter);
+
+    counter.write(0x40, true);
+    assert!(!counter.irq_pending());
+    for _ in 0..70_000 {
+        counter.clock();
+        assert!(!counter.irq_pending());
+    }
+}
+
+#[test]
+fn reset_restarts_sequence_in_same_mode() {
+    let mut counter = FrameCounter::new();
+    counter.write(0x80, true);
+    for _ in 0..10_000 {
+        counter.clock();
+    }
+
+    counter.reset();
+    assert_eq!(next_clocks(&mut counter), (4, HALF));
+}
+
+#[test]
+fn frame_counter_state_round_trips() {
+    let mut counter = FrameCounter::new();
+    counter.write(0x00, true);
+    for _ in 0..20_000 {
+        counter.clock();
+    }
+    let mut writer = StateWriter::new();
+    counter.save_state(&mut writer);
+    let state = writer.into_bytes();
+
+    let mut restored = FrameCounter::new();
+    let mut reader = StateReader::new(&state).unwrap();
+    restored.load_state(&mut reader).unwrap();
+    assert_eq!(
+        cycles_until_irq(&mut restored),
+        cycles_until_irq(&mut counter)
+    );
+}
+
+/* a CPU running NOPs, for the APU to raise its IRQ on */
+fn idle_cpu() -> Box<CPU> {
+    let mut image = vec![0xea; 0x10000];
+    image[0xfffc] = 0x00;
+    image[0xfffd] = 0x04;
+    CPU::new(Box::new(CoreMemory::new_flat(Box::new(FlatMemory::new(
+        &image, 0,
+    )))))
+}
+
+#[test]
+fn frame_irq_reaches_cpu_and_reading_status_acknowledges_it() {
+    let apu = APU::silent();
+    let mut apu = apu.borrow_mut();
+    let memory = CoreMemory::new_flat(Box::new(FlatMemory::new(&[], 0)));
+    let cpu = &mut idle_cpu();
+
+    apu.write(&memory, 0x4017, 0x00);
+    for _ in 0..29835 {
+        apu.tick(cpu);
+        cpu.idle_cycle();
+    }
+    assert!(cpu.irq_set());
+    assert_eq!(apu.read(&memory, 0x4015) & 0x40, 0x40);
+    assert_eq!(apu.read(&memory, 0x4015) & 0x40, 0x00);
+    apu.tick(cpu);
+    assert!(!cpu.irq_set());
+}
+
+#[test]
+fn five_step_mode_clocks_length_counters_at_once() {
+    let apu = APU::silent();
+    let mut apu = apu.borrow_mut();
+    let memory = CoreMemory::new_flat(Box::new(FlatMemory::new(&[], 0)));
+    let cpu = &mut idle_cpu();
+
+    /* pulse 1 with a length of 2 (index 3), not halted */
+    apu.write(&memory, 0x4015, 0x01);
+    apu.write(&memory, 0x4000, 0x00);
+    apu.write(&memory, 0x4003, 0x03 << 3);
+    assert_eq!(apu.read(&memory, 0x4015) & 0x01, 0x01);
+
+    /* entering 5-step mode clocks it once straight away, and the first half frame again */
+    apu.write(&memory, 0x4017, 0x80);
+    for _ in 0..5 {
+        apu.tick(cpu);
+        cpu.idle_cycle();
+    }
+    assert_eq!(apu.read(&memory, 0x4015) & 0x01, 0x01);
+    for _ in 0..14913 {
+        apu.tick(cpu);
+        cpu.idle_cycle();
+    }
+    assert_eq!(apu.read(&memory, 0x4015) & 0x01, 0x00);
+}
@@ -0,0 +1,4 @@
+mod apu_tests;
+mod dmc_tests;
+mod frame_counter_tests;
+mod recorder_tests;
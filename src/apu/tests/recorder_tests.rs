@@ -0,0 +1,94 @@
+use crate::apu::{AudioRecorder, WavFormat, WavWriter};
+use std::io::Cursor;
+use std::path::Path;
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_wav(format: WavFormat, samples: &[f32]) -> Vec<u8> {
+    let mut writer = WavWriter::new(Cursor::new(Vec::new()), format, 44744).unwrap();
+    for sample in samples {
+        writer.write_sample(*sample).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+#[test]
+fn pcm16_wav_has_a_plain_header() {
+    let wav = write_wav(WavFormat::Pcm16, &[0.0, 0.5, 1.0, 2.0, -1.0]);
+
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(u32_at(&wav, 4) as usize, wav.len() - 8);
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(u32_at(&wav, 16), 16);
+    assert_eq!(u16_at(&wav, 20), 1); // PCM
+    assert_eq!(u16_at(&wav, 22), 1); // mono
+    assert_eq!(u32_at(&wav, 24), 44744);
+    assert_eq!(u32_at(&wav, 28), 44744 * 2);
+    assert_eq!(u16_at(&wav, 32), 2);
+    assert_eq!(u16_at(&wav, 34), 16);
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(u32_at(&wav, 40), 10);
+
+    let samples: Vec<i16> = wav[44..]
+        .chunks(2)
+        .map(|sample| i16::from_le_bytes([sample[0], sample[1]]))
+        .collect();
+    /* beyond full scale is clipped */
+    assert_eq!(samples, vec![0, 16384, 32767, 32767, -32767]);
+}
+
+#[test]
+fn float_wav_keeps_samples_exactly() {
+    let samples = [0.0, 0.123_456_7, 1.5];
+    let wav = write_wav(WavFormat::Float, &samples);
+
+    assert_eq!(u32_at(&wav, 4) as usize, wav.len() - 8);
+    assert_eq!(u32_at(&wav, 16), 18);
+    assert_eq!(u16_at(&wav, 20), 3); // IEEE float
+    assert_eq!(u32_at(&wav, 28), 44744 * 4);
+    assert_eq!(u16_at(&wav, 34), 32);
+    assert_eq!(&wav[38..42], b"fact");
+    assert_eq!(u32_at(&wav, 46), 3);
+    assert_eq!(&wav[50..54], b"data");
+    assert_eq!(u32_at(&wav, 54), 12);
+
+    let written: Vec<f32> = wav[58..]
+        .chunks(4)
+        .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+        .collect();
+    assert_eq!(written, samples);
+}
+
+#[test]
+fn empty_wav_is_just_the_header() {
+    let wav = write_wav(WavFormat::Pcm16, &[]);
+    assert_eq!(wav.len(), 44);
+    assert_eq!(u32_at(&wav, 40), 0);
+}
+
+#[test]
+fn stems_go_beside_the_mix() {
+    assert_eq!(
+        AudioRecorder::stem_path(Path::new("/tmp/run/game.wav"), "pulse1"),
+        Path::new("/tmp/run/game.pulse1.wav")
+    );
+    assert_eq!(
+        AudioRecorder::stem_path(Path::new("game"), "dmc"),
+        Path::new("game.dmc.wav")
+    );
+}
+
+#[test]
+fn formats_parse_by_name() {
+    for format in [WavFormat::Pcm16, WavFormat::Float] {
+        assert_eq!(format.name().parse(), Ok(format));
+    }
+    assert_eq!("FLOAT".parse(), Ok(WavFormat::Float));
+    assert!("pcm8".parse::<WavFormat>().is_err());
+}
@@ -1,3 +1,6 @@
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
 pub struct Timer {
     pub period: u16,
     pub count: u16,
@@ -35,3 +38,16 @@ impl Timer {
         self.period = (self.period & 0xff) | (((data as u16) & 0x7) << 8);
     }
 }
+
+impl Savestate for Timer {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.period);
+        writer.write_u16(self.count);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.period = reader.read_u16()?;
+        self.count = reader.read_u16()?;
+        Ok(())
+    }
+}
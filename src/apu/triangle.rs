@@ -1,7 +1,9 @@
 use crate::apu::length_counter::LengthCounter;
 use crate::apu::timer::Timer;
 use crate::cpu::{CoreMemory, MemoryListener};
+use crate::savestate::{Savestate, StateReader, StateWriter};
 use std::cell::RefCell;
+use std::io;
 use std::rc::Rc;
 
 pub struct Triangle {
@@ -29,22 +31,10 @@ impl Triangle {
         }
     }
 
-    pub fn tick(&mut self, apu_counter: u16) {
+    pub fn tick(&mut self) {
         if !self.enabled {
             return;
         }
-        let is_half_frame = apu_counter == 7456 || apu_counter == 14914;
-        let is_quarter_frame = is_half_frame || apu_counter == 3728 || apu_counter == 11185;
-
-        if is_quarter_frame {
-            self.linear_counter.clock();
-
-            if is_half_frame {
-                self.length_counter.clock();
-            }
-        }
-
-        /* TODO: a lot of duplicate code with the pulse tick */
         /* only clock sequence timer if both counters are non-zero */
         if self.linear_counter.is_active() && self.length_counter.is_active() {
             self.sequencer.clock();
@@ -52,6 +42,14 @@ impl Triangle {
         }
     }
 
+    pub fn clock_quarter_frame(&mut self) {
+        self.linear_counter.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length_counter.clock();
+    }
+
     pub fn amplitude(&self) -> f32 {
         self.sequencer.amplitude()
     }
@@ -62,6 +60,11 @@ impl Triangle {
         }
         self.enabled = enabled;
     }
+
+    /* whether the length counter is still running, as read from 0x4015 */
+    pub fn is_active(&self) -> bool {
+        self.length_counter.is_active()
+    }
 }
 
 impl MemoryListener for Triangle {
@@ -169,3 +172,28 @@ impl LinearCounter {
         self.reload_value = data & 0x7f;
     }
 }
+
+impl Savestate for Triangle {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.sequencer.sequence_index);
+        self.sequencer.timer.save_state(writer);
+        self.length_counter.save_state(writer);
+        writer.write_bool(self.linear_counter.control_flag);
+        writer.write_u8(self.linear_counter.count);
+        writer.write_u8(self.linear_counter.reload_value);
+        writer.write_bool(self.linear_counter.reload_flag);
+        writer.write_bool(self.enabled);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.sequencer.sequence_index = reader.read_u8()? % 32;
+        self.sequencer.timer.load_state(reader)?;
+        self.length_counter.load_state(reader)?;
+        self.linear_counter.control_flag = reader.read_bool()?;
+        self.linear_counter.count = reader.read_u8()?;
+        self.linear_counter.reload_value = reader.read_u8()?;
+        self.linear_counter.reload_flag = reader.read_bool()?;
+        self.enabled = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -1,4 +0,0 @@
-/**
- * For global constants, and keys for configurable values.
- */
-pub const SCREENSHOT_KEY: &str = "s";
@@ -0,0 +1,79 @@
+use tao::keyboard::Key;
+
+/* every printable ASCII character, so single-character names can borrow a 'static str */
+const PRINTABLE_ASCII: &str =
+    "!\"#$%&'()*+,-./0123456789:;<=>?@ABCDEFGHIJKLMNOPQRSTUVWXYZ[\\]^_`abcdefghijklmnopqrstuvwxyz{|}~";
+
+/* keys that don't type a character, by the names used in the config file */
+const NAMED_KEYS: [(&str, Key<'static>); 32] = [
+    ("ArrowUp", Key::ArrowUp),
+    ("ArrowDown", Key::ArrowDown),
+    ("ArrowLeft", Key::ArrowLeft),
+    ("ArrowRight", Key::ArrowRight),
+    ("Enter", Key::Enter),
+    ("Tab", Key::Tab),
+    ("Space", Key::Space),
+    ("Backspace", Key::Backspace),
+    ("Escape", Key::Escape),
+    ("Shift", Key::Shift),
+    ("Control", Key::Control),
+    ("Alt", Key::Alt),
+    ("CapsLock", Key::CapsLock),
+    ("Insert", Key::Insert),
+    ("Delete", Key::Delete),
+    ("Home", Key::Home),
+    ("End", Key::End),
+    ("PageUp", Key::PageUp),
+    ("PageDown", Key::PageDown),
+    ("F1", Key::F1),
+    ("F2", Key::F2),
+    ("F3", Key::F3),
+    ("F4", Key::F4),
+    ("F5", Key::F5),
+    ("F6", Key::F6),
+    ("F7", Key::F7),
+    ("F8", Key::F8),
+    ("F9", Key::F9),
+    ("F10", Key::F10),
+    ("F11", Key::F11),
+    ("F12", Key::F12),
+    ("Pause", Key::Pause),
+];
+
+/**
+ * Looks up a key by its name in the config file: one of the names above, or the single character
+ * the key types. Names are case sensitive, since "X" is what tao reports for Shift+x.
+ */
+pub fn key_from_name(name: &str) -> Option<Key<'static>> {
+    if let Some((_, key)) = NAMED_KEYS.iter().find(|(key_name, _)| *key_name == name) {
+        return Some(key.clone());
+    }
+
+    let ch = single_character(name)?;
+    match PRINTABLE_ASCII.find(ch) {
+        Some(index) => Some(Key::Character(&PRINTABLE_ASCII[index..index + 1])),
+        /* other characters are rare enough in a config that leaking them is harmless */
+        None => Some(Key::Character(Box::leak(name.to_string().into_boxed_str()))),
+    }
+}
+
+/* the inverse of key_from_name, or None for keys that can't be written to the config */
+pub fn key_name(key: &Key) -> Option<String> {
+    if let Some((name, _)) = NAMED_KEYS.iter().find(|(_, named)| named == key) {
+        return Some(name.to_string());
+    }
+    match key {
+        Key::Character(text) if single_character(text).is_some() => Some(text.to_string()),
+        _ => None,
+    }
+}
+
+/* the character a name stands for, if it's a single visible one */
+fn single_character(name: &str) -> Option<char> {
+    let mut chars = name.chars();
+    let ch = chars.next()?;
+    if chars.next().is_some() || ch.is_control() || ch.is_whitespace() {
+        return None;
+    }
+    Some(ch)
+}
@@ -0,0 +1,381 @@
+mod key_names;
+mod rebinder;
+
+#[cfg(test)]
+mod tests;
+
+pub use key_names::{key_from_name, key_name};
+pub use rebinder::{RebindProgress, Rebinder};
+
+use patina::{RewindSettings, WavFormat};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::{env, fs, io};
+use tao::keyboard::Key;
+
+pub const CONFIG_FILE_NAME: &str = "config.ini";
+
+/* keys for each button, in the order the controller reports them:
+ * A B Select Start Up Down Left Right
+ */
+pub type ControllerKeys = [Key<'static>; 8];
+
+pub const PLAYER_1_KEYS: ControllerKeys = [
+    Key::Character("x"),
+    Key::Character("z"),
+    Key::Tab,
+    Key::Enter,
+    Key::ArrowUp,
+    Key::ArrowDown,
+    Key::ArrowLeft,
+    Key::ArrowRight,
+];
+
+pub const PLAYER_2_KEYS: ControllerKeys = [
+    Key::Character("m"),
+    Key::Character("n"),
+    Key::Character("u"),
+    Key::Character("o"),
+    Key::Character("i"),
+    Key::Character("k"),
+    Key::Character("j"),
+    Key::Character("l"),
+];
+
+/* the names of each controller's buttons in the config file, in ControllerKeys order */
+pub const BUTTON_NAMES: [&str; 8] = ["a", "b", "select", "start", "up", "down", "left", "right"];
+
+const PLAYER_SECTIONS: [&str; 2] = ["player1", "player2"];
+const HOTKEY_SECTION: &str = "hotkeys";
+const GAMEPAD_SECTION: &str = "gamepads";
+const STICK_THRESHOLD: &str = "stick_threshold";
+const REWIND_SECTION: &str = "rewind";
+const REWIND_INTERVAL: &str = "interval";
+const REWIND_DEPTH: &str = "depth";
+const REWIND_MEMORY: &str = "memory_mb";
+const BYTES_PER_MB: usize = 1024 * 1024;
+const AUDIO_RECORDING_SECTION: &str = "audio_recording";
+const WAV_FORMAT: &str = "format";
+const WAV_STEMS: &str = "stems";
+
+/**
+ * A key that controls the emulator itself, rather than being passed on to the game.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hotkey {
+    Screenshot,
+    Pause,
+    FrameAdvance,
+    Reset,
+    PowerCycle,
+    /* runs fast for as long as it's held */
+    FastForward,
+    /* runs backwards for as long as it's held */
+    Rewind,
+    /* starts recording the sound to a WAV file, or stops */
+    RecordAudio,
+}
+
+impl Hotkey {
+    pub const ALL: [Hotkey; 8] = [
+        Hotkey::Screenshot,
+        Hotkey::Pause,
+        Hotkey::FrameAdvance,
+        Hotkey::Reset,
+        Hotkey::PowerCycle,
+        Hotkey::FastForward,
+        Hotkey::Rewind,
+        Hotkey::RecordAudio,
+    ];
+
+    /* the name in the config file's [hotkeys] section */
+    pub fn name(self) -> &'static str {
+        match self {
+            Hotkey::Screenshot => "screenshot",
+            Hotkey::Pause => "pause",
+            Hotkey::FrameAdvance => "frame_advance",
+            Hotkey::Reset => "reset",
+            Hotkey::PowerCycle => "power_cycle",
+            Hotkey::FastForward => "fast_forward",
+            Hotkey::Rewind => "rewind",
+            Hotkey::RecordAudio => "record_audio",
+        }
+    }
+
+    fn default_key(self) -> Key<'static> {
+        match self {
+            Hotkey::Screenshot => Key::Character("s"),
+            Hotkey::Pause => Key::Character("p"),
+            Hotkey::FrameAdvance => Key::Character("f"),
+            Hotkey::Reset => Key::F5,
+            Hotkey::PowerCycle => Key::F6,
+            Hotkey::FastForward => Key::Character("`"),
+            Hotkey::Rewind => Key::Backspace,
+            Hotkey::RecordAudio => Key::F7,
+        }
+    }
+}
+
+/**
+ * User settings, kept as an INI file: a [player1] and [player2] section binding each controller
+ * button to a key, a [hotkeys] section for the emulator's own keys, a [gamepads] section tying
+ * players to gamepads by name, a [rewind] section saying how much history to keep, and an
+ * [audio_recording] section saying how to write WAV files. Anything the file leaves out keeps its
+ * default.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    pub controllers: [ControllerKeys; 2],
+    hotkeys: Vec<Key<'static>>,        /* indexed by Hotkey */
+    pub gamepads: [Option<String>; 2], /* part of the name of each player's pad, or any pad */
+    pub stick_threshold: f32,          /* how far an analog stick counts as a D-pad press */
+    pub rewind: RewindSettings,
+    pub wav_format: WavFormat,
+    pub wav_stems: bool, /* record each channel to its own file beside the mix */
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            controllers: [PLAYER_1_KEYS, PLAYER_2_KEYS],
+            hotkeys: Hotkey::ALL
+                .iter()
+                .map(|hotkey| hotkey.default_key())
+                .collect(),
+            gamepads: [None, None],
+            stick_threshold: 0.5,
+            rewind: RewindSettings::default(),
+            wav_format: WavFormat::default(),
+            wav_stems: false,
+        }
+    }
+}
+
+impl Config {
+    /* reads the config file at path; a file that doesn't exist yet just gives the defaults */
+    pub fn load(path: &Path) -> io::Result<Config> {
+        match fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, self.to_ini())
+    }
+
+    pub fn parse(text: &str) -> io::Result<Config> {
+        let mut config = Config::default();
+        let mut section: Option<&str> = None;
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", index + 1, message),
+                )
+            };
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = name.trim();
+                if !PLAYER_SECTIONS.contains(&name)
+                    && name != HOTKEY_SECTION
+                    && name != GAMEPAD_SECTION
+                    && name != REWIND_SECTION
+                    && name != AUDIO_RECORDING_SECTION
+                {
+                    return Err(error(format!("unknown section [{name}]")));
+                }
+                section = Some(name);
+                continue;
+            }
+
+            let Some((name, value)) = line.split_once('=') else {
+                return Err(error(format!("expected name = key, found \"{line}\"")));
+            };
+            let name = name.trim();
+            let value = unquote(value.trim());
+
+            match section {
+                None => return Err(error(format!("\"{name}\" is not in a section"))),
+                Some(GAMEPAD_SECTION) if name == STICK_THRESHOLD => {
+                    config.stick_threshold = value
+                        .parse()
+                        .ok()
+                        .filter(|threshold| (0.0..=1.0).contains(threshold))
+                        .ok_or_else(|| {
+                            error(format!("{STICK_THRESHOLD} must be 0 to 1, not \"{value}\""))
+                        })?;
+                }
+                Some(GAMEPAD_SECTION) => {
+                    let player = PLAYER_SECTIONS
+                        .iter()
+                        .position(|player| *player == name)
+                        .ok_or_else(|| error(format!("unknown gamepad setting \"{name}\"")))?;
+                    config.gamepads[player] = Some(value.to_string());
+                }
+                Some(REWIND_SECTION) => {
+                    let number = |min: usize| {
+                        value
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|number| *number >= min)
+                            .ok_or_else(|| {
+                                error(format!(
+                                    "{name} must be a number from {min}, not \"{value}\""
+                                ))
+                            })
+                    };
+                    match name {
+                        REWIND_INTERVAL => config.rewind.interval = number(1)? as u32,
+                        REWIND_DEPTH => config.rewind.depth = number(0)?,
+                        REWIND_MEMORY => config.rewind.memory_limit = number(1)? * BYTES_PER_MB,
+                        _ => return Err(error(format!("unknown rewind setting \"{name}\""))),
+                    }
+                }
+                Some(AUDIO_RECORDING_SECTION) => match name {
+                    WAV_FORMAT => config.wav_format = value.parse().map_err(error)?,
+                    WAV_STEMS => {
+                        config.wav_stems = value.parse().map_err(|_| {
+                            error(format!(
+                                "{WAV_STEMS} must be true or false, not \"{value}\""
+                            ))
+                        })?;
+                    }
+                    _ => return Err(error(format!("unknown recording setting \"{name}\""))),
+                },
+                Some(HOTKEY_SECTION) => {
+                    let hotkey = Hotkey::ALL
+                        .into_iter()
+                        .find(|hotkey| hotkey.name() == name)
+                        .ok_or_else(|| error(format!("unknown hotkey \"{name}\"")))?;
+                    config.set_hotkey(hotkey, parse_key(value).map_err(error)?);
+                }
+                Some(player) => {
+                    let player = PLAYER_SECTIONS.iter().position(|p| *p == player).unwrap();
+                    let button = BUTTON_NAMES
+                        .iter()
+                        .position(|button| *button == name)
+                        .ok_or_else(|| error(format!("unknown button \"{name}\"")))?;
+                    config.controllers[player][button] = parse_key(value).map_err(error)?;
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    pub fn to_ini(&self) -> String {
+        let mut text = String::from(
+            "# Patina settings. Keys are either the character they type, or one of\n\
+             # ArrowUp, ArrowDown, ArrowLeft, ArrowRight, Enter, Tab, Space, Backspace, F1-F12, etc.\n",
+        );
+        for (section, keys) in PLAYER_SECTIONS.iter().zip(&self.controllers) {
+            text.push_str(&format!("\n[{section}]\n"));
+            for (button, key) in BUTTON_NAMES.iter().zip(keys) {
+                push_entry(&mut text, button, key);
+            }
+        }
+        text.push_str(&format!("\n[{HOTKEY_SECTION}]\n"));
+        for hotkey in Hotkey::ALL {
+            push_entry(&mut text, hotkey.name(), self.hotkey(hotkey));
+        }
+        text.push_str(&format!(
+            "\n# players without a pad named here get any pad that's left\n[{GAMEPAD_SECTION}]\n"
+        ));
+        for (player, name) in PLAYER_SECTIONS.iter().zip(&self.gamepads) {
+            if let Some(name) = name {
+                text.push_str(&format!("{player} = {name}\n"));
+            }
+        }
+        text.push_str(&format!("{STICK_THRESHOLD} = {}\n", self.stick_threshold));
+        text.push_str(&format!(
+            "\n# a state every {REWIND_INTERVAL} frames, up to {REWIND_DEPTH} of them; 0 is off\n\
+             [{REWIND_SECTION}]\n\
+             {REWIND_INTERVAL} = {}\n\
+             {REWIND_DEPTH} = {}\n\
+             {REWIND_MEMORY} = {}\n",
+            self.rewind.interval,
+            self.rewind.depth,
+            self.rewind.memory_limit / BYTES_PER_MB
+        ));
+        text.push_str(&format!(
+            "\n# {WAV_FORMAT} is pcm16 or float; {WAV_STEMS} also records each channel on its own\n\
+             [{AUDIO_RECORDING_SECTION}]\n\
+             {WAV_FORMAT} = {}\n\
+             {WAV_STEMS} = {}\n",
+            self.wav_format.name(),
+            self.wav_stems
+        ));
+        text
+    }
+
+    pub fn hotkey(&self, hotkey: Hotkey) -> &Key<'static> {
+        &self.hotkeys[hotkey as usize]
+    }
+
+    pub fn set_hotkey(&mut self, hotkey: Hotkey, key: Key<'static>) {
+        self.hotkeys[hotkey as usize] = key;
+    }
+
+    /* which hotkey, if any, a key press triggers */
+    pub fn hotkey_for(&self, key: &Key) -> Option<Hotkey> {
+        Hotkey::ALL
+            .into_iter()
+            .find(|hotkey| self.hotkey(*hotkey) == key)
+    }
+
+    /* the buttons the held keys press on each controller, one bit per button in ControllerKeys
+     * order
+     */
+    pub fn controller_buttons(&self, held: &HashSet<Key<'static>>) -> [u8; 2] {
+        self.controllers.each_ref().map(|keys| {
+            keys.iter()
+                .enumerate()
+                .filter(|(_, key)| held.contains(*key))
+                .fold(0, |buttons, (button, _)| buttons | 1 << button)
+        })
+    }
+}
+
+/* keys without a name are never bound, since neither parse nor the Rebinder accepts them */
+fn push_entry(text: &mut String, name: &str, key: &Key) {
+    if let Some(key) = key_name(key) {
+        text.push_str(&format!("{name} = {key}\n"));
+    }
+}
+
+fn parse_key(value: &str) -> Result<Key<'static>, String> {
+    key_from_name(value).ok_or_else(|| format!("unknown key \"{value}\""))
+}
+
+/* values may be quoted, as TOML requires, but don't have to be */
+fn unquote(value: &str) -> &str {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) if !inner.is_empty() => inner,
+        _ => value,
+    }
+}
+
+/**
+ * Where the config file lives unless --config says otherwise: $XDG_CONFIG_HOME/patina, falling
+ * back to ~/.config/patina, or %APPDATA%\patina on Windows.
+ */
+pub fn default_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(dir.join("patina").join(CONFIG_FILE_NAME))
+}
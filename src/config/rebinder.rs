@@ -0,0 +1,73 @@
+use crate::config::{key_name, Config, Hotkey, BUTTON_NAMES};
+use tao::keyboard::Key;
+
+const PLAYERS: usize = 2;
+const BUTTONS_PER_PLAYER: usize = BUTTON_NAMES.len();
+const BUTTON_STEPS: usize = PLAYERS * BUTTONS_PER_PLAYER;
+
+/* how a key press moved the rebinding along */
+#[derive(Debug, Clone, PartialEq)]
+pub enum RebindProgress {
+    Next,
+    /* the key can't be bound, e.g. it has no name to save it under; the prompt is unchanged */
+    Rejected,
+    Done(Box<Config>),
+    Cancelled,
+}
+
+/**
+ * Walks through every controller button and then every hotkey, binding each to the next key
+ * pressed. The bindings only replace the old ones once the last is set, and Escape abandons the
+ * lot, so a half-finished rebinding never leaves the controls in a mess.
+ */
+pub struct Rebinder {
+    config: Config,
+    step: usize,
+}
+
+impl Rebinder {
+    pub fn new(config: &Config) -> Rebinder {
+        Rebinder {
+            config: config.clone(),
+            step: 0,
+        }
+    }
+
+    /* what to ask the user for next */
+    pub fn prompt(&self) -> String {
+        let target = if self.step < BUTTON_STEPS {
+            format!(
+                "Player {} {}",
+                self.step / BUTTONS_PER_PLAYER + 1,
+                BUTTON_NAMES[self.step % BUTTONS_PER_PLAYER]
+            )
+        } else {
+            Hotkey::ALL[self.step - BUTTON_STEPS].name().to_string()
+        };
+        format!("Press a key for {target} (Escape cancels)")
+    }
+
+    pub fn press(&mut self, key: &Key<'static>) -> RebindProgress {
+        if *key == Key::Escape {
+            return RebindProgress::Cancelled;
+        }
+        if key_name(key).is_none() {
+            return RebindProgress::Rejected;
+        }
+
+        if self.step < BUTTON_STEPS {
+            let player = self.step / BUTTONS_PER_PLAYER;
+            self.config.controllers[player][self.step % BUTTONS_PER_PLAYER] = key.clone();
+        } else {
+            self.config
+                .set_hotkey(Hotkey::ALL[self.step - BUTTON_STEPS], key.clone());
+        }
+
+        self.step += 1;
+        if self.step == BUTTON_STEPS + Hotkey::ALL.len() {
+            RebindProgress::Done(Box::new(self.config.clone()))
+        } else {
+            RebindProgress::Next
+        }
+    }
+}
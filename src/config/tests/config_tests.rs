@@ -0,0 +1,158 @@
+use crate::config::{key_from_name, key_name, Config, Hotkey, PLAYER_1_KEYS, PLAYER_2_KEYS};
+use patina::WavFormat;
+use std::collections::HashSet;
+use std::io;
+use tao::keyboard::Key;
+
+#[test]
+fn test_key_names() {
+    assert_eq!(key_from_name("x"), Some(Key::Character("x")));
+    assert_eq!(key_from_name("X"), Some(Key::Character("X")));
+    assert_eq!(key_from_name("="), Some(Key::Character("=")));
+    assert_eq!(key_from_name("ArrowUp"), Some(Key::ArrowUp));
+    assert_eq!(key_from_name("Space"), Some(Key::Space));
+    assert_eq!(key_from_name("F12"), Some(Key::F12));
+    assert_eq!(key_from_name("ü"), Some(Key::Character("ü")));
+    assert_eq!(key_from_name("arrowup"), None);
+    assert_eq!(key_from_name("xy"), None);
+    assert_eq!(key_from_name(" "), None);
+    assert_eq!(key_from_name(""), None);
+
+    assert_eq!(key_name(&Key::Enter), Some("Enter".to_string()));
+    assert_eq!(key_name(&Key::Character(";")), Some(";".to_string()));
+    assert_eq!(key_name(&Key::Character("ab")), None);
+    assert_eq!(key_name(&Key::Dead(None)), None);
+}
+
+#[test]
+fn test_empty_config_is_default() {
+    let config = Config::parse("").unwrap();
+    assert_eq!(config, Config::default());
+    assert_eq!(config.controllers, [PLAYER_1_KEYS, PLAYER_2_KEYS]);
+    assert_eq!(config.hotkey(Hotkey::Screenshot), &Key::Character("s"));
+}
+
+#[test]
+fn test_parse_overrides_only_what_is_given() {
+    let text = "\
+# comment
+; another comment
+[player1]
+a = k
+b=\"j\"
+
+[player2]
+  start = Space
+[hotkeys]
+screenshot = F9
+[gamepads]
+player2 = \"8BitDo SN30\"
+stick_threshold = 0.25
+";
+    let config = Config::parse(text).unwrap();
+
+    assert_eq!(config.controllers[0][0], Key::Character("k"));
+    assert_eq!(config.controllers[0][1], Key::Character("j"));
+    assert_eq!(config.controllers[0][2..], PLAYER_1_KEYS[2..]);
+    assert_eq!(config.controllers[1][3], Key::Space);
+    assert_eq!(config.controllers[1][0], PLAYER_2_KEYS[0]);
+    assert_eq!(config.hotkey(Hotkey::Screenshot), &Key::F9);
+    assert_eq!(config.hotkey_for(&Key::F9), Some(Hotkey::Screenshot));
+    assert_eq!(config.hotkey_for(&Key::Character("s")), None);
+    assert_eq!(config.gamepads, [None, Some("8BitDo SN30".to_string())]);
+    assert_eq!(config.stick_threshold, 0.25);
+}
+
+#[test]
+fn test_parse_errors_name_the_line() {
+    let cases = [
+        "a = x",
+        "[player1]\nturbo = x",
+        "[player3]",
+        "[hotkeys]\nquit = q",
+        "[player1]\n\na = NotAKey",
+        "[player1]\nup",
+        "[gamepads]\nplayer3 = Pad",
+        "[gamepads]\nstick_threshold = 2",
+        "[rewind]\ninterval = 0",
+        "[rewind]\ndepth = -1",
+        "[rewind]\nseconds = 10",
+        "[audio_recording]\nformat = mp3",
+        "[audio_recording]\nstems = yes",
+    ];
+    let lines = [1, 2, 1, 2, 3, 2, 2, 2, 2, 2, 2, 2, 2];
+    for (text, line) in cases.iter().zip(lines) {
+        let error = Config::parse(text).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(
+            error.to_string().starts_with(&format!("line {line}:")),
+            "{text:?} gave {error}"
+        );
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let mut config = Config::default();
+    config.controllers[0][4] = Key::Character("w");
+    config.controllers[1][7] = Key::Character("=");
+    config.set_hotkey(Hotkey::Screenshot, Key::F5);
+    config.gamepads[0] = Some("Xbox Wireless Controller".to_string());
+    config.stick_threshold = 0.3;
+    config.rewind.interval = 5;
+    config.rewind.depth = 0;
+    config.rewind.memory_limit = 16 * 1024 * 1024;
+    config.wav_format = WavFormat::Float;
+    config.wav_stems = true;
+
+    assert_eq!(Config::parse(&config.to_ini()).unwrap(), config);
+    assert_eq!(
+        Config::parse(&Config::default().to_ini()).unwrap(),
+        Config::default()
+    );
+}
+
+#[test]
+fn test_load_and_save() {
+    let dir = std::env::temp_dir().join(format!("patina-config-test-{}", std::process::id()));
+    let path = dir.join("nested").join("config.ini");
+
+    /* a missing file isn't an error, just the defaults */
+    assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+    let mut config = Config::default();
+    config.controllers[0][0] = Key::Character("a");
+    config.save(&path).unwrap();
+    assert_eq!(Config::load(&path).unwrap(), config);
+
+    std::fs::write(&path, "[player1]\na = ?!\n").unwrap();
+    let error = Config::load(&path).unwrap_err();
+    assert!(error.to_string().contains("config.ini: line 2:"), "{error}");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn each_controller_has_its_own_keys() {
+    let config = Config::default();
+    let held = HashSet::from([
+        PLAYER_1_KEYS[0].clone(), // player 1 A
+        PLAYER_2_KEYS[3].clone(), // player 2 start
+        PLAYER_2_KEYS[7].clone(), // player 2 right
+        Key::Character("t"),      // not bound
+    ]);
+    assert_eq!(config.controller_buttons(&held), [0b0000_0001, 0b1000_1000]);
+    assert_eq!(config.controller_buttons(&HashSet::new()), [0, 0]);
+}
+
+#[test]
+fn rebound_keys_replace_the_defaults() {
+    let mut config = Config::default();
+    config.controllers[0][0] = Key::Character("k");
+    config.controllers[1] = PLAYER_1_KEYS;
+    let held = HashSet::from([
+        Key::Character("k"), // player 1's new A
+        Key::Character("x"), // player 1's old A, now player 2's
+    ]);
+    assert_eq!(config.controller_buttons(&held), [0b0000_0001, 0b0000_0001]);
+}
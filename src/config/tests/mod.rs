@@ -0,0 +1,2 @@
+mod config_tests;
+mod rebinder_tests;
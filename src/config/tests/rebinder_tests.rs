@@ -0,0 +1,72 @@
+use crate::config::{Config, Hotkey, RebindProgress, Rebinder};
+use tao::keyboard::Key;
+
+/* one distinct key for each of the 16 buttons and then each hotkey */
+const KEYS: &str = "abcdefghijklmnopqrstuvwxyz";
+
+#[test]
+fn test_rebind_everything() {
+    let mut rebinder = Rebinder::new(&Config::default());
+    assert_eq!(
+        rebinder.prompt(),
+        "Press a key for Player 1 a (Escape cancels)"
+    );
+
+    let steps = 16 + Hotkey::ALL.len();
+    for index in 0..steps - 1 {
+        let key = Key::Character(&KEYS[index..index + 1]);
+        assert_eq!(rebinder.press(&key), RebindProgress::Next);
+    }
+    assert_eq!(
+        rebinder.prompt(),
+        format!(
+            "Press a key for {} (Escape cancels)",
+            Hotkey::ALL[Hotkey::ALL.len() - 1].name()
+        )
+    );
+
+    let last = Key::Character(&KEYS[steps - 1..steps]);
+    let RebindProgress::Done(config) = rebinder.press(&last) else {
+        panic!("rebinding should be done after the last hotkey");
+    };
+    assert_eq!(config.controllers[0][0], Key::Character("a"));
+    assert_eq!(config.controllers[0][7], Key::Character("h"));
+    assert_eq!(config.controllers[1][0], Key::Character("i"));
+    assert_eq!(config.controllers[1][7], Key::Character("p"));
+    assert_eq!(config.hotkey(Hotkey::Screenshot), &Key::Character("q"));
+    assert_eq!(config.hotkey(Hotkey::ALL[Hotkey::ALL.len() - 1]), &last);
+}
+
+#[test]
+fn test_rebind_prompts_follow_buttons() {
+    let mut rebinder = Rebinder::new(&Config::default());
+    for _ in 0..3 {
+        rebinder.press(&Key::Character("a"));
+    }
+    assert_eq!(
+        rebinder.prompt(),
+        "Press a key for Player 1 start (Escape cancels)"
+    );
+    for _ in 0..5 {
+        rebinder.press(&Key::Character("a"));
+    }
+    assert_eq!(
+        rebinder.prompt(),
+        "Press a key for Player 2 a (Escape cancels)"
+    );
+}
+
+#[test]
+fn test_rebind_rejects_unnameable_keys() {
+    let mut rebinder = Rebinder::new(&Config::default());
+    let prompt = rebinder.prompt();
+    assert_eq!(rebinder.press(&Key::Dead(None)), RebindProgress::Rejected);
+    assert_eq!(rebinder.prompt(), prompt);
+}
+
+#[test]
+fn test_rebind_cancel() {
+    let mut rebinder = Rebinder::new(&Config::default());
+    assert_eq!(rebinder.press(&Key::Character("a")), RebindProgress::Next);
+    assert_eq!(rebinder.press(&Key::Escape), RebindProgress::Cancelled);
+}
@@ -17,6 +17,10 @@ pub enum AddressingMode {
     Indirect,
     IndirectX,
     IndirectY,
+    /* 65C02 only */
+    ZeroPageIndirect, /* next byte is a zero page address holding the 16-bit address */
+    AbsoluteIndexedIndirect, /* next two bytes plus x point to the 16-bit address; only JMP */
+    ZeroPageRelative, /* a zero page address, then a branch offset; only BBR and BBS */
 }
 
 impl AddressingMode {
@@ -35,18 +39,29 @@ impl AddressingMode {
                     .0
             }
             Absolute => addr(byte1, byte2),
-            AbsoluteX => addr(byte1, byte2) + cpu.index_x as u16,
-            AbsoluteY => addr(byte1, byte2) + cpu.index_y as u16,
+            AbsoluteX => addr(byte1, byte2).wrapping_add(cpu.index_x as u16),
+            AbsoluteY => addr(byte1, byte2).wrapping_add(cpu.index_y as u16),
             Indirect =>
             /* only used for JMP */
             /* this implements a bug where this mode does not
-             * correctly handle crossing page boundaries
+             * correctly handle crossing page boundaries; the 65C02 fixed it
              */
             {
-                cpu.addr_from_mem16(addr(byte1, byte2))
+                if cpu.variant().is_cmos() {
+                    read_addr_no_wrap(cpu, addr(byte1, byte2))
+                } else {
+                    cpu.addr_from_mem16(addr(byte1, byte2))
+                }
             }
             IndirectX => cpu.read_mem16(zero_page_addr(byte1.wrapping_add(cpu.index_x))),
-            IndirectY => cpu.read_mem16(zero_page_addr(byte1)) + cpu.index_y as u16,
+            IndirectY => cpu
+                .read_mem16(zero_page_addr(byte1))
+                .wrapping_add(cpu.index_y as u16),
+            ZeroPageIndirect => cpu.read_mem16(zero_page_addr(byte1)),
+            AbsoluteIndexedIndirect => {
+                read_addr_no_wrap(cpu, addr(byte1, byte2).wrapping_add(cpu.index_x as u16))
+            }
+            ZeroPageRelative => zero_page_addr(byte1),
         };
 
         result
@@ -67,6 +82,9 @@ impl AddressingMode {
             Indirect => 3,
             IndirectX => 2,
             IndirectY => 2,
+            ZeroPageIndirect => 2,
+            AbsoluteIndexedIndirect => 3,
+            ZeroPageRelative => 3,
         }
     }
 
@@ -75,12 +93,41 @@ impl AddressingMode {
         self.resolve_address(cpu, (addr & 0xff) as u8, (addr >> 8) as u8)
     }
 
+    /**
+     * Resolves the address an instruction reads or writes, making the dummy reads the hardware
+     * makes along the way: indexed zero page modes read the unindexed address while adding the
+     * index, and indexed absolute modes read from the address before any carry into the high
+     * byte. Reads only take that extra cycle if there was a carry, since otherwise the address
+     * was right; writes always do.
+     */
+    fn resolve_for_access(&self, cpu: &mut CPU, byte1: u8, byte2: u8, access: Access) -> u16 {
+        if matches!(self, ZeroPageX | ZeroPageY | IndirectX) {
+            cpu.dummy_read(zero_page_addr(byte1));
+        }
+
+        let address = self.resolve_address(cpu, byte1, byte2);
+
+        let index = match self {
+            AbsoluteX => Some(cpu.index_x),
+            AbsoluteY | IndirectY => Some(cpu.index_y),
+            _ => None,
+        };
+        if let Some(index) = index {
+            let uncarried = (address.wrapping_sub(index as u16) & 0xff00) | (address & 0xff);
+            if access != Access::Read || uncarried != address {
+                cpu.dummy_read(uncarried);
+            }
+        }
+
+        address
+    }
+
     pub fn deref(self: &AddressingMode, cpu: &mut CPU, byte1: u8, byte2: u8) -> u8 {
         match self {
             Immediate => byte1,
             Accumulator => cpu.accumulator,
             _ => {
-                let address = self.resolve_address(cpu, byte1, byte2);
+                let address = self.resolve_for_access(cpu, byte1, byte2, Access::Read);
                 cpu.read_mem(address)
             }
         }
@@ -97,7 +144,7 @@ impl AddressingMode {
             Immediate => byte1,
             Accumulator => cpu.accumulator,
             _ => {
-                let address = self.resolve_address(cpu, byte1, byte2);
+                let address = self.resolve_for_access(cpu, byte1, byte2, Access::Read);
                 match self {
                     AbsoluteX | AbsoluteY | IndirectY => {
                         if (address >> 8) as u8 != byte2 {
@@ -111,13 +158,53 @@ impl AddressingMode {
         }
     }
 
+    /**
+     * The read half of a read-modify-write instruction. While it works out the new value, the
+     * NMOS 6502 writes the old one straight back (the 65C02 reads it again instead); the
+     * following write goes to the same address without resolving it again.
+     */
+    pub fn deref_for_modify(self: &AddressingMode, cpu: &mut CPU, byte1: u8, byte2: u8) -> u8 {
+        match self {
+            Accumulator => cpu.accumulator,
+            _ => {
+                let address = self.resolve_for_access(cpu, byte1, byte2, Access::Modify);
+                let value = cpu.read_mem(address);
+                if cpu.variant().is_cmos() {
+                    cpu.dummy_read(address);
+                } else {
+                    cpu.write_mem(address, value);
+                }
+                cpu.set_modify_address(address);
+                value
+            }
+        }
+    }
+
     pub fn write(self: &AddressingMode, cpu: &mut CPU, byte1: u8, byte2: u8, new_val: u8) {
         match self {
             Accumulator => cpu.accumulator = new_val,
             _ => {
-                let resolved_addr = self.resolve_address(cpu, byte1, byte2);
+                let resolved_addr = match cpu.take_modify_address() {
+                    Some(address) => address,
+                    None => self.resolve_for_access(cpu, byte1, byte2, Access::Write),
+                };
                 cpu.write_mem(resolved_addr, new_val)
             }
         }
     }
 }
+
+/* how an instruction uses the memory its addressing mode points to */
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    Modify,
+}
+
+/* reads a 16-bit address, carrying into the next page as the 65C02 does */
+fn read_addr_no_wrap(cpu: &mut CPU, lo_byte_addr: u16) -> u16 {
+    let lo_byte = cpu.read_mem(lo_byte_addr);
+    let hi_byte = cpu.read_mem(lo_byte_addr.wrapping_add(1));
+    addr(lo_byte, hi_byte)
+}
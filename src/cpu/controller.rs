@@ -1,74 +1,186 @@
 use crate::cpu::core_memory::MemoryListener;
 use crate::cpu::CoreMemory;
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
-use tao::keyboard::Key;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
 
 pub const CONTROLLER_ADDRESS: u16 = 0x4016;
+/* reads come from the second controller; writes go to the APU's frame counter */
+pub const CONTROLLER_2_ADDRESS: u16 = 0x4017;
+
+/* buttons held on each port by an input device, one bit per button, in the order the controller
+ * reports them: A B Select Start Up Down Left Right
+ */
+pub type ButtonSource = Arc<[AtomicU8; 2]>;
 
 #[derive(Clone)]
 pub struct Controller {
-    key_source: Arc<Mutex<HashSet<Key<'static>>>>,
+    key_source: ButtonSource,
+    pad_source: ButtonSource,
+    port: usize,
+    scripted_buttons: Option<u8>, /* held instead of the live keys and pad, e.g. by a movie */
+    frame_buttons: Option<u8>,    /* the buttons most recently latched, until taken */
     inputs_in_order: Vec<u8>,
     old_value: u8,
 }
 
 impl Controller {
-    pub fn new() -> Controller {
+    /* the controller plugged into the given port, which reads that port's bits from each source */
+    pub fn new(port: usize) -> Controller {
         Controller {
-            key_source: Arc::new(Mutex::new(HashSet::new())), /* will be overwritten, that's fine */
+            key_source: ButtonSource::default(), /* will be overwritten, that's fine */
+            pad_source: ButtonSource::default(),
+            port,
+            scripted_buttons: None,
+            frame_buttons: None,
             inputs_in_order: Vec::new(),
             old_value: 0,
         }
     }
 
-    pub fn set_key_source(&mut self, keys: Arc<Mutex<HashSet<Key<'static>>>>) {
+    pub fn set_key_source(&mut self, keys: ButtonSource) {
         self.key_source = keys;
     }
 
+    pub fn set_pad_source(&mut self, pads: ButtonSource) {
+        self.pad_source = pads;
+    }
+
+    pub fn set_scripted_buttons(&mut self, buttons: Option<u8>) {
+        self.scripted_buttons = buttons;
+    }
+
+    /* the buttons held right now, on the keyboard or the pad */
+    fn buttons(&self) -> u8 {
+        if let Some(buttons) = self.scripted_buttons {
+            return buttons;
+        }
+        self.key_source[self.port].load(Ordering::Relaxed)
+            | self.pad_source[self.port].load(Ordering::Relaxed)
+    }
+
     pub fn record_data(&mut self) {
-        let recorded_keys = self.key_source.lock().unwrap().clone();
-        self.inputs_in_order = Vec::new();
-        /* keys, in order: A B Select Start Up Down Left Right */
-        /* putting in a stack, so will reverse it */
-        self.push_key_press(&recorded_keys, Key::ArrowRight); /* right */
-        self.push_key_press(&recorded_keys, Key::ArrowLeft); /* left */
-        self.push_key_press(&recorded_keys, Key::ArrowDown); /* down */
-        self.push_key_press(&recorded_keys, Key::ArrowUp); /* up */
-        self.push_key_press(&recorded_keys, Key::Enter); /* start */
-        self.push_key_press(&recorded_keys, Key::Tab); /* select */
-        self.push_key_press(&recorded_keys, Key::Character("z")); /* B */
-        self.push_key_press(&recorded_keys, Key::Character("x"));
-        /* A */
+        let buttons = self.buttons();
+        self.frame_buttons = Some(buttons);
+        /* putting in a stack, so reverse it */
+        self.inputs_in_order = (0..8).rev().map(|button| buttons >> button & 1).collect();
+    }
+
+    /* the buttons the game last latched, or if it hasn't since the last call, those held now */
+    pub fn take_frame_buttons(&mut self) -> u8 {
+        self.frame_buttons.take().unwrap_or_else(|| self.buttons())
     }
 
     pub fn get_next_byte(&mut self) -> u8 {
         self.inputs_in_order.pop().unwrap_or(1)
     }
 
-    fn push_key_press(&mut self, recorded_keys: &HashSet<Key<'static>>, key: Key<'static>) {
-        self.inputs_in_order
-            .push(if recorded_keys.contains(&key) { 1 } else { 0 })
+    /* a write to 0x4016: the buttons are latched when the strobe bit goes from 1 to 0 */
+    pub fn strobe(&mut self, value: u8) {
+        if self.old_value & 1 == 1 && value & 1 == 0 {
+            self.record_data();
+        }
+        self.old_value = value;
+    }
+}
+
+/* the latched buttons, not the live key source, nor any script driving them */
+impl Savestate for Controller {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.inputs_in_order);
+        writer.write_u8(self.old_value);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.inputs_in_order = reader.read_bytes()?;
+        self.old_value = reader.read_u8()?;
+        Ok(())
     }
 }
 
-impl MemoryListener for Controller {
-    fn get_addresses(&self) -> Vec<u16> {
-        let mut addrs = Vec::new();
+/**
+ * Both controller ports. Each port is read from its own address, but a write to 0x4016 strobes
+ * both controllers at once, as on the hardware.
+ */
+pub struct ControllerPorts {
+    pub ports: [Controller; 2],
+}
+
+impl ControllerPorts {
+    pub fn new() -> ControllerPorts {
+        ControllerPorts {
+            ports: [Controller::new(0), Controller::new(1)],
+        }
+    }
+
+    pub fn set_key_source(&mut self, keys: ButtonSource) {
+        for controller in &mut self.ports {
+            controller.set_key_source(keys.clone());
+        }
+    }
+
+    pub fn set_pad_source(&mut self, pads: ButtonSource) {
+        for controller in &mut self.ports {
+            controller.set_pad_source(pads.clone());
+        }
+    }
+
+    /* buttons to hold on each port, e.g. a movie's, or None to go back to the keyboard and pads */
+    pub fn set_scripted_buttons(&mut self, buttons: Option<[u8; 2]>) {
+        for (port, controller) in self.ports.iter_mut().enumerate() {
+            controller.set_scripted_buttons(buttons.map(|buttons| buttons[port]));
+        }
+    }
+
+    pub fn take_frame_buttons(&mut self) -> [u8; 2] {
+        [
+            self.ports[0].take_frame_buttons(),
+            self.ports[1].take_frame_buttons(),
+        ]
+    }
+}
+
+impl Default for ControllerPorts {
+    fn default() -> Self {
+        ControllerPorts::new()
+    }
+}
 
-        addrs.push(CONTROLLER_ADDRESS);
+impl MemoryListener for ControllerPorts {
+    fn get_addresses(&self) -> Vec<u16> {
+        vec![CONTROLLER_ADDRESS]
+    }
 
-        addrs
+    fn get_read_addresses(&self) -> Vec<u16> {
+        vec![CONTROLLER_ADDRESS, CONTROLLER_2_ADDRESS]
     }
 
-    fn read(&mut self, _memory: &CoreMemory, _address: u16) -> u8 {
-        self.get_next_byte()
+    fn read(&mut self, _memory: &CoreMemory, address: u16) -> u8 {
+        match address {
+            CONTROLLER_2_ADDRESS => self.ports[1].get_next_byte(),
+            _ => self.ports[0].get_next_byte(),
+        }
     }
 
     fn write(&mut self, _memory: &CoreMemory, _address: u16, value: u8) {
-        if self.old_value & 1 == 1 && value & 1 == 0 {
-            self.record_data();
+        for controller in &mut self.ports {
+            controller.strobe(value);
         }
-        self.old_value = value;
+    }
+}
+
+impl Savestate for ControllerPorts {
+    fn save_state(&self, writer: &mut StateWriter) {
+        for controller in &self.ports {
+            controller.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        for controller in &mut self.ports {
+            controller.load_state(reader)?;
+        }
+        Ok(())
     }
 }
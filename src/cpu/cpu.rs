@@ -1,17 +1,52 @@
 use crate::cpu;
+use crate::cpu::instruction::decode;
 use crate::cpu::operation::Operation;
+use crate::cpu::tracer::Tracer;
 use crate::cpu::{
-    AddressingMode, Controller, CoreMemory, StatusFlag, INITIAL_PC_LOCATION, IRQ_HANDLER_LOCATION,
-    NMI_HANDLER_LOCATION,
+    AddressingMode, ButtonSource, ControllerPorts, CoreMemory, CpuVariant, StatusFlag,
+    INITIAL_PC_LOCATION, IRQ_HANDLER_LOCATION, NMI_HANDLER_LOCATION,
 };
 use crate::ppu::PPURegister;
-use crate::ppu::PPURegister::OAMDMA;
+use crate::ppu::PPURegister::{OAMDATA, OAMDMA};
 use crate::processor::Processor;
+use crate::savestate::{Savestate, StateReader, StateWriter};
 use std::cell::RefCell;
-use std::collections::HashSet;
+use std::io;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
-use tao::keyboard::Key;
+use std::str::FromStr;
+
+/**
+ * What to do when the CPU hits an unstable unofficial opcode (XAA, AHX, TAS, KIL, etc.), whose
+ * behavior varies between chips. KIL always jams the CPU; halting jams it on the others too.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum UnstableOpcodePolicy {
+    #[default]
+    Execute,
+    Log,  /* execute, but report each one */
+    Halt, /* report it and jam the CPU until reset */
+}
+
+impl FromStr for UnstableOpcodePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "execute" => Ok(UnstableOpcodePolicy::Execute),
+            "log" => Ok(UnstableOpcodePolicy::Log),
+            "halt" => Ok(UnstableOpcodePolicy::Halt),
+            _ => Err(format!("expected execute, log, or halt, not '{}'", s)),
+        }
+    }
+}
+
+/**
+ * Runs the rest of the machine in step with the CPU. The CPU calls it once per cycle, just before
+ * that cycle's bus access, so everything the CPU reads or writes happens on the right PPU dot.
+ */
+pub trait CycleListener {
+    fn cycle(&mut self, cpu: &mut CPU);
+}
 
 pub struct CPU {
     pub accumulator: u8,
@@ -20,10 +55,24 @@ pub struct CPU {
     pub s_register: u8,
     pub program_counter: u16,
     pub status: u8,
+    variant: CpuVariant,
     nmi_flag: bool,
+    apu_irq: bool, /* the APU's IRQ line, which it drives every APU cycle */
+    /* the interrupt to take after this instruction, as polled just before its last bus access */
+    nmi_polled: bool,
+    irq_polled: bool, /* already masked by the I flag */
     memory: Box<CoreMemory>,
-    controller: Rc<RefCell<Controller>>,
-    doing_oamdma: bool,
+    controllers: Rc<RefCell<ControllerPorts>>,
+    oam_dma_page: Option<u8>, /* set by a write to OAMDMA; the transfer runs after the write */
+    modify_address: Option<u16>, /* where a read-modify-write instruction will write back to */
+    dmc_dma_address: Option<u16>, /* a sample byte the DMC wants; fetched on the next read cycle */
+    dmc_sample: Option<u8>,   /* the fetched byte, until the DMC picks it up */
+    cycles: u64,
+    cycle_listener: Option<Box<dyn CycleListener>>,
+    tracer: Option<Tracer>,
+    unstable_opcode_policy: UnstableOpcodePolicy,
+    jammed: bool,  /* stuck on a KIL (or a halted unstable opcode) until reset */
+    waiting: bool, /* stopped by the 65C02's WAI until an interrupt comes in */
 }
 
 impl Processor for CPU {
@@ -33,11 +82,15 @@ impl Processor for CPU {
 }
 
 impl CPU {
-    /* TODO comment */
-    pub fn new(mut memory: Box<CoreMemory>) -> Box<Self> {
-        let controller = Rc::new(RefCell::new(Controller::new()));
+    /* the NES's own CPU, the Ricoh 2A03 */
+    pub fn new(memory: Box<CoreMemory>) -> Box<Self> {
+        Self::with_variant(memory, CpuVariant::Ricoh2A03)
+    }
 
-        memory.register_listener(controller.clone());
+    pub fn with_variant(mut memory: Box<CoreMemory>, variant: CpuVariant) -> Box<Self> {
+        let controllers = Rc::new(RefCell::new(ControllerPorts::new()));
+
+        memory.register_listener(controllers.clone());
 
         /* set program counter to value in memory at this location */
         let mut result = Self {
@@ -46,11 +99,24 @@ impl CPU {
             index_y: 0x00,
             s_register: 0xff,
             program_counter: 0x00,
-            status: (0x11) << 4,
+            status: 0x34, /* interrupts disabled, as at power-on */
+            variant,
             nmi_flag: false,
+            apu_irq: false,
+            nmi_polled: false,
+            irq_polled: false,
             memory,
-            controller,
-            doing_oamdma: false,
+            controllers,
+            oam_dma_page: None,
+            modify_address: None,
+            dmc_dma_address: None,
+            dmc_sample: None,
+            cycles: 0,
+            cycle_listener: None,
+            tracer: None,
+            unstable_opcode_policy: UnstableOpcodePolicy::default(),
+            jammed: false,
+            waiting: false,
         };
 
         result.program_counter =
@@ -59,28 +125,240 @@ impl CPU {
         Box::new(result)
     }
 
-    /* performs one operation, then returns how long it took, in cycles */
+    /**
+     * Performs one operation (or takes an interrupt, then performs the first operation of its
+     * handler), then returns how long it took, in cycles. Every cycle makes exactly one bus
+     * access, with the cycle listener run before each one.
+     */
     pub fn transition(&mut self) -> u16 {
-        if self.nmi_set() {
+        let start = self.cycles;
+
+        /* a jammed CPU doesn't even respond to interrupts */
+        if self.jammed {
+            self.idle_cycle();
+            return 1;
+        }
+
+        if self.waiting {
+            /* WAI wakes up on an IRQ even if interrupts are disabled; it just doesn't take it */
+            if !self.nmi_set() && !self.irq_set() {
+                self.idle_cycle();
+                return 1;
+            }
+            self.waiting = false;
+            self.poll_interrupts();
+        }
+
+        /* decided on the last cycle of the previous instruction, so an interrupt raised during
+         * that cycle, or unmasked by it (CLI, PLP), waits until after the next instruction
+         */
+        if self.nmi_polled {
             self.trigger_nmi();
+        } else if self.irq_polled {
+            self.trigger_irq();
         }
 
+        if let Some(mut tracer) = self.tracer.take() {
+            if tracer.trace(self) {
+                self.tracer = Some(tracer);
+            }
+        }
+
+        let operation_start = self.cycles;
         let operation_loc = self.program_counter;
-        /* TODO: what if this hits the top of program memory */
-        let mut operation = Operation::operation_from_memory(
-            self.read_mem(operation_loc),
-            self.read_mem(operation_loc.wrapping_add(1)),
-            self.read_mem(operation_loc.wrapping_add(2)),
-        );
+        let opcode = self.read_mem(operation_loc);
+        let realized_instruction = decode(self.variant, opcode);
+        /* one-byte instructions still read the byte after the opcode, and throw it away, except
+         * for the 65C02's one-cycle NOPs, which are done as soon as the opcode is fetched
+         */
+        let byte1 = if realized_instruction.cycles == 1 {
+            0
+        } else {
+            self.read_mem(operation_loc.wrapping_add(1))
+        };
+        let byte2 = if realized_instruction.addr_mode.get_bytes() == 3 {
+            self.read_mem(operation_loc.wrapping_add(2))
+        } else {
+            0
+        };
+        let mut operation = Operation::new(realized_instruction, byte1, byte2);
+
+        if operation.realized_instruction.instruction.is_unstable()
+            && !self.allow_unstable_opcode(operation_loc, opcode)
+        {
+            self.jam();
+            return (self.cycles - start) as u16;
+        }
 
         operation.apply(self);
 
-        if self.doing_oamdma {
-            self.doing_oamdma = false;
-            return 513 + operation.cycles();
+        /* the 65C02 spends some cycles without touching the bus as the NMOS parts would */
+        while self.cycles - operation_start < operation.realized_instruction.cycles as u64 {
+            self.idle_cycle();
+        }
+
+        if let Some(page) = self.oam_dma_page.take() {
+            self.oam_dma(page);
         }
 
-        operation.cycles()
+        let cycles = (self.cycles - start) as u16;
+        if let Some(tracer) = &mut self.tracer {
+            tracer.add_cycles(cycles);
+        }
+
+        cycles
+    }
+
+    /**
+     * Copies a page of memory to OAMDATA: the CPU halts for a cycle (two, if it has to wait for a
+     * read cycle), then reads and writes each byte in turn, for 513 or 514 cycles in all.
+     */
+    fn oam_dma(&mut self, page: u8) {
+        self.idle_cycle();
+        if self.cycles % 2 == 1 {
+            self.idle_cycle();
+        }
+        for offset in 0..=0xff {
+            let value = self.read_mem(cpu::addr(offset, page));
+            self.write_mem(PPURegister::address(&OAMDATA), value);
+        }
+    }
+
+    /* advances the rest of the machine by one CPU cycle */
+    fn tick(&mut self) {
+        self.poll_interrupts();
+        self.cycles += 1;
+        if let Some(mut listener) = self.cycle_listener.take() {
+            listener.cycle(self);
+            self.cycle_listener = Some(listener);
+        }
+    }
+
+    /* a cycle in which the CPU is busy (or halted) and its bus access doesn't matter */
+    pub fn idle_cycle(&mut self) {
+        if let Some(sample_address) = self.dmc_dma_address.take() {
+            self.dmc_dma(None, sample_address);
+        }
+        self.tick();
+    }
+
+    /**
+     * Fetches a sample byte for the DMC. The DMC can only take the bus on a read cycle, so the
+     * CPU halts there, repeating the read it was about to make; after a dummy cycle (and another
+     * if it has to wait for a get cycle), the DMC reads its byte: 3 or 4 cycles stolen in all.
+     */
+    fn dmc_dma(&mut self, halted_read: Option<u16>, sample_address: u16) {
+        let stall = |cpu: &mut CPU| match halted_read {
+            Some(addr) => cpu.dummy_read(addr),
+            None => cpu.idle_cycle(),
+        };
+        stall(self);
+        stall(self);
+        if self.cycles % 2 == 1 {
+            stall(self);
+        }
+        self.tick();
+        self.dmc_sample = Some(self.memory.read(sample_address));
+    }
+
+    /* asks for a sample byte; the DMC takes it with take_dmc_sample once it's been read */
+    pub fn request_dmc_dma(&mut self, sample_address: u16) {
+        self.dmc_dma_address = Some(sample_address);
+    }
+
+    pub fn take_dmc_sample(&mut self) -> Option<u8> {
+        self.dmc_sample.take()
+    }
+
+    /* a read made only because the hardware makes it; the value is thrown away, but reading
+     * some registers has side effects
+     */
+    pub fn dummy_read(&mut self, addr: u16) {
+        self.read_mem(addr);
+    }
+
+    /* see AddressingMode::deref_for_modify */
+    pub(super) fn set_modify_address(&mut self, address: u16) {
+        self.modify_address = Some(address);
+    }
+
+    pub(super) fn take_modify_address(&mut self) -> Option<u16> {
+        self.modify_address.take()
+    }
+
+    /* the number of cycles the CPU has run since it was created */
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn set_cycle_listener(&mut self, listener: Option<Box<dyn CycleListener>>) {
+        self.cycle_listener = listener;
+    }
+
+    /* reports the unstable opcode at address per the policy; returns whether to run it */
+    fn allow_unstable_opcode(&self, address: u16, opcode: u8) -> bool {
+        match self.unstable_opcode_policy {
+            UnstableOpcodePolicy::Execute => true,
+            UnstableOpcodePolicy::Log => {
+                eprintln!("Unstable opcode 0x{opcode:02x} at ${address:04x}");
+                true
+            }
+            UnstableOpcodePolicy::Halt => {
+                eprintln!("Halting on unstable opcode 0x{opcode:02x} at ${address:04x}");
+                false
+            }
+        }
+    }
+
+    pub fn set_unstable_opcode_policy(&mut self, policy: UnstableOpcodePolicy) {
+        self.unstable_opcode_policy = policy;
+    }
+
+    /* stops the CPU until it's reset, as KIL does */
+    pub fn jam(&mut self) {
+        self.jammed = true;
+    }
+
+    /* stops the CPU until the next interrupt, as WAI does */
+    pub fn wait_for_interrupt(&mut self) {
+        self.waiting = true;
+    }
+
+    pub fn variant(&self) -> CpuVariant {
+        self.variant
+    }
+
+    /* logs each instruction as it's executed; see Tracer */
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    /* the console's reset button: RAM and most registers are left as they were */
+    pub fn reset(&mut self) {
+        /* reset goes through the motions of an interrupt, but reads the stack instead of
+         * pushing to it
+         */
+        self.dummy_read(self.program_counter);
+        self.dummy_read(self.program_counter);
+        for _ in 0..3 {
+            self.dummy_read(0x0100 + self.s_register as u16);
+            self.s_register = self.s_register.wrapping_sub(1);
+        }
+        self.update_flag(StatusFlag::InterruptDisable, true);
+        self.nmi_flag = false;
+        self.jammed = false;
+        self.waiting = false;
+        if self.variant.is_cmos() {
+            self.update_flag(StatusFlag::Decimal, false);
+        }
+        self.program_counter =
+            AddressingMode::Indirect.resolve_address_u16(self, INITIAL_PC_LOCATION);
+    }
+
+    /* samples the interrupt lines as they stood at the end of the previous cycle */
+    fn poll_interrupts(&mut self) {
+        self.nmi_polled = self.nmi_set();
+        self.irq_polled = self.irq_set() && !StatusFlag::InterruptDisable.is_set(self);
     }
 
     pub fn set_nmi(&mut self, nmi_set: bool) {
@@ -91,6 +369,15 @@ impl CPU {
         self.nmi_flag
     }
 
+    /* IRQ is level-triggered: it's asserted for as long as any source is holding the line */
+    pub fn irq_set(&self) -> bool {
+        self.apu_irq || self.memory.mapper.borrow().irq_pending()
+    }
+
+    pub fn set_apu_irq(&mut self, irq_set: bool) {
+        self.apu_irq = irq_set;
+    }
+
     pub fn update_flag(&mut self, flag: StatusFlag, new_val: bool) {
         flag.update_bool(self, new_val);
     }
@@ -102,7 +389,7 @@ impl CPU {
 
     pub fn push(&mut self, data: u8) {
         self.write_mem(cpu::addr(self.s_register, 0x01), data);
-        self.s_register -= 1;
+        self.s_register = self.s_register.wrapping_sub(1);
     }
 
     pub fn push_memory_loc(&mut self, mem_loc: u16) {
@@ -118,7 +405,7 @@ impl CPU {
     }
 
     pub fn pop(&mut self) -> u8 {
-        self.s_register += 1;
+        self.s_register = self.s_register.wrapping_add(1);
         let value = self.read_mem(0x0100 + self.s_register as u16);
         value
     }
@@ -127,35 +414,74 @@ impl CPU {
         self.push_memory_loc(self.program_counter.wrapping_add(offset as u16));
         self.push((self.status | (1 << 4)) | (1 << 5));
         self.update_flag(StatusFlag::InterruptDisable, true);
+        self.clear_decimal_on_interrupt();
         self.program_counter =
             AddressingMode::Indirect.resolve_address_u16(self, IRQ_HANDLER_LOCATION);
     }
+
     pub fn addr_from_mem16(&mut self, lo_byte_addr: u16) -> u16 {
         self.read_mem16(lo_byte_addr)
     }
 
+    /* before taking an interrupt, the CPU reads the next opcode twice and ignores it */
+    fn interrupt_dummy_reads(&mut self) {
+        self.dummy_read(self.program_counter);
+        self.dummy_read(self.program_counter);
+    }
+
     fn trigger_nmi(&mut self) {
         self.set_nmi(false);
+        self.interrupt_dummy_reads();
         /* push PC onto stack */
         self.push_memory_loc(self.program_counter);
         /* push processor status register on stack */
         self.push((self.status & !(1 << 4)) | (1 << 5));
+        self.clear_decimal_on_interrupt();
         /* read NMI handler address from 0xFFFA/0xFFFB and jump to that address*/
         self.program_counter =
             AddressingMode::Indirect.resolve_address_u16(self, NMI_HANDLER_LOCATION);
     }
 
+    fn trigger_irq(&mut self) {
+        /* same as BRK, except the B flag is pushed as 0 and the PC isn't advanced */
+        self.interrupt_dummy_reads();
+        self.push_memory_loc(self.program_counter);
+        self.push((self.status & !(1 << 4)) | (1 << 5));
+        self.update_flag(StatusFlag::InterruptDisable, true);
+        self.clear_decimal_on_interrupt();
+        self.program_counter =
+            AddressingMode::Indirect.resolve_address_u16(self, IRQ_HANDLER_LOCATION);
+    }
+
+    /* the 65C02 enters interrupt handlers in binary mode; the NMOS 6502 leaves D alone */
+    fn clear_decimal_on_interrupt(&mut self) {
+        if self.variant.is_cmos() {
+            self.update_flag(StatusFlag::Decimal, false);
+        }
+    }
+
+    /* every read and write is a bus access, and so takes a cycle */
     pub fn write_mem(&mut self, addr: u16, data: u8) {
+        self.tick();
         if addr == PPURegister::address(&OAMDMA) {
-            self.doing_oamdma = true;
+            self.oam_dma_page = Some(data);
         }
         self.memory.write(addr, data);
     }
 
-    pub fn read_mem(&self, addr: u16) -> u8 {
+    pub fn read_mem(&mut self, addr: u16) -> u8 {
+        if let Some(sample_address) = self.dmc_dma_address.take() {
+            self.dmc_dma(Some(addr), sample_address);
+        }
+        self.tick();
         self.memory.read(addr)
     }
 
+    /* reads memory without triggering any side effects */
+    pub fn peek_mem(&self, addr: u16) -> u8 {
+        self.memory.peek(addr)
+    }
+
     /**
      * Returns the current value of the data that would be saved in RAM, if it exists,
      * or None if the current mapper doesn't support it.
@@ -172,11 +498,76 @@ impl CPU {
         self.memory.set_save_data(data)
     }
 
+    /* two reads; the high byte comes from the same page as the low byte, as on the hardware */
     pub fn read_mem16(&mut self, addr: u16) -> u16 {
-        self.memory.read16(addr)
+        let lo_byte = self.read_mem(addr);
+        let hi_byte = self.read_mem((addr & 0xff00) | (addr as u8).wrapping_add(1) as u16);
+        cpu::addr(lo_byte, hi_byte)
+    }
+
+    pub fn set_key_source(&mut self, keys: ButtonSource) {
+        self.controllers.borrow_mut().set_key_source(keys);
+    }
+
+    pub fn set_pad_source(&mut self, pads: ButtonSource) {
+        self.controllers.borrow_mut().set_pad_source(pads);
+    }
+
+    /* has the controllers hold the given buttons instead of reading the keyboard and gamepads */
+    pub fn set_scripted_buttons(&mut self, buttons: Option<[u8; 2]>) {
+        self.controllers.borrow_mut().set_scripted_buttons(buttons);
+    }
+
+    /* the buttons each controller latched most recently, for recording a movie */
+    pub fn take_frame_buttons(&mut self) -> [u8; 2] {
+        self.controllers.borrow_mut().take_frame_buttons()
+    }
+}
+
+impl Savestate for CPU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.accumulator);
+        writer.write_u8(self.index_x);
+        writer.write_u8(self.index_y);
+        writer.write_u8(self.s_register);
+        writer.write_u16(self.program_counter);
+        writer.write_u8(self.status);
+        writer.write_bool(self.nmi_flag);
+        writer.write_bool(self.apu_irq);
+        writer.write_bool(self.nmi_polled);
+        writer.write_bool(self.irq_polled);
+        writer.write_bool(self.jammed);
+        writer.write_bool(self.waiting);
+        writer.write_u64(self.cycles);
+        writer.write_bool(self.dmc_dma_address.is_some());
+        writer.write_u16(self.dmc_dma_address.unwrap_or(0));
+        writer.write_bool(self.dmc_sample.is_some());
+        writer.write_u8(self.dmc_sample.unwrap_or(0));
+        self.memory.save_state(writer);
+        self.controllers.borrow().save_state(writer);
     }
 
-    pub fn set_key_source(&mut self, keys: Arc<Mutex<HashSet<Key<'static>>>>) {
-        self.controller.borrow_mut().set_key_source(keys);
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.accumulator = reader.read_u8()?;
+        self.index_x = reader.read_u8()?;
+        self.index_y = reader.read_u8()?;
+        self.s_register = reader.read_u8()?;
+        self.program_counter = reader.read_u16()?;
+        self.status = reader.read_u8()?;
+        self.nmi_flag = reader.read_bool()?;
+        self.apu_irq = reader.read_bool()?;
+        self.nmi_polled = reader.read_bool()?;
+        self.irq_polled = reader.read_bool()?;
+        self.jammed = reader.read_bool()?;
+        self.waiting = reader.read_bool()?;
+        self.cycles = reader.read_u64()?;
+        let has_dma = reader.read_bool()?;
+        let dma_address = reader.read_u16()?;
+        self.dmc_dma_address = if has_dma { Some(dma_address) } else { None };
+        let has_sample = reader.read_bool()?;
+        let sample = reader.read_u8()?;
+        self.dmc_sample = if has_sample { Some(sample) } else { None };
+        self.memory.load_state(reader)?;
+        self.controllers.borrow_mut().load_state(reader)
     }
 }
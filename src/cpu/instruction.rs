@@ -1,9 +1,9 @@
-use crate::cpu::{AddressingMode, StatusFlag};
+use crate::cpu::{AddressingMode, CpuVariant, StatusFlag};
 
 use crate::cpu::cpu::CPU;
 use AddressingMode::*;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Instruction {
     /* load/store opcodes */
     LDA, /* loads fixed value into A; can set zero flag */
@@ -83,16 +83,95 @@ pub enum Instruction {
 
     /* others */
     BRK, /* Break (software IRQ) */
-    NOP, /* No-op */
+    NOP, /* No-op; unofficial variants with operands read memory and discard it */
+
+    /* stable unofficial opcodes */
+    ALR, /* AND immediate, then LSR A */
+    ANC, /* AND immediate, then copy bit 7 into carry */
+    ARR, /* AND immediate, then ROR A, with odd carry and overflow flags */
+    AXS, /* X = (A & X) - immediate, setting carry like CMP */
+    DCP, /* DEC, then CMP */
+    ISC, /* INC, then SBC */
+    LAS, /* A, X, and S = memory & S */
+    LAX, /* LDA and LDX at once */
+    RLA, /* ROL, then AND */
+    RRA, /* ROR, then ADC */
+    SAX, /* stores A & X */
+    SLO, /* ASL, then ORA */
+    SRE, /* LSR, then EOR */
+
+    /* unstable unofficial opcodes; the results depend on the chip, and the CPU's policy decides
+     * whether they run at all
+     */
+    AHX, /* stores A & X & (high byte of address + 1) */
+    KIL, /* jams the CPU until reset */
+    LXA, /* A and X = (A | magic) & immediate */
+    SHX, /* stores X & (high byte of address + 1) */
+    SHY, /* stores Y & (high byte of address + 1) */
+    TAS, /* S = A & X, then stores S & (high byte of address + 1) */
+    XAA, /* A = (A | magic) & X & immediate */
+
+    /* 65C02 additions */
+    BBR(u8), /* Branch on Bit Reset: branches if the given bit of a zero page byte is clear */
+    BBS(u8), /* Branch on Bit Set */
+    BRA,     /* Branch Always */
+    PHX,     /* Push X */
+    PHY,     /* Push Y */
+    PLX,     /* Pull X */
+    PLY,     /* Pull Y */
+    RMB(u8), /* Reset Memory Bit: clears the given bit of a zero page byte */
+    SMB(u8), /* Set Memory Bit */
+    STP,     /* Stop the clock until reset */
+    STZ,     /* Store Zero */
+    TRB,     /* Test and Reset Bits: like BIT, then clears A's bits in memory */
+    TSB,     /* Test and Set Bits: like BIT, then sets A's bits in memory */
+    WAI,     /* Wait for Interrupt */
 }
 
+/* the "magic" constant XAA and LXA mix into A varies by chip; this is the most common value */
+const UNSTABLE_MAGIC: u8 = 0xee;
+
 impl Instruction {
+    /* unofficial instructions whose behavior isn't consistent across chips (or that jam) */
+    pub fn is_unstable(&self) -> bool {
+        matches!(
+            self,
+            Instruction::AHX
+                | Instruction::KIL
+                | Instruction::LXA
+                | Instruction::SHX
+                | Instruction::SHY
+                | Instruction::TAS
+                | Instruction::XAA
+        )
+    }
+
+    /* instructions that only exist as unofficial opcodes; NOP and SBC have unofficial opcodes too */
+    pub fn is_unofficial(&self) -> bool {
+        matches!(
+            self,
+            Instruction::ALR
+                | Instruction::ANC
+                | Instruction::ARR
+                | Instruction::AXS
+                | Instruction::DCP
+                | Instruction::ISC
+                | Instruction::LAS
+                | Instruction::LAX
+                | Instruction::RLA
+                | Instruction::RRA
+                | Instruction::SAX
+                | Instruction::SLO
+                | Instruction::SRE
+        ) || self.is_unstable()
+    }
+
     pub fn apply(&self, cpu: &mut CPU, addr_mode: &AddressingMode, b1: u8, b2: u8) -> u16 {
         let mut extra_cycles = 0;
         match self {
             Instruction::ADC => {
                 let val = addr_mode.deref_check_boundary_cross(cpu, b1, b2, &mut extra_cycles);
-                add_with_carry_and_update(cpu, val, StatusFlag::Carry.as_num(cpu));
+                extra_cycles += add(cpu, val);
             }
             Instruction::AND => {
                 let mem_val = addr_mode.deref_check_boundary_cross(cpu, b1, b2, &mut extra_cycles);
@@ -100,7 +179,7 @@ impl Instruction {
                 cpu.update_zero_neg_flags(cpu.accumulator);
             }
             Instruction::ASL => {
-                let old_val: u8 = addr_mode.deref(cpu, b1, b2);
+                let old_val: u8 = addr_mode.deref_for_modify(cpu, b1, b2);
                 let result = old_val << 1;
                 cpu.update_flag(StatusFlag::Carry, old_val & 0x80 != 0);
                 cpu.update_zero_neg_flags(result);
@@ -116,11 +195,14 @@ impl Instruction {
                 extra_cycles += Self::branch_instr(cpu, StatusFlag::Zero, true, b1);
             }
             Instruction::BIT => {
-                let mem = addr_mode.deref(cpu, b1, b2);
+                let mem = addr_mode.deref_check_boundary_cross(cpu, b1, b2, &mut extra_cycles);
                 let val = cpu.accumulator & mem;
                 cpu.update_flag(StatusFlag::Zero, val == 0);
-                cpu.update_flag(StatusFlag::Overflow, mem & 0x40 != 0);
-                cpu.update_flag(StatusFlag::Negative, mem & 0x80 != 0);
+                /* the 65C02's BIT #imm only affects the zero flag */
+                if *addr_mode != Immediate {
+                    cpu.update_flag(StatusFlag::Overflow, mem & 0x40 != 0);
+                    cpu.update_flag(StatusFlag::Negative, mem & 0x80 != 0);
+                }
             }
             Instruction::BMI => {
                 extra_cycles += Self::branch_instr(cpu, StatusFlag::Negative, true, b1);
@@ -166,7 +248,7 @@ impl Instruction {
                 Self::compare(cpu, addr_mode, b1, b2, cpu.index_y, &mut extra_cycles);
             }
             Instruction::DEC => {
-                let new_val = addr_mode.deref(cpu, b1, b2).wrapping_sub(1);
+                let new_val = addr_mode.deref_for_modify(cpu, b1, b2).wrapping_sub(1);
                 addr_mode.write(cpu, b1, b2, new_val);
                 cpu.update_zero_neg_flags(new_val);
             }
@@ -184,7 +266,7 @@ impl Instruction {
                 cpu.update_zero_neg_flags(cpu.accumulator);
             }
             Instruction::INC => {
-                let new_val = addr_mode.deref(cpu, b1, b2).wrapping_add(1);
+                let new_val = addr_mode.deref_for_modify(cpu, b1, b2).wrapping_add(1);
                 addr_mode.write(cpu, b1, b2, new_val);
                 cpu.update_zero_neg_flags(new_val);
             }
@@ -200,7 +282,8 @@ impl Instruction {
                 cpu.program_counter = addr_mode.resolve_address(cpu, b1, b2);
             }
             Instruction::JSR => {
-                cpu.push_memory_loc(cpu.program_counter + 2);
+                stack_dummy_read(cpu);
+                cpu.push_memory_loc(cpu.program_counter.wrapping_add(2));
                 cpu.program_counter = addr_mode.resolve_address(cpu, b1, b2);
             }
             Instruction::LDA => {
@@ -217,14 +300,19 @@ impl Instruction {
                 cpu.update_zero_neg_flags(cpu.index_y);
             }
             Instruction::LSR => {
-                let val = addr_mode.deref(cpu, b1, b2);
+                let val = addr_mode.deref_for_modify(cpu, b1, b2);
                 let new_val = val >> 1;
                 addr_mode.write(cpu, b1, b2, new_val);
                 cpu.update_flag(StatusFlag::Carry, (val & 0x1) != 0);
                 cpu.update_flag(StatusFlag::Zero, new_val == 0);
                 cpu.update_flag(StatusFlag::Negative, false);
             }
-            Instruction::NOP => { /* nothing */ }
+            Instruction::NOP => {
+                /* unofficial NOPs with operands still perform the read */
+                if *addr_mode != Implicit {
+                    addr_mode.deref_check_boundary_cross(cpu, b1, b2, &mut extra_cycles);
+                }
+            }
             Instruction::ORA => {
                 cpu.accumulator |=
                     addr_mode.deref_check_boundary_cross(cpu, b1, b2, &mut extra_cycles);
@@ -239,17 +327,19 @@ impl Instruction {
                 cpu.push(cpu.status | (1 << 5) | (1 << 4));
             }
             Instruction::PLA => {
+                stack_dummy_read(cpu);
                 cpu.accumulator = cpu.pop();
                 cpu.update_zero_neg_flags(cpu.accumulator);
             }
             Instruction::PLP => {
                 /* reads status from the stack, except for bits 4 and 5 */
+                stack_dummy_read(cpu);
                 let val = cpu.pop();
                 cpu.status = (cpu.status & 0x30) | (val & !0x30);
                 /* TODO: update to interrupt disable should be delayed one instruction */
             }
             Instruction::ROL => {
-                let val = addr_mode.deref(cpu, b1, b2);
+                let val = addr_mode.deref_for_modify(cpu, b1, b2);
                 let mut result = StatusFlag::Carry.as_num(cpu);
                 result = result | (val << 1);
                 addr_mode.write(cpu, b1, b2, result);
@@ -257,7 +347,7 @@ impl Instruction {
                 cpu.update_zero_neg_flags(result);
             }
             Instruction::ROR => {
-                let val = addr_mode.deref(cpu, b1, b2);
+                let val = addr_mode.deref_for_modify(cpu, b1, b2);
                 let mut result = StatusFlag::Carry.as_num(cpu) << 7;
                 result = result | (val >> 1);
                 addr_mode.write(cpu, b1, b2, result);
@@ -266,16 +356,21 @@ impl Instruction {
             }
             Instruction::RTI => {
                 /* does not pull flags 4 and 5; update is immediate */
+                stack_dummy_read(cpu);
                 cpu.status = cpu.pop() & !0b0011_0000 | (cpu.status & 0b0011_0000);
 
                 cpu.program_counter = cpu.pop_memory_loc();
             }
             Instruction::RTS => {
-                cpu.program_counter = cpu.pop_memory_loc() + 1;
+                stack_dummy_read(cpu);
+                let return_address = cpu.pop_memory_loc();
+                /* reads the last byte of the JSR while incrementing past it */
+                cpu.dummy_read(return_address);
+                cpu.program_counter = return_address.wrapping_add(1);
             }
             Instruction::SBC => {
                 let val = addr_mode.deref_check_boundary_cross(cpu, b1, b2, &mut extra_cycles);
-                add_with_carry_and_update(cpu, !val, StatusFlag::Carry.as_num(cpu));
+                extra_cycles += subtract(cpu, val);
             }
             Instruction::SEC => {
                 StatusFlag::Carry.update_bool(cpu, true);
@@ -323,23 +418,225 @@ impl Instruction {
                 cpu.accumulator = cpu.index_y;
                 cpu.update_zero_neg_flags(cpu.accumulator);
             }
+            Instruction::ALR => {
+                let val = cpu.accumulator & addr_mode.deref(cpu, b1, b2);
+                cpu.accumulator = val >> 1;
+                cpu.update_flag(StatusFlag::Carry, val & 0x1 != 0);
+                cpu.update_zero_neg_flags(cpu.accumulator);
+            }
+            Instruction::ANC => {
+                cpu.accumulator &= addr_mode.deref(cpu, b1, b2);
+                cpu.update_zero_neg_flags(cpu.accumulator);
+                cpu.update_flag(StatusFlag::Carry, cpu.accumulator & 0x80 != 0);
+            }
+            Instruction::ARR => {
+                let val = cpu.accumulator & addr_mode.deref(cpu, b1, b2);
+                cpu.accumulator = (StatusFlag::Carry.as_num(cpu) << 7) | (val >> 1);
+                cpu.update_zero_neg_flags(cpu.accumulator);
+                /* carry is bit 6 of the result, overflow is bit 6 xor bit 5 */
+                let bit6 = cpu.accumulator & 0x40 != 0;
+                let bit5 = cpu.accumulator & 0x20 != 0;
+                cpu.update_flag(StatusFlag::Carry, bit6);
+                cpu.update_flag(StatusFlag::Overflow, bit6 != bit5);
+            }
+            Instruction::AXS => {
+                let mem_val = addr_mode.deref(cpu, b1, b2);
+                let and_val = cpu.accumulator & cpu.index_x;
+                cpu.index_x = and_val.wrapping_sub(mem_val);
+                cpu.update_flag(StatusFlag::Carry, and_val >= mem_val);
+                cpu.update_zero_neg_flags(cpu.index_x);
+            }
+            Instruction::DCP => {
+                let new_val = addr_mode.deref_for_modify(cpu, b1, b2).wrapping_sub(1);
+                addr_mode.write(cpu, b1, b2, new_val);
+                Self::compare_values(cpu, cpu.accumulator, new_val);
+            }
+            Instruction::ISC => {
+                let new_val = addr_mode.deref_for_modify(cpu, b1, b2).wrapping_add(1);
+                addr_mode.write(cpu, b1, b2, new_val);
+                subtract(cpu, new_val);
+            }
+            Instruction::LAS => {
+                let val = addr_mode.deref_check_boundary_cross(cpu, b1, b2, &mut extra_cycles)
+                    & cpu.s_register;
+                cpu.accumulator = val;
+                cpu.index_x = val;
+                cpu.s_register = val;
+                cpu.update_zero_neg_flags(val);
+            }
+            Instruction::LAX => {
+                let val = addr_mode.deref_check_boundary_cross(cpu, b1, b2, &mut extra_cycles);
+                cpu.accumulator = val;
+                cpu.index_x = val;
+                cpu.update_zero_neg_flags(val);
+            }
+            Instruction::RLA => {
+                let val = addr_mode.deref_for_modify(cpu, b1, b2);
+                let result = (val << 1) | StatusFlag::Carry.as_num(cpu);
+                addr_mode.write(cpu, b1, b2, result);
+                cpu.update_flag(StatusFlag::Carry, val & 0x80 != 0);
+                cpu.accumulator &= result;
+                cpu.update_zero_neg_flags(cpu.accumulator);
+            }
+            Instruction::RRA => {
+                let val = addr_mode.deref_for_modify(cpu, b1, b2);
+                let result = (StatusFlag::Carry.as_num(cpu) << 7) | (val >> 1);
+                addr_mode.write(cpu, b1, b2, result);
+                cpu.update_flag(StatusFlag::Carry, val & 0x1 != 0);
+                add(cpu, result);
+            }
+            Instruction::SAX => {
+                addr_mode.write(cpu, b1, b2, cpu.accumulator & cpu.index_x);
+            }
+            Instruction::SLO => {
+                let val = addr_mode.deref_for_modify(cpu, b1, b2);
+                let result = val << 1;
+                addr_mode.write(cpu, b1, b2, result);
+                cpu.update_flag(StatusFlag::Carry, val & 0x80 != 0);
+                cpu.accumulator |= result;
+                cpu.update_zero_neg_flags(cpu.accumulator);
+            }
+            Instruction::SRE => {
+                let val = addr_mode.deref_for_modify(cpu, b1, b2);
+                let result = val >> 1;
+                addr_mode.write(cpu, b1, b2, result);
+                cpu.update_flag(StatusFlag::Carry, val & 0x1 != 0);
+                cpu.accumulator ^= result;
+                cpu.update_zero_neg_flags(cpu.accumulator);
+            }
+            Instruction::AHX => {
+                Self::unstable_store(cpu, addr_mode, b1, b2, cpu.accumulator & cpu.index_x);
+            }
+            Instruction::KIL => {
+                cpu.jam();
+            }
+            Instruction::LXA => {
+                let val = (cpu.accumulator | UNSTABLE_MAGIC) & addr_mode.deref(cpu, b1, b2);
+                cpu.accumulator = val;
+                cpu.index_x = val;
+                cpu.update_zero_neg_flags(val);
+            }
+            Instruction::SHX => {
+                Self::unstable_store(cpu, addr_mode, b1, b2, cpu.index_x);
+            }
+            Instruction::SHY => {
+                Self::unstable_store(cpu, addr_mode, b1, b2, cpu.index_y);
+            }
+            Instruction::TAS => {
+                cpu.s_register = cpu.accumulator & cpu.index_x;
+                Self::unstable_store(cpu, addr_mode, b1, b2, cpu.s_register);
+            }
+            Instruction::XAA => {
+                cpu.accumulator =
+                    (cpu.accumulator | UNSTABLE_MAGIC) & cpu.index_x & addr_mode.deref(cpu, b1, b2);
+                cpu.update_zero_neg_flags(cpu.accumulator);
+            }
+            Instruction::BBR(bit) => {
+                let val = addr_mode.deref(cpu, b1, b2);
+                cpu.dummy_read(b1 as u16); /* the 65C02 reads the byte a second time */
+                extra_cycles += Self::branch(cpu, val & (1 << bit) == 0, b2, 3);
+            }
+            Instruction::BBS(bit) => {
+                let val = addr_mode.deref(cpu, b1, b2);
+                cpu.dummy_read(b1 as u16); /* the 65C02 reads the byte a second time */
+                extra_cycles += Self::branch(cpu, val & (1 << bit) != 0, b2, 3);
+            }
+            Instruction::BRA => {
+                extra_cycles += Self::branch(cpu, true, b1, 2);
+            }
+            Instruction::PHX => {
+                cpu.push(cpu.index_x);
+            }
+            Instruction::PHY => {
+                cpu.push(cpu.index_y);
+            }
+            Instruction::PLX => {
+                stack_dummy_read(cpu);
+                cpu.index_x = cpu.pop();
+                cpu.update_zero_neg_flags(cpu.index_x);
+            }
+            Instruction::PLY => {
+                stack_dummy_read(cpu);
+                cpu.index_y = cpu.pop();
+                cpu.update_zero_neg_flags(cpu.index_y);
+            }
+            Instruction::RMB(bit) => {
+                let val = addr_mode.deref_for_modify(cpu, b1, b2) & !(1 << bit);
+                addr_mode.write(cpu, b1, b2, val);
+            }
+            Instruction::SMB(bit) => {
+                let val = addr_mode.deref_for_modify(cpu, b1, b2) | (1 << bit);
+                addr_mode.write(cpu, b1, b2, val);
+            }
+            Instruction::STP => {
+                cpu.jam();
+            }
+            Instruction::STZ => {
+                addr_mode.write(cpu, b1, b2, 0);
+            }
+            Instruction::TRB => {
+                let val = addr_mode.deref_for_modify(cpu, b1, b2);
+                cpu.update_flag(StatusFlag::Zero, val & cpu.accumulator == 0);
+                addr_mode.write(cpu, b1, b2, val & !cpu.accumulator);
+            }
+            Instruction::TSB => {
+                let val = addr_mode.deref_for_modify(cpu, b1, b2);
+                cpu.update_flag(StatusFlag::Zero, val & cpu.accumulator == 0);
+                addr_mode.write(cpu, b1, b2, val | cpu.accumulator);
+            }
+            Instruction::WAI => {
+                cpu.wait_for_interrupt();
+            }
         }
 
         extra_cycles
     }
 
+    /* the indexed stores AHX, SHX, SHY, and TAS AND the value with the high byte of the base
+     * address plus one; if indexing crosses a page, that value also replaces the high byte
+     */
+    fn unstable_store(cpu: &mut CPU, addr_mode: &AddressingMode, b1: u8, b2: u8, value: u8) {
+        let index = match addr_mode {
+            AbsoluteX => cpu.index_x,
+            _ => cpu.index_y,
+        };
+        let target = addr_mode.resolve_address(cpu, b1, b2);
+        let base = target.wrapping_sub(index as u16);
+        cpu.dummy_read((base & 0xff00) | (target & 0xff));
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let address = if base & 0xff00 != target & 0xff00 {
+            ((value as u16) << 8) | (target & 0xff)
+        } else {
+            target
+        };
+        cpu.write_mem(address, value);
+    }
+
     fn branch_instr(cpu: &mut CPU, flag: StatusFlag, is_positive: bool, offset: u8) -> u16 {
-        if is_positive == flag.is_set(cpu) {
-            let old_pc = cpu.program_counter;
-            cpu.program_counter = Relative.resolve_address(cpu, offset, 0);
-            /* TODO comment */
-            if old_pc & !0xff != cpu.program_counter & !0xff {
-                2
-            } else {
-                1
-            }
+        Self::branch(cpu, is_positive == flag.is_set(cpu), offset, 2)
+    }
+
+    /**
+     * Branches relative to the instruction after this one, which is length bytes on. Returns
+     * the extra cycles taken: one for taking the branch, in which the CPU reads the next opcode
+     * while it adds the offset, and another if the add carried into the high byte, in which it
+     * reads from the target address before the carry.
+     */
+    fn branch(cpu: &mut CPU, taken: bool, offset: u8, length: u16) -> u16 {
+        if !taken {
+            return 0;
+        }
+
+        let next = cpu.program_counter.wrapping_add(length);
+        let target = next.wrapping_add_signed(offset as i8 as i16);
+        cpu.dummy_read(next);
+        /* apply() advances past the instruction afterwards, as it does for any other */
+        cpu.program_counter = target.wrapping_sub(length);
+        if next & 0xff00 != target & 0xff00 {
+            cpu.dummy_read((next & 0xff00) | (target & 0xff));
+            2
         } else {
-            0
+            1
         }
     }
 
@@ -352,7 +649,10 @@ impl Instruction {
         extra_cycles: &mut u16,
     ) {
         let mem_val = addr_mode.deref_check_boundary_cross(cpu, b1, b2, extra_cycles);
+        Self::compare_values(cpu, compare_val, mem_val);
+    }
 
+    fn compare_values(cpu: &mut CPU, compare_val: u8, mem_val: u8) {
         cpu.update_flag(StatusFlag::Carry, compare_val >= mem_val);
         cpu.update_flag(StatusFlag::Zero, compare_val == mem_val);
         cpu.update_flag(
@@ -382,6 +682,8 @@ impl RealizedInstruction {
             Instruction::RTS => {}
             Instruction::RTI => {}
             Instruction::BRK => {} // acts like a JMP
+            Instruction::KIL => {} // the CPU is stuck here
+            Instruction::STP => {} // ditto
             _ => {
                 cpu.program_counter = cpu
                     .program_counter
@@ -400,162 +702,260 @@ pub fn from_opcode(opcode: u8) -> RealizedInstruction {
         /* branch instructions also take an extra cycle if branch taken */
         0x00 => (Instruction::BRK, Implicit, 7),
         0x01 => (Instruction::ORA, IndirectX, 6),
+        0x02 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0x03 => (Instruction::SLO, IndirectX, 8), /* unofficial */
+        0x04 => (Instruction::NOP, ZeroPage, 3), /* unofficial */
         0x05 => (Instruction::ORA, ZeroPage, 3),
         0x06 => (Instruction::ASL, ZeroPage, 5),
+        0x07 => (Instruction::SLO, ZeroPage, 5), /* unofficial */
         0x08 => (Instruction::PHP, Implicit, 3),
         0x09 => (Instruction::ORA, Immediate, 2),
         0x0a => (Instruction::ASL, Accumulator, 2),
+        0x0b => (Instruction::ANC, Immediate, 2), /* unofficial */
+        0x0c => (Instruction::NOP, Absolute, 4),  /* unofficial */
         0x0d => (Instruction::ORA, Absolute, 4),
         0x0e => (Instruction::ASL, Absolute, 6),
+        0x0f => (Instruction::SLO, Absolute, 6), /* unofficial */
         0x10 => (Instruction::BPL, Relative, 2), /*boundary*/
         0x11 => (Instruction::ORA, IndirectY, 5), /*boundary*/
+        0x12 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0x13 => (Instruction::SLO, IndirectY, 8), /* unofficial */
+        0x14 => (Instruction::NOP, ZeroPageX, 4), /* unofficial */
         0x15 => (Instruction::ORA, ZeroPageX, 4),
         0x16 => (Instruction::ASL, ZeroPageX, 6),
+        0x17 => (Instruction::SLO, ZeroPageX, 6), /* unofficial */
         0x18 => (Instruction::CLC, Implicit, 2),
         0x19 => (Instruction::ORA, AbsoluteY, 4), /*boundary*/
         0x1a => (Instruction::NOP, Implicit, 2),  /* unofficial */
+        0x1b => (Instruction::SLO, AbsoluteY, 7), /* unofficial */
+        0x1c => (Instruction::NOP, AbsoluteX, 4), /* unofficial, boundary */
         0x1d => (Instruction::ORA, AbsoluteX, 4), /*boundary*/
         0x1e => (Instruction::ASL, AbsoluteX, 7),
+        0x1f => (Instruction::SLO, AbsoluteX, 7), /* unofficial */
         0x20 => (Instruction::JSR, Absolute, 6),
         0x21 => (Instruction::AND, IndirectX, 6),
+        0x22 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0x23 => (Instruction::RLA, IndirectX, 8), /* unofficial */
         0x24 => (Instruction::BIT, ZeroPage, 3),
         0x25 => (Instruction::AND, ZeroPage, 3),
         0x26 => (Instruction::ROL, ZeroPage, 5),
+        0x27 => (Instruction::RLA, ZeroPage, 5), /* unofficial */
         0x28 => (Instruction::PLP, Implicit, 4),
         0x29 => (Instruction::AND, Immediate, 2),
         0x2a => (Instruction::ROL, Accumulator, 2),
+        0x2b => (Instruction::ANC, Immediate, 2), /* unofficial */
         0x2c => (Instruction::BIT, Absolute, 4),
         0x2d => (Instruction::AND, Absolute, 4),
         0x2e => (Instruction::ROL, Absolute, 6),
+        0x2f => (Instruction::RLA, Absolute, 6), /* unofficial */
         0x30 => (Instruction::BMI, Relative, 2), /*boundary*/
         0x31 => (Instruction::AND, IndirectY, 5), /*boundary*/
+        0x32 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0x33 => (Instruction::RLA, IndirectY, 8), /* unofficial */
+        0x34 => (Instruction::NOP, ZeroPageX, 4), /* unofficial */
         0x35 => (Instruction::AND, ZeroPageX, 4),
         0x36 => (Instruction::ROL, ZeroPageX, 6),
+        0x37 => (Instruction::RLA, ZeroPageX, 6), /* unofficial */
         0x38 => (Instruction::SEC, Implicit, 2),
         0x39 => (Instruction::AND, AbsoluteY, 4), /*boundary*/
         0x3a => (Instruction::NOP, Implicit, 2),  /* unofficial */
+        0x3b => (Instruction::RLA, AbsoluteY, 7), /* unofficial */
+        0x3c => (Instruction::NOP, AbsoluteX, 4), /* unofficial, boundary */
         0x3d => (Instruction::AND, AbsoluteX, 4), /*boundary*/
         0x3e => (Instruction::ROL, AbsoluteX, 7),
+        0x3f => (Instruction::RLA, AbsoluteX, 7), /* unofficial */
         0x40 => (Instruction::RTI, Implicit, 6),
         0x41 => (Instruction::EOR, IndirectX, 6),
+        0x42 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0x43 => (Instruction::SRE, IndirectX, 8), /* unofficial */
+        0x44 => (Instruction::NOP, ZeroPage, 3), /* unofficial */
         0x45 => (Instruction::EOR, ZeroPage, 3),
         0x46 => (Instruction::LSR, ZeroPage, 5),
+        0x47 => (Instruction::SRE, ZeroPage, 5), /* unofficial */
         0x48 => (Instruction::PHA, Implicit, 3),
         0x49 => (Instruction::EOR, Immediate, 2),
         0x4a => (Instruction::LSR, Accumulator, 2),
+        0x4b => (Instruction::ALR, Immediate, 2), /* unofficial */
         0x4c => (Instruction::JMP, Absolute, 3),
         0x4d => (Instruction::EOR, Absolute, 4),
         0x4e => (Instruction::LSR, Absolute, 6),
+        0x4f => (Instruction::SRE, Absolute, 6), /* unofficial */
         0x50 => (Instruction::BVC, Relative, 2), /*boundary*/
         0x51 => (Instruction::EOR, IndirectY, 5), /*boundary*/
+        0x52 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0x53 => (Instruction::SRE, IndirectY, 8), /* unofficial */
+        0x54 => (Instruction::NOP, ZeroPageX, 4), /* unofficial */
         0x55 => (Instruction::EOR, ZeroPageX, 4),
         0x56 => (Instruction::LSR, ZeroPageX, 6),
+        0x57 => (Instruction::SRE, ZeroPageX, 6), /* unofficial */
         0x58 => (Instruction::CLI, Implicit, 2),
         0x59 => (Instruction::EOR, AbsoluteY, 4), /*boundary*/
         0x5a => (Instruction::NOP, Implicit, 2),  /* unofficial */
+        0x5b => (Instruction::SRE, AbsoluteY, 7), /* unofficial */
+        0x5c => (Instruction::NOP, AbsoluteX, 4), /* unofficial, boundary */
         0x5d => (Instruction::EOR, AbsoluteX, 4), /*boundary*/
         0x5e => (Instruction::LSR, AbsoluteX, 7),
+        0x5f => (Instruction::SRE, AbsoluteX, 7), /* unofficial */
         0x60 => (Instruction::RTS, Implicit, 6),
         0x61 => (Instruction::ADC, IndirectX, 6),
+        0x62 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0x63 => (Instruction::RRA, IndirectX, 8), /* unofficial */
+        0x64 => (Instruction::NOP, ZeroPage, 3), /* unofficial */
         0x65 => (Instruction::ADC, ZeroPage, 3),
         0x66 => (Instruction::ROR, ZeroPage, 5),
+        0x67 => (Instruction::RRA, ZeroPage, 5), /* unofficial */
         0x68 => (Instruction::PLA, Implicit, 4),
         0x69 => (Instruction::ADC, Immediate, 2),
         0x6a => (Instruction::ROR, Accumulator, 2),
+        0x6b => (Instruction::ARR, Immediate, 2), /* unofficial */
         0x6c => (Instruction::JMP, Indirect, 5),
         0x6d => (Instruction::ADC, Absolute, 4),
         0x6e => (Instruction::ROR, Absolute, 6),
+        0x6f => (Instruction::RRA, Absolute, 6), /* unofficial */
         0x70 => (Instruction::BVS, Relative, 2), /*boundary*/
         0x71 => (Instruction::ADC, IndirectY, 5), /*boundary*/
+        0x72 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0x73 => (Instruction::RRA, IndirectY, 8), /* unofficial */
+        0x74 => (Instruction::NOP, ZeroPageX, 4), /* unofficial */
         0x75 => (Instruction::ADC, ZeroPageX, 4),
         0x76 => (Instruction::ROR, ZeroPageX, 6),
+        0x77 => (Instruction::RRA, ZeroPageX, 6), /* unofficial */
         0x78 => (Instruction::SEI, Implicit, 2),
         0x79 => (Instruction::ADC, AbsoluteY, 4), /*boundary*/
         0x7a => (Instruction::NOP, Implicit, 2),  /* unofficial */
+        0x7b => (Instruction::RRA, AbsoluteY, 7), /* unofficial */
+        0x7c => (Instruction::NOP, AbsoluteX, 4), /* unofficial, boundary */
         0x7d => (Instruction::ADC, AbsoluteX, 4), /*boundary*/
         0x7e => (Instruction::ROR, AbsoluteX, 7),
+        0x7f => (Instruction::RRA, AbsoluteX, 7), /* unofficial */
+        0x80 => (Instruction::NOP, Immediate, 2), /* unofficial */
         0x81 => (Instruction::STA, IndirectX, 6),
+        0x82 => (Instruction::NOP, Immediate, 2), /* unofficial */
+        0x83 => (Instruction::SAX, IndirectX, 6), /* unofficial */
         0x84 => (Instruction::STY, ZeroPage, 3),
         0x85 => (Instruction::STA, ZeroPage, 3),
         0x86 => (Instruction::STX, ZeroPage, 3),
+        0x87 => (Instruction::SAX, ZeroPage, 3), /* unofficial */
         0x88 => (Instruction::DEY, Implicit, 2),
+        0x89 => (Instruction::NOP, Immediate, 2), /* unofficial */
         0x8a => (Instruction::TXA, Implicit, 2),
+        0x8b => (Instruction::XAA, Immediate, 2), /* unstable */
         0x8c => (Instruction::STY, Absolute, 4),
         0x8d => (Instruction::STA, Absolute, 4),
         0x8e => (Instruction::STX, Absolute, 4),
+        0x8f => (Instruction::SAX, Absolute, 4), /* unofficial */
         0x90 => (Instruction::BCC, Relative, 2), /*boundary*/
         0x91 => (Instruction::STA, IndirectY, 6),
+        0x92 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0x93 => (Instruction::AHX, IndirectY, 6), /* unstable */
         0x94 => (Instruction::STY, ZeroPageX, 4),
         0x95 => (Instruction::STA, ZeroPageX, 4),
         0x96 => (Instruction::STX, ZeroPageY, 4),
+        0x97 => (Instruction::SAX, ZeroPageY, 4), /* unofficial */
         0x98 => (Instruction::TYA, Implicit, 2),
         0x99 => (Instruction::STA, AbsoluteY, 5),
         0x9a => (Instruction::TXS, Implicit, 2),
+        0x9b => (Instruction::TAS, AbsoluteY, 5), /* unstable */
+        0x9c => (Instruction::SHY, AbsoluteX, 5), /* unstable */
         0x9d => (Instruction::STA, AbsoluteX, 5),
+        0x9e => (Instruction::SHX, AbsoluteY, 5), /* unstable */
+        0x9f => (Instruction::AHX, AbsoluteY, 5), /* unstable */
         0xa0 => (Instruction::LDY, Immediate, 2),
         0xa1 => (Instruction::LDA, IndirectX, 6),
         0xa2 => (Instruction::LDX, Immediate, 2),
+        0xa3 => (Instruction::LAX, IndirectX, 6), /* unofficial */
         0xa4 => (Instruction::LDY, ZeroPage, 3),
         0xa5 => (Instruction::LDA, ZeroPage, 3),
         0xa6 => (Instruction::LDX, ZeroPage, 3),
+        0xa7 => (Instruction::LAX, ZeroPage, 3), /* unofficial */
         0xa8 => (Instruction::TAY, Implicit, 2),
         0xa9 => (Instruction::LDA, Immediate, 2),
         0xaa => (Instruction::TAX, Implicit, 2),
+        0xab => (Instruction::LXA, Immediate, 2), /* unstable */
         0xac => (Instruction::LDY, Absolute, 4),
         0xad => (Instruction::LDA, Absolute, 4),
         0xae => (Instruction::LDX, Absolute, 4),
+        0xaf => (Instruction::LAX, Absolute, 4), /* unofficial */
         0xb0 => (Instruction::BCS, Relative, 2), /*boundary*/
         0xb1 => (Instruction::LDA, IndirectY, 5), /*boundary*/
+        0xb2 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0xb3 => (Instruction::LAX, IndirectY, 5), /* unofficial, boundary */
         0xb4 => (Instruction::LDY, ZeroPageX, 4),
         0xb5 => (Instruction::LDA, ZeroPageX, 4),
         0xb6 => (Instruction::LDX, ZeroPageY, 4),
+        0xb7 => (Instruction::LAX, ZeroPageY, 4), /* unofficial */
         0xb8 => (Instruction::CLV, Implicit, 2),
         0xb9 => (Instruction::LDA, AbsoluteY, 4), /*boundary*/
         0xba => (Instruction::TSX, Implicit, 2),
+        0xbb => (Instruction::LAS, AbsoluteY, 4), /* unofficial, boundary */
         0xbc => (Instruction::LDY, AbsoluteX, 4), /*boundary*/
         0xbd => (Instruction::LDA, AbsoluteX, 4), /*boundary*/
         0xbe => (Instruction::LDX, AbsoluteY, 4), /*boundary*/
+        0xbf => (Instruction::LAX, AbsoluteY, 4), /* unofficial, boundary */
         0xc0 => (Instruction::CPY, Immediate, 2),
         0xc1 => (Instruction::CMP, IndirectX, 6),
+        0xc2 => (Instruction::NOP, Immediate, 2), /* unofficial */
+        0xc3 => (Instruction::DCP, IndirectX, 8), /* unofficial */
         0xc4 => (Instruction::CPY, ZeroPage, 3),
         0xc5 => (Instruction::CMP, ZeroPage, 3),
         0xc6 => (Instruction::DEC, ZeroPage, 5),
+        0xc7 => (Instruction::DCP, ZeroPage, 5), /* unofficial */
         0xc8 => (Instruction::INY, Implicit, 2),
         0xc9 => (Instruction::CMP, Immediate, 2),
         0xca => (Instruction::DEX, Implicit, 2),
+        0xcb => (Instruction::AXS, Immediate, 2), /* unofficial */
         0xcc => (Instruction::CPY, Absolute, 4),
         0xcd => (Instruction::CMP, Absolute, 4),
         0xce => (Instruction::DEC, Absolute, 6),
+        0xcf => (Instruction::DCP, Absolute, 6), /* unofficial */
         0xd0 => (Instruction::BNE, Relative, 2), /*boundary*/
         0xd1 => (Instruction::CMP, IndirectY, 5), /*boundary*/
+        0xd2 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0xd3 => (Instruction::DCP, IndirectY, 8), /* unofficial */
+        0xd4 => (Instruction::NOP, ZeroPageX, 4), /* unofficial */
         0xd5 => (Instruction::CMP, ZeroPageX, 4),
         0xd6 => (Instruction::DEC, ZeroPageX, 6),
+        0xd7 => (Instruction::DCP, ZeroPageX, 6), /* unofficial */
         0xd8 => (Instruction::CLD, Implicit, 2),
         0xd9 => (Instruction::CMP, AbsoluteY, 4), /*boundary*/
         0xda => (Instruction::NOP, Implicit, 2),  /* unofficial */
+        0xdb => (Instruction::DCP, AbsoluteY, 7), /* unofficial */
+        0xdc => (Instruction::NOP, AbsoluteX, 4), /* unofficial, boundary */
         0xdd => (Instruction::CMP, AbsoluteX, 4), /*boundary*/
         0xde => (Instruction::DEC, AbsoluteX, 7),
+        0xdf => (Instruction::DCP, AbsoluteX, 7), /* unofficial */
         0xe0 => (Instruction::CPX, Immediate, 2),
         0xe1 => (Instruction::SBC, IndirectX, 6),
+        0xe2 => (Instruction::NOP, Immediate, 2), /* unofficial */
+        0xe3 => (Instruction::ISC, IndirectX, 8), /* unofficial */
         0xe4 => (Instruction::CPX, ZeroPage, 3),
         0xe5 => (Instruction::SBC, ZeroPage, 3),
         0xe6 => (Instruction::INC, ZeroPage, 5),
+        0xe7 => (Instruction::ISC, ZeroPage, 5), /* unofficial */
         0xe8 => (Instruction::INX, Implicit, 2),
         0xe9 => (Instruction::SBC, Immediate, 2),
         0xea => (Instruction::NOP, Implicit, 2),
+        0xeb => (Instruction::SBC, Immediate, 2), /* unofficial */
         0xec => (Instruction::CPX, Absolute, 4),
         0xed => (Instruction::SBC, Absolute, 4),
         0xee => (Instruction::INC, Absolute, 6),
+        0xef => (Instruction::ISC, Absolute, 6), /* unofficial */
         0xf0 => (Instruction::BEQ, Relative, 2), /*boundary*/
         0xf1 => (Instruction::SBC, IndirectY, 5),
+        0xf2 => (Instruction::KIL, Implicit, 2), /* unstable */
+        0xf3 => (Instruction::ISC, IndirectY, 8), /* unofficial */
+        0xf4 => (Instruction::NOP, ZeroPageX, 4), /* unofficial */
         0xf5 => (Instruction::SBC, ZeroPageX, 4),
         0xf6 => (Instruction::INC, ZeroPageX, 6),
+        0xf7 => (Instruction::ISC, ZeroPageX, 6), /* unofficial */
         0xf8 => (Instruction::SED, Implicit, 2),
         0xf9 => (Instruction::SBC, AbsoluteY, 4), /*boundary*/
         0xfa => (Instruction::NOP, Implicit, 2),  /* unofficial */
+        0xfb => (Instruction::ISC, AbsoluteY, 7), /* unofficial */
+        0xfc => (Instruction::NOP, AbsoluteX, 4), /* unofficial, boundary */
         0xfd => (Instruction::SBC, AbsoluteX, 4), /*boundary*/
         0xfe => (Instruction::INC, AbsoluteX, 7),
-        _ => handle_unknown_opcode(opcode),
+        0xff => (Instruction::ISC, AbsoluteX, 7), /* unofficial */
     };
 
     RealizedInstruction {
@@ -565,8 +965,186 @@ pub fn from_opcode(opcode: u8) -> RealizedInstruction {
     }
 }
 
-fn handle_unknown_opcode(opcode: u8) -> (Instruction, AddressingMode, u16) /* unused */ {
-    panic!("Unknown opcode 0x{opcode:x}");
+/* true for opcodes outside the documented instruction set, including the extra NOPs and SBC */
+pub fn is_unofficial_opcode(opcode: u8) -> bool {
+    match from_opcode(opcode).instruction {
+        Instruction::NOP => opcode != 0xea,
+        Instruction::SBC => opcode == 0xeb,
+        instruction => instruction.is_unofficial(),
+    }
+}
+
+/* decodes an opcode as the given member of the 6502 family would */
+pub fn decode(variant: CpuVariant, opcode: u8) -> RealizedInstruction {
+    match variant {
+        CpuVariant::Wdc65C02 => match cmos_opcode(opcode) {
+            Some((instruction, addr_mode, cycles)) => RealizedInstruction {
+                instruction,
+                addr_mode,
+                cycles,
+            },
+            None => from_opcode(opcode),
+        },
+        _ => from_opcode(opcode),
+    }
+}
+
+/**
+ * The opcodes the 65C02 decodes differently from the NMOS 6502: its new instructions, the extra
+ * addressing modes for existing ones, and NOPs in place of every unofficial opcode.
+ */
+fn cmos_opcode(opcode: u8) -> Option<(Instruction, AddressingMode, u16)> {
+    let bit = (opcode >> 4) & 0x7;
+    let decoded = match opcode {
+        0x04 => (Instruction::TSB, ZeroPage, 5),
+        0x0c => (Instruction::TSB, Absolute, 6),
+        0x14 => (Instruction::TRB, ZeroPage, 5),
+        0x1c => (Instruction::TRB, Absolute, 6),
+        0x12 => (Instruction::ORA, ZeroPageIndirect, 5),
+        0x32 => (Instruction::AND, ZeroPageIndirect, 5),
+        0x52 => (Instruction::EOR, ZeroPageIndirect, 5),
+        0x72 => (Instruction::ADC, ZeroPageIndirect, 5),
+        0x92 => (Instruction::STA, ZeroPageIndirect, 5),
+        0xb2 => (Instruction::LDA, ZeroPageIndirect, 5),
+        0xd2 => (Instruction::CMP, ZeroPageIndirect, 5),
+        0xf2 => (Instruction::SBC, ZeroPageIndirect, 5),
+        0x1a => (Instruction::INC, Accumulator, 2),
+        0x3a => (Instruction::DEC, Accumulator, 2),
+        0x34 => (Instruction::BIT, ZeroPageX, 4),
+        0x3c => (Instruction::BIT, AbsoluteX, 4), /*boundary*/
+        0x89 => (Instruction::BIT, Immediate, 2),
+        0x5a => (Instruction::PHY, Implicit, 3),
+        0x7a => (Instruction::PLY, Implicit, 4),
+        0xda => (Instruction::PHX, Implicit, 3),
+        0xfa => (Instruction::PLX, Implicit, 4),
+        0x64 => (Instruction::STZ, ZeroPage, 3),
+        0x74 => (Instruction::STZ, ZeroPageX, 4),
+        0x9c => (Instruction::STZ, Absolute, 4),
+        0x9e => (Instruction::STZ, AbsoluteX, 5),
+        0x80 => (Instruction::BRA, Relative, 2), /*boundary*/
+        0x6c => (Instruction::JMP, Indirect, 6),
+        0x7c => (Instruction::JMP, AbsoluteIndexedIndirect, 6),
+        0xcb => (Instruction::WAI, Implicit, 3),
+        0xdb => (Instruction::STP, Implicit, 3),
+        /* the Rockwell bit instructions, with the bit number in the high nibble */
+        0x07 | 0x17 | 0x27 | 0x37 | 0x47 | 0x57 | 0x67 | 0x77 => {
+            (Instruction::RMB(bit), ZeroPage, 5)
+        }
+        0x87 | 0x97 | 0xa7 | 0xb7 | 0xc7 | 0xd7 | 0xe7 | 0xf7 => {
+            (Instruction::SMB(bit), ZeroPage, 5)
+        }
+        0x0f | 0x1f | 0x2f | 0x3f | 0x4f | 0x5f | 0x6f | 0x7f => {
+            (Instruction::BBR(bit), ZeroPageRelative, 5) /*boundary*/
+        }
+        0x8f | 0x9f | 0xaf | 0xbf | 0xcf | 0xdf | 0xef | 0xff => {
+            (Instruction::BBS(bit), ZeroPageRelative, 5) /*boundary*/
+        }
+        /* the remaining undefined opcodes are NOPs of various lengths */
+        0x02 | 0x22 | 0x42 | 0x62 | 0x82 | 0xc2 | 0xe2 => (Instruction::NOP, Immediate, 2),
+        0x44 => (Instruction::NOP, ZeroPage, 3),
+        0x54 | 0xd4 | 0xf4 => (Instruction::NOP, ZeroPageX, 4),
+        0x5c => (Instruction::NOP, Absolute, 8),
+        0xdc | 0xfc => (Instruction::NOP, Absolute, 4),
+        _ if opcode & 0x03 == 0x03 => (Instruction::NOP, Implicit, 1),
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+/* reads the top of the stack and ignores it, as the CPU does while it adjusts the stack pointer */
+fn stack_dummy_read(cpu: &mut CPU) {
+    cpu.dummy_read(0x0100 + cpu.s_register as u16);
+}
+
+/* true if ADC and SBC should work in BCD */
+fn decimal_mode(cpu: &CPU) -> bool {
+    cpu.variant().has_decimal_mode() && StatusFlag::Decimal.is_set(cpu)
+}
+
+/* ADC with the carry flag; returns any extra cycles, since the 65C02 takes one for decimal mode */
+fn add(cpu: &mut CPU, mem_val: u8) -> u16 {
+    let carry = StatusFlag::Carry.as_num(cpu);
+    if !decimal_mode(cpu) {
+        add_with_carry_and_update(cpu, mem_val, carry);
+        return 0;
+    }
+
+    /* see http://www.6502.org/tutorials/decimal_mode.html, appendix A */
+    let a = cpu.accumulator as u16;
+    let b = mem_val as u16;
+    let mut low = (a & 0x0f) + (b & 0x0f) + carry as u16;
+    if low >= 0x0a {
+        low = ((low + 0x06) & 0x0f) + 0x10;
+    }
+    let mut sum = (a & 0xf0) + (b & 0xf0) + low;
+    /* N and V come from the sum before the high digit is adjusted (signed, for V) */
+    let signed_sum = (a & 0xf0) as u8 as i8 as i16 + (b & 0xf0) as u8 as i8 as i16 + low as i16;
+    let negative = sum & 0x80 != 0;
+    if sum >= 0xa0 {
+        sum += 0x60;
+    }
+    let result = sum as u8;
+
+    cpu.update_flag(StatusFlag::Carry, sum >= 0x100);
+    cpu.update_flag(StatusFlag::Overflow, !(-128..=127).contains(&signed_sum));
+    cpu.accumulator = result;
+    if cpu.variant().is_cmos() {
+        cpu.update_zero_neg_flags(result);
+        cpu.idle_cycle();
+        1
+    } else {
+        /* the NMOS 6502 sets Z from the binary sum */
+        let binary = (a + b + carry as u16) as u8;
+        cpu.update_flag(StatusFlag::Zero, binary == 0);
+        cpu.update_flag(StatusFlag::Negative, negative);
+        0
+    }
+}
+
+/* SBC with the carry flag as not-borrow; returns any extra cycles, as add() does */
+fn subtract(cpu: &mut CPU, mem_val: u8) -> u16 {
+    let carry = StatusFlag::Carry.as_num(cpu);
+    let old_a = cpu.accumulator;
+    /* C and V always come from the binary subtraction, as do N and Z on the NMOS 6502 */
+    add_with_carry_and_update(cpu, !mem_val, carry);
+    if !decimal_mode(cpu) {
+        return 0;
+    }
+
+    let a = old_a as i16;
+    let b = mem_val as i16;
+    let borrow = 1 - carry as i16;
+    let low = (a & 0x0f) - (b & 0x0f) - borrow;
+    let result = if cpu.variant().is_cmos() {
+        let mut difference = a - b - borrow;
+        if difference < 0 {
+            difference -= 0x60;
+        }
+        if low < 0 {
+            difference -= 0x06;
+        }
+        difference
+    } else {
+        let low = if low < 0 {
+            ((low - 0x06) & 0x0f) - 0x10
+        } else {
+            low
+        };
+        let mut difference = (a & 0xf0) - (b & 0xf0) + low;
+        if difference < 0 {
+            difference -= 0x60;
+        }
+        difference
+    } as u8;
+
+    cpu.accumulator = result;
+    if cpu.variant().is_cmos() {
+        cpu.update_zero_neg_flags(result);
+        cpu.idle_cycle();
+        1
+    } else {
+        0
+    }
 }
 
 fn add_with_carry_and_update(cpu: &mut CPU, mem_val: u8, carry: u8) {
@@ -5,18 +5,22 @@ mod cpu;
 mod instruction;
 mod operation;
 mod status_flag;
+mod tracer;
+mod variant;
 
 #[cfg(test)]
 pub mod tests;
 
-pub use crate::cpu::instruction::from_opcode;
+pub use crate::cpu::instruction::{decode, is_unofficial_opcode};
 pub use addressing_mode::AddressingMode;
-pub use controller::Controller;
+pub use controller::{ButtonSource, ControllerPorts};
 pub use core_memory::CoreMemory;
 pub use core_memory::MemoryListener;
-pub use cpu::CPU;
+pub use cpu::{CycleListener, UnstableOpcodePolicy, CPU};
 pub use instruction::RealizedInstruction;
 pub use status_flag::StatusFlag;
+pub use tracer::Tracer;
+pub use variant::CpuVariant;
 pub const MEMORY_SIZE: usize = 1 << 11; /* 2kB onboard RAM */
 
 const NMI_HANDLER_LOCATION: u16 = 0xfffa;
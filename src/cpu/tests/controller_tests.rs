@@ -1,20 +1,16 @@
-use crate::cpu::{tests, Controller, MemoryListener};
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
-use tao::keyboard::Key;
+use crate::cpu::controller::{Controller, CONTROLLER_2_ADDRESS, CONTROLLER_ADDRESS};
+use crate::cpu::{tests, ButtonSource, ControllerPorts, CoreMemory, MemoryListener, CPU};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
 
 #[test]
 fn test_controller() {
-    let mut controller = Controller::new();
-    let key_source = Arc::new(Mutex::new(HashSet::new()));
+    let mut controller = Controller::new(0);
+    let key_source = ButtonSource::default();
     controller.set_key_source(key_source.clone());
-    {
-        let mut unwrapped_key_source = key_source.lock().unwrap();
-        unwrapped_key_source.insert(Key::ArrowUp); // up press
-        unwrapped_key_source.insert(Key::Tab); // select press
-        unwrapped_key_source.insert(Key::Character("z")); // B press
-        unwrapped_key_source.insert(Key::Character("t")); // no effect
-    }
+    key_source[0].store(0b0001_0110, Ordering::Relaxed); // up, select and B press
+    key_source[1].store(0b1111_1111, Ordering::Relaxed); // the other controller: no effect
     controller.record_data();
     /* make sure we got the write output */
     assert_eq!(controller.get_next_byte(), 0); // A off
@@ -29,30 +25,184 @@ fn test_controller() {
     assert_eq!(controller.get_next_byte(), 1); // will output 1 indefinitely
     assert_eq!(controller.get_next_byte(), 1); // will output 1 indefinitely
 
-    /* as a memory listener, Controller only listens to one address but will respond
-     * to any address--this should be firmed up, to allow for two controllers
-     */
-    key_source.lock().unwrap().clear(); // clear out keys except start
-    key_source
-        .lock()
-        .unwrap()
-        .insert(Key::Enter); // start press
+    /* through the ports, controller 1 is read from 0x4016 */
+    let mut ports = ControllerPorts::new();
+    ports.set_key_source(key_source.clone());
+    key_source[0].store(0b0000_1000, Ordering::Relaxed); // only start pressed
+    let memory = tests::memory_for_testing();
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 1); // always returns 1 now
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 1); // always returns 1 now
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 1); // always returns 1 now
+    ports.write(&memory, CONTROLLER_ADDRESS, 1);
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 1); // STILL always returns 1 now
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 1); // always returns 1 now
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 1); // always returns 1 now
+    ports.write(&memory, CONTROLLER_ADDRESS, 0); // after this write, we can read!
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 0); // A off
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 0); // B off
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 0); // select off
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 1); // start on
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 0); // directions off
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 0); // directions off
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 0); // directions off
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 0); // directions off
+    assert_eq!(ports.read(&memory, CONTROLLER_ADDRESS), 1); // always returns 1 now
+}
+
+/* reads all eight buttons from a port, A first */
+fn read_buttons(ports: &mut ControllerPorts, address: u16) -> Vec<u8> {
     let memory = tests::memory_for_testing();
-    assert_eq!(controller.read(&memory, 0x1234), 1); // always returns 1 now
-    assert_eq!(controller.read(&memory, 0x1234), 1); // always returns 1 now
-    assert_eq!(controller.read(&memory, 0x1234), 1); // always returns 1 now
-    controller.write(&memory, 0x1234, 1);
-    assert_eq!(controller.read(&memory, 0x1234), 1); // STILL always returns 1 now
-    assert_eq!(controller.read(&memory, 0x1234), 1); // always returns 1 now
-    assert_eq!(controller.read(&memory, 0x1234), 1); // always returns 1 now
-    controller.write(&memory, 0x1234, 0); // after this write, we can read!
-    assert_eq!(controller.read(&memory, 0x1234), 0); // A off
-    assert_eq!(controller.read(&memory, 0x1234), 0); // B off
-    assert_eq!(controller.read(&memory, 0x1234), 0); // select off
-    assert_eq!(controller.read(&memory, 0x1234), 1); // start on
-    assert_eq!(controller.read(&memory, 0x1234), 0); // directions off
-    assert_eq!(controller.read(&memory, 0x1234), 0); // directions off
-    assert_eq!(controller.read(&memory, 0x1234), 0); // directions off
-    assert_eq!(controller.read(&memory, 0x1234), 0); // directions off
-    assert_eq!(controller.read(&memory, 0x1234), 1); // always returns 1 now
+    (0..8).map(|_| ports.read(&memory, address)).collect()
+}
+
+fn strobe(ports: &mut ControllerPorts) {
+    let memory = tests::memory_for_testing();
+    ports.write(&memory, CONTROLLER_ADDRESS, 1);
+    ports.write(&memory, CONTROLLER_ADDRESS, 0);
+}
+
+#[test]
+fn second_controller_has_its_own_buttons() {
+    let mut ports = ControllerPorts::new();
+    let key_source = ButtonSource::default();
+    ports.set_key_source(key_source.clone());
+    key_source[0].store(0b0000_0001, Ordering::Relaxed); // player 1 A
+    key_source[1].store(0b1000_1000, Ordering::Relaxed); // player 2 start and right
+    strobe(&mut ports);
+
+    assert_eq!(
+        read_buttons(&mut ports, CONTROLLER_ADDRESS),
+        vec![1, 0, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        read_buttons(&mut ports, CONTROLLER_2_ADDRESS),
+        vec![0, 0, 0, 1, 0, 0, 0, 1]
+    );
+}
+
+#[test]
+fn gamepad_buttons_join_the_keyboard() {
+    let mut ports = ControllerPorts::new();
+    let key_source = ButtonSource::default();
+    let pads = ButtonSource::default();
+    ports.set_key_source(key_source.clone());
+    ports.set_pad_source(pads.clone());
+    key_source[0].store(0b0000_0001, Ordering::Relaxed); // player 1 A on the keyboard
+    pads[0].store(0b0000_0011, Ordering::Relaxed); // player 1 A and B on the pad
+    pads[1].store(0b1000_0000, Ordering::Relaxed); // player 2 right on the pad
+    strobe(&mut ports);
+
+    assert_eq!(
+        read_buttons(&mut ports, CONTROLLER_ADDRESS),
+        vec![1, 1, 0, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        read_buttons(&mut ports, CONTROLLER_2_ADDRESS),
+        vec![0, 0, 0, 0, 0, 0, 0, 1]
+    );
+}
+
+#[test]
+fn one_strobe_latches_both_controllers() {
+    let mut ports = ControllerPorts::new();
+    let key_source = ButtonSource::default();
+    ports.set_key_source(key_source.clone());
+    key_source[1].store(0b0000_0010, Ordering::Relaxed); // player 2 B
+    strobe(&mut ports);
+
+    /* pressing more buttons after the strobe doesn't change what's latched */
+    key_source[1].store(0b0000_0011, Ordering::Relaxed);
+    assert_eq!(
+        read_buttons(&mut ports, CONTROLLER_2_ADDRESS),
+        vec![0, 1, 0, 0, 0, 0, 0, 0]
+    );
+    /* each port shifts out independently */
+    assert_eq!(read_buttons(&mut ports, CONTROLLER_ADDRESS), vec![0; 8]);
+    assert_eq!(
+        ports.read(&tests::memory_for_testing(), CONTROLLER_2_ADDRESS),
+        1
+    );
+}
+
+#[test]
+fn scripted_buttons_replace_the_live_ones() {
+    let mut ports = ControllerPorts::new();
+    let key_source = ButtonSource::default();
+    ports.set_key_source(key_source.clone());
+    key_source[0].store(0b0000_0001, Ordering::Relaxed); // player 1 A
+    ports.set_scripted_buttons(Some([0b0000_1000, 0b1000_0000])); // player 1 start, player 2 right
+    strobe(&mut ports);
+
+    assert_eq!(
+        read_buttons(&mut ports, CONTROLLER_ADDRESS),
+        vec![0, 0, 0, 1, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        read_buttons(&mut ports, CONTROLLER_2_ADDRESS),
+        vec![0, 0, 0, 0, 0, 0, 0, 1]
+    );
+
+    ports.set_scripted_buttons(None);
+    strobe(&mut ports);
+    assert_eq!(
+        read_buttons(&mut ports, CONTROLLER_ADDRESS),
+        vec![1, 0, 0, 0, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn frame_buttons_are_the_last_latched() {
+    let mut ports = ControllerPorts::new();
+    let key_source = ButtonSource::default();
+    ports.set_key_source(key_source.clone());
+    key_source[1].store(0b0000_0010, Ordering::Relaxed); // player 2 B
+    strobe(&mut ports);
+    key_source[1].store(0, Ordering::Relaxed);
+
+    assert_eq!(ports.take_frame_buttons(), [0, 0b0000_0010]);
+    /* nothing latched since, so it's whatever is held now */
+    key_source[0].store(0b1000_0000, Ordering::Relaxed); // player 1 right
+    assert_eq!(ports.take_frame_buttons(), [0b1000_0000, 0]);
+}
+
+/* stands in for the APU, which takes writes to 0x4017 but not reads */
+struct FrameCounterWrites {
+    values: Vec<u8>,
+}
+
+impl MemoryListener for FrameCounterWrites {
+    fn get_addresses(&self) -> Vec<u16> {
+        vec![CONTROLLER_2_ADDRESS]
+    }
+
+    fn get_read_addresses(&self) -> Vec<u16> {
+        vec![]
+    }
+
+    fn read(&mut self, _memory: &CoreMemory, _address: u16) -> u8 {
+        unreachable!()
+    }
+
+    fn write(&mut self, _memory: &CoreMemory, _address: u16, value: u8) {
+        self.values.push(value);
+    }
+}
+
+#[test]
+fn cpu_reads_4017_from_second_controller_and_writes_it_elsewhere() {
+    let mut memory = tests::memory_for_testing();
+    let frame_counter = Rc::new(RefCell::new(FrameCounterWrites { values: vec![] }));
+    memory.register_listener(frame_counter.clone());
+    let mut cpu = CPU::new(Box::new(memory));
+    let key_source = ButtonSource::default();
+    cpu.set_key_source(key_source.clone());
+    key_source[1].store(0b0000_0001, Ordering::Relaxed); // player 2 A
+
+    cpu.write_mem(CONTROLLER_ADDRESS, 1);
+    cpu.write_mem(CONTROLLER_2_ADDRESS, 0); // not a strobe
+    cpu.write_mem(CONTROLLER_ADDRESS, 0);
+    assert_eq!(cpu.read_mem(CONTROLLER_2_ADDRESS), 1);
+    assert_eq!(cpu.read_mem(CONTROLLER_2_ADDRESS), 0);
+    assert_eq!(cpu.read_mem(CONTROLLER_ADDRESS), 0);
+    assert_eq!(frame_counter.borrow().values, vec![0]);
 }
@@ -1,14 +1,12 @@
 use crate::cpu::controller::CONTROLLER_ADDRESS;
 use crate::cpu::tests::{cpu_for_testing, memory_for_testing, NoOpMemoryListener};
-use crate::cpu::CPU;
+use crate::cpu::{ButtonSource, CPU};
 use crate::ppu::PPURegister;
-use crate::ppu::PPURegister::OAMDMA;
+use crate::ppu::PPURegister::{OAMDATA, OAMDMA};
 use crate::processor::Processor;
 use std::cell::RefCell;
-use std::collections::HashSet;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
-use tao::keyboard::Key;
+use std::sync::atomic::Ordering;
 
 #[test]
 fn test_cpu() {
@@ -41,7 +39,7 @@ fn test_cpu() {
     cpu.write_mem(0x9002, 0x03); // LSR absolute address to change hi
     cpu.write_mem(0x0305, 0b0011_0011);
     let cycles = cpu.transition();
-    assert_eq!(cycles, 6); // NOP takes two cycles, LSR Absolute takes 6
+    assert_eq!(cycles, 7 + 6); // taking the NMI takes 7 cycles, then LSR Absolute takes 6
     assert_eq!(cpu.read_mem(0x0305), 0b0001_1001); // LSRed this value
     assert_eq!(cpu.program_counter, 0x9003); // next instruction in hypothetical NMI handler
     assert_eq!(cpu.s_register, 0x4d); // pushed 3 values onto stack
@@ -58,14 +56,10 @@ fn test_cpu() {
 fn test_set_key_source() {
     let mut cpu = cpu_for_testing();
 
-    let key_source = Arc::new(Mutex::new(HashSet::new()));
+    let key_source = ButtonSource::default();
     cpu.set_key_source(key_source.clone());
 
-    {
-        let mut unwrapped_key_source = key_source.lock().unwrap();
-        unwrapped_key_source.insert(Key::Enter); // start
-        unwrapped_key_source.insert(Key::Character("z")); // B
-    }
+    key_source[0].store(0b0000_1010, Ordering::Relaxed); // start and B
     cpu.write_mem(CONTROLLER_ADDRESS, 0x01);
     cpu.write_mem(CONTROLLER_ADDRESS, 0x00);
     assert_eq!(cpu.read_mem(CONTROLLER_ADDRESS), 0); // A
@@ -88,13 +82,20 @@ fn test_oamdma() {
 
     let mut memory = memory_for_testing();
     memory.register_listener(Rc::new(RefCell::new(NoOpMemoryListener::new(addr))));
+    memory.register_listener(Rc::new(RefCell::new(NoOpMemoryListener::new(
+        PPURegister::address(&OAMDATA),
+    ))));
 
     let mut cpu = CPU::new(Box::new(memory));
 
     cpu.write_mem(addr, 0x10);
     cpu.program_counter = 0xfff0;
     cpu.write_mem(0xfff0, 0xe8); // perform the two cycle INX instruction
-    assert_eq!(cpu.transition(), 2 + 513); // 513 extra cycles for OAMDMA
+    let start = cpu.cycles();
+    let cycles = cpu.transition() as u64;
+    /* 513 extra cycles for OAMDMA, plus one if it has to wait for a read cycle */
+    let alignment = (start + 2 + 1) % 2;
+    assert_eq!(cycles, 2 + 513 + alignment);
 }
 
 /* trying to save without a mapper should have no effect */
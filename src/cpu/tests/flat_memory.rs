@@ -0,0 +1,79 @@
+use crate::mapper::Mapper;
+use crate::ppu::NametableMirroring;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+use std::sync::{Arc, Mutex};
+
+/* a single read or write the CPU made, in the order it made them */
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BusAccess {
+    Read(u16),
+    Write(u16, u8),
+}
+
+pub type BusLog = Arc<Mutex<Vec<BusAccess>>>;
+
+/**
+ * 64kB of plain RAM for CoreMemory::new_flat, so the CPU can run programs written for a bare
+ * 6502 rather than a NES.
+ */
+pub struct FlatMemory {
+    memory: Box<[u8; 0x10000]>,
+    log: Option<BusLog>,
+}
+
+impl FlatMemory {
+    /* copies image into memory starting at load_address */
+    pub fn new(image: &[u8], load_address: u16) -> Self {
+        let mut memory = Box::new([0; 0x10000]);
+        let start = load_address as usize;
+        memory[start..start + image.len()].copy_from_slice(image);
+        FlatMemory { memory, log: None }
+    }
+
+    /* records every access the CPU makes in log */
+    pub fn with_log(image: &[u8], load_address: u16, log: BusLog) -> Self {
+        FlatMemory {
+            log: Some(log),
+            ..FlatMemory::new(image, load_address)
+        }
+    }
+}
+
+impl Mapper for FlatMemory {
+    fn read_prg(&self, address: u16) -> u8 {
+        if let Some(log) = &self.log {
+            log.lock().unwrap().push(BusAccess::Read(address));
+        }
+        self.memory[address as usize]
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if let Some(log) = &self.log {
+            log.lock().unwrap().push(BusAccess::Write(address, value));
+        }
+        self.memory[address as usize] = value;
+    }
+
+    fn read_chr(&self, _address: u16) -> u8 {
+        panic!("should never be called")
+    }
+
+    fn write_chr(&mut self, _address: u16, _value: u8) {
+        panic!("should never be called")
+    }
+
+    fn get_nametable_mirroring(&self) -> NametableMirroring {
+        panic!("should never be called")
+    }
+}
+
+impl Savestate for FlatMemory {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&*self.memory);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut *self.memory)
+    }
+}
@@ -1,9 +1,10 @@
+use crate::cpu::instruction::from_opcode;
 use crate::cpu::instruction::Instruction;
 use crate::cpu::instruction::Instruction::*;
 use crate::cpu::tests::cpu_for_testing;
 use crate::cpu::AddressingMode::*;
 use crate::cpu::StatusFlag::*;
-use crate::cpu::{from_opcode, AddressingMode, CPU};
+use crate::cpu::{is_unofficial_opcode, AddressingMode, UnstableOpcodePolicy, CPU};
 
 #[test]
 fn test_instructions() {
@@ -76,7 +77,7 @@ fn test_instructions() {
     assert_eq!(cpu.accumulator, 0b10100000);
     /* TODO clean up flag access */
     assert_eq!(cpu.status & (1 << 7) != 0, true); /* result is negative */
-    assert_eq!(cpu.status & (1 << 2) != 0, false); /* result is not zero */
+    assert!(cpu.status & (1 << 1) == 0); /* result is not zero */
     AND.apply(cpu, &Immediate, 0b00001111, 0x0);
     assert_eq!(cpu.status & (1 << 7) != 0, false); /* result is not negative */
     assert_eq!(cpu.status & (1 << 1) != 0, true); /* result is zero */
@@ -88,7 +89,7 @@ fn test_instructions() {
     /* TODO clean up flags */
     assert_eq!(cpu.status & 1 != 0, false); // no carry
     assert_eq!(cpu.status & 2 != 0, false); // result is not zero
-    assert_eq!(cpu.status & 7 != 0, false); // result not negative
+    assert!(cpu.status & (1 << 7) == 0); // result not negative
     ASL.apply(cpu, &Accumulator, 0xab, 0xcd);
     assert_eq!(cpu.accumulator, 0b11110000);
     assert_eq!(cpu.status & 1 != 0, false); // no carry
@@ -987,10 +988,243 @@ fn test_opcode(
 }
 
 #[test]
-#[should_panic(expected = "Unknown opcode 0x2")]
-fn test_unknown_opcode_causes_panic() {
-    /* will have to update this when unofficial opcodes are finished */
-    from_opcode(0x02);
+fn test_unofficial_opcodes() {
+    /* read-modify-write combinations all share the same modes and timing */
+    for (instruction, opcodes) in [
+        (SLO, [0x07, 0x17, 0x0f, 0x1f, 0x1b, 0x03, 0x13]),
+        (RLA, [0x27, 0x37, 0x2f, 0x3f, 0x3b, 0x23, 0x33]),
+        (SRE, [0x47, 0x57, 0x4f, 0x5f, 0x5b, 0x43, 0x53]),
+        (RRA, [0x67, 0x77, 0x6f, 0x7f, 0x7b, 0x63, 0x73]),
+        (DCP, [0xc7, 0xd7, 0xcf, 0xdf, 0xdb, 0xc3, 0xd3]),
+        (ISC, [0xe7, 0xf7, 0xef, 0xff, 0xfb, 0xe3, 0xf3]),
+    ] {
+        test_opcode(opcodes[0], instruction.clone(), ZeroPage, 5);
+        test_opcode(opcodes[1], instruction.clone(), ZeroPageX, 6);
+        test_opcode(opcodes[2], instruction.clone(), Absolute, 6);
+        test_opcode(opcodes[3], instruction.clone(), AbsoluteX, 7);
+        test_opcode(opcodes[4], instruction.clone(), AbsoluteY, 7);
+        test_opcode(opcodes[5], instruction.clone(), IndirectX, 8);
+        test_opcode(opcodes[6], instruction, IndirectY, 8);
+    }
+
+    /* LAX */
+    test_opcode(0xa7, LAX, ZeroPage, 3);
+    test_opcode(0xb7, LAX, ZeroPageY, 4);
+    test_opcode(0xaf, LAX, Absolute, 4);
+    test_opcode(0xbf, LAX, AbsoluteY, 4);
+    test_opcode(0xa3, LAX, IndirectX, 6);
+    test_opcode(0xb3, LAX, IndirectY, 5);
+
+    /* SAX */
+    test_opcode(0x87, SAX, ZeroPage, 3);
+    test_opcode(0x97, SAX, ZeroPageY, 4);
+    test_opcode(0x8f, SAX, Absolute, 4);
+    test_opcode(0x83, SAX, IndirectX, 6);
+
+    /* immediate combinations */
+    test_opcode(0x0b, ANC, Immediate, 2);
+    test_opcode(0x2b, ANC, Immediate, 2);
+    test_opcode(0x4b, ALR, Immediate, 2);
+    test_opcode(0x6b, ARR, Immediate, 2);
+    test_opcode(0xcb, AXS, Immediate, 2);
+    test_opcode(0xeb, SBC, Immediate, 2);
+    test_opcode(0xbb, LAS, AbsoluteY, 4);
+
+    /* NOPs that read (SKB/IGN) */
+    test_opcode(0x80, NOP, Immediate, 2);
+    test_opcode(0x04, NOP, ZeroPage, 3);
+    test_opcode(0x14, NOP, ZeroPageX, 4);
+    test_opcode(0x0c, NOP, Absolute, 4);
+    test_opcode(0x1c, NOP, AbsoluteX, 4);
+
+    /* unstable */
+    test_opcode(0x8b, XAA, Immediate, 2);
+    test_opcode(0xab, LXA, Immediate, 2);
+    test_opcode(0x93, AHX, IndirectY, 6);
+    test_opcode(0x9f, AHX, AbsoluteY, 5);
+    test_opcode(0x9b, TAS, AbsoluteY, 5);
+    test_opcode(0x9c, SHY, AbsoluteX, 5);
+    test_opcode(0x9e, SHX, AbsoluteY, 5);
+    for opcode in [
+        0x02, 0x12, 0x22, 0x32, 0x42, 0x52, 0x62, 0x72, 0x92, 0xb2, 0xd2, 0xf2,
+    ] {
+        test_opcode(opcode, KIL, Implicit, 2);
+    }
+}
+
+#[test]
+fn every_opcode_decodes() {
+    let unofficial = (0..=0xffu8).filter(|op| is_unofficial_opcode(*op)).count();
+    /* 151 documented opcodes */
+    assert_eq!(unofficial, 256 - 151);
+    assert!(is_unofficial_opcode(0xeb));
+    assert!(!is_unofficial_opcode(0xe9));
+    assert!(!is_unofficial_opcode(0xea));
+}
+
+#[test]
+fn test_unofficial_instructions() {
+    let cpu = &mut cpu_for_testing();
+
+    /* LAX loads both A and X */
+    cpu.write_mem(0x10, 0x83);
+    LAX.apply(cpu, &ZeroPage, 0x10, 0);
+    assert_eq!(cpu.accumulator, 0x83);
+    assert_eq!(cpu.index_x, 0x83);
+    assert!(Negative.is_set(cpu));
+
+    /* SAX stores A & X without touching flags */
+    cpu.accumulator = 0xf0;
+    cpu.index_x = 0x3c;
+    SAX.apply(cpu, &ZeroPage, 0x11, 0);
+    assert_eq!(cpu.read_mem(0x11), 0x30);
+    assert!(Negative.is_set(cpu));
+
+    /* DCP decrements, then compares with A */
+    cpu.accumulator = 0x40;
+    cpu.write_mem(0x12, 0x41);
+    DCP.apply(cpu, &ZeroPage, 0x12, 0);
+    assert_eq!(cpu.read_mem(0x12), 0x40);
+    assert!(Zero.is_set(cpu));
+    assert!(Carry.is_set(cpu));
+
+    /* ISC increments, then subtracts from A (carry set: no borrow) */
+    cpu.accumulator = 0x10;
+    cpu.write_mem(0x13, 0x04);
+    ISC.apply(cpu, &ZeroPage, 0x13, 0);
+    assert_eq!(cpu.read_mem(0x13), 0x05);
+    assert_eq!(cpu.accumulator, 0x0b);
+    assert!(Carry.is_set(cpu));
+
+    /* SLO shifts left into carry, then ORs into A */
+    cpu.accumulator = 0x01;
+    cpu.write_mem(0x14, 0x81);
+    SLO.apply(cpu, &ZeroPage, 0x14, 0);
+    assert_eq!(cpu.read_mem(0x14), 0x02);
+    assert_eq!(cpu.accumulator, 0x03);
+    assert!(Carry.is_set(cpu));
+
+    /* RLA rotates left through carry, then ANDs into A */
+    cpu.accumulator = 0xff;
+    cpu.write_mem(0x15, 0x40);
+    RLA.apply(cpu, &ZeroPage, 0x15, 0);
+    assert_eq!(cpu.read_mem(0x15), 0x81);
+    assert_eq!(cpu.accumulator, 0x81);
+    assert!(!Carry.is_set(cpu));
+
+    /* SRE shifts right into carry, then EORs into A */
+    cpu.accumulator = 0x0f;
+    cpu.write_mem(0x16, 0x03);
+    SRE.apply(cpu, &ZeroPage, 0x16, 0);
+    assert_eq!(cpu.read_mem(0x16), 0x01);
+    assert_eq!(cpu.accumulator, 0x0e);
+    assert!(Carry.is_set(cpu));
+
+    /* RRA rotates right through carry, then adds with the carry it shifted out */
+    cpu.accumulator = 0x10;
+    cpu.write_mem(0x17, 0x03);
+    RRA.apply(cpu, &ZeroPage, 0x17, 0);
+    assert_eq!(cpu.read_mem(0x17), 0x81);
+    assert_eq!(cpu.accumulator, 0x92);
+    assert!(!Carry.is_set(cpu));
+
+    /* ANC copies the sign into carry */
+    cpu.accumulator = 0xff;
+    ANC.apply(cpu, &Immediate, 0x80, 0);
+    assert_eq!(cpu.accumulator, 0x80);
+    assert!(Carry.is_set(cpu));
+
+    /* ALR is AND then LSR */
+    cpu.accumulator = 0xff;
+    ALR.apply(cpu, &Immediate, 0x03, 0);
+    assert_eq!(cpu.accumulator, 0x01);
+    assert!(Carry.is_set(cpu));
+
+    /* ARR is AND then ROR, with carry from bit 6 and overflow from bit 6 ^ bit 5 */
+    cpu.accumulator = 0xff;
+    cpu.status |= Carry.mask();
+    ARR.apply(cpu, &Immediate, 0x80, 0);
+    assert_eq!(cpu.accumulator, 0xc0);
+    assert!(Carry.is_set(cpu));
+    assert!(Overflow.is_set(cpu));
+
+    /* AXS subtracts from A & X without borrow */
+    cpu.accumulator = 0x0f;
+    cpu.index_x = 0x07;
+    AXS.apply(cpu, &Immediate, 0x08, 0);
+    assert_eq!(cpu.index_x, 0xff);
+    assert!(!Carry.is_set(cpu));
+
+    /* LAS loads memory & S into A, X, and S */
+    cpu.s_register = 0xf3;
+    cpu.index_y = 0;
+    cpu.write_mem(0x0300, 0x5f);
+    LAS.apply(cpu, &AbsoluteY, 0x00, 0x03);
+    assert_eq!(cpu.accumulator, 0x53);
+    assert_eq!(cpu.index_x, 0x53);
+    assert_eq!(cpu.s_register, 0x53);
+
+    /* SHX stores X & (high byte + 1) */
+    cpu.index_x = 0xff;
+    cpu.index_y = 0x01;
+    SHX.apply(cpu, &AbsoluteY, 0x00, 0x02);
+    assert_eq!(cpu.read_mem(0x0201), 0x03);
+}
+
+#[test]
+fn unofficial_nops_take_extra_cycle_on_page_cross() {
+    let cpu = &mut cpu_for_testing();
+
+    cpu.index_x = 0x01;
+    assert_eq!(NOP.apply(cpu, &AbsoluteX, 0x00, 0x02), 0);
+    assert_eq!(NOP.apply(cpu, &AbsoluteX, 0xff, 0x02), 1);
+}
+
+#[test]
+fn kil_jams_the_cpu_until_reset() {
+    let cpu = &mut cpu_for_testing();
+    cpu.write_mem(0xfffc, 0x00);
+    cpu.write_mem(0xfffd, 0x80);
+    cpu.write_mem(0x8000, 0x02); /* KIL */
+    cpu.program_counter = 0x8000;
+
+    cpu.transition();
+    cpu.set_nmi(true);
+    assert_eq!(cpu.transition(), 1);
+    assert_eq!(cpu.program_counter, 0x8000);
+    assert!(cpu.nmi_set()); /* not even NMI gets through */
+
+    cpu.write_mem(0x8000, 0xe8); /* INX */
+    cpu.reset();
+    cpu.index_x = 0;
+    cpu.transition();
+    assert_eq!(cpu.index_x, 1);
+}
+
+#[test]
+fn unstable_opcode_policy_decides_whether_they_run() {
+    let cpu = &mut cpu_for_testing();
+    cpu.write_mem(0x8000, 0x8b); /* XAA #$ff */
+    cpu.write_mem(0x8001, 0xff);
+
+    cpu.accumulator = 0x00;
+    cpu.index_x = 0x0f;
+    cpu.program_counter = 0x8000;
+    cpu.set_unstable_opcode_policy(UnstableOpcodePolicy::Log);
+    cpu.transition();
+    assert_eq!(cpu.accumulator, 0x0e);
+    assert_eq!(cpu.program_counter, 0x8002);
+
+    cpu.accumulator = 0x00;
+    cpu.program_counter = 0x8000;
+    cpu.set_unstable_opcode_policy(UnstableOpcodePolicy::Halt);
+    cpu.transition();
+    assert_eq!(cpu.accumulator, 0x00);
+    assert_eq!(cpu.program_counter, 0x8000);
+    assert_eq!(cpu.transition(), 1);
+
+    assert_eq!("halt".parse(), Ok(UnstableOpcodePolicy::Halt));
+    assert!("ignore".parse::<UnstableOpcodePolicy>().is_err());
 }
 
 #[test]
@@ -1011,9 +1245,9 @@ fn realized_jump_instructions_dont_increment_pc() {
     cpu.program_counter = 0;
     cpu.s_register = 0x50;
     cpu.write_mem(0x0151, 0x22);
-    cpu.write_mem(0x0152, 0x33);
+    cpu.write_mem(0x0152, 0x83);
     realized_rts.apply(cpu, 0xff /* unused */, 0xff /* unused */);
-    assert_eq!(cpu.program_counter, 0x3323);
+    assert_eq!(cpu.program_counter, 0x8323);
 
     let realized_rti = from_opcode(0x40);
     cpu.program_counter = 0;
@@ -1,6 +1,6 @@
 use crate::cpu::tests::{memory_for_testing, NoOpMemoryListener};
 use crate::cpu::{CoreMemory, MemoryListener};
-use crate::rom::Rom;
+use crate::rom::{Rom, RomHeader};
 use std::cell::RefCell;
 use std::rc::Rc;
 
@@ -47,35 +47,10 @@ fn test_memory_listener() {
     assert_eq!(memory.read(0x2000), 0x64);
 }
 
-#[test]
-fn test_copy_from_slice() {
-    let mut memory = memory_for_testing();
-
-    for i in 0x100..0x200 {
-        memory.write(i, (i % 0x100) as u8);
-    }
-
-    let mut destination: [u8; 0x100] = [0; 0x100];
-    memory.copy_slice(0x0100, 0x0100, &mut destination);
-
-    for i in 0x00..=0xff {
-        assert_eq!(destination[i as usize], i);
-    }
-
-    /* test we can do this in the mapper, too */
-    for i in 0x8000..0x8100 {
-        memory.write(i, (i % 0x100) as u8 - 5);
-    }
-    memory.copy_slice(0x8000, 0x0100, &mut destination);
-    for i in 0x00..=0xff {
-        assert_eq!(destination[i as usize], i - 5);
-    }
-}
-
 #[test]
 fn test_memory_from_rom() {
     let rom = basic_test_rom();
-    let memory = CoreMemory::new(&rom);
+    let memory = CoreMemory::new(&rom).unwrap();
     /* read PRG data */
     assert_eq!(memory.read(0xffff), 0x12);
     assert_eq!(memory.read(0xc000), 0x12);
@@ -86,7 +61,7 @@ fn test_memory_from_rom() {
 #[should_panic]
 fn test_memory_from_rom_no_prg_ram() {
     let rom = basic_test_rom();
-    let memory = CoreMemory::new(&rom);
+    let memory = CoreMemory::new(&rom).unwrap();
     /* attempt to read PRG-RAM */
     memory.read(0x7fff);
 }
@@ -102,13 +77,10 @@ fn basic_test_rom() -> Rom {
         chr_data.push(0x34);
     }
     Rom {
+        header: RomHeader::default(), /* NROM */
         prg_data,
         chr_data,
-        byte_6_flags: 0, /* NROM */
-        byte_7_flags: 0,
         _trainer: vec![],
-        _prg_ram: vec![],
-        _tv_system: 0,
     }
 }
 
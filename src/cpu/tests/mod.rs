@@ -4,9 +4,13 @@ use crate::cpu::{CoreMemory, MemoryListener, CPU};
 mod addressing_mode_tests;
 mod controller_tests;
 mod cpu_tests;
+pub mod flat_memory;
 mod instruction_tests;
 mod memory_tests;
 pub mod test_mapper;
+mod tracer_tests;
+mod timing_tests;
+mod variant_tests;
 
 fn memory_for_testing() -> CoreMemory {
     CoreMemory::new_from_mapper(Box::new(TestMapper::new()))
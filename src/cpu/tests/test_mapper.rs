@@ -1,11 +1,19 @@
 use crate::mapper::Mapper;
 use crate::ppu::NametableMirroring;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
 
 pub struct TestMapper {
     memory: Box<[u8; 0x8000]>,
     save_data: Option<Vec<u8>>,
 }
 
+impl Default for TestMapper {
+    fn default() -> Self {
+        TestMapper::new()
+    }
+}
+
 impl TestMapper {
     pub fn new() -> Self {
         TestMapper { memory: Box::new([0; 0x8000]), save_data: None }
@@ -28,11 +36,6 @@ impl Mapper for TestMapper {
         self.memory[Self::map_address(address)]
     }
 
-    fn read_prg_slice(&self, address: u16, _size: usize) -> &[u8] {
-        let map_address = Self::map_address(address);
-        &self.memory[map_address..map_address + _size]
-    }
-
     fn write_prg(&mut self, address: u16, value: u8) {
         if address >= 0x8000 {
             self.memory[Self::map_address(address)] = value;
@@ -57,3 +60,13 @@ impl Mapper for TestMapper {
         self.save_data.clone()
     }
 }
+
+impl Savestate for TestMapper {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&*self.memory);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut *self.memory)
+    }
+}
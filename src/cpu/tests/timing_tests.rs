@@ -0,0 +1,281 @@
+use crate::cpu::instruction::Instruction;
+use crate::cpu::tests::flat_memory::{BusAccess, BusAccess::*, BusLog, FlatMemory};
+use crate::cpu::{decode, CoreMemory, CpuVariant, CycleListener, StatusFlag, CPU};
+use std::sync::{Arc, Mutex};
+
+const PROGRAM_START: u16 = 0x0400;
+
+/* a CPU on flat memory that logs its bus accesses, with the log cleared after reset */
+fn logged_cpu(variant: CpuVariant, program: &[u8]) -> (Box<CPU>, BusLog) {
+    let mut image = [0; 0x10000];
+    image[PROGRAM_START as usize..PROGRAM_START as usize + program.len()].copy_from_slice(program);
+    image[0xfffc] = PROGRAM_START as u8;
+    image[0xfffd] = (PROGRAM_START >> 8) as u8;
+    let log: BusLog = Arc::new(Mutex::new(Vec::new()));
+    let mapper = FlatMemory::with_log(&image, 0, log.clone());
+    let cpu = CPU::with_variant(Box::new(CoreMemory::new_flat(Box::new(mapper))), variant);
+    log.lock().unwrap().clear();
+    (cpu, log)
+}
+
+fn accesses(log: &BusLog) -> Vec<BusAccess> {
+    log.lock().unwrap().clone()
+}
+
+#[test]
+fn every_opcode_touches_the_bus_once_per_cycle() {
+    for opcode in 0..=0xffu8 {
+        /* zero page and absolute operands point at $0010, which holds a pointer to $0200 */
+        let (mut cpu, log) = logged_cpu(CpuVariant::Ricoh2A03, &[opcode, 0x10, 0x00]);
+        cpu.write_mem(0x0010, 0x00);
+        cpu.write_mem(0x0011, 0x02);
+        log.lock().unwrap().clear();
+
+        let cycles = cpu.transition();
+        let realized = decode(CpuVariant::Ricoh2A03, opcode);
+        let branched = realized.addr_mode.get_bytes() == 2
+            && matches!(
+                realized.instruction,
+                Instruction::BCC
+                    | Instruction::BCS
+                    | Instruction::BEQ
+                    | Instruction::BMI
+                    | Instruction::BNE
+                    | Instruction::BPL
+                    | Instruction::BVC
+                    | Instruction::BVS
+            )
+            && cpu.program_counter != PROGRAM_START + 2;
+        let expected = realized.cycles + branched as u16;
+
+        assert_eq!(cycles, expected, "opcode {:02X}", opcode);
+        assert_eq!(
+            log.lock().unwrap().len(),
+            expected as usize,
+            "opcode {:02X}",
+            opcode
+        );
+    }
+}
+
+#[test]
+fn read_modify_write_writes_the_old_value_back() {
+    /* INC $10 */
+    let (mut cpu, log) = logged_cpu(CpuVariant::Ricoh2A03, &[0xe6, 0x10]);
+    cpu.write_mem(0x0010, 0x41);
+    log.lock().unwrap().clear();
+
+    cpu.transition();
+
+    assert_eq!(
+        accesses(&log),
+        vec![
+            Read(0x0400),
+            Read(0x0401),
+            Read(0x0010),
+            Write(0x0010, 0x41),
+            Write(0x0010, 0x42),
+        ]
+    );
+}
+
+#[test]
+fn cmos_read_modify_write_reads_twice() {
+    /* INC $10 */
+    let (mut cpu, log) = logged_cpu(CpuVariant::Wdc65C02, &[0xe6, 0x10]);
+    cpu.write_mem(0x0010, 0x41);
+    log.lock().unwrap().clear();
+
+    cpu.transition();
+
+    assert_eq!(
+        accesses(&log),
+        vec![
+            Read(0x0400),
+            Read(0x0401),
+            Read(0x0010),
+            Read(0x0010),
+            Write(0x0010, 0x42),
+        ]
+    );
+}
+
+#[test]
+fn indexed_read_across_a_page_reads_the_uncarried_address_first() {
+    /* LDA $02F0,X */
+    let (mut cpu, log) = logged_cpu(CpuVariant::Ricoh2A03, &[0xbd, 0xf0, 0x02]);
+    cpu.index_x = 0x20;
+
+    assert_eq!(cpu.transition(), 5);
+    assert_eq!(
+        accesses(&log),
+        vec![
+            Read(0x0400),
+            Read(0x0401),
+            Read(0x0402),
+            Read(0x0210),
+            Read(0x0310),
+        ]
+    );
+}
+
+#[test]
+fn indexed_read_within_a_page_reads_once() {
+    /* LDA $0280,X */
+    let (mut cpu, log) = logged_cpu(CpuVariant::Ricoh2A03, &[0xbd, 0x80, 0x02]);
+    cpu.index_x = 0x20;
+
+    assert_eq!(cpu.transition(), 4);
+    assert_eq!(
+        accesses(&log),
+        vec![Read(0x0400), Read(0x0401), Read(0x0402), Read(0x02a0)]
+    );
+}
+
+#[test]
+fn indexed_store_always_reads_before_writing() {
+    /* STA $0280,X */
+    let (mut cpu, log) = logged_cpu(CpuVariant::Ricoh2A03, &[0x9d, 0x80, 0x02]);
+    cpu.index_x = 0x20;
+    cpu.accumulator = 0x99;
+
+    assert_eq!(cpu.transition(), 5);
+    assert_eq!(
+        accesses(&log),
+        vec![
+            Read(0x0400),
+            Read(0x0401),
+            Read(0x0402),
+            Read(0x02a0),
+            Write(0x02a0, 0x99),
+        ]
+    );
+}
+
+#[test]
+fn zero_page_indexed_reads_the_base_address() {
+    /* LDA $F0,X wraps within the zero page */
+    let (mut cpu, log) = logged_cpu(CpuVariant::Ricoh2A03, &[0xb5, 0xf0]);
+    cpu.index_x = 0x20;
+
+    assert_eq!(cpu.transition(), 4);
+    assert_eq!(
+        accesses(&log),
+        vec![Read(0x0400), Read(0x0401), Read(0x00f0), Read(0x0010)]
+    );
+}
+
+#[test]
+fn cycle_counter_advances_with_every_access() {
+    /* NOP; LDA $10 */
+    let (mut cpu, log) = logged_cpu(CpuVariant::Ricoh2A03, &[0xea, 0xa5, 0x10]);
+    let start = cpu.cycles();
+
+    cpu.transition();
+    cpu.transition();
+
+    assert_eq!(cpu.cycles() - start, 5);
+    assert_eq!(log.lock().unwrap().len(), 5);
+}
+
+#[test]
+fn cmos_one_cycle_nops_only_fetch_the_opcode() {
+    /* the undefined 0x03, then 0x13 */
+    let (mut cpu, log) = logged_cpu(CpuVariant::Wdc65C02, &[0x03, 0x13]);
+
+    assert_eq!(cpu.transition(), 1);
+    assert_eq!(cpu.transition(), 1);
+
+    assert_eq!(accesses(&log), vec![Read(0x0400), Read(0x0401)]);
+    assert_eq!(cpu.program_counter, PROGRAM_START + 2);
+}
+
+/* where both interrupt vectors point; it holds a NOP */
+const HANDLER: u16 = 0x0300;
+
+/* raises NMI or IRQ during one cycle, as the PPU or APU would */
+struct RaiseInterrupt {
+    cycle: u64,
+    nmi: bool,
+}
+
+impl CycleListener for RaiseInterrupt {
+    fn cycle(&mut self, cpu: &mut CPU) {
+        if cpu.cycles() == self.cycle {
+            if self.nmi {
+                cpu.set_nmi(true);
+            } else {
+                cpu.set_apu_irq(true);
+            }
+        }
+    }
+}
+
+fn interruptible_cpu(program: &[u8]) -> Box<CPU> {
+    let (mut cpu, _) = logged_cpu(CpuVariant::Ricoh2A03, program);
+    cpu.write_mem(HANDLER, 0xea);
+    for vector in [0xfffa, 0xfffe] {
+        cpu.write_mem(vector, HANDLER as u8);
+        cpu.write_mem(vector + 1, (HANDLER >> 8) as u8);
+    }
+    cpu
+}
+
+/* after cpu_interrupts_v2's 1-cli_latency: CLI's change to I comes too late for its own poll */
+#[test]
+fn cli_lets_an_irq_in_only_after_the_next_instruction() {
+    /* CLI; NOP; NOP */
+    let mut cpu = interruptible_cpu(&[0x58, 0xea, 0xea]);
+    cpu.set_apu_irq(true);
+
+    cpu.transition();
+    cpu.transition();
+    assert_eq!(cpu.program_counter, PROGRAM_START + 2);
+    cpu.transition();
+    assert_eq!(cpu.program_counter, HANDLER + 1);
+}
+
+/* ...and SEI's comes too late to stop an IRQ raised during it, which pushes I set */
+#[test]
+fn sei_still_takes_an_irq_raised_during_it() {
+    /* CLI; NOP; SEI; NOP */
+    let mut cpu = interruptible_cpu(&[0x58, 0xea, 0x78, 0xea]);
+    cpu.transition();
+    cpu.transition();
+    let sei_start = cpu.cycles();
+    cpu.set_cycle_listener(Some(Box::new(RaiseInterrupt {
+        cycle: sei_start + 1,
+        nmi: false,
+    })));
+
+    cpu.transition();
+    cpu.transition();
+    assert_eq!(cpu.program_counter, HANDLER + 1);
+    let pushed_status = cpu.peek_mem(0x0100 + cpu.s_register as u16 + 1);
+    assert_ne!(pushed_status & StatusFlag::InterruptDisable.mask(), 0);
+}
+
+/* an NMI is only seen if it comes before an instruction's last cycle */
+#[test]
+fn nmi_raised_on_the_last_cycle_waits_for_the_next_instruction() {
+    /* NOP; NOP; NOP, and the NMI raised on either cycle of the first NOP */
+    for (raised_on, nops_first) in [(1, 1), (2, 2)] {
+        let mut cpu = interruptible_cpu(&[0xea, 0xea, 0xea]);
+        let start = cpu.cycles();
+        cpu.set_cycle_listener(Some(Box::new(RaiseInterrupt {
+            cycle: start + raised_on,
+            nmi: true,
+        })));
+
+        for _ in 0..nops_first {
+            cpu.transition();
+        }
+        assert_eq!(cpu.program_counter, PROGRAM_START + nops_first as u16);
+        cpu.transition();
+        assert_eq!(
+            cpu.program_counter,
+            HANDLER + 1,
+            "raised on cycle {raised_on}"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use crate::apu::APU;
+use crate::cpu::tests::cpu_for_testing;
+use crate::cpu::{CoreMemory, Tracer, CPU};
+use crate::ppu::ppu_listener::PPUListener;
+use crate::ppu::{PPU, WRITE_BUFFER_SIZE};
+use crate::rom::Rom;
+use std::cell::RefCell;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::{fs, io};
+
+/* collects trace output where the test can still get at it */
+#[derive(Clone)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedOutput {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.borrow().clone())
+            .unwrap()
+            .lines()
+            .map(|line| line.to_string())
+            .collect()
+    }
+}
+
+fn traced_cpu(cpu: &mut CPU) -> SharedOutput {
+    let output = SharedOutput(Rc::new(RefCell::new(Vec::new())));
+    cpu.set_tracer(Some(Tracer::new(Box::new(output.clone()))));
+    cpu.s_register = 0xfd;
+    cpu.status = 0x24;
+    output
+}
+
+#[test]
+fn trace_lines_match_nestest_format() {
+    let mut cpu = cpu_for_testing();
+    let output = traced_cpu(&mut cpu);
+
+    /* LDX #$05; LDA $10,X; JMP $9000 */
+    let program = [0xa2, 0x05, 0xb5, 0x10, 0x4c, 0x00, 0x90];
+    for (i, byte) in program.iter().enumerate() {
+        cpu.write_mem(0x8000 + i as u16, *byte);
+    }
+    /* JMP ($02FF) reads its high byte from $0200, not $0300 */
+    for (i, byte) in [0x6c, 0xff, 0x02].iter().enumerate() {
+        cpu.write_mem(0x9000 + i as u16, *byte);
+    }
+    cpu.write_mem(0x15, 0x42);
+    cpu.write_mem(0x02ff, 0x34);
+    cpu.write_mem(0x0200, 0x80);
+    cpu.write_mem(0x0300, 0x12);
+    cpu.program_counter = 0x8000;
+
+    for _ in 0..4 {
+        cpu.transition();
+    }
+
+    assert_eq!(
+        output.lines(),
+        vec![
+            "8000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0,  0 CYC:7",
+            "8002  B5 10     LDA $10,X @ 15 = 42             A:00 X:05 Y:00 P:24 SP:FD PPU:  0,  0 CYC:9",
+            "8004  4C 00 90  JMP $9000                       A:42 X:05 Y:00 P:24 SP:FD PPU:  0,  0 CYC:13",
+            "9000  6C FF 02  JMP ($02FF) = 8034              A:42 X:05 Y:00 P:24 SP:FD PPU:  0,  0 CYC:16",
+        ]
+    );
+    assert_eq!(cpu.program_counter, 0x8034);
+}
+
+#[test]
+fn tracing_does_not_trigger_io_side_effects() {
+    let mut cpu = cpu_for_testing();
+    let output = traced_cpu(&mut cpu);
+
+    /* LDA $4016 */
+    for (i, byte) in [0xad, 0x16, 0x40].iter().enumerate() {
+        cpu.write_mem(0x8000 + i as u16, *byte);
+    }
+    cpu.program_counter = 0x8000;
+    cpu.transition();
+
+    assert!(output.lines()[0].contains("LDA $4016 = FF "));
+}
+
+#[test]
+fn unofficial_opcodes_are_starred() {
+    let mut cpu = cpu_for_testing();
+    let output = traced_cpu(&mut cpu);
+
+    /* LAX $10; ISC $10 */
+    for (i, byte) in [0xa7, 0x10, 0xe7, 0x10].iter().enumerate() {
+        cpu.write_mem(0x8000 + i as u16, *byte);
+    }
+    cpu.program_counter = 0x8000;
+    cpu.transition();
+    cpu.transition();
+
+    let lines = output.lines();
+    assert!(lines[0].starts_with("8000  A7 10    *LAX $10 = 00 "));
+    assert!(lines[1].starts_with("8002  E7 10    *ISB $10 = 00 "));
+}
+
+/* strips the PPU column, which won't match until the CPU and PPU are cycle-accurate */
+fn without_ppu_column(line: &str) -> String {
+    match (line.find(" PPU:"), line.find(" CYC:")) {
+        (Some(start), Some(end)) => format!("{}{}", &line[..start], &line[end..]),
+        _ => line.to_string(),
+    }
+}
+
+/*
+ * Runs nestest in automation mode, starting from $C000 rather than the reset vector, and compares
+ * the trace against the reference log from Nintendulator. Neither file is distributed with
+ * Patina; put them in test_roms/ to run this with `cargo test -- --ignored`.
+ */
+#[test]
+#[ignore = "needs test_roms/nestest.nes and test_roms/nestest.log"]
+fn nestest_matches_reference_log() {
+    let test_roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_roms");
+    let rom = Rom::parse_file(test_roms.join("nestest.nes").to_string_lossy().to_string())
+        .expect("nestest.nes should be in test_roms/");
+    let reference = fs::read_to_string(test_roms.join("nestest.log"))
+        .expect("nestest.log should be in test_roms/");
+    let reference: Vec<&str> = reference.lines().collect();
+
+    let mut memory = Box::new(CoreMemory::new(&rom).unwrap());
+    let ppu = PPU::new(
+        Arc::new(Mutex::new([0; WRITE_BUFFER_SIZE])),
+        memory.mapper.clone(),
+    );
+    memory.register_listener(APU::silent());
+    memory.register_listener(Rc::new(RefCell::new(PPUListener::new(ppu))));
+    let mut cpu = CPU::new(memory);
+    let output = traced_cpu(&mut cpu);
+    cpu.program_counter = 0xc000;
+
+    for _ in 0..reference.len() {
+        cpu.transition();
+    }
+
+    for (i, (actual, expected)) in output.lines().iter().zip(&reference).enumerate() {
+        assert_eq!(
+            without_ppu_column(actual),
+            without_ppu_column(expected),
+            "trace differs from nestest.log at line {}",
+            i + 1
+        );
+    }
+    /* nestest leaves its error codes for official and unofficial opcodes in $02 and $03 */
+    assert_eq!(cpu.read_mem(0x02), 0);
+    assert_eq!(cpu.read_mem(0x03), 0);
+}
@@ -0,0 +1,358 @@
+use crate::cpu::tests::flat_memory::FlatMemory;
+use crate::cpu::StatusFlag::*;
+use crate::cpu::{decode, is_unofficial_opcode, CoreMemory, CpuVariant, CPU};
+use std::fs;
+use std::path::Path;
+
+const PROGRAM_START: u16 = 0x0400;
+
+/* a CPU on flat memory, with the reset vector pointing at the program */
+fn cpu_with_program(variant: CpuVariant, program: &[u8]) -> Box<CPU> {
+    let mut image = [0; 0x10000];
+    image[PROGRAM_START as usize..PROGRAM_START as usize + program.len()].copy_from_slice(program);
+    image[0xfffc] = PROGRAM_START as u8;
+    image[0xfffd] = (PROGRAM_START >> 8) as u8;
+    cpu_with_image(variant, &image, 0)
+}
+
+fn cpu_with_image(variant: CpuVariant, image: &[u8], load_address: u16) -> Box<CPU> {
+    let memory = CoreMemory::new_flat(Box::new(FlatMemory::new(image, load_address)));
+    CPU::with_variant(Box::new(memory), variant)
+}
+
+#[test]
+fn flat_memory_has_no_mirroring_or_registers() {
+    let cpu = &mut cpu_with_program(CpuVariant::Nmos6502, &[]);
+    cpu.write_mem(0x0000, 0x11);
+    cpu.write_mem(0x0800, 0x22);
+    cpu.write_mem(0x2000, 0x33);
+    cpu.write_mem(0x4016, 0x44);
+    cpu.write_mem(0x4020, 0x55);
+
+    assert_eq!(cpu.read_mem(0x0000), 0x11);
+    assert_eq!(cpu.read_mem(0x0800), 0x22);
+    assert_eq!(cpu.read_mem(0x2000), 0x33);
+    assert_eq!(cpu.read_mem(0x2008), 0x00);
+    assert_eq!(cpu.read_mem(0x4016), 0x44);
+    assert_eq!(cpu.read_mem(0x4020), 0x55);
+    assert_eq!(cpu.program_counter, PROGRAM_START);
+}
+
+/* runs ADC or SBC (opcode) with decimal mode on; returns the CPU and the cycles taken */
+fn decimal_op(variant: CpuVariant, opcode: u8, a: u8, operand: u8, carry: bool) -> (Box<CPU>, u16) {
+    let mut cpu = cpu_with_program(variant, &[opcode, operand]);
+    cpu.accumulator = a;
+    cpu.update_flag(Decimal, true);
+    cpu.update_flag(Carry, carry);
+    let cycles = cpu.transition();
+    (cpu, cycles)
+}
+
+#[test]
+fn decimal_adc() {
+    let (cpu, _) = decimal_op(CpuVariant::Nmos6502, 0x69, 0x09, 0x01, false);
+    assert_eq!(cpu.accumulator, 0x10);
+    assert!(!Carry.is_set(&cpu));
+
+    let (cpu, _) = decimal_op(CpuVariant::Nmos6502, 0x69, 0x58, 0x46, true);
+    assert_eq!(cpu.accumulator, 0x05);
+    assert!(Carry.is_set(&cpu));
+
+    /* the NMOS 6502 takes Z from the binary sum and N from before the high digit is adjusted */
+    let (cpu, cycles) = decimal_op(CpuVariant::Nmos6502, 0x69, 0x99, 0x01, false);
+    assert_eq!(cpu.accumulator, 0x00);
+    assert!(Carry.is_set(&cpu));
+    assert!(!Zero.is_set(&cpu));
+    assert!(Negative.is_set(&cpu));
+    assert_eq!(cycles, 2);
+
+    /* the 65C02 sets them from the result, at the cost of a cycle */
+    let (cpu, cycles) = decimal_op(CpuVariant::Wdc65C02, 0x69, 0x99, 0x01, false);
+    assert_eq!(cpu.accumulator, 0x00);
+    assert!(Carry.is_set(&cpu));
+    assert!(Zero.is_set(&cpu));
+    assert!(!Negative.is_set(&cpu));
+    assert_eq!(cycles, 3);
+
+    /* V comes from the signed sum of the high digits */
+    let (cpu, _) = decimal_op(CpuVariant::Nmos6502, 0x69, 0x79, 0x00, true);
+    assert_eq!(cpu.accumulator, 0x80);
+    assert!(Overflow.is_set(&cpu));
+
+    /* the 2A03 has no decimal mode */
+    let (cpu, cycles) = decimal_op(CpuVariant::Ricoh2A03, 0x69, 0x09, 0x01, false);
+    assert_eq!(cpu.accumulator, 0x0a);
+    assert_eq!(cycles, 2);
+}
+
+#[test]
+fn decimal_sbc() {
+    let (cpu, _) = decimal_op(CpuVariant::Nmos6502, 0xe9, 0x10, 0x01, true);
+    assert_eq!(cpu.accumulator, 0x09);
+    assert!(Carry.is_set(&cpu));
+
+    let (cpu, _) = decimal_op(CpuVariant::Nmos6502, 0xe9, 0x00, 0x01, true);
+    assert_eq!(cpu.accumulator, 0x99);
+    assert!(!Carry.is_set(&cpu));
+    assert!(Negative.is_set(&cpu));
+
+    let (cpu, _) = decimal_op(CpuVariant::Nmos6502, 0xe9, 0x32, 0x02, false);
+    assert_eq!(cpu.accumulator, 0x29);
+    assert!(Carry.is_set(&cpu));
+
+    /* the 65C02 takes a cycle longer to set the flags from the result */
+    let (cpu, cycles) = decimal_op(CpuVariant::Nmos6502, 0xe9, 0x90, 0x05, true);
+    assert_eq!(cpu.accumulator, 0x85);
+    assert!(Negative.is_set(&cpu));
+    assert_eq!(cycles, 2);
+    let (cpu, cycles) = decimal_op(CpuVariant::Wdc65C02, 0xe9, 0x90, 0x05, true);
+    assert_eq!(cpu.accumulator, 0x85);
+    assert!(Negative.is_set(&cpu));
+    assert_eq!(cycles, 3);
+
+    let (cpu, _) = decimal_op(CpuVariant::Ricoh2A03, 0xe9, 0x10, 0x01, true);
+    assert_eq!(cpu.accumulator, 0x0f);
+}
+
+#[test]
+fn no_65c02_opcode_is_unofficial() {
+    for opcode in 0..=0xff {
+        let instruction = decode(CpuVariant::Wdc65C02, opcode).instruction;
+        assert!(
+            !instruction.is_unofficial() && !instruction.is_unstable(),
+            "0x{:02x} decodes as {:?}",
+            opcode,
+            instruction
+        );
+    }
+    /* the NMOS decoding is unchanged */
+    for opcode in 0..=0xff {
+        let nmos = decode(CpuVariant::Nmos6502, opcode);
+        let nes = decode(CpuVariant::Ricoh2A03, opcode);
+        assert_eq!(nmos.instruction, nes.instruction);
+        assert_eq!(nmos.addr_mode, nes.addr_mode);
+    }
+    assert!(is_unofficial_opcode(0x07));
+}
+
+#[test]
+fn jmp_indirect_page_bug() {
+    /* JMP ($02FF): the NMOS 6502 reads the high byte from $0200 rather than $0300 */
+    for (variant, target) in [
+        (CpuVariant::Nmos6502, 0x5634),
+        (CpuVariant::Wdc65C02, 0x1234),
+    ] {
+        let cpu = &mut cpu_with_program(variant, &[0x6c, 0xff, 0x02]);
+        cpu.write_mem(0x02ff, 0x34);
+        cpu.write_mem(0x0300, 0x12);
+        cpu.write_mem(0x0200, 0x56);
+        cpu.transition();
+        assert_eq!(cpu.program_counter, target);
+    }
+}
+
+#[test]
+fn cmos_instructions() {
+    /* BRA */
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &[0x80, 0x05]);
+    assert_eq!(cpu.transition(), 3);
+    assert_eq!(cpu.program_counter, PROGRAM_START + 7);
+
+    /* STZ abs */
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &[0x9c, 0x00, 0x03]);
+    cpu.write_mem(0x0300, 0xff);
+    cpu.transition();
+    assert_eq!(cpu.read_mem(0x0300), 0x00);
+
+    /* TSB zp, TRB zp */
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &[0x04, 0x10, 0x14, 0x10]);
+    cpu.accumulator = 0x0f;
+    cpu.write_mem(0x0010, 0xf0);
+    cpu.transition();
+    assert_eq!(cpu.read_mem(0x0010), 0xff);
+    assert!(Zero.is_set(cpu));
+    cpu.transition();
+    assert_eq!(cpu.read_mem(0x0010), 0xf0);
+    assert!(!Zero.is_set(cpu));
+
+    /* PHX, PLY */
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &[0xda, 0x7a]);
+    cpu.index_x = 0x42;
+    cpu.transition();
+    cpu.transition();
+    assert_eq!(cpu.index_y, 0x42);
+
+    /* LDA (zp) */
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &[0xb2, 0x10]);
+    cpu.write_mem(0x0010, 0x00);
+    cpu.write_mem(0x0011, 0x03);
+    cpu.write_mem(0x0300, 0x77);
+    assert_eq!(cpu.transition(), 5);
+    assert_eq!(cpu.accumulator, 0x77);
+
+    /* JMP (abs,X) */
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &[0x7c, 0x00, 0x03]);
+    cpu.index_x = 0x02;
+    cpu.write_mem(0x0302, 0x34);
+    cpu.write_mem(0x0303, 0x12);
+    cpu.transition();
+    assert_eq!(cpu.program_counter, 0x1234);
+
+    /* BIT # only sets Z */
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &[0x89, 0xc0]);
+    cpu.accumulator = 0x00;
+    cpu.update_flag(Overflow, false);
+    cpu.update_flag(Negative, false);
+    cpu.transition();
+    assert!(Zero.is_set(cpu));
+    assert!(!Overflow.is_set(cpu));
+    assert!(!Negative.is_set(cpu));
+
+    /* INC A, DEC A */
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &[0x1a, 0x1a, 0x3a]);
+    cpu.accumulator = 0xff;
+    cpu.transition();
+    assert_eq!(cpu.accumulator, 0x00);
+    cpu.transition();
+    cpu.transition();
+    assert_eq!(cpu.accumulator, 0x00);
+    assert!(Zero.is_set(cpu));
+
+    /* on NMOS, 0x1a is a NOP */
+    let cpu = &mut cpu_with_program(CpuVariant::Nmos6502, &[0x1a]);
+    cpu.accumulator = 0xff;
+    cpu.transition();
+    assert_eq!(cpu.accumulator, 0xff);
+}
+
+#[test]
+fn rockwell_bit_instructions() {
+    let program = [
+        0x07, 0x10, /* RMB0 $10 */
+        0xf7, 0x10, /* SMB7 $10 */
+        0x7f, 0x10, 0x05, /* BBR7 $10,+5: not taken */
+        0x8f, 0x10, 0x05, /* BBS0 $10,+5: not taken */
+        0xff, 0x10, 0x05, /* BBS7 $10,+5: taken */
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x0f, 0x10, 0xfb, /* BBR0 $10,-5: taken */
+    ];
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &program);
+    cpu.write_mem(0x0010, 0x01);
+
+    cpu.transition();
+    assert_eq!(cpu.read_mem(0x0010), 0x00);
+    cpu.transition();
+    assert_eq!(cpu.read_mem(0x0010), 0x80);
+    assert_eq!(cpu.transition(), 5);
+    assert_eq!(cpu.program_counter, PROGRAM_START + 7);
+    cpu.transition();
+    assert_eq!(cpu.program_counter, PROGRAM_START + 10);
+    assert_eq!(cpu.transition(), 6);
+    assert_eq!(cpu.program_counter, PROGRAM_START + 18);
+    cpu.transition();
+    assert_eq!(cpu.program_counter, PROGRAM_START + 16);
+}
+
+#[test]
+fn interrupts_clear_decimal_only_on_cmos() {
+    for variant in [CpuVariant::Nmos6502, CpuVariant::Wdc65C02] {
+        let cpu = &mut cpu_with_program(variant, &[0x00, 0x00]); /* BRK */
+        cpu.write_mem(0xfffe, 0x00);
+        cpu.write_mem(0xffff, 0x03);
+        cpu.update_flag(Decimal, true);
+        cpu.transition();
+        assert_eq!(cpu.program_counter, 0x0300);
+        assert_eq!(Decimal.is_set(cpu), !variant.is_cmos());
+    }
+}
+
+#[test]
+fn wai_waits_for_an_interrupt() {
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &[0xcb, 0xe8]); /* WAI, INX */
+    cpu.write_mem(0xfffa, 0x00);
+    cpu.write_mem(0xfffb, 0x03);
+    cpu.write_mem(0x0300, 0xea); /* NOP */
+    cpu.transition();
+    assert_eq!(cpu.transition(), 1);
+    assert_eq!(cpu.transition(), 1);
+    assert_eq!(cpu.program_counter, PROGRAM_START + 1);
+
+    cpu.set_nmi(true);
+    cpu.transition();
+    assert_eq!(cpu.program_counter, 0x0301); /* the handler's first instruction has run */
+}
+
+#[test]
+fn stp_stops_until_reset() {
+    let cpu = &mut cpu_with_program(CpuVariant::Wdc65C02, &[0xdb]);
+    cpu.transition();
+    cpu.set_nmi(true);
+    assert_eq!(cpu.transition(), 1);
+    assert_eq!(cpu.program_counter, PROGRAM_START);
+    cpu.reset();
+    assert_eq!(cpu.program_counter, PROGRAM_START);
+}
+
+/* runs until the program is stuck in a loop that jumps to itself, and returns where that is */
+fn run_until_trapped(cpu: &mut CPU, stop_opcode: Option<u8>) -> u16 {
+    loop {
+        let pc = cpu.program_counter;
+        if Some(cpu.peek_mem(pc)) == stop_opcode {
+            return pc;
+        }
+        cpu.transition();
+        if cpu.program_counter == pc {
+            return pc;
+        }
+    }
+}
+
+fn klaus_binary(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test_roms")
+        .join(name);
+    fs::read(&path).unwrap_or_else(|_| panic!("{} should be in test_roms/", name))
+}
+
+/*
+ * Klaus Dormann's 6502 test suites (https://github.com/Klaus2m5/6502_65C02_functional_tests),
+ * assembled with their default settings. They aren't distributed with Patina; put the binaries
+ * in test_roms/ to run these with `cargo test -- --ignored`. Each test traps in a loop on
+ * failure, so the trap address says which one failed (see the listing).
+ */
+#[test]
+#[ignore = "needs test_roms/6502_functional_test.bin"]
+fn klaus_functional_test() {
+    let cpu = &mut cpu_with_image(
+        CpuVariant::Nmos6502,
+        &klaus_binary("6502_functional_test.bin"),
+        0,
+    );
+    cpu.program_counter = 0x0400;
+    assert_eq!(run_until_trapped(cpu, None), 0x3469);
+}
+
+#[test]
+#[ignore = "needs test_roms/65C02_extended_opcodes_test.bin"]
+fn klaus_65c02_extended_opcodes_test() {
+    let cpu = &mut cpu_with_image(
+        CpuVariant::Wdc65C02,
+        &klaus_binary("65C02_extended_opcodes_test.bin"),
+        0,
+    );
+    cpu.program_counter = 0x0400;
+    assert_eq!(run_until_trapped(cpu, None), 0x24f1);
+}
+
+/* Bruce Clark's decimal mode test, which ends on an STP and leaves 0 in ERROR ($000B) if it passed */
+#[test]
+#[ignore = "needs test_roms/6502_decimal_test.bin"]
+fn klaus_decimal_test() {
+    let cpu = &mut cpu_with_image(
+        CpuVariant::Nmos6502,
+        &klaus_binary("6502_decimal_test.bin"),
+        0x0200,
+    );
+    cpu.program_counter = 0x0200;
+    run_until_trapped(cpu, Some(0xdb));
+    assert_eq!(cpu.read_mem(0x000b), 0);
+}
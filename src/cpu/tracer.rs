@@ -0,0 +1,222 @@
+use crate::cpu::instruction::Instruction;
+use crate::cpu::{addr, decode, is_unofficial_opcode, AddressingMode, RealizedInstruction, CPU};
+use crate::ppu::PPU;
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+use AddressingMode::*;
+
+/* the reset sequence takes 7 cycles before the first instruction runs */
+const RESET_CYCLES: u64 = 7;
+
+/**
+ * Logs every instruction the CPU executes, one line per instruction, in the format of nestest.log
+ * (as produced by Nintendulator), so traces can be diffed against a known-good emulator:
+ *
+ * C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+ *
+ * Unofficial opcodes are marked with a * before the mnemonic (except on the 65C02, which has none).
+ */
+pub struct Tracer {
+    output: Box<dyn Write>,
+    ppu: Option<Rc<RefCell<PPU>>>,
+    cycles: u64,
+}
+
+impl Tracer {
+    pub fn new(output: Box<dyn Write>) -> Tracer {
+        Tracer {
+            output,
+            ppu: None,
+            cycles: RESET_CYCLES,
+        }
+    }
+
+    /* without a PPU, the PPU column is always 0, 0 */
+    pub fn set_ppu(&mut self, ppu: Rc<RefCell<PPU>>) {
+        self.ppu = Some(ppu);
+    }
+
+    /* logs the instruction about to run; returns false if the output can't be written */
+    pub(super) fn trace(&mut self, cpu: &CPU) -> bool {
+        let line = self.trace_line(cpu);
+        match writeln!(self.output, "{}", line) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("Unable to write CPU trace, disabling tracing: {}", e);
+                false
+            }
+        }
+    }
+
+    pub(super) fn add_cycles(&mut self, cycles: u16) {
+        self.cycles += cycles as u64;
+    }
+
+    fn trace_line(&self, cpu: &CPU) -> String {
+        let pc = cpu.program_counter;
+        let opcode = cpu.peek_mem(pc);
+        let b1 = cpu.peek_mem(pc.wrapping_add(1));
+        let b2 = cpu.peek_mem(pc.wrapping_add(2));
+        let instruction = decode(cpu.variant(), opcode);
+        let unofficial = !cpu.variant().is_cmos() && is_unofficial_opcode(opcode);
+
+        let bytes = [opcode, b1, b2][..instruction.addr_mode.get_bytes() as usize]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<String>>()
+            .join(" ");
+        let (scanline, dot) = match &self.ppu {
+            Some(ppu) => ppu.borrow().position(),
+            None => (0, 0),
+        };
+
+        format!(
+            "{:04X}  {:<8} {}{:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+            pc,
+            bytes,
+            if unofficial { '*' } else { ' ' },
+            disassemble(cpu, &instruction, b1, b2),
+            cpu.accumulator,
+            cpu.index_x,
+            cpu.index_y,
+            cpu.status,
+            cpu.s_register,
+            scanline,
+            dot,
+            self.cycles
+        )
+    }
+}
+
+/* disassembles an instruction, along with the addresses and values it's about to touch */
+fn disassemble(cpu: &CPU, instruction: &RealizedInstruction, b1: u8, b2: u8) -> String {
+    let name = match &instruction.instruction {
+        /* nestest.log calls it by its other name */
+        Instruction::ISC => "ISB".to_string(),
+        /* the bit number is part of the mnemonic for the Rockwell bit instructions */
+        Instruction::BBR(bit) => format!("BBR{}", bit),
+        Instruction::BBS(bit) => format!("BBS{}", bit),
+        Instruction::RMB(bit) => format!("RMB{}", bit),
+        Instruction::SMB(bit) => format!("SMB{}", bit),
+        _ => format!("{:?}", instruction.instruction),
+    };
+    let absolute = addr(b1, b2);
+    /* jumps don't touch the memory they point at */
+    let is_jump = matches!(instruction.instruction, Instruction::JMP | Instruction::JSR);
+
+    let operand = match instruction.addr_mode {
+        Implicit => String::new(),
+        Accumulator => "A".to_string(),
+        Immediate => format!("#${:02X}", b1),
+        ZeroPage => format!("${:02X} = {:02X}", b1, cpu.peek_mem(b1 as u16)),
+        ZeroPageX => indexed_zero_page(cpu, b1, cpu.index_x, "X"),
+        ZeroPageY => indexed_zero_page(cpu, b1, cpu.index_y, "Y"),
+        Relative => {
+            let target = cpu
+                .program_counter
+                .wrapping_add(2)
+                .wrapping_add_signed(b1 as i8 as i16);
+            format!("${:04X}", target)
+        }
+        Absolute if is_jump => format!("${:04X}", absolute),
+        Absolute => format!("${:04X} = {:02X}", absolute, cpu.peek_mem(absolute)),
+        AbsoluteX => indexed_absolute(cpu, absolute, cpu.index_x, "X"),
+        AbsoluteY => indexed_absolute(cpu, absolute, cpu.index_y, "Y"),
+        Indirect => {
+            /* the high byte comes from the same page, as with the hardware bug (fixed on CMOS) */
+            let high_address = if cpu.variant().is_cmos() {
+                absolute.wrapping_add(1)
+            } else {
+                (absolute & 0xff00) | (absolute as u8).wrapping_add(1) as u16
+            };
+            let target = addr(cpu.peek_mem(absolute), cpu.peek_mem(high_address));
+            format!("(${:04X}) = {:04X}", absolute, target)
+        }
+        IndirectX => {
+            let pointer = b1.wrapping_add(cpu.index_x);
+            let target = peek_zero_page_pointer(cpu, pointer);
+            format!(
+                "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                b1,
+                pointer,
+                target,
+                cpu.peek_mem(target)
+            )
+        }
+        IndirectY => {
+            let base = peek_zero_page_pointer(cpu, b1);
+            let target = base.wrapping_add(cpu.index_y as u16);
+            format!(
+                "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                b1,
+                base,
+                target,
+                cpu.peek_mem(target)
+            )
+        }
+        ZeroPageIndirect => {
+            let target = peek_zero_page_pointer(cpu, b1);
+            format!(
+                "(${:02X}) = {:04X} = {:02X}",
+                b1,
+                target,
+                cpu.peek_mem(target)
+            )
+        }
+        AbsoluteIndexedIndirect => {
+            let pointer = absolute.wrapping_add(cpu.index_x as u16);
+            let target = addr(cpu.peek_mem(pointer), cpu.peek_mem(pointer.wrapping_add(1)));
+            format!("(${:04X},X) @ {:04X} = {:04X}", absolute, pointer, target)
+        }
+        ZeroPageRelative => {
+            let target = cpu
+                .program_counter
+                .wrapping_add(3)
+                .wrapping_add_signed(b2 as i8 as i16);
+            format!(
+                "${:02X} = {:02X}, ${:04X}",
+                b1,
+                cpu.peek_mem(b1 as u16),
+                target
+            )
+        }
+    };
+
+    if operand.is_empty() {
+        name
+    } else {
+        format!("{} {}", name, operand)
+    }
+}
+
+fn indexed_zero_page(cpu: &CPU, base: u8, index: u8, register: &str) -> String {
+    let target = base.wrapping_add(index);
+    format!(
+        "${:02X},{} @ {:02X} = {:02X}",
+        base,
+        register,
+        target,
+        cpu.peek_mem(target as u16)
+    )
+}
+
+fn indexed_absolute(cpu: &CPU, base: u16, index: u8, register: &str) -> String {
+    let target = base.wrapping_add(index as u16);
+    format!(
+        "${:04X},{} @ {:04X} = {:02X}",
+        base,
+        register,
+        target,
+        cpu.peek_mem(target)
+    )
+}
+
+/* pointers in the zero page wrap around within it */
+fn peek_zero_page_pointer(cpu: &CPU, pointer: u8) -> u16 {
+    addr(
+        cpu.peek_mem(pointer as u16),
+        cpu.peek_mem(pointer.wrapping_add(1) as u16),
+    )
+}
@@ -0,0 +1,26 @@
+/**
+ * The members of the 6502 family the CPU core can emulate. The NES uses the Ricoh 2A03, an NMOS
+ * 6502 with decimal mode disconnected; the others are for running the core outside of the NES.
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CpuVariant {
+    #[default]
+    Ricoh2A03,
+    /* the original NMOS 6502, with decimal mode and its undocumented flag behavior */
+    #[cfg(test)]
+    Nmos6502,
+    /* the WDC 65C02: extra instructions (including the Rockwell bit instructions, WAI, and STP),
+     * valid flags in decimal mode, every undefined opcode a NOP, and JMP ($xxFF) fixed
+     */
+    Wdc65C02,
+}
+
+impl CpuVariant {
+    pub fn has_decimal_mode(&self) -> bool {
+        *self != CpuVariant::Ricoh2A03
+    }
+
+    pub fn is_cmos(&self) -> bool {
+        *self == CpuVariant::Wdc65C02
+    }
+}
@@ -0,0 +1,75 @@
+use crate::gamepad::{GamepadBackend, GamepadEvent, PadAxis, PadButton};
+use gilrs::{Axis, Button, EventType, Gilrs};
+use std::io;
+
+/**
+ * Gamepads as gilrs sees them: evdev on Linux, with SDL's game controller database mapping each
+ * pad's buttons into a standard layout.
+ */
+pub struct GilrsBackend {
+    gilrs: Gilrs,
+    pending: Vec<GamepadEvent>, /* pads that were already plugged in at startup */
+}
+
+impl GilrsBackend {
+    pub fn new() -> io::Result<GilrsBackend> {
+        let gilrs = Gilrs::new().map_err(|e| io::Error::other(e.to_string()))?;
+        let pending = gilrs
+            .gamepads()
+            .map(|(id, gamepad)| GamepadEvent::Connected(id.into(), gamepad.name().to_string()))
+            .collect();
+        Ok(GilrsBackend { gilrs, pending })
+    }
+}
+
+impl GamepadBackend for GilrsBackend {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+        let mut events = std::mem::take(&mut self.pending);
+        while let Some(event) = self.gilrs.next_event() {
+            let id = event.id.into();
+            let converted = match event.event {
+                EventType::Connected => Some(GamepadEvent::Connected(
+                    id,
+                    self.gilrs.gamepad(event.id).name().to_string(),
+                )),
+                EventType::Disconnected => Some(GamepadEvent::Disconnected(id)),
+                EventType::ButtonPressed(button, _) => {
+                    pad_button(button).map(|button| GamepadEvent::Button(id, button, true))
+                }
+                EventType::ButtonReleased(button, _) => {
+                    pad_button(button).map(|button| GamepadEvent::Button(id, button, false))
+                }
+                EventType::AxisChanged(axis, value, _) => {
+                    pad_axis(axis).map(|axis| GamepadEvent::Axis(id, axis, value))
+                }
+                _ => None,
+            };
+            events.extend(converted);
+        }
+        events
+    }
+}
+
+fn pad_button(button: Button) -> Option<PadButton> {
+    match button {
+        Button::South => Some(PadButton::South),
+        Button::East => Some(PadButton::East),
+        Button::West => Some(PadButton::West),
+        Button::North => Some(PadButton::North),
+        Button::Select => Some(PadButton::Select),
+        Button::Start => Some(PadButton::Start),
+        Button::DPadUp => Some(PadButton::DPadUp),
+        Button::DPadDown => Some(PadButton::DPadDown),
+        Button::DPadLeft => Some(PadButton::DPadLeft),
+        Button::DPadRight => Some(PadButton::DPadRight),
+        _ => None,
+    }
+}
+
+fn pad_axis(axis: Axis) -> Option<PadAxis> {
+    match axis {
+        Axis::LeftStickX => Some(PadAxis::LeftStickX),
+        Axis::LeftStickY => Some(PadAxis::LeftStickY),
+        _ => None,
+    }
+}
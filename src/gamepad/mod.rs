@@ -0,0 +1,263 @@
+/* without a backend built in, nothing but the tests produces gamepad events */
+#![cfg_attr(not(feature = "gamepad"), allow(dead_code))]
+
+#[cfg(feature = "gamepad")]
+mod gilrs_backend;
+
+#[cfg(test)]
+mod tests;
+
+#[cfg(feature = "gamepad")]
+pub use gilrs_backend::GilrsBackend;
+
+use crate::config::Config;
+use patina::ButtonSource;
+use std::sync::atomic::Ordering;
+
+/* bits in a controller's button state, in the order the controller reports them */
+const BUTTON_A: u8 = 1 << 0;
+const BUTTON_B: u8 = 1 << 1;
+const BUTTON_SELECT: u8 = 1 << 2;
+const BUTTON_START: u8 = 1 << 3;
+const BUTTON_UP: u8 = 1 << 4;
+const BUTTON_DOWN: u8 = 1 << 5;
+const BUTTON_LEFT: u8 = 1 << 6;
+const BUTTON_RIGHT: u8 = 1 << 7;
+
+pub type DeviceId = usize;
+
+/**
+ * The buttons of a standard game controller that the NES has a use for, named by position as in
+ * SDL's game controller mappings, so every pad lays out the same way.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadButton {
+    South,
+    East,
+    West,
+    North,
+    Select,
+    Start,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+impl PadButton {
+    /* East and South sit where the NES pad's A and B do; the other face buttons double them */
+    fn nes_button(self) -> u8 {
+        match self {
+            PadButton::East | PadButton::North => BUTTON_A,
+            PadButton::South | PadButton::West => BUTTON_B,
+            PadButton::Select => BUTTON_SELECT,
+            PadButton::Start => BUTTON_START,
+            PadButton::DPadUp => BUTTON_UP,
+            PadButton::DPadDown => BUTTON_DOWN,
+            PadButton::DPadLeft => BUTTON_LEFT,
+            PadButton::DPadRight => BUTTON_RIGHT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PadAxis {
+    LeftStickX, /* -1.0 is left, 1.0 is right */
+    LeftStickY, /* -1.0 is down, 1.0 is up */
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum GamepadEvent {
+    Connected(DeviceId, String),
+    Disconnected(DeviceId),
+    Button(DeviceId, PadButton, bool),
+    Axis(DeviceId, PadAxis, f32),
+}
+
+/**
+ * A source of gamepad events, e.g. the OS's joystick devices. Pads already plugged in when the
+ * backend starts are reported as connecting on the first poll.
+ */
+pub trait GamepadBackend {
+    /* every event since the last poll, oldest first; never blocks */
+    fn poll(&mut self) -> Vec<GamepadEvent>;
+}
+
+/* gamepad input from the OS, if it was built in and the OS allows it */
+pub fn native_input(pad_source: ButtonSource, config: &Config) -> Option<GamepadInput> {
+    #[cfg(feature = "gamepad")]
+    match GilrsBackend::new() {
+        Ok(backend) => {
+            let mut input = GamepadInput::new(Box::new(backend), pad_source);
+            input.set_config(config);
+            Some(input)
+        }
+        Err(e) => {
+            eprintln!("Gamepads are unavailable: {e}");
+            None
+        }
+    }
+    #[cfg(not(feature = "gamepad"))]
+    {
+        let _ = (pad_source, config);
+        None
+    }
+}
+
+struct Device {
+    id: DeviceId,
+    name: String,
+    /* the pad's own buttons, since two of them can stand for the same NES button */
+    held: Vec<PadButton>,
+    stick_x: f32,
+    stick_y: f32,
+}
+
+/**
+ * Turns gamepad events into NES button state for each controller port. Each player can be tied to
+ * a pad by name in the config; players who aren't get the remaining pads in the order they were
+ * plugged in. Pads come and go at any time, and a player whose pad is unplugged just has no
+ * buttons held until another one is assigned.
+ */
+pub struct GamepadInput {
+    backend: Box<dyn GamepadBackend>,
+    devices: Vec<Device>, /* in the order they connected */
+    assignments: [Option<String>; 2],
+    stick_threshold: f32,
+    pad_source: ButtonSource,
+}
+
+impl GamepadInput {
+    pub fn new(backend: Box<dyn GamepadBackend>, pad_source: ButtonSource) -> GamepadInput {
+        let config = Config::default();
+        GamepadInput {
+            backend,
+            devices: Vec::new(),
+            assignments: config.gamepads,
+            stick_threshold: config.stick_threshold,
+            pad_source,
+        }
+    }
+
+    pub fn set_config(&mut self, config: &Config) {
+        self.assignments = config.gamepads.clone();
+        self.stick_threshold = config.stick_threshold;
+        self.publish();
+    }
+
+    /* handles everything the backend has seen since the last poll */
+    pub fn poll(&mut self) {
+        let events = self.backend.poll();
+        if events.is_empty() {
+            return;
+        }
+        for event in events {
+            self.handle_event(event);
+        }
+        self.publish();
+    }
+
+    fn handle_event(&mut self, event: GamepadEvent) {
+        match event {
+            GamepadEvent::Connected(id, name) => {
+                self.devices.retain(|device| device.id != id);
+                self.devices.push(Device {
+                    id,
+                    name,
+                    held: Vec::new(),
+                    stick_x: 0.0,
+                    stick_y: 0.0,
+                });
+            }
+            GamepadEvent::Disconnected(id) => {
+                self.devices.retain(|device| device.id != id);
+            }
+            GamepadEvent::Button(id, button, pressed) => {
+                if let Some(device) = self.device_mut(id) {
+                    device.held.retain(|&held| held != button);
+                    if pressed {
+                        device.held.push(button);
+                    }
+                }
+            }
+            GamepadEvent::Axis(id, axis, value) => {
+                if let Some(device) = self.device_mut(id) {
+                    match axis {
+                        PadAxis::LeftStickX => device.stick_x = value,
+                        PadAxis::LeftStickY => device.stick_y = value,
+                    }
+                }
+            }
+        }
+    }
+
+    fn device_mut(&mut self, id: DeviceId) -> Option<&mut Device> {
+        self.devices.iter_mut().find(|device| device.id == id)
+    }
+
+    /**
+     * Which pad each player is using. A player with a name in the config gets the first pad
+     * whose name contains it, ignoring case; the rest share out whatever's left.
+     */
+    pub fn player_devices(&self) -> [Option<DeviceId>; 2] {
+        let mut players: [Option<DeviceId>; 2] = [None, None];
+
+        for (player, assignment) in self.assignments.iter().enumerate() {
+            if let Some(wanted) = assignment {
+                let wanted = wanted.to_lowercase();
+                players[player] = self
+                    .devices
+                    .iter()
+                    .find(|device| {
+                        device.name.to_lowercase().contains(&wanted)
+                            && !players.contains(&Some(device.id))
+                    })
+                    .map(|device| device.id);
+            }
+        }
+
+        let named = players;
+        let mut unassigned = self
+            .devices
+            .iter()
+            .filter(|device| !named.contains(&Some(device.id)));
+        for (player, assignment) in self.assignments.iter().enumerate() {
+            if assignment.is_none() {
+                players[player] = unassigned.next().map(|device| device.id);
+            }
+        }
+
+        players
+    }
+
+    /* the NES buttons a player is holding, with the stick pushed past the threshold as D-pad */
+    pub fn buttons(&self, player: usize) -> u8 {
+        let Some(id) = self.player_devices()[player] else {
+            return 0;
+        };
+        let device = self.devices.iter().find(|device| device.id == id).unwrap();
+
+        let mut buttons = device
+            .held
+            .iter()
+            .fold(0, |buttons, button| buttons | button.nes_button());
+        let threshold = self.stick_threshold;
+        if device.stick_y >= threshold {
+            buttons |= BUTTON_UP;
+        } else if device.stick_y <= -threshold {
+            buttons |= BUTTON_DOWN;
+        }
+        if device.stick_x >= threshold {
+            buttons |= BUTTON_RIGHT;
+        } else if device.stick_x <= -threshold {
+            buttons |= BUTTON_LEFT;
+        }
+        buttons
+    }
+
+    fn publish(&self) {
+        for (player, pad) in self.pad_source.iter().enumerate() {
+            pad.store(self.buttons(player), Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use crate::config::Config;
+use crate::gamepad::GamepadEvent::{Axis, Button, Connected, Disconnected};
+use crate::gamepad::{GamepadBackend, GamepadEvent, GamepadInput, PadAxis, PadButton};
+use patina::ButtonSource;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+
+/* stands in for the OS: hands over whatever events the test queued up */
+struct ScriptedBackend {
+    events: Rc<RefCell<Vec<GamepadEvent>>>,
+}
+
+impl GamepadBackend for ScriptedBackend {
+    fn poll(&mut self) -> Vec<GamepadEvent> {
+        self.events.borrow_mut().drain(..).collect()
+    }
+}
+
+struct Harness {
+    input: GamepadInput,
+    events: Rc<RefCell<Vec<GamepadEvent>>>,
+    pads: ButtonSource,
+}
+
+impl Harness {
+    fn new() -> Harness {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let pads = ButtonSource::default();
+        let backend = ScriptedBackend {
+            events: events.clone(),
+        };
+        Harness {
+            input: GamepadInput::new(Box::new(backend), pads.clone()),
+            events,
+            pads,
+        }
+    }
+
+    fn send(&mut self, events: Vec<GamepadEvent>) {
+        self.events.borrow_mut().extend(events);
+        self.input.poll();
+    }
+
+    /* what each controller port would latch */
+    fn pads(&self) -> [u8; 2] {
+        [
+            self.pads[0].load(Ordering::Relaxed),
+            self.pads[1].load(Ordering::Relaxed),
+        ]
+    }
+}
+
+#[test]
+fn test_buttons_map_to_nes_layout() {
+    let mut harness = Harness::new();
+    harness.send(vec![
+        Connected(7, "Pad".to_string()),
+        Button(7, PadButton::East, true),
+        Button(7, PadButton::Start, true),
+        Button(7, PadButton::DPadLeft, true),
+    ]);
+    assert_eq!(harness.pads(), [0b0100_1001, 0]);
+
+    harness.send(vec![
+        Button(7, PadButton::East, false),
+        Button(7, PadButton::South, true),
+        Button(7, PadButton::Select, true),
+    ]);
+    assert_eq!(harness.pads(), [0b0100_1110, 0]);
+}
+
+#[test]
+fn test_overlapping_presses_of_the_same_nes_button() {
+    let mut harness = Harness::new();
+    harness.send(vec![
+        Connected(0, "Pad".to_string()),
+        Button(0, PadButton::East, true),
+        Button(0, PadButton::North, true),
+        Button(0, PadButton::East, false),
+    ]);
+    /* North still holds A */
+    assert_eq!(harness.pads(), [0b0000_0001, 0]);
+
+    harness.send(vec![Button(0, PadButton::North, false)]);
+    assert_eq!(harness.pads(), [0, 0]);
+}
+
+#[test]
+fn test_stick_past_threshold_is_dpad() {
+    let mut harness = Harness::new();
+    harness.send(vec![
+        Connected(0, "Pad".to_string()),
+        Axis(0, PadAxis::LeftStickX, 0.4),
+        Axis(0, PadAxis::LeftStickY, -0.2),
+    ]);
+    assert_eq!(harness.pads(), [0, 0]);
+
+    harness.send(vec![Axis(0, PadAxis::LeftStickX, 0.6)]);
+    assert_eq!(harness.pads(), [0b1000_0000, 0]); // right
+
+    harness.send(vec![
+        Axis(0, PadAxis::LeftStickX, -0.9),
+        Axis(0, PadAxis::LeftStickY, 0.5),
+    ]);
+    assert_eq!(harness.pads(), [0b0101_0000, 0]); // up left
+
+    harness.send(vec![Axis(0, PadAxis::LeftStickY, -1.0)]);
+    assert_eq!(harness.pads(), [0b0110_0000, 0]); // down left
+
+    /* a stricter threshold ignores the same push */
+    let mut config = Config::default();
+    config.stick_threshold = 0.95;
+    harness.input.set_config(&config);
+    assert_eq!(harness.pads(), [0b0010_0000, 0]); // down
+}
+
+#[test]
+fn test_pads_go_to_players_in_connection_order() {
+    let mut harness = Harness::new();
+    harness.send(vec![Connected(3, "First".to_string())]);
+    assert_eq!(harness.input.player_devices(), [Some(3), None]);
+
+    harness.send(vec![
+        Connected(1, "Second".to_string()),
+        Connected(2, "Third".to_string()),
+        Button(1, PadButton::Start, true),
+    ]);
+    assert_eq!(harness.input.player_devices(), [Some(3), Some(1)]);
+    assert_eq!(harness.pads(), [0, 0b1000]);
+
+    /* unplugging player 1's pad hands the next spare one over, and nothing is left held */
+    harness.send(vec![Disconnected(3)]);
+    assert_eq!(harness.input.player_devices(), [Some(1), Some(2)]);
+    assert_eq!(harness.pads(), [0b1000, 0]);
+
+    harness.send(vec![Disconnected(1), Disconnected(2)]);
+    assert_eq!(harness.input.player_devices(), [None, None]);
+    assert_eq!(harness.pads(), [0, 0]);
+}
+
+#[test]
+fn test_replugged_pad_starts_released() {
+    let mut harness = Harness::new();
+    harness.send(vec![
+        Connected(0, "Pad".to_string()),
+        Button(0, PadButton::North, true),
+        Disconnected(0),
+        Connected(0, "Pad".to_string()),
+    ]);
+    assert_eq!(harness.pads(), [0, 0]);
+}
+
+#[test]
+fn test_events_from_unknown_pads_are_ignored() {
+    let mut harness = Harness::new();
+    harness.send(vec![Button(9, PadButton::South, true)]);
+    assert_eq!(harness.pads(), [0, 0]);
+}
+
+#[test]
+fn test_players_assigned_by_name() {
+    let mut harness = Harness::new();
+    let mut config = Config::default();
+    config.gamepads[1] = Some("8bitdo".to_string());
+    harness.input.set_config(&config);
+
+    harness.send(vec![Connected(0, "Xbox Controller".to_string())]);
+    assert_eq!(harness.input.player_devices(), [Some(0), None]);
+
+    harness.send(vec![
+        Connected(1, "8BitDo SN30 Pro".to_string()),
+        Connected(2, "Another Pad".to_string()),
+    ]);
+    assert_eq!(harness.input.player_devices(), [Some(0), Some(1)]);
+
+    /* a named player waits for their own pad rather than taking a spare */
+    harness.send(vec![Disconnected(1)]);
+    assert_eq!(harness.input.player_devices(), [Some(0), None]);
+
+    /* and a pad named for one player can't be given to the other */
+    config.gamepads = [Some("pad".to_string()), Some("pad".to_string())];
+    harness.input.set_config(&config);
+    assert_eq!(harness.input.player_devices(), [Some(2), None]);
+}
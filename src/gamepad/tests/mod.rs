@@ -0,0 +1 @@
+mod gamepad_tests;
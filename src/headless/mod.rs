@@ -0,0 +1,229 @@
+use crate::apu::{AudioRecorder, WavFormat};
+use crate::cpu::UnstableOpcodePolicy;
+use crate::movie::{self, Movie, MovieFrame, COMMAND_POWER, COMMAND_RESET};
+use crate::ppu::{WriteBuffer, DISPLAY_HEIGHT, DISPLAY_WIDTH, WRITE_BUFFER_SIZE};
+use crate::rom::Rom;
+use crate::simulator::{Emulator, FrameBuffer, Input};
+use fnv::FnvHasher;
+use image::codecs::png::PngEncoder;
+use image::{ExtendedColorType, ImageEncoder, ImageResult};
+use std::error::Error;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[cfg(test)]
+mod tests;
+
+/* blargg's test ROMs report through PRG-RAM: a status byte, a signature marking the protocol as
+ * in use, then a NUL-terminated text description of the results
+ */
+const STATUS_ADDRESS: u16 = 0x6000;
+const SIGNATURE_ADDRESS: u16 = 0x6001;
+const SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const TEXT_ADDRESS: u16 = 0x6004;
+const MAX_TEXT_LENGTH: u16 = 0x1000;
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_RESET_REQUESTED: u8 = 0x81;
+/* the ROM asks for at least 100ms between requesting a reset and getting one */
+const RESET_DELAY_FRAMES: u64 = 6;
+
+/* exit codes, besides the test ROM's own result code, which is always below 0x80 */
+pub const EXIT_HASH_MISMATCH: u8 = 1;
+pub const EXIT_TIMED_OUT: u8 = 124;
+
+pub struct HeadlessOptions {
+    pub max_frames: u64,
+    pub png_path: Option<String>,
+    pub trace_path: Option<String>,
+    pub unstable_opcodes: UnstableOpcodePolicy,
+    pub movie: Option<Movie>, /* played from power-on, ending the run when it ends */
+    pub wav_path: Option<String>,
+    pub wav_format: WavFormat,
+    pub wav_stems: bool, /* record each channel beside the WAV file too */
+}
+
+/**
+ * What happened during a headless run: how long it ran, what the test ROM (if it speaks the
+ * $6000 protocol) reported, and a hash of the final frame for comparing against known-good runs.
+ */
+#[derive(Debug)]
+pub struct HeadlessReport {
+    pub frames: u64,
+    pub test_detected: bool,
+    pub test_result: Option<u8>,
+    pub test_text: String,
+    pub frame_hash: u64,
+}
+
+impl HeadlessReport {
+    /* 0 on success; otherwise the test's result code, or one of the EXIT_ constants */
+    pub fn exit_code(&self, expected_hash: Option<u64>) -> u8 {
+        match self.test_result {
+            Some(result) if result != 0 => return result,
+            None if self.test_detected => return EXIT_TIMED_OUT,
+            _ => {}
+        }
+        match expected_hash {
+            Some(hash) if hash != self.frame_hash => EXIT_HASH_MISMATCH,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum TestStatus {
+    NotDetected,
+    Running,
+    ResetRequested,
+    Finished(u8),
+}
+
+/**
+ * Runs a ROM without a window or audio, as fast as possible, until the test ROM reports a result,
+ * the movie finishes, or max_frames have been rendered, then optionally writes the final frame
+ * out as a PNG. The sound can be recorded to a WAV file along the way.
+ */
+pub fn run(rom: &Rom, options: &HeadlessOptions) -> Result<HeadlessReport, Box<dyn Error>> {
+    let mut emulator = Emulator::new(rom)?;
+    emulator.set_unstable_opcode_policy(options.unstable_opcodes);
+    if let Some(path) = &options.trace_path {
+        emulator.set_tracer(Box::new(BufWriter::new(File::create(path)?)));
+    }
+    if let Some(movie) = &options.movie {
+        if movie.rom_checksum != movie::rom_checksum(rom) {
+            eprintln!("Warning: the movie was recorded with a different ROM");
+        }
+    }
+
+    if let Some(path) = &options.wav_path {
+        let recorder =
+            AudioRecorder::create(Path::new(path), options.wav_format, options.wav_stems)?;
+        emulator.start_audio_recording(recorder)?;
+    }
+
+    let (report, frame) = run_emulator(&mut emulator, options);
+
+    emulator.stop_audio_recording()?;
+    if let Some(path) = &options.png_path {
+        write_png(path, &frame)?;
+    }
+
+    Ok(report)
+}
+
+/* returns the report, along with the last frame */
+fn run_emulator(
+    emulator: &mut Emulator,
+    options: &HeadlessOptions,
+) -> (HeadlessReport, Box<FrameBuffer>) {
+    let mut test_detected = false;
+    let mut test_result = None;
+    let mut reset_at_frame = None;
+    /* after a reset, the status still reads as a reset request until the ROM restarts */
+    let mut awaiting_restart = false;
+
+    let mut frame = Box::new([0; WRITE_BUFFER_SIZE]);
+    let mut frames = 0;
+    while frames < options.max_frames {
+        let input = match &options.movie {
+            Some(movie) => match movie.frames.get(frames as usize) {
+                Some(movie_frame) => movie_input(emulator, movie_frame),
+                None => break,
+            },
+            None => Input::default(),
+        };
+        frame.copy_from_slice(emulator.run_frame(input));
+        frames += 1;
+
+        let status = test_status(emulator);
+        if status != TestStatus::NotDetected {
+            test_detected = true;
+        }
+        match status {
+            TestStatus::Finished(result) => {
+                test_result = Some(result);
+                break;
+            }
+            TestStatus::ResetRequested if !awaiting_restart && reset_at_frame.is_none() => {
+                reset_at_frame = Some(frames + RESET_DELAY_FRAMES);
+            }
+            TestStatus::ResetRequested => {}
+            _ => awaiting_restart = false,
+        }
+
+        if reset_at_frame == Some(frames) {
+            emulator.reset();
+            reset_at_frame = None;
+            awaiting_restart = true;
+        }
+    }
+
+    let report = HeadlessReport {
+        frames,
+        test_detected,
+        test_result,
+        test_text: if test_detected {
+            test_text(emulator)
+        } else {
+            String::new()
+        },
+        frame_hash: frame_hash(&frame),
+    };
+    (report, frame)
+}
+
+/* carries out a movie frame's commands, returning the buttons to hold through it */
+fn movie_input(emulator: &mut Emulator, movie_frame: &MovieFrame) -> Input {
+    if movie_frame.commands & COMMAND_POWER != 0 {
+        emulator.power_cycle();
+    } else if movie_frame.commands & COMMAND_RESET != 0 {
+        emulator.reset();
+    }
+    Input {
+        buttons: movie_frame.buttons,
+    }
+}
+
+fn test_status(emulator: &Emulator) -> TestStatus {
+    let signature = [0, 1, 2].map(|i| emulator.read_mem(SIGNATURE_ADDRESS + i));
+    if signature != SIGNATURE {
+        return TestStatus::NotDetected;
+    }
+    match emulator.read_mem(STATUS_ADDRESS) {
+        STATUS_RUNNING => TestStatus::Running,
+        STATUS_RESET_REQUESTED => TestStatus::ResetRequested,
+        result if result < STATUS_RUNNING => TestStatus::Finished(result),
+        _ => TestStatus::Running, /* undefined by the protocol; keep waiting */
+    }
+}
+
+fn test_text(emulator: &Emulator) -> String {
+    let text: Vec<u8> = (0..MAX_TEXT_LENGTH)
+        .map(|i| emulator.read_mem(TEXT_ADDRESS + i))
+        .take_while(|byte| *byte != 0)
+        .collect();
+    String::from_utf8_lossy(&text).to_string()
+}
+
+/* FNV-1a, so hashes are stable across platforms and Rust versions */
+pub fn frame_hash(frame: &WriteBuffer) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(frame);
+    hasher.finish()
+}
+
+/* writes a frame out as a PNG; shared with the windowed frontend's screenshots */
+pub fn write_png(path: &str, frame: &WriteBuffer) -> ImageResult<()> {
+    let mut file = File::create(path)?;
+    let encoder = PngEncoder::new(&file);
+    encoder.write_image(
+        frame.as_ref(),
+        DISPLAY_WIDTH,
+        DISPLAY_HEIGHT,
+        ExtendedColorType::Rgba8,
+    )?;
+    file.flush()?;
+    Ok(())
+}
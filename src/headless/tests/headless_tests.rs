@@ -0,0 +1,219 @@
+use crate::apu::{AudioRecorder, WavFormat, STEM_NAMES};
+use crate::cpu::UnstableOpcodePolicy;
+use crate::headless::{run, HeadlessOptions, EXIT_HASH_MISMATCH, EXIT_TIMED_OUT};
+use crate::movie::{Movie, MovieFrame};
+use crate::rom::{Rom, RomHeader};
+use std::fs;
+use std::path::Path;
+
+/* LDA #value; STA address */
+fn store(program: &mut Vec<u8>, address: u16, value: u8) {
+    let [low, high] = address.to_le_bytes();
+    program.extend_from_slice(&[0xa9, value, 0x8d, low, high]);
+}
+
+fn store_signature(program: &mut Vec<u8>) {
+    store(program, 0x6001, 0xde);
+    store(program, 0x6002, 0xb0);
+    store(program, 0x6003, 0x61);
+}
+
+/* JMP to itself */
+fn spin(program: &mut Vec<u8>) {
+    let [low, high] = (0x8000 + program.len() as u16).to_le_bytes();
+    program.extend_from_slice(&[0x4c, low, high]);
+}
+
+/* an NROM cartridge with PRG-RAM, running program from $8000 */
+fn test_rom(program: &[u8]) -> Rom {
+    let mut prg_data = vec![0u8; 0x4000];
+    prg_data[..program.len()].copy_from_slice(program);
+    prg_data[0x3fff] = 0x40; /* RTI for NMI and IRQ */
+    prg_data[0x3ffa..0x3fff].copy_from_slice(&[0xff, 0xbf, 0x00, 0x80, 0xff]);
+
+    Rom {
+        header: RomHeader {
+            prg_ram_size: 0x2000,
+            ..RomHeader::default()
+        },
+        prg_data,
+        chr_data: vec![0; 0x2000],
+        _trainer: vec![],
+    }
+}
+
+fn finishing_rom(result: u8, text: &str) -> Rom {
+    let mut program = Vec::new();
+    store(&mut program, 0x6000, 0x80);
+    store_signature(&mut program);
+    for (i, byte) in text.bytes().chain([0]).enumerate() {
+        store(&mut program, 0x6004 + i as u16, byte);
+    }
+    store(&mut program, 0x6000, result);
+    spin(&mut program);
+    test_rom(&program)
+}
+
+fn options(max_frames: u64) -> HeadlessOptions {
+    HeadlessOptions {
+        max_frames,
+        png_path: None,
+        trace_path: None,
+        unstable_opcodes: UnstableOpcodePolicy::default(),
+        movie: None,
+        wav_path: None,
+        wav_format: WavFormat::default(),
+        wav_stems: false,
+    }
+}
+
+#[test]
+fn passing_test_rom_exits_cleanly() {
+    let report = run(&finishing_rom(0, "Passed\n"), &options(60)).unwrap();
+
+    assert!(report.test_detected);
+    assert_eq!(report.test_result, Some(0));
+    assert_eq!(report.test_text, "Passed\n");
+    assert_eq!(report.frames, 1);
+    assert_eq!(report.exit_code(None), 0);
+}
+
+#[test]
+fn failing_test_rom_exits_with_its_result_code() {
+    let report = run(&finishing_rom(3, "Failed #3"), &options(60)).unwrap();
+
+    assert_eq!(report.test_result, Some(3));
+    assert_eq!(report.test_text, "Failed #3");
+    assert_eq!(report.exit_code(None), 3);
+}
+
+#[test]
+fn unfinished_test_rom_times_out() {
+    let mut program = Vec::new();
+    store(&mut program, 0x6000, 0x80);
+    store_signature(&mut program);
+    spin(&mut program);
+
+    let report = run(&test_rom(&program), &options(30)).unwrap();
+
+    assert!(report.test_detected);
+    assert_eq!(report.test_result, None);
+    assert_eq!(report.frames, 30);
+    assert_eq!(report.exit_code(None), EXIT_TIMED_OUT);
+}
+
+#[test]
+fn reset_request_restarts_the_test_rom() {
+    let mut program = vec![0xad, 0x00, 0x61]; /* LDA $6100 */
+    let mut first_boot = Vec::new();
+    store(&mut first_boot, 0x6100, 1);
+    store_signature(&mut first_boot);
+    store(&mut first_boot, 0x6000, 0x81);
+    /* BNE over the first boot code, which ends in a 3 byte JMP */
+    program.extend_from_slice(&[0xd0, first_boot.len() as u8 + 3]);
+    program.extend_from_slice(&first_boot);
+    spin(&mut program);
+    store(&mut program, 0x6000, 0x80);
+    store(&mut program, 0x6004, 0);
+    store(&mut program, 0x6000, 0);
+    spin(&mut program);
+
+    let report = run(&test_rom(&program), &options(60)).unwrap();
+
+    assert_eq!(report.test_result, Some(0));
+    assert!(report.frames > 6);
+}
+
+#[test]
+fn rom_without_protocol_runs_for_every_frame() {
+    let mut program = Vec::new();
+    spin(&mut program);
+
+    let report = run(&test_rom(&program), &options(10)).unwrap();
+
+    assert!(!report.test_detected);
+    assert_eq!(report.frames, 10);
+    assert_eq!(report.test_text, "");
+    assert_eq!(report.exit_code(None), 0);
+}
+
+#[test]
+fn frame_hash_is_deterministic_and_checked() {
+    let rom = finishing_rom(0, "Passed");
+    let first = run(&rom, &options(60)).unwrap();
+    let second = run(&rom, &options(60)).unwrap();
+
+    assert_eq!(first.frame_hash, second.frame_hash);
+    assert_eq!(first.exit_code(Some(first.frame_hash)), 0);
+    assert_eq!(
+        first.exit_code(Some(first.frame_hash ^ 1)),
+        EXIT_HASH_MISMATCH
+    );
+}
+
+#[test]
+fn movie_runs_until_it_ends() {
+    let mut program = Vec::new();
+    spin(&mut program);
+    let mut movie = Movie::new("base64:AAAA", "test.nes");
+    movie.frames = vec![MovieFrame::default(); 10];
+    let options = HeadlessOptions {
+        movie: Some(movie),
+        ..options(60)
+    };
+
+    let report = run(&test_rom(&program), &options).unwrap();
+
+    assert_eq!(report.frames, 10);
+    assert_eq!(report.exit_code(None), 0);
+}
+
+#[test]
+fn sound_is_recorded_for_the_whole_run() {
+    let dir = std::env::temp_dir().join(format!("patina-headless-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("run.wav");
+    let mut program = Vec::new();
+    spin(&mut program);
+    let options = HeadlessOptions {
+        wav_path: Some(path.to_string_lossy().to_string()),
+        wav_stems: true,
+        ..options(10)
+    };
+
+    run(&test_rom(&program), &options).unwrap();
+
+    /* ten frames of 16-bit samples, 40 CPU cycles apart, after the header */
+    let size = fs::metadata(&path).unwrap().len();
+    assert!(
+        (44 + 2 * 7000..44 + 2 * 7500).contains(&size),
+        "{size} bytes"
+    );
+    for name in STEM_NAMES {
+        let stem = fs::metadata(AudioRecorder::stem_path(&path, name)).unwrap();
+        assert_eq!(stem.len(), size, "{name}");
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/* blargg's apu_test, which checks the frame counter's timing and the DMC down to the cycle */
+#[test]
+#[ignore = "needs test_roms/apu_test/rom_singles/"]
+fn blargg_apu_test() {
+    let singles = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_roms/apu_test/rom_singles");
+    for name in [
+        "1-len_ctr.nes",
+        "2-len_table.nes",
+        "3-irq_flag.nes",
+        "4-jitter.nes",
+        "5-len_timing.nes",
+        "6-irq_flag_timing.nes",
+        "7-dmc_basics.nes",
+        "8-dmc_rates.nes",
+    ] {
+        let rom = Rom::parse_file(singles.join(name).to_string_lossy().to_string())
+            .unwrap_or_else(|_| panic!("{} should be in test_roms/apu_test/rom_singles/", name));
+        let report = run(&rom, &options(1800)).unwrap();
+        assert_eq!(report.exit_code(None), 0, "{}: {}", name, report.test_text);
+    }
+}
@@ -0,0 +1 @@
+mod headless_tests;
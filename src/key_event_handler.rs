@@ -1,36 +1,46 @@
-use crate::config::SCREENSHOT_KEY;
-use crate::ppu::{WriteBuffer, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::config::{Config, Hotkey};
 use chrono::Utc;
-use image::codecs::png::PngEncoder;
-use image::{ExtendedColorType, ImageEncoder, ImageResult};
+use image::ImageResult;
+use patina::headless::write_png;
+use patina::{ButtonSource, WriteBuffer};
 use std::collections::HashSet;
-use std::fs::File;
-use std::io::Write;
+use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 use tao::event::{ElementState, KeyEvent};
 use tao::keyboard::Key;
-use tao::keyboard::Key::Character;
-
-type PressedKeys = Arc<Mutex<HashSet<Key<'static>>>>;
 
+/**
+ * Handles key presses: hotkeys of its own, like taking a screenshot, and the controllers' keys,
+ * which it turns into buttons held on each port for the emulation to read.
+ */
 pub struct KeyEventHandler {
-    pressed_keys: PressedKeys,
+    pressed_keys: HashSet<Key<'static>>,
+    buttons: ButtonSource,
     write_buffer: Arc<Mutex<WriteBuffer>>,
+    config: Config,
 }
 
 impl KeyEventHandler {
     pub fn new(
-        pressed_keys: PressedKeys,
+        buttons: ButtonSource,
         write_buffer: Arc<Mutex<WriteBuffer>>,
+        config: Config,
     ) -> KeyEventHandler {
         KeyEventHandler {
-            pressed_keys,
+            pressed_keys: HashSet::new(),
+            buttons,
             write_buffer,
+            config,
         }
     }
 
+    /* picks up rebound controls and hotkeys */
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+        self.update_buttons();
+    }
+
     // TODO document
-    // TODO how will this interact with configuration?
     pub fn set_write_buffer(&mut self, write_buffer: Arc<Mutex<WriteBuffer>>) {
         self.write_buffer = write_buffer;
     }
@@ -38,31 +48,29 @@ impl KeyEventHandler {
     pub fn handle_key_event(&mut self, key_event: &KeyEvent) {
         match key_event.state {
             ElementState::Pressed => {
-                self.pressed_keys
-                    .lock()
-                    .unwrap()
-                    .insert(key_event.logical_key.clone());
+                self.pressed_keys.insert(key_event.logical_key.clone());
+                self.update_buttons();
 
-                match &key_event.logical_key {
-                    Character(key) => match *key {
-                        SCREENSHOT_KEY => {
-                            self.take_screenshot();
-                        }
-                        _ => {}
-                    },
-                    _ => {}
+                /* the other hotkeys need the emulation, so the window handles them */
+                if let Some(Hotkey::Screenshot) = self.config.hotkey_for(&key_event.logical_key) {
+                    self.take_screenshot();
                 }
             }
             ElementState::Released => {
-                self.pressed_keys
-                    .lock()
-                    .unwrap()
-                    .remove(&key_event.logical_key.clone());
+                self.pressed_keys.remove(&key_event.logical_key.clone());
+                self.update_buttons();
             }
             _ => {}
         }
     }
 
+    fn update_buttons(&self) {
+        let buttons = self.config.controller_buttons(&self.pressed_keys);
+        for (port, buttons) in buttons.into_iter().enumerate() {
+            self.buttons[port].store(buttons, Ordering::Relaxed);
+        }
+    }
+
     // TODO check for errors
     // TODO document
     // TODO screenshot handling should probably be in its own type
@@ -77,16 +85,7 @@ impl KeyEventHandler {
     }
 
     fn take_screenshot_with_path(&self, path: String) -> ImageResult<()> {
-        let mut screenshot_path = File::create(path)?;
-        let encoder = PngEncoder::new(&screenshot_path);
-        encoder.write_image(
-            self.write_buffer.lock().unwrap().as_ref(),
-            DISPLAY_WIDTH,
-            DISPLAY_HEIGHT,
-            ExtendedColorType::Rgba8,
-        )?;
-        screenshot_path.flush().expect("TODO: panic message");
-        Ok(())
+        write_png(&path, &self.write_buffer.lock().unwrap())
     }
 
     // fn lol(&self, path: String) -> ImageResult<()> {
@@ -0,0 +1,49 @@
+/*!
+ * The emulator core, with no windowing or input devices of its own: parse a ROM, build an
+ * Emulator around it, then run it a frame (or an instruction) at a time, handing it the buttons
+ * held on each controller and taking back the finished frame, the sound it made, and the
+ * cartridge's save RAM. Nothing here reads the wall clock, so the same ROM and inputs always give
+ * the same output.
+ *
+ * ```no_run
+ * use patina::{Emulator, Input, Rom};
+ *
+ * let rom = Rom::parse_file("game.nes".to_string())?;
+ * let mut emulator = Emulator::new(&rom)?;
+ * let input = Input {
+ *     buttons: [Input::START, 0],
+ * };
+ * for _ in 0..60 {
+ *     let frame = emulator.run_frame(input);
+ *     let samples = emulator.audio_samples();
+ *     // draw frame, play samples at patina::SAMPLE_RATE...
+ * }
+ * let save_data = emulator.save_data();
+ * # Ok::<(), std::io::Error>(())
+ * ```
+ *
+ * The patina binary, built with the `window` feature, is the windowed frontend around this.
+ */
+/* without the audio feature there's no real-time runner, which is all that uses the machine's
+ * live controllers, speed and rewind
+ */
+#![cfg_attr(not(feature = "audio"), allow(dead_code))]
+
+mod apu;
+mod cpu;
+pub mod headless;
+mod mapper;
+pub mod movie;
+mod ppu;
+mod processor;
+mod rom;
+mod savestate;
+mod simulator;
+
+pub use apu::{AudioRecorder, WavFormat, SAMPLE_RATE};
+pub use cpu::{ButtonSource, UnstableOpcodePolicy};
+pub use ppu::{WriteBuffer, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+pub use rom::{ConsoleType, HeaderFormat, Rom, RomHeader, Timing};
+#[cfg(feature = "audio")]
+pub use simulator::program_state::ProgramState;
+pub use simulator::{Emulator, FrameBuffer, Input, RewindSettings, Speed};
@@ -1,35 +1,118 @@
 use clap::Parser;
-use std::collections::HashSet;
 use std::error::Error;
-use std::sync::{Arc, Mutex};
-
-mod cpu;
-mod rom;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
+use crate::config::Config;
 use crate::key_event_handler::KeyEventHandler;
-use crate::simulator::program_state::ProgramState;
-use rom::Rom;
+use patina::movie::{self, Movie};
+use patina::{headless, ButtonSource, ProgramState, Rom, UnstableOpcodePolicy, WavFormat};
 
-mod apu;
 mod config;
+mod gamepad;
 mod key_event_handler;
-mod mapper;
 mod menu;
-mod ppu;
-mod processor;
 mod renderer;
-mod simulator;
 mod window;
 
-fn main() -> Result<(), Box<dyn Error>> {
+fn main() -> Result<ExitCode, Box<dyn Error>> {
     let args = CommandLineArgs::parse();
 
-    let rom = Rom::parse_file(args.rom)?;
-    let keys = Arc::new(Mutex::new(HashSet::new()));
-    let program_state = ProgramState::simulate_async(&rom, &args.savefile, keys.clone());
-    let key_event_handler = KeyEventHandler::new(keys, program_state.write_buffer.clone());
+    let rom = Rom::parse_file(args.rom.clone())?;
+    if args.headless {
+        return run_headless(&rom, &args);
+    }
+    println!("Rom header: {:?}", rom.header);
+    println!("PRG size: {}", rom.prg_data.len());
+    println!("CHR size: {}", rom.chr_data.len());
+
+    let config_path = args
+        .config
+        .clone()
+        .map(PathBuf::from)
+        .or_else(config::default_path);
+    let config = match &config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    let keys = ButtonSource::default();
+    let pads = ButtonSource::default();
+    let trace = match &args.trace {
+        Some(path) => Some(Box::new(BufWriter::new(File::create(path)?)) as Box<dyn Write + Send>),
+        None => None,
+    };
+    let program_state = ProgramState::simulate_async(
+        &rom,
+        &args.savefile,
+        keys.clone(),
+        pads.clone(),
+        trace,
+        args.unstable_opcodes,
+    )?;
+    program_state.set_rewind_settings(config.rewind);
+    let key_event_handler =
+        KeyEventHandler::new(keys, program_state.write_buffer.clone(), config.clone());
+    let gamepads = gamepad::native_input(pads, &config);
+
+    let game = window::Game {
+        rom_checksum: movie::rom_checksum(&rom),
+        rom_path: args.rom,
+        savefile: args.savefile,
+        movie: args.movie.map(PathBuf::from),
+    };
+    window::initialize_ui(
+        program_state,
+        key_event_handler,
+        gamepads,
+        config,
+        config_path,
+        game,
+    )?;
+    Ok(ExitCode::SUCCESS)
+}
+
+fn run_headless(rom: &Rom, args: &CommandLineArgs) -> Result<ExitCode, Box<dyn Error>> {
+    let expected_hash = match &args.expect_hash {
+        Some(hash) => Some(u64::from_str_radix(hash.trim_start_matches("0x"), 16)?),
+        None => None,
+    };
+    let options = headless::HeadlessOptions {
+        max_frames: args.frames,
+        png_path: args.png.clone(),
+        trace_path: args.trace.clone(),
+        unstable_opcodes: args.unstable_opcodes,
+        movie: match &args.movie {
+            Some(path) => Some(Movie::load(Path::new(path))?),
+            None => None,
+        },
+        wav_path: args.wav.clone(),
+        wav_format: args.wav_format,
+        wav_stems: args.wav_stems,
+    };
+
+    let report = headless::run(rom, &options)?;
+
+    if !report.test_text.is_empty() {
+        println!("{}", report.test_text.trim_end());
+    }
+    match report.test_result {
+        Some(result) => println!(
+            "Test finished with result {} after {} frames",
+            result, report.frames
+        ),
+        None if report.test_detected => {
+            println!("Test did not finish within {} frames", report.frames)
+        }
+        None => println!("Ran {} frames", report.frames),
+    }
+    if args.hash || expected_hash.is_some() {
+        println!("Frame hash: {:016x}", report.frame_hash);
+    }
 
-    window::initialize_ui(program_state, key_event_handler, args.savefile)
+    Ok(ExitCode::from(report.exit_code(expected_hash)))
 }
 
 #[derive(Parser, Debug)]
@@ -42,4 +125,52 @@ struct CommandLineArgs {
     /// save file for games with battery-backed saves
     #[arg(short, long)]
     savefile: Option<String>,
+
+    /// settings file with key bindings; defaults to patina/config.ini in the user's config dir
+    #[arg(long)]
+    config: Option<String>,
+
+    /// log every CPU instruction to this file, in the format of nestest.log
+    #[arg(long)]
+    trace: Option<String>,
+
+    /// what to do on unstable unofficial opcodes like XAA and KIL: execute, log, or halt
+    #[arg(long, default_value = "execute")]
+    unstable_opcodes: UnstableOpcodePolicy,
+
+    /// play back this FCEUX .fm2 input movie from power-on
+    #[arg(long)]
+    movie: Option<String>,
+
+    /// run without a window or audio, as fast as possible, then exit with the test result
+    #[arg(long)]
+    headless: bool,
+
+    /// in headless mode, the most frames to run before giving up
+    #[arg(long, default_value_t = 3600)]
+    frames: u64,
+
+    /// in headless mode, write the final frame to this PNG file
+    #[arg(long)]
+    png: Option<String>,
+
+    /// in headless mode, print a hash of the final frame
+    #[arg(long)]
+    hash: bool,
+
+    /// in headless mode, fail unless the final frame has this hash (hex)
+    #[arg(long)]
+    expect_hash: Option<String>,
+
+    /// in headless mode, record the sound to this WAV file
+    #[arg(long)]
+    wav: Option<String>,
+
+    /// the WAV file's samples: pcm16 or float
+    #[arg(long, default_value = "pcm16")]
+    wav_format: WavFormat,
+
+    /// with --wav, also record each channel to its own file beside it, e.g. foo.pulse1.wav
+    #[arg(long)]
+    wav_stems: bool,
 }
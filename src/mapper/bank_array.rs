@@ -1,3 +1,6 @@
+use crate::savestate::{invalid_data, Savestate, StateReader, StateWriter};
+use std::io;
+
 pub struct BankArray {
     bank_size_log: usize,
     bank_size: usize,
@@ -45,7 +48,12 @@ impl BankArray {
     }
 
     pub fn set_last_bank(&mut self, index: u8) {
-        self.set_bank(index, (self.data.len() >> self.bank_size_log) as u8 - 1);
+        self.set_bank(index, self.bank_count() as u8 - 1);
+    }
+
+    /* number of banks of the current size in the backing data */
+    pub fn bank_count(&self) -> usize {
+        self.data.len() >> self.bank_size_log
     }
 
     pub fn set_bank(&mut self, index: u8, bank: u8) {
@@ -67,16 +75,6 @@ impl BankArray {
         }
     }
 
-    pub fn read_slice(&self, address: u16, size: usize) -> &[u8] {
-        let usize_addr = address as usize;
-        if usize_addr >= self.base_address {
-            let index = self.map_address(usize_addr);
-            &self.data[index..index + size]
-        } else {
-            &[]
-        }
-    }
-
     pub fn write(&mut self, address: u16, value: u8) {
         let usize_addr = address as usize;
         if usize_addr >= self.base_address {
@@ -85,8 +83,43 @@ impl BankArray {
         }
     }
 
+    /* the backing data; mappers with CHR-RAM include this in their save states */
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
     /* TODO explain */
     fn map_address(&self, address: usize) -> usize {
         self.banks[address >> self.bank_size_log] | (address & self.bank_size_mask)
     }
 }
+
+/* only the bank layout; the backing data is either ROM, or saved separately by the mapper */
+impl Savestate for BankArray {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.bank_size_log as u8);
+        writer.write_u32(self.banks.len() as u32);
+        for bank in &self.banks {
+            writer.write_u32(*bank as u32);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.change_bank_size(reader.read_u8()? as usize);
+        let bank_count = reader.read_u32()? as usize;
+        let mut banks = Vec::with_capacity(bank_count);
+        for _i in 0..bank_count {
+            let bank = reader.read_u32()? as usize;
+            if bank >= self.data.len() {
+                return Err(invalid_data("Save state bank is out of range"));
+            }
+            banks.push(bank);
+        }
+        self.banks = banks;
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use crate::mapper::bank_array::BankArray;
+use crate::mapper::{Mapper, SIZE_16_KB, SIZE_8_KB};
+use crate::ppu::NametableMirroring;
+use crate::rom::Rom;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
+/**
+ * Mapper 3: fixed PRG-ROM like NROM, and a register at 0x8000-0xffff selecting the 8kb CHR
+ * bank. The board doesn't stop the ROM driving the data bus while the CPU writes to it, so the
+ * register gets the written value ANDed with the ROM byte at that address.
+ */
+pub struct CNROM {
+    prg_banks: BankArray,
+    chr_bank: BankArray,
+    nametable_mirroring: NametableMirroring,
+}
+
+impl CNROM {
+    pub fn new(rom: &Rom) -> Self {
+        let mut chr_bank = BankArray::new(SIZE_8_KB, 0, rom.chr_rom_or_ram());
+        chr_bank.set_bank(0, 0);
+
+        /* 16kb of PRG-ROM is mirrored at 0xc000, since the last bank is also the first */
+        let mut prg_banks = BankArray::new(SIZE_16_KB, 0x8000, rom.prg_data.clone());
+        prg_banks.set_bank(0, 0);
+        prg_banks.set_last_bank(1);
+
+        CNROM {
+            nametable_mirroring: rom.nametable_mirroring(),
+            chr_bank,
+            prg_banks,
+        }
+    }
+}
+
+impl Mapper for CNROM {
+    fn read_prg(&self, address: u16) -> u8 {
+        self.prg_banks.read(address)
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            let value = value & self.prg_banks.read(address);
+            /* only 2 bits on the original boards, but oversized homebrew uses the rest */
+            let bank = value as usize % self.chr_bank.bank_count();
+            self.chr_bank.set_bank(0, bank as u8);
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr_bank.read(address)
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr_bank.write(address, value);
+    }
+
+    fn get_nametable_mirroring(&self) -> NametableMirroring {
+        self.nametable_mirroring.clone()
+    }
+}
+
+impl Savestate for CNROM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.chr_bank.save_state(writer);
+        writer.write_bytes(self.chr_bank.data());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.chr_bank.load_state(reader)?;
+        reader.read_bytes_into(self.chr_bank.data_mut())
+    }
+}
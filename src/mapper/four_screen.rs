@@ -0,0 +1,111 @@
+use crate::mapper::{Mapper, NametableSource, PPUFetch};
+use crate::ppu::NametableMirroring;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
+const VRAM_SIZE: usize = 1 << 12;
+
+/**
+ * Boards with the iNES four-screen bit set (e.g. Gauntlet, Rad Racer II) carry 4kb of their own
+ * VRAM wired to all four nametables, so each is distinct and whatever mirroring control the
+ * mapper has does nothing. This wraps the board's mapper, passing everything else through.
+ */
+pub struct FourScreenVram {
+    mapper: Box<dyn Mapper>,
+    vram: Vec<u8>,
+}
+
+impl FourScreenVram {
+    pub fn new(mapper: Box<dyn Mapper>) -> FourScreenVram {
+        FourScreenVram {
+            mapper,
+            vram: vec![0; VRAM_SIZE],
+        }
+    }
+}
+
+impl Mapper for FourScreenVram {
+    fn read_prg(&self, address: u16) -> u8 {
+        self.mapper.read_prg(address)
+    }
+
+    fn peek_prg(&self, address: u16) -> u8 {
+        self.mapper.peek_prg(address)
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        self.mapper.write_prg(address, value);
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.mapper.read_chr(address)
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.mapper.write_chr(address, value);
+    }
+
+    fn get_nametable_mirroring(&self) -> NametableMirroring {
+        NametableMirroring::FourScreen
+    }
+
+    fn nametable_source(&self, _nametable: u8) -> NametableSource {
+        NametableSource::Cartridge
+    }
+
+    fn read_nametable(&self, address: u16) -> u8 {
+        self.vram[address as usize & (VRAM_SIZE - 1)]
+    }
+
+    fn write_nametable(&mut self, address: u16, value: u8) {
+        self.vram[address as usize & (VRAM_SIZE - 1)] = value;
+    }
+
+    fn ppu_render_fetch(&mut self, address: u16, fetch: PPUFetch, value: u8) -> u8 {
+        self.mapper.ppu_render_fetch(address, fetch, value)
+    }
+
+    fn ppu_background_tile(&mut self, scanline: u8, column: u8) {
+        self.mapper.ppu_background_tile(scanline, column);
+    }
+
+    fn ppu_scanline_start(&mut self, scanline: u16, rendering: bool) {
+        self.mapper.ppu_scanline_start(scanline, rendering);
+    }
+
+    fn ppu_register_write(&mut self, address: u16, value: u8) {
+        self.mapper.ppu_register_write(address, value);
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.mapper.cpu_cycle();
+    }
+
+    fn expansion_audio(&self) -> f32 {
+        self.mapper.expansion_audio()
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.mapper.irq_pending()
+    }
+
+    fn get_save_data(&self) -> Option<Vec<u8>> {
+        self.mapper.get_save_data()
+    }
+
+    fn set_save_data(&mut self, data: &Vec<u8>) {
+        self.mapper.set_save_data(data);
+    }
+}
+
+impl Savestate for FourScreenVram {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.mapper.save_state(writer);
+        writer.write_bytes(&self.vram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.mapper.load_state(reader)?;
+        reader.read_bytes_into(&mut self.vram)
+    }
+}
@@ -0,0 +1,74 @@
+use crate::mapper::bank_array::BankArray;
+use crate::mapper::{Mapper, SIZE_32_KB, SIZE_8_KB};
+use crate::ppu::NametableMirroring;
+use crate::rom::Rom;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
+/**
+ * Mapper 66: one register at 0x8000-0xffff, with the 32kb PRG bank in bits 4-5 and the 8kb CHR
+ * bank in bits 0-1. Like CNROM, writes conflict with the ROM byte at the address written.
+ */
+pub struct GxROM {
+    prg_banks: BankArray,
+    chr_bank: BankArray,
+    nametable_mirroring: NametableMirroring,
+}
+
+impl GxROM {
+    pub fn new(rom: &Rom) -> Self {
+        let mut chr_bank = BankArray::new(SIZE_8_KB, 0, rom.chr_rom_or_ram());
+        chr_bank.set_bank(0, 0);
+
+        let mut prg_banks = BankArray::new(SIZE_32_KB, 0x8000, rom.prg_data.clone());
+        prg_banks.set_bank(0, 0);
+
+        GxROM {
+            nametable_mirroring: rom.nametable_mirroring(),
+            chr_bank,
+            prg_banks,
+        }
+    }
+}
+
+impl Mapper for GxROM {
+    fn read_prg(&self, address: u16) -> u8 {
+        self.prg_banks.read(address)
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address >= 0x8000 {
+            let value = value & self.prg_banks.read(address);
+            let prg_bank = ((value >> 4) & 0x3) as usize % self.prg_banks.bank_count();
+            let chr_bank = (value & 0x3) as usize % self.chr_bank.bank_count();
+            self.prg_banks.set_bank(0, prg_bank as u8);
+            self.chr_bank.set_bank(0, chr_bank as u8);
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr_bank.read(address)
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr_bank.write(address, value);
+    }
+
+    fn get_nametable_mirroring(&self) -> NametableMirroring {
+        self.nametable_mirroring.clone()
+    }
+}
+
+impl Savestate for GxROM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.prg_banks.save_state(writer);
+        self.chr_bank.save_state(writer);
+        writer.write_bytes(self.chr_bank.data());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.prg_banks.load_state(reader)?;
+        self.chr_bank.load_state(reader)?;
+        reader.read_bytes_into(self.chr_bank.data_mut())
+    }
+}
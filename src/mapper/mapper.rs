@@ -1,9 +1,32 @@
 use crate::ppu::NametableMirroring;
+use crate::savestate::Savestate;
 
-pub trait Mapper: Send {
+/* where the bytes of one of the PPU's four nametables come from */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NametableSource {
+    Ciram(u8), /* one of the two 1kb pages of the console's own nametable RAM */
+    Cartridge, /* the mapper's read_nametable and write_nametable: extra VRAM, ROM, etc. */
+}
+
+/* which of the bytes that make up a tile the PPU is fetching to draw */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PPUFetch {
+    Nametable,         /* the background tile's index */
+    Attribute,         /* the byte holding the background tile's palette */
+    BackgroundPattern, /* either bit plane of a row of a background tile */
+    SpritePattern,     /* either bit plane of a row of a sprite */
+}
+
+/* Savestate covers everything that can change while running: registers, RAM, CHR-RAM, etc. */
+pub trait Mapper: Send + Savestate {
     fn read_prg(&self, address: u16) -> u8;
 
-    fn read_prg_slice(&self, address: u16, size: usize) -> &[u8];
+    /* reads as read_prg does but without side effects, e.g. for debugging; only mappers whose
+     * registers change when read (e.g. MMC5's IRQ acknowledge) need to override this
+     */
+    fn peek_prg(&self, address: u16) -> u8 {
+        self.read_prg(address)
+    }
 
     fn write_prg(&mut self, address: u16, value: u8);
 
@@ -13,6 +36,71 @@ pub trait Mapper: Send {
 
     fn get_nametable_mirroring(&self) -> NametableMirroring;
 
+    /**
+     * Where each of the four nametables, at 0x2000, 0x2400, 0x2800, and 0x2c00, comes from. By
+     * default they're the console's RAM, arranged by get_nametable_mirroring; mappers wired to
+     * do something else can pick per nametable.
+     */
+    fn nametable_source(&self, nametable: u8) -> NametableSource {
+        self.get_nametable_mirroring().nametable_source(nametable)
+    }
+
+    /* reads a nametable byte that nametable_source puts on the cartridge; address is in
+     * 0x2000-0x2fff
+     */
+    fn read_nametable(&self, _address: u16) -> u8 {
+        0
+    }
+
+    fn write_nametable(&mut self, _address: u16, _value: u8) {}
+
+    /**
+     * Called by the PPU for each byte it fetches to draw while rendering, with the address and
+     * the byte it read there; whatever this returns is drawn instead. Reads through the PPU's
+     * data port don't count. Mappers that switch banks on what's being drawn (e.g. MMC2's
+     * latches, MMC3's scanline counter) watch these, and ones that draw from elsewhere (e.g.
+     * MMC5's split screen) substitute their own bytes.
+     */
+    fn ppu_render_fetch(&mut self, _address: u16, _fetch: PPUFetch, value: u8) -> u8 {
+        value
+    }
+
+    /**
+     * Called by the PPU while rendering just before it fetches each background tile, with the
+     * scanline the tile is drawn on and its column: 0 is the first, fetched at the end of the
+     * line before, and 32 is the one partly scrolled into view at the right edge.
+     */
+    fn ppu_background_tile(&mut self, _scanline: u8, _column: u8) {}
+
+    /**
+     * Called by the PPU at the start (dot 1) of every scanline, 0-261, with whether it's
+     * rendering then. Mappers that count scanlines by watching the PPU (e.g. MMC5) clock here.
+     */
+    fn ppu_scanline_start(&mut self, _scanline: u16, _rendering: bool) {}
+
+    /* called after the CPU writes one of the PPU's registers, 0x2000-0x2007, so mappers can
+     * snoop on them
+     */
+    fn ppu_register_write(&mut self, _address: u16, _value: u8) {}
+
+    /* called once every CPU cycle, for mappers with timers or sound of their own */
+    fn cpu_cycle(&mut self) {}
+
+    /**
+     * The output of the cartridge's own sound channels, if it has any, on the same scale as the
+     * APU's mix: a pair of full volume pulse waves adds about a quarter.
+     */
+    fn expansion_audio(&self) -> f32 {
+        0.0
+    }
+
+    /**
+     * Returns true if the mapper is currently asserting the CPU's IRQ line.
+     */
+    fn irq_pending(&self) -> bool {
+        false
+    }
+
     /**
      * Returns the current value of the data that would be saved in RAM, if it exists,
      * or None if this mapper doesn't support it.
@@ -2,6 +2,8 @@ use crate::mapper::bank_array::BankArray;
 use crate::mapper::{Mapper, SIZE_16_KB, SIZE_32_KB, SIZE_4_KB, SIZE_8_KB};
 use crate::ppu::NametableMirroring;
 use crate::rom::Rom;
+use crate::savestate::{invalid_data, Savestate, StateReader, StateWriter};
+use std::io;
 
 const SHIFT_REGISTER_INITIAL_VAL: u8 = 1 << 4;
 
@@ -20,7 +22,7 @@ enum PrgRomBankMode {
 
 pub struct MMC1 {
     shift_register: u8,
-    prg_ram: Box<[u8; 1 << 15]>, /* optional RAM; TODO size can only be determined in NES 2.0 ROMs*/
+    prg_ram: Vec<u8>, /* optional RAM; sized from the header, may be empty */
     prg_banks: BankArray,
     chr_banks: BankArray,
     chr_bank_0: u8,
@@ -34,11 +36,11 @@ pub struct MMC1 {
 impl MMC1 {
     pub fn new(rom: &Rom) -> MMC1 {
         let prg_banks = BankArray::new(SIZE_16_KB, 0x8000, rom.prg_data.clone());
-        let chr_banks = BankArray::new(SIZE_8_KB, 0, rom.chr_data.clone());
+        let chr_banks = BankArray::new(SIZE_8_KB, 0, rom.chr_rom_or_ram());
 
         let mut result = MMC1 {
             shift_register: SHIFT_REGISTER_INITIAL_VAL,
-            prg_ram: Box::new([0; 1 << 15]),
+            prg_ram: vec![0; rom.header.work_ram_size()],
             prg_banks,
             chr_banks,
             chr_bank_0: 0,
@@ -140,6 +142,9 @@ impl MMC1 {
         }
     }
 
+    /* TODO: boards with more than 8kb of PRG-RAM (SOROM, SXROM) bank it; only the first 8kb
+     * is reachable for now
+     */
     fn prg_ram_index(&self, address: u16) -> usize {
         address as usize - 0x6000
     }
@@ -148,25 +153,23 @@ impl MMC1 {
 impl Mapper for MMC1 {
     fn read_prg(&self, address: u16) -> u8 {
         if address < 0x8000 {
-            self.prg_ram[self.prg_ram_index(address)]
+            /* no RAM: reads are open bus */
+            self.prg_ram
+                .get(self.prg_ram_index(address))
+                .copied()
+                .unwrap_or(0)
         } else {
             self.prg_banks.read(address)
         }
     }
 
-    fn read_prg_slice(&self, address: u16, size: usize) -> &[u8] {
-        if address < 0x8000 {
-            let index = self.prg_ram_index(address);
-            &self.prg_ram[index..index + size]
-        } else {
-            self.prg_banks.read_slice(address, size)
-        }
-    }
-
     fn write_prg(&mut self, address: u16, value: u8) {
-        /* below 0x8000, it's writing to PRG-RAM, which we assume exists TODO update for NES 2.0 */
+        /* below 0x8000, it's writing to PRG-RAM, if the board has any */
         if address < 0x8000 {
-            self.prg_ram[self.prg_ram_index(address)] = value;
+            let index = self.prg_ram_index(address);
+            if let Some(byte) = self.prg_ram.get_mut(index) {
+                *byte = value;
+            }
         /* otherwise, writing to an MMC1 register */
         } else {
             self.listen_for_state_change(address, value);
@@ -186,10 +189,55 @@ impl Mapper for MMC1 {
     }
 
     fn get_save_data(&self) -> Option<Vec<u8>> {
-        Some(Vec::from(*self.prg_ram))
+        if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(self.prg_ram.clone())
+        }
     }
 
     fn set_save_data(&mut self, data: &Vec<u8>) {
-        self.prg_ram[0..data.len()].copy_from_slice(data);
+        /* tolerate save files from before RAM was sized from the header */
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[0..len].copy_from_slice(&data[0..len]);
+    }
+}
+
+impl Savestate for MMC1 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.shift_register);
+        writer.write_bytes(&self.prg_ram);
+        self.prg_banks.save_state(writer);
+        self.chr_banks.save_state(writer);
+        writer.write_bytes(self.chr_banks.data());
+        writer.write_u8(self.chr_bank_0);
+        writer.write_u8(self.chr_bank_1);
+        writer.write_bool(self.chr_bank_mode);
+        writer.write_u8(match self.prg_bank_mode {
+            PrgRomBankMode::Mode32kb => 0,
+            PrgRomBankMode::Mode16KbFixLower => 1,
+            PrgRomBankMode::Mode16KbFixUpper => 2,
+        });
+        writer.write_u8(self.prg_bank_index);
+        self.nametable_mirroring.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.shift_register = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.prg_ram)?;
+        self.prg_banks.load_state(reader)?;
+        self.chr_banks.load_state(reader)?;
+        reader.read_bytes_into(self.chr_banks.data_mut())?;
+        self.chr_bank_0 = reader.read_u8()?;
+        self.chr_bank_1 = reader.read_u8()?;
+        self.chr_bank_mode = reader.read_bool()?;
+        self.prg_bank_mode = match reader.read_u8()? {
+            0 => PrgRomBankMode::Mode32kb,
+            1 => PrgRomBankMode::Mode16KbFixLower,
+            2 => PrgRomBankMode::Mode16KbFixUpper,
+            mode => return Err(invalid_data(&format!("Invalid PRG bank mode {}", mode))),
+        };
+        self.prg_bank_index = reader.read_u8()?;
+        self.nametable_mirroring.load_state(reader)
     }
 }
@@ -0,0 +1,195 @@
+use crate::mapper::bank_array::BankArray;
+use crate::mapper::{Mapper, PPUFetch, SIZE_16_KB, SIZE_4_KB, SIZE_8_KB};
+use crate::ppu::NametableMirroring;
+use crate::rom::Rom;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
+/* the tiles whose fetches flip a latch, and which of each pair of CHR banks they select */
+const LATCH_FD: u8 = 0xfd;
+const LATCH_FE: u8 = 0xfe;
+
+/**
+ * MMC2 (mapper 9, Punch-Out!!) and MMC4 (mapper 10, the Fire Emblem games). Each 4kb half of
+ * CHR has two bank registers, and a latch choosing between them that flips when the PPU
+ * fetches tile 0xfd or 0xfe from that half, so a game can switch banks partway through a
+ * scanline just by putting one of those tiles in the nametable. The two chips differ only in
+ * PRG banking, and in MMC2 watching just one row of tile 0xfd/0xfe in the left half.
+ */
+pub struct MMC2 {
+    mmc4: bool,
+    prg_ram: Vec<u8>, /* MMC4 boards have 8kb at 0x6000-0x7fff, sized from the header */
+    prg_banks: BankArray,
+    chr_banks: BankArray,
+    chr_registers: [u8; 4], /* 0x0000 for 0xfd and 0xfe, then 0x1000 for 0xfd and 0xfe */
+    latches: [u8; 2],       /* LATCH_FD or LATCH_FE for each half */
+    nametable_mirroring: NametableMirroring,
+}
+
+impl MMC2 {
+    /* mapper 9: an 8kb switchable PRG bank, then the last three fixed */
+    pub fn new(rom: &Rom) -> MMC2 {
+        let mut prg_banks = BankArray::new(SIZE_8_KB, 0x8000, rom.prg_data.clone());
+        let bank_count = prg_banks.bank_count() as u8;
+        prg_banks.set_bank(0, 0);
+        for i in 1..4 {
+            prg_banks.set_bank(i, bank_count - 4 + i);
+        }
+        Self::with_prg_banks(rom, prg_banks, false)
+    }
+
+    /* mapper 10: a 16kb switchable PRG bank, then the last fixed */
+    pub fn new_mmc4(rom: &Rom) -> MMC2 {
+        let mut prg_banks = BankArray::new(SIZE_16_KB, 0x8000, rom.prg_data.clone());
+        prg_banks.set_bank(0, 0);
+        prg_banks.set_last_bank(1);
+        Self::with_prg_banks(rom, prg_banks, true)
+    }
+
+    fn with_prg_banks(rom: &Rom, prg_banks: BankArray, mmc4: bool) -> MMC2 {
+        let mut chr_banks = BankArray::new(SIZE_4_KB, 0, rom.chr_rom_or_ram());
+        chr_banks.set_bank(0, 0);
+        chr_banks.set_bank(1, 0);
+
+        let mut result = MMC2 {
+            mmc4,
+            prg_ram: vec![0; rom.header.work_ram_size()],
+            prg_banks,
+            chr_banks,
+            chr_registers: [0; 4],
+            latches: [LATCH_FE; 2],
+            nametable_mirroring: rom.nametable_mirroring(),
+        };
+        result.update_chr_banks();
+        result
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address & 0xf000 {
+            0xa000 => {
+                let bank = (value & 0xf) as usize % self.prg_banks.bank_count();
+                self.prg_banks.set_bank(0, bank as u8);
+            }
+            0xb000..=0xe000 => {
+                self.chr_registers[((address >> 12) - 0xb) as usize] = value & 0x1f;
+                self.update_chr_banks();
+            }
+            /* NB this crate names mirroring by the direction pages repeat */
+            0xf000 => {
+                self.nametable_mirroring = if value & 1 == 0 {
+                    NametableMirroring::Horizontal
+                } else {
+                    NametableMirroring::Vertical
+                };
+            }
+            _ => { /* 0x8000-0x9fff has no registers */ }
+        }
+    }
+
+    fn update_chr_banks(&mut self) {
+        let bank_count = self.chr_banks.bank_count();
+        for half in 0..2 {
+            let register = half * 2 + (self.latches[half] == LATCH_FE) as usize;
+            let bank = self.chr_registers[register] as usize % bank_count;
+            self.chr_banks.set_bank(half as u8, bank as u8);
+        }
+    }
+
+    fn prg_ram_index(&self, address: u16) -> usize {
+        address as usize - 0x6000
+    }
+}
+
+impl Mapper for MMC2 {
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        } else if address < 0x8000 {
+            match self.prg_ram.get(self.prg_ram_index(address)) {
+                Some(value) => *value,
+                None => 0, /* TODO open bus */
+            }
+        } else {
+            self.prg_banks.read(address)
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x6000 {
+            /* nothing mapped here */
+        } else if address < 0x8000 {
+            let index = self.prg_ram_index(address);
+            if let Some(byte) = self.prg_ram.get_mut(index) {
+                *byte = value;
+            }
+        } else {
+            self.write_register(address, value);
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr_banks.read(address)
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr_banks.write(address, value);
+    }
+
+    fn get_nametable_mirroring(&self) -> NametableMirroring {
+        self.nametable_mirroring.clone()
+    }
+
+    /* the latch flips once the fetch is done, so the tile that flips it is still drawn from the
+     * old bank
+     */
+    fn ppu_render_fetch(&mut self, address: u16, fetch: PPUFetch, value: u8) -> u8 {
+        if fetch == PPUFetch::Nametable || fetch == PPUFetch::Attribute {
+            return value;
+        }
+        let half = (address >> 12) as usize & 1;
+        let tile = (address >> 4) as u8;
+        let high_plane = address & 0x8 != 0;
+        /* MMC2 only sees the first row of the left half's tiles */
+        let row_matches = self.mmc4 || half == 1 || address & 0x7 == 0;
+        if high_plane && row_matches && (tile == LATCH_FD || tile == LATCH_FE) {
+            self.latches[half] = tile;
+            self.update_chr_banks();
+        }
+        value
+    }
+
+    fn get_save_data(&self) -> Option<Vec<u8>> {
+        if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(self.prg_ram.clone())
+        }
+    }
+
+    fn set_save_data(&mut self, data: &Vec<u8>) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[0..len].copy_from_slice(&data[0..len]);
+    }
+}
+
+impl Savestate for MMC2 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        self.prg_banks.save_state(writer);
+        self.chr_banks.save_state(writer);
+        writer.write_bytes(self.chr_banks.data());
+        writer.write_bytes(&self.chr_registers);
+        writer.write_bytes(&self.latches);
+        self.nametable_mirroring.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.prg_ram)?;
+        self.prg_banks.load_state(reader)?;
+        self.chr_banks.load_state(reader)?;
+        reader.read_bytes_into(self.chr_banks.data_mut())?;
+        reader.read_bytes_into(&mut self.chr_registers)?;
+        reader.read_bytes_into(&mut self.latches)?;
+        self.nametable_mirroring.load_state(reader)
+    }
+}
@@ -0,0 +1,295 @@
+use crate::mapper::bank_array::BankArray;
+use crate::mapper::{Mapper, PPUFetch, SIZE_1_KB, SIZE_8_KB};
+use crate::ppu::NametableMirroring;
+use crate::rom::Rom;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
+/* TODO only implements the "new"/Sharp IRQ behavior, where a reload to 0 fires every clock;
+ * MMC6 and the older NEC chips are not distinguished
+ */
+
+/* how many CPU cycles PPU A12 has to stay low for its next rise to clock the IRQ counter; more
+ * than the gaps between neighbouring fetches from the same pattern table, and far less than a
+ * line's worth of fetches from the other
+ */
+const A12_FILTER_CYCLES: u8 = 4;
+
+pub struct MMC3 {
+    prg_ram: Vec<u8>, /* up to 8kb at 0x6000-0x7fff, sized from the header */
+    prg_banks: BankArray,
+    chr_banks: BankArray,
+    bank_registers: [u8; 8], /* R0-R7, selected by the low bits of 0x8000 */
+    bank_select: u8,
+    prg_ram_enabled: bool,
+    prg_ram_write_protect: bool,
+    nametable_mirroring: NametableMirroring,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12_low_cycles: u8, /* CPU cycles since the PPU last fetched from 0x1000-0x1fff */
+}
+
+impl MMC3 {
+    pub fn new(rom: &Rom) -> MMC3 {
+        /* BankArray grows as banks are set in order, so populate every slot up front; after
+         * that the update functions can set them in whatever order the mode dictates
+         */
+        let mut prg_banks = BankArray::new(SIZE_8_KB, 0x8000, rom.prg_data.clone());
+        for i in 0..4 {
+            prg_banks.set_bank(i, 0);
+        }
+        let mut chr_banks = BankArray::new(SIZE_1_KB, 0, rom.chr_rom_or_ram());
+        for i in 0..8 {
+            chr_banks.set_bank(i, 0);
+        }
+
+        let mut result = MMC3 {
+            prg_ram: vec![0; rom.header.work_ram_size()],
+            prg_banks,
+            chr_banks,
+            bank_registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            bank_select: 0,
+            prg_ram_enabled: true,
+            prg_ram_write_protect: false,
+            nametable_mirroring: rom.nametable_mirroring(),
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12_low_cycles: 0,
+        };
+
+        result.update_prg_banks();
+        result.update_chr_banks();
+
+        result
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        /* registers are selected by address range and whether the address is even or odd */
+        let is_even = address & 1 == 0;
+        match (address & 0xe000, is_even) {
+            /* bank select: bits 0-2 choose the register, bit 6 the PRG mode, bit 7 CHR inversion */
+            (0x8000, true) => {
+                self.bank_select = value;
+                self.update_prg_banks();
+                self.update_chr_banks();
+            }
+            /* bank data */
+            (0x8000, false) => {
+                self.bank_registers[(self.bank_select & 0x7) as usize] = value;
+                self.update_prg_banks();
+                self.update_chr_banks();
+            }
+            /* mirroring; NB this crate names mirroring by the direction pages repeat */
+            (0xa000, true) => {
+                self.nametable_mirroring = if value & 1 == 0 {
+                    NametableMirroring::Horizontal
+                } else {
+                    NametableMirroring::Vertical
+                };
+            }
+            /* PRG-RAM protect */
+            (0xa000, false) => {
+                self.prg_ram_enabled = value & 0x80 != 0;
+                self.prg_ram_write_protect = value & 0x40 != 0;
+            }
+            (0xc000, true) => {
+                self.irq_latch = value;
+            }
+            /* IRQ reload: the counter is reloaded from the latch on the next clock */
+            (0xc000, false) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            /* IRQ disable, which also acknowledges any pending interrupt */
+            (0xe000, true) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            (0xe000, false) => {
+                self.irq_enabled = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    fn update_prg_banks(&mut self) {
+        let bank_count = self.prg_banks.bank_count();
+        let r6 = self.bank_registers[6] as usize % bank_count;
+        let r7 = self.bank_registers[7] as usize % bank_count;
+        /* a ROM with just the one 8kb bank has it everywhere */
+        let second_to_last = bank_count.saturating_sub(2) as u8;
+
+        /* in mode 0, 0x8000 is switchable and 0xc000 is fixed to the second-to-last bank;
+         * mode 1 swaps those two. 0xa000 is always R7, and 0xe000 always the last bank
+         */
+        if self.bank_select & 0x40 == 0 {
+            self.prg_banks.set_bank(0, r6 as u8);
+            self.prg_banks.set_bank(2, second_to_last);
+        } else {
+            self.prg_banks.set_bank(0, second_to_last);
+            self.prg_banks.set_bank(2, r6 as u8);
+        }
+        self.prg_banks.set_bank(1, r7 as u8);
+        self.prg_banks.set_last_bank(3);
+    }
+
+    fn update_chr_banks(&mut self) {
+        let bank_count = self.chr_banks.bank_count();
+        let bank = |register: u8| (register as usize % bank_count) as u8;
+        /* with CHR inversion, the two 2kb banks and four 1kb banks trade places */
+        let base_2kb = if self.bank_select & 0x80 == 0 { 0 } else { 4 };
+        let base_1kb = base_2kb ^ 4;
+
+        /* R0 and R1 select 2kb banks, so their low bit is ignored */
+        let r0 = self.bank_registers[0] & !1;
+        let r1 = self.bank_registers[1] & !1;
+        self.chr_banks.set_bank(base_2kb, bank(r0));
+        self.chr_banks.set_bank(base_2kb + 1, bank(r0 | 1));
+        self.chr_banks.set_bank(base_2kb + 2, bank(r1));
+        self.chr_banks.set_bank(base_2kb + 3, bank(r1 | 1));
+
+        for i in 0..4 {
+            self.chr_banks
+                .set_bank(base_1kb + i, bank(self.bank_registers[2 + i as usize]));
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn prg_ram_index(&self, address: u16) -> usize {
+        address as usize - 0x6000
+    }
+}
+
+impl Mapper for MMC3 {
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        } else if address < 0x8000 {
+            match self.prg_ram.get(self.prg_ram_index(address)) {
+                Some(value) if self.prg_ram_enabled => *value,
+                _ => 0, /* TODO open bus */
+            }
+        } else {
+            self.prg_banks.read(address)
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x6000 {
+            /* nothing mapped here */
+        } else if address < 0x8000 {
+            let index = self.prg_ram_index(address);
+            if self.prg_ram_enabled && !self.prg_ram_write_protect {
+                if let Some(byte) = self.prg_ram.get_mut(index) {
+                    *byte = value;
+                }
+            }
+        } else {
+            self.write_register(address, value);
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr_banks.read(address)
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr_banks.write(address, value);
+    }
+
+    fn get_nametable_mirroring(&self) -> NametableMirroring {
+        self.nametable_mirroring.clone()
+    }
+
+    /* the IRQ counter is clocked when PPU address line A12 rises after staying low for a few
+     * CPU cycles, which ignores the brief lows between fetches from the 0x1000 pattern table.
+     * When the background and sprites use different tables that's once a line.
+     */
+    fn ppu_render_fetch(&mut self, address: u16, _fetch: PPUFetch, value: u8) -> u8 {
+        if address & 0x1000 != 0 {
+            if self.a12_low_cycles >= A12_FILTER_CYCLES {
+                self.clock_irq_counter();
+            }
+            self.a12_low_cycles = 0;
+        }
+        value
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn get_save_data(&self) -> Option<Vec<u8>> {
+        if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(self.prg_ram.clone())
+        }
+    }
+
+    fn set_save_data(&mut self, data: &Vec<u8>) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[0..len].copy_from_slice(&data[0..len]);
+    }
+}
+
+impl Savestate for MMC3 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        self.prg_banks.save_state(writer);
+        self.chr_banks.save_state(writer);
+        writer.write_bytes(self.chr_banks.data());
+        writer.write_bytes(&self.bank_registers);
+        writer.write_u8(self.bank_select);
+        writer.write_bool(self.prg_ram_enabled);
+        writer.write_bool(self.prg_ram_write_protect);
+        self.nametable_mirroring.save_state(writer);
+        writer.write_u8(self.irq_latch);
+        writer.write_u8(self.irq_counter);
+        writer.write_bool(self.irq_reload);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_pending);
+        writer.write_u8(self.a12_low_cycles);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.prg_ram)?;
+        self.prg_banks.load_state(reader)?;
+        self.chr_banks.load_state(reader)?;
+        reader.read_bytes_into(self.chr_banks.data_mut())?;
+        reader.read_bytes_into(&mut self.bank_registers)?;
+        self.bank_select = reader.read_u8()?;
+        self.prg_ram_enabled = reader.read_bool()?;
+        self.prg_ram_write_protect = reader.read_bool()?;
+        self.nametable_mirroring.load_state(reader)?;
+        self.irq_latch = reader.read_u8()?;
+        self.irq_counter = reader.read_u8()?;
+        self.irq_reload = reader.read_bool()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_pending = reader.read_bool()?;
+        self.a12_low_cycles = reader.read_u8()?;
+        Ok(())
+    }
+}
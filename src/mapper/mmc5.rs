@@ -0,0 +1,603 @@
+use crate::apu::{mix_pulses, mix_tnd, Pulse};
+use crate::mapper::{
+    Mapper, NametableSource, PPUFetch, SIZE_16_KB, SIZE_32_KB, SIZE_4_KB, SIZE_8_KB,
+};
+use crate::ppu::NametableMirroring;
+use crate::rom::Rom;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::cell::Cell;
+use std::io;
+
+const EXRAM_SIZE: usize = 1 << 10;
+
+/* what 0x5104 makes of ExRAM */
+const EXRAM_NAMETABLE: u8 = 0; /* a nametable, wherever 0x5105 puts it */
+const EXRAM_EXTENDED_ATTRIBUTES: u8 = 1; /* a palette and CHR bank for each background tile */
+const EXRAM_RAM: u8 = 2; /* plain RAM for the CPU; 3 makes it read-only */
+
+/* what 0x5105 puts in each nametable, 2 bits apiece */
+const NAMETABLE_EXRAM: u8 = 2;
+const NAMETABLE_FILL: u8 = 3;
+
+/* CPU cycles between clocks of the sound channels' envelopes and length counters, which come at
+ * a steady 240Hz rather than from the APU's frame counter
+ */
+const AUDIO_FRAME_PERIOD: u16 = 7457;
+
+/* where an address at 0x6000-0xffff reads from, with the offset into it */
+enum PrgTarget {
+    Rom(usize),
+    Ram(usize),
+}
+
+/**
+ * MMC5 (mapper 5; Castlevania III, the Koei strategy games). Besides fine-grained PRG and CHR
+ * banking and up to 64kb of PRG-RAM, it has 1kb of its own RAM, ExRAM, which can serve as a
+ * nametable, as a palette and CHR bank for every background tile (extended attributes), or as
+ * the nametable of a vertical split screen region. It can also fill a nametable with a single
+ * tile, bank sprites and the background separately in 8x16 sprite mode, count scanlines to raise
+ * an IRQ, multiply, and play two more pulse channels and 8-bit PCM. It watches the PPU's fetches
+ * to do much of this, so it needs to see them all.
+ */
+pub struct MMC5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>, /* up to 64kb, in 8kb banks, sized from the header */
+    chr: Vec<u8>,
+    prg_mode: u8,
+    prg_registers: [u8; 5], /* 0x5113-0x5117; bit 7 of 0x5114-0x5116 picks ROM over RAM */
+    prg_ram_protect: [u8; 2], /* 0x5102 and 0x5103; RAM is only writable as 2 and 1 */
+    chr_mode: u8,
+    chr_registers: [u16; 12], /* set A, 0x5120-0x5127, then set B, 0x5128-0x512b */
+    chr_upper: u8,            /* 0x5130: the high bits of CHR registers written after it */
+    chr_b_written_last: bool, /* which set of CHR registers the CPU last wrote */
+    tall_sprites: bool,       /* snooped from PPUCTRL */
+    exram: Vec<u8>,
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_color: u8,
+    split_control: u8, /* 0x5200: enable, which side, and how many tiles across */
+    split_scroll: u8,
+    split_bank: u8,
+    /* the background tile being fetched */
+    in_split: bool,
+    split_y: u8,
+    split_column: u8,
+    split_tile: u8,
+    extended_attribute: u8, /* the tile's ExRAM byte, with extended attributes */
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: Cell<bool>, /* acknowledged by reading 0x5204 */
+    in_frame: bool,
+    scanline_counter: u8,
+    multiplicand: u8,
+    multiplier: u8,
+    audio: MMC5Audio,
+}
+
+impl MMC5 {
+    pub fn new(rom: &Rom) -> MMC5 {
+        MMC5 {
+            prg_rom: rom.prg_data.clone(),
+            prg_ram: vec![0; rom.header.work_ram_size()],
+            chr: rom.chr_rom_or_ram(),
+            /* mode 3 with the last bank everywhere, so the reset vector is there to start */
+            prg_mode: 3,
+            prg_registers: [0, 0xff, 0xff, 0xff, 0xff],
+            prg_ram_protect: [0; 2],
+            chr_mode: 0,
+            chr_registers: [0; 12],
+            chr_upper: 0,
+            chr_b_written_last: false,
+            tall_sprites: false,
+            exram: vec![0; EXRAM_SIZE],
+            exram_mode: EXRAM_NAMETABLE,
+            nametable_mapping: 0,
+            fill_tile: 0,
+            fill_color: 0,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            in_split: false,
+            split_y: 0,
+            split_column: 0,
+            split_tile: 0,
+            extended_attribute: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: Cell::new(false),
+            in_frame: false,
+            scanline_counter: 0,
+            multiplicand: 0xff,
+            multiplier: 0xff,
+            audio: MMC5Audio::new(),
+        }
+    }
+
+    fn read_register(&self, address: u16) -> u8 {
+        let value = self.peek_register(address);
+        if address == 0x5204 {
+            self.irq_pending.set(false);
+        }
+        value
+    }
+
+    /* what reading a register gives, without acknowledging the IRQ */
+    fn peek_register(&self, address: u16) -> u8 {
+        match address {
+            0x5015 => self.audio.read_status(),
+            0x5204 => (self.irq_pending.get() as u8) << 7 | (self.in_frame as u8) << 6,
+            0x5205 => self.product() as u8,
+            0x5206 => (self.product() >> 8) as u8,
+            0x5c00..=0x5fff if self.exram_mode >= EXRAM_RAM => {
+                self.exram[address as usize & (EXRAM_SIZE - 1)]
+            }
+            _ => 0, /* TODO open bus */
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address {
+            0x5000..=0x5015 => self.audio.write(address, value),
+            0x5100 => self.prg_mode = value & 3,
+            0x5101 => self.chr_mode = value & 3,
+            0x5102 | 0x5103 => self.prg_ram_protect[(address - 0x5102) as usize] = value & 3,
+            0x5104 => self.exram_mode = value & 3,
+            0x5105 => self.nametable_mapping = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_color = value & 3,
+            0x5113..=0x5117 => self.prg_registers[(address - 0x5113) as usize] = value,
+            0x5120..=0x512b => {
+                self.chr_registers[(address - 0x5120) as usize] =
+                    value as u16 | (self.chr_upper as u16) << 8;
+                self.chr_b_written_last = address >= 0x5128;
+            }
+            0x5130 => self.chr_upper = value & 3,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5c00..=0x5fff => {
+                let index = address as usize & (EXRAM_SIZE - 1);
+                match self.exram_mode {
+                    /* the PPU has it while drawing; the CPU can only write zeroes otherwise */
+                    EXRAM_NAMETABLE | EXRAM_EXTENDED_ATTRIBUTES => {
+                        self.exram[index] = if self.in_frame { value } else { 0 };
+                    }
+                    EXRAM_RAM => self.exram[index] = value,
+                    _ => { /* read-only */ }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn product(&self) -> u16 {
+        self.multiplicand as u16 * self.multiplier as u16
+    }
+
+    /* registers count 8kb banks, ignoring the low bits when they select larger ones; 0x5117
+     * always selects ROM
+     */
+    fn map_prg(&self, address: u16) -> PrgTarget {
+        let (register, size_log) = match (self.prg_mode, address) {
+            (_, 0x6000..=0x7fff) => (0, SIZE_8_KB),
+            (0, _) => (4, SIZE_32_KB),
+            (1, 0x8000..=0xbfff) | (2, 0x8000..=0xbfff) => (2, SIZE_16_KB),
+            (1, _) => (4, SIZE_16_KB),
+            (2, 0xc000..=0xdfff) => (3, SIZE_8_KB),
+            (2, _) => (4, SIZE_8_KB),
+            _ => (((address - 0x8000) >> 13) as usize + 1, SIZE_8_KB),
+        };
+        let value = self.prg_registers[register];
+        let size_mask = (1 << size_log) - 1;
+        let offset =
+            (((value & 0x7f) as usize) << SIZE_8_KB) & !size_mask | (address as usize & size_mask);
+        if register == 4 || (register > 0 && value & 0x80 != 0) {
+            PrgTarget::Rom(offset % self.prg_rom.len())
+        } else {
+            PrgTarget::Ram(offset % self.prg_ram.len().max(1))
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [2, 1]
+    }
+
+    /* the offset into CHR of a pattern address, through set A or set B; each set has a register
+     * per 1kb, and bigger banks use the last register of each group. Set B only covers 4kb,
+     * which appears twice
+     */
+    fn chr_offset(&self, address: u16, set_b: bool) -> usize {
+        let mode = self.chr_mode as usize;
+        let size_log = SIZE_8_KB - mode;
+        let group = 1 << (3 - mode);
+        let register = if !set_b {
+            ((address as usize >> size_log) + 1) * group - 1
+        } else if mode == 0 {
+            11
+        } else {
+            8 + (((address as usize & 0xfff) >> size_log) + 1) * group - 1
+        };
+        let bank = self.chr_registers[register] as usize;
+        ((bank << size_log) | (address as usize & ((1 << size_log) - 1))) % self.chr.len()
+    }
+
+    /* in 8x16 sprite mode, sprites draw from set A and the background from set B; otherwise
+     * (and for the CPU) it's whichever was written last
+     */
+    fn chr_set_b(&self, fetch: PPUFetch) -> bool {
+        match (self.tall_sprites, fetch) {
+            (true, PPUFetch::SpritePattern) => false,
+            (true, PPUFetch::BackgroundPattern) => true,
+            _ => self.chr_b_written_last,
+        }
+    }
+
+    /* the split region draws from ExRAM as a nametable, scrolled by 0x5201, with its own 4kb
+     * CHR bank; attributes are repeated across the byte since the PPU picks from it by its own
+     * position
+     */
+    fn split_fetch(&mut self, address: u16, fetch: PPUFetch) -> u8 {
+        let column = self.split_column as usize;
+        let row = self.split_y as usize / 8;
+        match fetch {
+            PPUFetch::Nametable => {
+                self.split_tile = self.exram[row * 32 + column];
+                self.split_tile
+            }
+            PPUFetch::Attribute => {
+                let attribute = self.exram[0x3c0 + row / 4 * 8 + column / 4];
+                let shift = (row & 2) << 1 | (column & 2);
+                ((attribute >> shift) & 3) * 0x55
+            }
+            _ => {
+                let offset = (self.split_bank as usize) << SIZE_4_KB
+                    | (self.split_tile as usize) << 4
+                    | (address as usize & 8)
+                    | (self.split_y as usize & 7);
+                self.chr[offset % self.chr.len()]
+            }
+        }
+    }
+}
+
+impl Mapper for MMC5 {
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            self.read_register(address)
+        } else {
+            match self.map_prg(address) {
+                PrgTarget::Rom(offset) => self.prg_rom[offset],
+                PrgTarget::Ram(offset) => match self.prg_ram.get(offset) {
+                    Some(value) => *value,
+                    None => 0, /* TODO open bus */
+                },
+            }
+        }
+    }
+
+    fn peek_prg(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            self.peek_register(address)
+        } else {
+            self.read_prg(address)
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x6000 {
+            self.write_register(address, value);
+        } else if let PrgTarget::Ram(offset) = self.map_prg(address) {
+            if self.prg_ram_writable() {
+                if let Some(byte) = self.prg_ram.get_mut(offset) {
+                    *byte = value;
+                }
+            }
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr[self.chr_offset(address, self.chr_b_written_last)]
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        let offset = self.chr_offset(address, self.chr_b_written_last);
+        self.chr[offset] = value;
+    }
+
+    /* only approximate, since each nametable can be any of four things */
+    fn get_nametable_mirroring(&self) -> NametableMirroring {
+        match self.nametable_mapping {
+            0x00 => NametableMirroring::SingleNametable0,
+            0x55 => NametableMirroring::SingleNametable1,
+            0x44 => NametableMirroring::Horizontal,
+            0x50 => NametableMirroring::Vertical,
+            _ => NametableMirroring::FourScreen,
+        }
+    }
+
+    fn nametable_source(&self, nametable: u8) -> NametableSource {
+        match (self.nametable_mapping >> (nametable * 2)) & 3 {
+            page @ 0..=1 => NametableSource::Ciram(page),
+            _ => NametableSource::Cartridge,
+        }
+    }
+
+    fn read_nametable(&self, address: u16) -> u8 {
+        let index = address as usize & (EXRAM_SIZE - 1);
+        match (self.nametable_mapping >> (((address >> 10) & 3) * 2)) & 3 {
+            NAMETABLE_EXRAM if self.exram_mode <= EXRAM_EXTENDED_ATTRIBUTES => self.exram[index],
+            NAMETABLE_FILL if index >= 0x3c0 => self.fill_color * 0x55,
+            NAMETABLE_FILL => self.fill_tile,
+            _ => 0,
+        }
+    }
+
+    fn write_nametable(&mut self, address: u16, value: u8) {
+        let nametable = (address >> 10) & 3;
+        if (self.nametable_mapping >> (nametable * 2)) & 3 == NAMETABLE_EXRAM
+            && self.exram_mode <= EXRAM_EXTENDED_ATTRIBUTES
+        {
+            self.exram[address as usize & (EXRAM_SIZE - 1)] = value;
+        }
+    }
+
+    fn ppu_render_fetch(&mut self, address: u16, fetch: PPUFetch, value: u8) -> u8 {
+        if self.in_split && fetch != PPUFetch::SpritePattern {
+            return self.split_fetch(address, fetch);
+        }
+        let extended = self.exram_mode == EXRAM_EXTENDED_ATTRIBUTES;
+        match fetch {
+            PPUFetch::Nametable => {
+                if extended {
+                    self.extended_attribute = self.exram[address as usize & (EXRAM_SIZE - 1)];
+                }
+                value
+            }
+            /* with extended attributes, the top two bits are the palette and the rest pick a 4kb
+             * bank of CHR
+             */
+            PPUFetch::Attribute if extended => (self.extended_attribute >> 6) * 0x55,
+            PPUFetch::BackgroundPattern if extended => {
+                let bank =
+                    (self.extended_attribute & 0x3f) as usize | (self.chr_upper as usize) << 6;
+                self.chr[((bank << SIZE_4_KB) | (address as usize & 0xfff)) % self.chr.len()]
+            }
+            PPUFetch::Attribute => value,
+            PPUFetch::BackgroundPattern | PPUFetch::SpritePattern => {
+                self.chr[self.chr_offset(address, self.chr_set_b(fetch))]
+            }
+        }
+    }
+
+    fn ppu_background_tile(&mut self, scanline: u8, column: u8) {
+        let threshold = self.split_control & 0x1f;
+        let right_side = self.split_control & 0x40 != 0;
+        self.in_split = self.split_control & 0x80 != 0
+            && self.exram_mode <= EXRAM_EXTENDED_ATTRIBUTES
+            && (column >= threshold) == right_side;
+        if self.in_split {
+            self.split_y = ((self.split_scroll as u16 + scanline as u16) % 240) as u8;
+            self.split_column = column & 0x1f;
+        }
+    }
+
+    /* the scanline counter starts over with each frame, and fires when it reaches 0x5203 */
+    fn ppu_scanline_start(&mut self, scanline: u16, rendering: bool) {
+        if !rendering || scanline >= 240 {
+            self.in_frame = false;
+        } else if self.in_frame {
+            self.scanline_counter = self.scanline_counter.wrapping_add(1);
+            if self.scanline_counter == self.irq_compare {
+                self.irq_pending.set(true);
+            }
+        } else {
+            self.in_frame = true;
+            self.scanline_counter = 0;
+        }
+    }
+
+    fn ppu_register_write(&mut self, address: u16, value: u8) {
+        if address == 0x2000 {
+            self.tall_sprites = value & 0x20 != 0;
+        }
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.audio.cycle();
+    }
+
+    fn expansion_audio(&self) -> f32 {
+        self.audio.level()
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq_enabled && self.irq_pending.get()
+    }
+
+    fn get_save_data(&self) -> Option<Vec<u8>> {
+        if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(self.prg_ram.clone())
+        }
+    }
+
+    fn set_save_data(&mut self, data: &Vec<u8>) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[0..len].copy_from_slice(&data[0..len]);
+    }
+}
+
+impl Savestate for MMC5 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        writer.write_bytes(&self.chr);
+        writer.write_u8(self.prg_mode);
+        writer.write_bytes(&self.prg_registers);
+        writer.write_bytes(&self.prg_ram_protect);
+        writer.write_u8(self.chr_mode);
+        for register in self.chr_registers {
+            writer.write_u16(register);
+        }
+        writer.write_u8(self.chr_upper);
+        writer.write_bool(self.chr_b_written_last);
+        writer.write_bool(self.tall_sprites);
+        writer.write_bytes(&self.exram);
+        writer.write_u8(self.exram_mode);
+        writer.write_u8(self.nametable_mapping);
+        writer.write_u8(self.fill_tile);
+        writer.write_u8(self.fill_color);
+        writer.write_u8(self.split_control);
+        writer.write_u8(self.split_scroll);
+        writer.write_u8(self.split_bank);
+        writer.write_bool(self.in_split);
+        writer.write_u8(self.split_y);
+        writer.write_u8(self.split_column);
+        writer.write_u8(self.split_tile);
+        writer.write_u8(self.extended_attribute);
+        writer.write_u8(self.irq_compare);
+        writer.write_bool(self.irq_enabled);
+        writer.write_bool(self.irq_pending.get());
+        writer.write_bool(self.in_frame);
+        writer.write_u8(self.scanline_counter);
+        writer.write_u8(self.multiplicand);
+        writer.write_u8(self.multiplier);
+        self.audio.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.prg_ram)?;
+        reader.read_bytes_into(&mut self.chr)?;
+        self.prg_mode = reader.read_u8()? & 3;
+        reader.read_bytes_into(&mut self.prg_registers)?;
+        reader.read_bytes_into(&mut self.prg_ram_protect)?;
+        self.chr_mode = reader.read_u8()? & 3;
+        for register in &mut self.chr_registers {
+            *register = reader.read_u16()?;
+        }
+        self.chr_upper = reader.read_u8()?;
+        self.chr_b_written_last = reader.read_bool()?;
+        self.tall_sprites = reader.read_bool()?;
+        reader.read_bytes_into(&mut self.exram)?;
+        self.exram_mode = reader.read_u8()? & 3;
+        self.nametable_mapping = reader.read_u8()?;
+        self.fill_tile = reader.read_u8()?;
+        self.fill_color = reader.read_u8()? & 3;
+        self.split_control = reader.read_u8()?;
+        self.split_scroll = reader.read_u8()?;
+        self.split_bank = reader.read_u8()?;
+        self.in_split = reader.read_bool()?;
+        self.split_y = reader.read_u8()?;
+        self.split_column = reader.read_u8()? & 0x1f;
+        self.split_tile = reader.read_u8()?;
+        self.extended_attribute = reader.read_u8()?;
+        self.irq_compare = reader.read_u8()?;
+        self.irq_enabled = reader.read_bool()?;
+        self.irq_pending.set(reader.read_bool()?);
+        self.in_frame = reader.read_bool()?;
+        self.scanline_counter = reader.read_u8()?;
+        self.multiplicand = reader.read_u8()?;
+        self.multiplier = reader.read_u8()?;
+        self.audio.load_state(reader)
+    }
+}
+
+/**
+ * MMC5's sound: two pulse channels like the APU's, minus the sweep units, and an 8-bit PCM
+ * channel the CPU writes samples to directly. Unlike the MMC5's, these pulses are silent at
+ * periods below 8, as the APU's are.
+ */
+struct MMC5Audio {
+    pulses: [Pulse; 2],
+    pcm_control: u8, /* 0x5010 */
+    pcm: u8,
+    frame_clock: u16, /* CPU cycles since the envelopes and length counters were last clocked */
+    apu_cycle: bool,  /* whether the pulses' timers run this CPU cycle, as on the APU's */
+}
+
+impl MMC5Audio {
+    fn new() -> MMC5Audio {
+        MMC5Audio {
+            pulses: [Pulse::new(0x5000, true), Pulse::new(0x5004, false)],
+            pcm_control: 0,
+            pcm: 0,
+            frame_clock: 0,
+            apu_cycle: false,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        match address {
+            /* no sweep, so 0x5001 and 0x5005 do nothing */
+            0x5001 | 0x5005 => {}
+            0x5000..=0x5007 => {
+                self.pulses[(address as usize >> 2) & 1].write_register(address & 3, value)
+            }
+            /* TODO read mode, where the channel plays what the CPU reads from 0x8000-0xbfff,
+             * and its IRQ
+             */
+            0x5010 => self.pcm_control = value,
+            /* 0 can't be written, since it would end a sample in read mode */
+            0x5011 if value != 0 => self.pcm = value,
+            0x5015 => {
+                self.pulses[0].set_enabled(value & 1 != 0);
+                self.pulses[1].set_enabled(value & 2 != 0);
+            }
+            _ => {}
+        }
+    }
+
+    /* which pulses' length counters are still running */
+    fn read_status(&self) -> u8 {
+        (self.pulses[0].is_active() as u8) | (self.pulses[1].is_active() as u8) << 1
+    }
+
+    fn cycle(&mut self) {
+        self.frame_clock += 1;
+        if self.frame_clock == AUDIO_FRAME_PERIOD {
+            self.frame_clock = 0;
+            for pulse in &mut self.pulses {
+                pulse.clock_quarter_frame();
+                pulse.clock_half_frame();
+            }
+        }
+        self.apu_cycle = !self.apu_cycle;
+        if self.apu_cycle {
+            for pulse in &mut self.pulses {
+                pulse.tick();
+            }
+        }
+    }
+
+    /* the pulses are mixed as the APU's are, and PCM about as loud as the DMC at its loudest */
+    fn level(&self) -> f32 {
+        mix_pulses(self.pulses[0].amplitude() + self.pulses[1].amplitude())
+            + mix_tnd(0.0, 0.0, self.pcm as f32 / 2.0)
+    }
+}
+
+impl Savestate for MMC5Audio {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.pulses[0].save_state(writer);
+        self.pulses[1].save_state(writer);
+        writer.write_u8(self.pcm_control);
+        writer.write_u8(self.pcm);
+        writer.write_u16(self.frame_clock);
+        writer.write_bool(self.apu_cycle);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.pulses[0].load_state(reader)?;
+        self.pulses[1].load_state(reader)?;
+        self.pcm_control = reader.read_u8()?;
+        self.pcm = reader.read_u8()?;
+        self.frame_clock = reader.read_u16()? % AUDIO_FRAME_PERIOD;
+        self.apu_cycle = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -1,29 +1,74 @@
 mod axrom;
 mod bank_array;
+mod cnrom;
+mod four_screen;
+mod gxrom;
 mod mapper;
 mod mmc1;
+mod mmc2;
+mod mmc3;
+mod mmc5;
 mod nrom;
 mod uxrom;
+mod vrc;
+mod vrc4;
+mod vrc6;
+mod vrc7;
+mod vrc7_audio;
+
+#[cfg(test)]
+mod tests;
 
 use nrom::NROM;
 
 use crate::mapper::axrom::AxROM;
+use crate::mapper::cnrom::CNROM;
+use crate::mapper::four_screen::FourScreenVram;
+use crate::mapper::gxrom::GxROM;
 use crate::mapper::mmc1::MMC1;
+use crate::mapper::mmc2::MMC2;
+use crate::mapper::mmc3::MMC3;
+use crate::mapper::mmc5::MMC5;
 use crate::mapper::uxrom::UxROM;
+use crate::mapper::vrc4::VRC4;
+use crate::mapper::vrc6::VRC6;
+use crate::mapper::vrc7::VRC7;
 use crate::rom::Rom;
-pub use mapper::Mapper;
+pub use mapper::{Mapper, NametableSource, PPUFetch};
+use std::io;
+use std::io::ErrorKind;
 /* common bank sizes; u16 since they must fit in the CPU address space */
+const SIZE_1_KB: usize = 10;
 const SIZE_4_KB: usize = 12;
 const SIZE_8_KB: usize = 13;
 const SIZE_16_KB: usize = 14;
 const SIZE_32_KB: usize = 15;
 
-pub fn load_mapper(mapper_num: u8, rom: &Rom) -> Box<dyn Mapper> {
-    match mapper_num {
+pub fn load_mapper(mapper_num: u16, rom: &Rom) -> io::Result<Box<dyn Mapper>> {
+    let mapper: Box<dyn Mapper> = match mapper_num {
         0 => Box::new(NROM::new(rom)),
         1 => Box::new(MMC1::new(rom)),
         2 => Box::new(UxROM::new(rom)),
+        3 => Box::new(CNROM::new(rom)),
+        4 => Box::new(MMC3::new(rom)),
+        5 => Box::new(MMC5::new(rom)),
         7 => Box::new(AxROM::new(rom)),
-        _ => todo!("mapper {mapper_num}"),
+        9 => Box::new(MMC2::new(rom)),
+        10 => Box::new(MMC2::new_mmc4(rom)),
+        21 | 22 | 23 | 25 => Box::new(VRC4::new(rom, mapper_num)),
+        24 | 26 => Box::new(VRC6::new(rom, mapper_num)),
+        66 => Box::new(GxROM::new(rom)),
+        85 => Box::new(VRC7::new(rom)),
+        _ => {
+            return Err(io::Error::new(
+                ErrorKind::Unsupported,
+                format!("Mapper {mapper_num} isn't supported"),
+            ))
+        }
+    };
+    if rom.header.four_screen {
+        Ok(Box::new(FourScreenVram::new(mapper)))
+    } else {
+        Ok(mapper)
     }
 }
@@ -1,6 +1,8 @@
 use crate::mapper::Mapper;
 use crate::ppu::NametableMirroring;
 use crate::rom::Rom;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
 
 const PRG_BANK_SIZE: usize = 1 << 15;
 const CHR_BANK_SIZE: usize = 1 << 13; /* 8kb CHR RAM */
@@ -8,7 +10,9 @@ const CHR_BANK_SIZE: usize = 1 << 13; /* 8kb CHR RAM */
 pub struct NROM {
     prg_ram: Box<[u8; PRG_BANK_SIZE]>,
     chr: Box<[u8; CHR_BANK_SIZE]>,
-    is_32_kb: bool, /* NROM can be either 32kb or 16kb mirrored */
+    work_ram: Vec<u8>, /* optional PRG-RAM at 0x6000-0x7fff, as on Family BASIC */
+    battery: bool,     /* whether work RAM is worth saving */
+    is_32_kb: bool,    /* NROM can be either 32kb or 16kb mirrored */
     nametable_mirroring: NametableMirroring,
 }
 
@@ -26,6 +30,8 @@ impl NROM {
         NROM {
             prg_ram,
             chr: chr_ram,
+            work_ram: vec![0; rom.header.work_ram_size()],
+            battery: rom.header.battery,
             is_32_kb,
             nametable_mirroring: rom.nametable_mirroring(),
         }
@@ -40,20 +46,30 @@ impl NROM {
             ((address & !0x4000) - 0x8000) as usize
         }
     }
+
+    /* the index into work RAM, if the board has any and the address falls within it */
+    fn work_ram_index(&self, address: u16) -> Option<usize> {
+        if (0x6000..0x8000).contains(&address) && !self.work_ram.is_empty() {
+            Some((address as usize - 0x6000) % self.work_ram.len())
+        } else {
+            None
+        }
+    }
 }
 
 impl Mapper for NROM {
     fn read_prg(&self, address: u16) -> u8 {
-        self.prg_ram[self.map_address(address)]
-    }
-
-    fn read_prg_slice(&self, address: u16, size: usize) -> &[u8] {
-        let mapped_address = self.map_address(address);
-        &self.prg_ram[mapped_address..mapped_address + size]
+        match self.work_ram_index(address) {
+            Some(index) => self.work_ram[index],
+            None => self.prg_ram[self.map_address(address)],
+        }
     }
 
-    fn write_prg(&mut self, _address: u16, _value: u8) {
-        /* no effect */
+    fn write_prg(&mut self, address: u16, value: u8) {
+        /* PRG-ROM writes have no effect */
+        if let Some(index) = self.work_ram_index(address) {
+            self.work_ram[index] = value;
+        }
     }
 
     fn read_chr(&self, address: u16) -> u8 {
@@ -67,4 +83,29 @@ impl Mapper for NROM {
     fn get_nametable_mirroring(&self) -> NametableMirroring {
         self.nametable_mirroring.clone()
     }
+
+    fn get_save_data(&self) -> Option<Vec<u8>> {
+        if self.work_ram.is_empty() || !self.battery {
+            None
+        } else {
+            Some(self.work_ram.clone())
+        }
+    }
+
+    fn set_save_data(&mut self, data: &Vec<u8>) {
+        let len = data.len().min(self.work_ram.len());
+        self.work_ram[0..len].copy_from_slice(&data[0..len]);
+    }
+}
+
+impl Savestate for NROM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&*self.chr);
+        writer.write_bytes(&self.work_ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut *self.chr)?;
+        reader.read_bytes_into(&mut self.work_ram)
+    }
 }
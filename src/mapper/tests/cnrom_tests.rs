@@ -0,0 +1,94 @@
+use super::rom_with_numbered_banks;
+use crate::mapper::cnrom::CNROM;
+use crate::mapper::Mapper;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+/* 32kb PRG whose bytes are all 0xff, so writes don't conflict, and 32kb CHR (4 banks) */
+fn make_cnrom() -> CNROM {
+    let mut rom = rom_with_numbered_banks(4, 32);
+    rom.prg_data.fill(0xff);
+    CNROM::new(&rom)
+}
+
+#[test]
+fn prg_is_fixed() {
+    let mapper = CNROM::new(&rom_with_numbered_banks(4, 32));
+    assert_eq!(mapper.read_prg(0x8000), 0);
+    assert_eq!(mapper.read_prg(0xa000), 1);
+    assert_eq!(mapper.read_prg(0xc000), 2);
+    assert_eq!(mapper.read_prg(0xffff), 3);
+}
+
+#[test]
+fn prg_16kb_is_mirrored() {
+    let mapper = CNROM::new(&rom_with_numbered_banks(2, 32));
+    assert_eq!(mapper.read_prg(0xc000), 0);
+    assert_eq!(mapper.read_prg(0xe000), 1);
+}
+
+#[test]
+fn register_selects_8kb_chr_bank() {
+    let mut mapper = make_cnrom();
+    assert_eq!(mapper.read_chr(0x0000), 0);
+    mapper.write_prg(0x8000, 2);
+    assert_eq!(mapper.read_chr(0x0000), 16);
+    assert_eq!(mapper.read_chr(0x1fff), 23);
+    mapper.write_prg(0xffff, 3);
+    assert_eq!(mapper.read_chr(0x0000), 24);
+}
+
+#[test]
+fn chr_bank_numbers_wrap_to_rom_size() {
+    let mut mapper = make_cnrom();
+    mapper.write_prg(0x8000, 4 + 1);
+    assert_eq!(mapper.read_chr(0x0000), 8);
+}
+
+#[test]
+fn writes_conflict_with_rom() {
+    let mut rom = rom_with_numbered_banks(4, 32);
+    rom.prg_data[0x0000] = 0x01;
+    rom.prg_data[0x0001] = 0xfe;
+    let mut mapper = CNROM::new(&rom);
+
+    /* 3 & 0x01 */
+    mapper.write_prg(0x8000, 3);
+    assert_eq!(mapper.read_chr(0x0000), 8);
+
+    /* 3 & 0xfe */
+    mapper.write_prg(0x8001, 3);
+    assert_eq!(mapper.read_chr(0x0000), 16);
+}
+
+#[test]
+fn writes_below_0x8000_are_ignored() {
+    let mut mapper = make_cnrom();
+    mapper.write_prg(0x6000, 3);
+    assert_eq!(mapper.read_chr(0x0000), 0);
+}
+
+#[test]
+fn savestate_round_trips() {
+    let mut mapper = make_cnrom();
+    mapper.write_prg(0x8000, 3);
+
+    let mut writer = StateWriter::new();
+    mapper.save_state(&mut writer);
+    let state = writer.into_bytes();
+
+    let mut restored = make_cnrom();
+    restored
+        .load_state(&mut StateReader::new(&state).unwrap())
+        .unwrap();
+    assert_eq!(restored.read_chr(0x0000), 24);
+}
+
+#[test]
+fn a_header_without_chr_gets_8kb_of_chr_ram() {
+    let mut rom = rom_with_numbered_banks(4, 0);
+    rom.header.chr_ram_size = 0;
+    let mut mapper = CNROM::new(&rom);
+    mapper.write_prg(0x8000, 0xff);
+    mapper.write_chr(0x1fff, 0x42);
+    assert_eq!(mapper.read_chr(0x1fff), 0x42);
+}
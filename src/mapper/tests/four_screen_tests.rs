@@ -0,0 +1,89 @@
+use super::{a12_rise, rom_with_numbered_banks};
+use crate::mapper::{load_mapper, Mapper, NametableSource};
+use crate::ppu::NametableMirroring;
+use crate::savestate::{StateReader, StateWriter};
+
+/* an MMC3 board with the four-screen bit set, like Rad Racer II */
+fn make_four_screen_mmc3() -> Box<dyn Mapper> {
+    let mut rom = rom_with_numbered_banks(16, 128);
+    rom.header.four_screen = true;
+    load_mapper(4, &rom).unwrap()
+}
+
+#[test]
+fn four_screen_bit_puts_every_nametable_on_the_cartridge() {
+    let mapper = make_four_screen_mmc3();
+    for nametable in 0..4 {
+        assert_eq!(
+            mapper.nametable_source(nametable),
+            NametableSource::Cartridge
+        );
+    }
+}
+
+#[test]
+fn without_four_screen_bit_nametables_follow_mirroring() {
+    let mapper = load_mapper(4, &rom_with_numbered_banks(16, 128)).unwrap();
+    assert_eq!(mapper.nametable_source(1), NametableSource::Ciram(0));
+    assert_eq!(mapper.nametable_source(2), NametableSource::Ciram(1));
+}
+
+#[test]
+fn nametables_are_4kb_of_vram() {
+    let mut mapper = make_four_screen_mmc3();
+    for (i, address) in (0x2000..0x3000).step_by(0x400).enumerate() {
+        mapper.write_nametable(address + 0x3ff, i as u8 + 1);
+    }
+    for (i, address) in (0x2000..0x3000).step_by(0x400).enumerate() {
+        assert_eq!(mapper.read_nametable(address + 0x3ff), i as u8 + 1);
+    }
+}
+
+#[test]
+fn mirroring_register_is_ignored() {
+    let mut mapper = make_four_screen_mmc3();
+    mapper.write_prg(0xa000, 1);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::FourScreen
+    ));
+    assert_eq!(mapper.nametable_source(0), NametableSource::Cartridge);
+}
+
+#[test]
+fn everything_else_reaches_the_mapper() {
+    let mut mapper = make_four_screen_mmc3();
+    mapper.write_prg(0x8000, 6);
+    mapper.write_prg(0x8001, 3);
+    assert_eq!(mapper.read_prg(0x8000), 3);
+    mapper.write_prg(0x8000, 2);
+    mapper.write_prg(0x8001, 40);
+    assert_eq!(mapper.read_chr(0x1000), 40);
+
+    mapper.write_prg(0xc000, 0);
+    mapper.write_prg(0xe001, 0);
+    a12_rise(mapper.as_mut());
+    assert!(mapper.irq_pending());
+
+    mapper.write_prg(0x6000, 0x42);
+    assert_eq!(mapper.get_save_data().unwrap()[0], 0x42);
+}
+
+#[test]
+fn save_state_round_trips_vram() {
+    let mut mapper = make_four_screen_mmc3();
+    mapper.write_prg(0x8000, 6);
+    mapper.write_prg(0x8001, 3);
+    mapper.write_nametable(0x2c00, 0x99);
+
+    let mut writer = StateWriter::new();
+    mapper.save_state(&mut writer);
+    let state = writer.into_bytes();
+
+    let mut restored = make_four_screen_mmc3();
+    restored
+        .load_state(&mut StateReader::new(&state).unwrap())
+        .unwrap();
+    assert_eq!(restored.read_prg(0x8000), 3);
+    assert_eq!(restored.read_nametable(0x2c00), 0x99);
+}
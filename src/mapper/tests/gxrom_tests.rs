@@ -0,0 +1,73 @@
+use super::rom_with_numbered_banks;
+use crate::mapper::gxrom::GxROM;
+use crate::mapper::Mapper;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+/* 128kb PRG (4 32kb banks) and 32kb CHR (4 banks); each PRG bank starts with 0xff, so writes
+ * to 0x8000 don't conflict
+ */
+fn make_gxrom() -> GxROM {
+    let mut rom = rom_with_numbered_banks(16, 32);
+    for bank in 0..4 {
+        rom.prg_data[bank << 15] = 0xff;
+    }
+    GxROM::new(&rom)
+}
+
+#[test]
+fn starts_in_first_banks() {
+    let mapper = GxROM::new(&rom_with_numbered_banks(16, 32));
+    assert_eq!(mapper.read_prg(0x8000), 0);
+    assert_eq!(mapper.read_prg(0xe000), 3);
+    assert_eq!(mapper.read_chr(0x0000), 0);
+}
+
+#[test]
+fn register_selects_prg_and_chr_banks() {
+    let mut mapper = make_gxrom();
+    mapper.write_prg(0x8000, 0x21);
+    assert_eq!(mapper.read_prg(0x8001), 8);
+    assert_eq!(mapper.read_prg(0xffff), 11);
+    assert_eq!(mapper.read_chr(0x0000), 8);
+    assert_eq!(mapper.read_chr(0x1fff), 15);
+}
+
+#[test]
+fn writes_conflict_with_rom() {
+    let mut rom = rom_with_numbered_banks(16, 32);
+    rom.prg_data[0x0000] = 0x12;
+    let mut mapper = GxROM::new(&rom);
+
+    /* 0x33 & 0x12 */
+    mapper.write_prg(0x8000, 0x33);
+    assert_eq!(mapper.read_prg(0x8000), 4);
+    assert_eq!(mapper.read_chr(0x0000), 16);
+}
+
+#[test]
+fn savestate_round_trips() {
+    let mut mapper = make_gxrom();
+    mapper.write_prg(0x8000, 0x33);
+
+    let mut writer = StateWriter::new();
+    mapper.save_state(&mut writer);
+    let state = writer.into_bytes();
+
+    let mut restored = make_gxrom();
+    restored
+        .load_state(&mut StateReader::new(&state).unwrap())
+        .unwrap();
+    assert_eq!(restored.read_prg(0x8001), 12);
+    assert_eq!(restored.read_chr(0x0000), 24);
+}
+
+#[test]
+fn a_header_without_chr_gets_8kb_of_chr_ram() {
+    let mut rom = rom_with_numbered_banks(16, 0);
+    rom.header.chr_ram_size = 0;
+    rom.prg_data[0] = 0xff;
+    let mut mapper = GxROM::new(&rom);
+    mapper.write_prg(0x8000, 0x01);
+    mapper.write_chr(0x1fff, 0x42);
+    assert_eq!(mapper.read_chr(0x1fff), 0x42);
+}
@@ -0,0 +1,181 @@
+use super::rom_with_numbered_banks;
+use crate::mapper::mmc2::MMC2;
+use crate::mapper::{Mapper, PPUFetch};
+use crate::ppu::NametableMirroring;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+/* 128kb PRG (16 8kb banks), 128kb CHR (32 4kb banks) */
+fn make_mmc2() -> MMC2 {
+    MMC2::new(&rom_with_numbered_banks(16, 128))
+}
+
+fn make_mmc4() -> MMC2 {
+    MMC2::new_mmc4(&rom_with_numbered_banks(16, 128))
+}
+
+/* the 4kb CHR bank each half reads from, given 1kb-numbered CHR */
+fn chr_banks(mapper: &MMC2) -> (u8, u8) {
+    (mapper.read_chr(0x0000) / 4, mapper.read_chr(0x1000) / 4)
+}
+
+/* draws a byte of background pattern */
+fn fetch(mapper: &mut MMC2, address: u16) {
+    mapper.ppu_render_fetch(address, PPUFetch::BackgroundPattern, 0);
+}
+
+/* points the 0xfd and 0xfe registers of both halves at different banks */
+fn set_chr_registers(mapper: &mut MMC2) {
+    mapper.write_prg(0xb000, 1);
+    mapper.write_prg(0xc000, 2);
+    mapper.write_prg(0xd000, 3);
+    mapper.write_prg(0xe000, 4);
+}
+
+#[test]
+fn mmc2_switches_8kb_and_fixes_the_last_three() {
+    let mut mapper = make_mmc2();
+    mapper.write_prg(0xa000, 5);
+    assert_eq!(mapper.read_prg(0x8000), 5);
+    assert_eq!(mapper.read_prg(0xa000), 13);
+    assert_eq!(mapper.read_prg(0xc000), 14);
+    assert_eq!(mapper.read_prg(0xe000), 15);
+}
+
+#[test]
+fn mmc4_switches_16kb_and_fixes_the_last() {
+    let mut mapper = make_mmc4();
+    mapper.write_prg(0xa000, 3);
+    assert_eq!(mapper.read_prg(0x8000), 6);
+    assert_eq!(mapper.read_prg(0xa000), 7);
+    assert_eq!(mapper.read_prg(0xc000), 14);
+    assert_eq!(mapper.read_prg(0xe000), 15);
+}
+
+#[test]
+fn latches_start_on_0xfe() {
+    let mut mapper = make_mmc2();
+    set_chr_registers(&mut mapper);
+    assert_eq!(chr_banks(&mapper), (2, 4));
+}
+
+#[test]
+fn fetching_0xfd_and_0xfe_flips_each_half() {
+    let mut mapper = make_mmc2();
+    set_chr_registers(&mut mapper);
+
+    fetch(&mut mapper, 0x0fd8);
+    assert_eq!(chr_banks(&mapper), (1, 4));
+    fetch(&mut mapper, 0x1fdb);
+    assert_eq!(chr_banks(&mapper), (1, 3));
+    fetch(&mut mapper, 0x0fe8);
+    assert_eq!(chr_banks(&mapper), (2, 3));
+    fetch(&mut mapper, 0x1fef);
+    assert_eq!(chr_banks(&mapper), (2, 4));
+}
+
+#[test]
+fn only_the_high_plane_flips_latches() {
+    let mut mapper = make_mmc4();
+    set_chr_registers(&mut mapper);
+    fetch(&mut mapper, 0x0fd0);
+    fetch(&mut mapper, 0x1fd7);
+    assert_eq!(chr_banks(&mapper), (2, 4));
+}
+
+#[test]
+fn other_tiles_leave_latches_alone() {
+    let mut mapper = make_mmc2();
+    set_chr_registers(&mut mapper);
+    fetch(&mut mapper, 0x0fd8);
+    for address in [0x0fc8, 0x0ff8, 0x1008, 0x1fc8] {
+        fetch(&mut mapper, address);
+    }
+    assert_eq!(chr_banks(&mapper), (1, 4));
+}
+
+#[test]
+fn mmc2_left_latch_only_sees_the_first_row() {
+    let mut mapper = make_mmc2();
+    set_chr_registers(&mut mapper);
+    fetch(&mut mapper, 0x0fd9);
+    assert_eq!(chr_banks(&mapper), (2, 4));
+
+    let mut mapper = make_mmc4();
+    set_chr_registers(&mut mapper);
+    fetch(&mut mapper, 0x0fd9);
+    assert_eq!(chr_banks(&mapper), (1, 4));
+}
+
+#[test]
+fn registers_apply_to_the_latched_bank_immediately() {
+    let mut mapper = make_mmc2();
+    fetch(&mut mapper, 0x0fd8);
+    mapper.write_prg(0xb000, 7);
+    assert_eq!(chr_banks(&mapper).0, 7);
+    /* not latched, so no change */
+    mapper.write_prg(0xc000, 9);
+    assert_eq!(chr_banks(&mapper).0, 7);
+}
+
+#[test]
+fn sprites_flip_latches_too_but_nametable_fetches_dont() {
+    let mut mapper = make_mmc2();
+    set_chr_registers(&mut mapper);
+    mapper.ppu_render_fetch(0x1fd8, PPUFetch::SpritePattern, 0);
+    assert_eq!(chr_banks(&mapper), (2, 3));
+    assert_eq!(
+        mapper.ppu_render_fetch(0x2fd8, PPUFetch::Nametable, 0x12),
+        0x12
+    );
+    assert_eq!(chr_banks(&mapper), (2, 3));
+}
+
+#[test]
+fn mirroring_register() {
+    let mut mapper = make_mmc2();
+    mapper.write_prg(0xf000, 1);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::Vertical
+    ));
+    mapper.write_prg(0xf000, 0);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::Horizontal
+    ));
+}
+
+#[test]
+fn mmc4_save_data_round_trips() {
+    let mut mapper = make_mmc4();
+    mapper.write_prg(0x6001, 0x99);
+    let save_data = mapper.get_save_data().unwrap();
+    assert_eq!(save_data[1], 0x99);
+
+    let mut restored = make_mmc4();
+    restored.set_save_data(&save_data);
+    assert_eq!(restored.read_prg(0x6001), 0x99);
+}
+
+#[test]
+fn save_state_round_trips() {
+    let mut mapper = make_mmc2();
+    set_chr_registers(&mut mapper);
+    mapper.write_prg(0xa000, 5);
+    fetch(&mut mapper, 0x1fd8);
+
+    let mut writer = StateWriter::new();
+    mapper.save_state(&mut writer);
+    let state = writer.into_bytes();
+
+    let mut restored = make_mmc2();
+    restored
+        .load_state(&mut StateReader::new(&state).unwrap())
+        .unwrap();
+    assert_eq!(restored.read_prg(0x8000), 5);
+    assert_eq!(chr_banks(&restored), (2, 3));
+
+    /* the registers come back too, not just the banks they selected */
+    fetch(&mut restored, 0x0fd8);
+    assert_eq!(chr_banks(&restored), (1, 3));
+}
@@ -0,0 +1,312 @@
+use super::{a12_rise, rom_with_numbered_banks};
+use crate::cpu::{CoreMemory, StatusFlag, CPU};
+use crate::mapper::mmc3::MMC3;
+use crate::mapper::{Mapper, PPUFetch};
+use crate::ppu::NametableMirroring;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+fn make_mmc3() -> MMC3 {
+    /* 128kb PRG (16 banks), 128kb CHR (128 banks) */
+    MMC3::new(&rom_with_numbered_banks(16, 128))
+}
+
+fn select_bank(mapper: &mut MMC3, register: u8, bank: u8) {
+    mapper.write_prg(0x8000, register);
+    mapper.write_prg(0x8001, bank);
+}
+
+#[test]
+fn last_bank_is_fixed_at_0xe000() {
+    let mut mapper = make_mmc3();
+    assert_eq!(mapper.read_prg(0xe000), 15);
+    select_bank(&mut mapper, 6, 3);
+    select_bank(&mut mapper, 7, 4);
+    assert_eq!(mapper.read_prg(0xffff), 15);
+}
+
+#[test]
+fn a_single_8kb_prg_bank_is_mapped_everywhere() {
+    let mut mapper = MMC3::new(&rom_with_numbered_banks(1, 8));
+    mapper.write_prg(0x8000, 0x40); // PRG mode 1
+    for address in [0x8000, 0xa000, 0xc000, 0xe000] {
+        assert_eq!(mapper.read_prg(address), 0);
+    }
+}
+
+#[test]
+fn prg_mode_0_switches_0x8000_and_fixes_0xc000() {
+    let mut mapper = make_mmc3();
+    select_bank(&mut mapper, 6, 3);
+    select_bank(&mut mapper, 7, 4);
+    assert_eq!(mapper.read_prg(0x8000), 3);
+    assert_eq!(mapper.read_prg(0xa000), 4);
+    assert_eq!(mapper.read_prg(0xc000), 14); // second-to-last bank
+}
+
+#[test]
+fn prg_mode_1_swaps_0x8000_and_0xc000() {
+    let mut mapper = make_mmc3();
+    select_bank(&mut mapper, 0x40 | 6, 3);
+    select_bank(&mut mapper, 0x40 | 7, 4);
+    assert_eq!(mapper.read_prg(0x8000), 14); // second-to-last bank
+    assert_eq!(mapper.read_prg(0xa000), 4);
+    assert_eq!(mapper.read_prg(0xc000), 3);
+}
+
+#[test]
+fn prg_bank_numbers_wrap_to_rom_size() {
+    let mut mapper = make_mmc3();
+    select_bank(&mut mapper, 6, 16 + 5);
+    assert_eq!(mapper.read_prg(0x8000), 5);
+}
+
+#[test]
+fn chr_banks_without_inversion() {
+    let mut mapper = make_mmc3();
+    select_bank(&mut mapper, 0, 9); // 2kb bank: low bit ignored
+    select_bank(&mut mapper, 1, 20);
+    for (register, bank) in (2..6).zip([30, 31, 40, 41]) {
+        select_bank(&mut mapper, register, bank);
+    }
+    assert_eq!(mapper.read_chr(0x0000), 8);
+    assert_eq!(mapper.read_chr(0x0400), 9);
+    assert_eq!(mapper.read_chr(0x0800), 20);
+    assert_eq!(mapper.read_chr(0x0c00), 21);
+    assert_eq!(mapper.read_chr(0x1000), 30);
+    assert_eq!(mapper.read_chr(0x1400), 31);
+    assert_eq!(mapper.read_chr(0x1800), 40);
+    assert_eq!(mapper.read_chr(0x1c00), 41);
+}
+
+#[test]
+fn chr_inversion_swaps_pattern_table_halves() {
+    let mut mapper = make_mmc3();
+    select_bank(&mut mapper, 0x80, 10);
+    select_bank(&mut mapper, 0x80 | 1, 20);
+    for (register, bank) in (2..6).zip([30, 31, 40, 41]) {
+        select_bank(&mut mapper, 0x80 | register, bank);
+    }
+    assert_eq!(mapper.read_chr(0x0000), 30);
+    assert_eq!(mapper.read_chr(0x0c00), 41);
+    assert_eq!(mapper.read_chr(0x1000), 10);
+    assert_eq!(mapper.read_chr(0x1400), 11);
+    assert_eq!(mapper.read_chr(0x1800), 20);
+    assert_eq!(mapper.read_chr(0x1c00), 21);
+}
+
+#[test]
+fn chr_ram_used_when_rom_has_no_chr() {
+    let mut mapper = MMC3::new(&rom_with_numbered_banks(16, 0));
+    mapper.write_chr(0x1fff, 0xab);
+    assert_eq!(mapper.read_chr(0x1fff), 0xab);
+}
+
+#[test]
+fn mirroring_register() {
+    let mut mapper = make_mmc3();
+    mapper.write_prg(0xa000, 0);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::Horizontal
+    ));
+    mapper.write_prg(0xa000, 1);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::Vertical
+    ));
+}
+
+#[test]
+fn prg_ram_protect() {
+    let mut mapper = make_mmc3();
+    mapper.write_prg(0x6000, 0x12);
+    assert_eq!(mapper.read_prg(0x6000), 0x12);
+
+    /* write-protected: reads work, writes are ignored */
+    mapper.write_prg(0xa001, 0xc0);
+    mapper.write_prg(0x6000, 0x34);
+    assert_eq!(mapper.read_prg(0x6000), 0x12);
+
+    /* disabled: reads don't see the RAM */
+    mapper.write_prg(0xa001, 0x00);
+    assert_eq!(mapper.read_prg(0x6000), 0);
+
+    mapper.write_prg(0xa001, 0x80);
+    mapper.write_prg(0x7fff, 0x56);
+    assert_eq!(mapper.read_prg(0x7fff), 0x56);
+}
+
+#[test]
+fn save_data_round_trips() {
+    let mut mapper = make_mmc3();
+    mapper.write_prg(0x6001, 0x99);
+    let save_data = mapper.get_save_data().unwrap();
+    assert_eq!(save_data.len(), 0x2000);
+    assert_eq!(save_data[1], 0x99);
+
+    let mut restored = make_mmc3();
+    restored.set_save_data(&save_data);
+    assert_eq!(restored.read_prg(0x6001), 0x99);
+}
+
+#[test]
+fn irq_fires_after_latch_plus_one_scanlines() {
+    let mut mapper = make_mmc3();
+    mapper.write_prg(0xc000, 3); // latch
+    mapper.write_prg(0xc001, 0); // reload
+    mapper.write_prg(0xe001, 0); // enable
+
+    /* first clock reloads the counter to 3, then it counts down 2, 1, 0 */
+    for _ in 0..3 {
+        a12_rise(&mut mapper);
+        assert!(!mapper.irq_pending());
+    }
+    a12_rise(&mut mapper);
+    assert!(mapper.irq_pending());
+
+    /* writing to 0xe000 acknowledges the interrupt */
+    mapper.write_prg(0xe000, 0);
+    assert!(!mapper.irq_pending());
+}
+
+#[test]
+fn only_a12_rising_after_staying_low_clocks_the_counter() {
+    let mut mapper = make_mmc3();
+    mapper.write_prg(0xc000, 0); // latch 0, so every clock fires
+    mapper.write_prg(0xe001, 0);
+
+    /* the left pattern table and the nametables leave A12 low */
+    for (address, fetch) in [
+        (0x0ff8, PPUFetch::BackgroundPattern),
+        (0x2000, PPUFetch::Nametable),
+        (0x2fc0, PPUFetch::Attribute),
+    ] {
+        for _ in 0..100 {
+            mapper.cpu_cycle();
+        }
+        mapper.ppu_render_fetch(address, fetch, 0);
+    }
+    assert!(!mapper.irq_pending());
+
+    /* the eight sprite slots fetching from 0x1000 in a row are just the one rise */
+    a12_rise(&mut mapper);
+    assert!(mapper.irq_pending());
+    mapper.write_prg(0xe000, 0);
+    mapper.write_prg(0xe001, 0);
+    for slot in 1..8 {
+        for _ in 0..3 {
+            mapper.cpu_cycle();
+        }
+        mapper.ppu_render_fetch(0x1000 + slot * 16, PPUFetch::SpritePattern, 0);
+    }
+    assert!(!mapper.irq_pending());
+}
+
+#[test]
+fn irq_does_not_fire_when_disabled() {
+    let mut mapper = make_mmc3();
+    mapper.write_prg(0xc000, 1);
+    mapper.write_prg(0xc001, 0);
+    for _ in 0..4 {
+        a12_rise(&mut mapper);
+    }
+    assert!(!mapper.irq_pending());
+}
+
+#[test]
+fn irq_counter_reloads_after_reaching_zero() {
+    let mut mapper = make_mmc3();
+    mapper.write_prg(0xc000, 1);
+    mapper.write_prg(0xc001, 0);
+    mapper.write_prg(0xe001, 0);
+
+    a12_rise(&mut mapper); // reload to 1
+    a12_rise(&mut mapper); // 0: fires
+    assert!(mapper.irq_pending());
+    mapper.write_prg(0xe000, 0);
+    mapper.write_prg(0xe001, 0);
+
+    a12_rise(&mut mapper); // reload to 1
+    assert!(!mapper.irq_pending());
+    a12_rise(&mut mapper); // 0: fires again
+    assert!(mapper.irq_pending());
+}
+
+#[test]
+fn mapper_irq_interrupts_cpu() {
+    /* the last bank is fixed at 0xe000, so the IRQ vector lives at the end of the ROM */
+    let mut rom = rom_with_numbered_banks(16, 128);
+    let prg_len = rom.prg_data.len();
+    rom.prg_data[prg_len - 2..].copy_from_slice(&[0x00, 0x03]); // IRQ handler at 0x0300
+    let memory = CoreMemory::new_from_mapper(Box::new(MMC3::new(&rom)));
+    let mapper = memory.mapper.clone();
+    let mut cpu = CPU::new(Box::new(memory));
+
+    cpu.write_mem(0x0200, 0xea); // NOP
+    cpu.write_mem(0x0201, 0x58); // CLI
+    cpu.write_mem(0x0202, 0xea); // NOP
+    cpu.write_mem(0x0300, 0xea); // NOP in the IRQ handler
+    cpu.write_mem(0xc000, 0); // latch
+    cpu.write_mem(0xc001, 0); // reload
+    cpu.write_mem(0xe001, 0); // enable
+    a12_rise(mapper.borrow_mut().as_mut());
+    assert!(cpu.irq_set());
+
+    /* masked while interrupt disable is set */
+    cpu.status = StatusFlag::InterruptDisable.mask();
+    cpu.program_counter = 0x0200;
+    cpu.transition();
+    assert_eq!(cpu.program_counter, 0x0201);
+
+    /* the instruction after CLI still runs before the IRQ is taken */
+    cpu.s_register = 0xff;
+    cpu.transition();
+    cpu.transition();
+    assert_eq!(cpu.program_counter, 0x0203);
+    cpu.transition();
+    assert_eq!(cpu.program_counter, 0x0301); // ran the handler's NOP
+    assert_eq!(cpu.read_mem(0x01ff), 0x02); // return address hi
+    assert_eq!(cpu.read_mem(0x01fe), 0x03); // return address lo
+    assert_eq!(cpu.read_mem(0x01fd) & (1 << 4), 0); // B flag clear for hardware IRQ
+    assert!(StatusFlag::InterruptDisable.is_set(&cpu));
+}
+
+#[test]
+fn save_state_round_trips() {
+    let mut mapper = make_mmc3();
+    mapper.write_prg(0x8000, 0x46); // PRG mode 1, select R6
+    mapper.write_prg(0x8001, 5);
+    mapper.write_prg(0xa000, 1);
+    mapper.write_prg(0x6000, 0x99);
+    mapper.write_prg(0xc000, 3);
+    mapper.write_prg(0xc001, 0);
+    mapper.write_prg(0xe001, 0);
+    a12_rise(&mut mapper);
+
+    let mut writer = StateWriter::new();
+    mapper.save_state(&mut writer);
+    let state = writer.into_bytes();
+
+    let mut restored = make_mmc3();
+    restored
+        .load_state(&mut StateReader::new(&state).unwrap())
+        .unwrap();
+    for address in [0x6000, 0x8000, 0xa000, 0xc000, 0xe000] {
+        assert_eq!(restored.read_prg(address), mapper.read_prg(address));
+    }
+    for address in (0..0x2000).step_by(0x400) {
+        assert_eq!(restored.read_chr(address), mapper.read_chr(address));
+    }
+    assert!(matches!(
+        restored.get_nametable_mirroring(),
+        NametableMirroring::Vertical
+    ));
+
+    /* the IRQ counter picks up where it left off: 3 -> 2 -> 1 -> 0 */
+    for _i in 0..2 {
+        a12_rise(&mut restored);
+        assert!(!restored.irq_pending());
+    }
+    a12_rise(&mut restored);
+    assert!(restored.irq_pending());
+}
@@ -0,0 +1,499 @@
+use super::rom_with_numbered_banks;
+use crate::apu::{mix_pulses, mix_tnd};
+use crate::cpu::CoreMemory;
+use crate::mapper::mmc5::MMC5;
+use crate::mapper::{Mapper, NametableSource, PPUFetch};
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+/* 256kb PRG (32 8kb banks), 256kb CHR (256 1kb banks), 32kb PRG-RAM */
+fn make_mmc5() -> MMC5 {
+    let mut rom = rom_with_numbered_banks(32, 256);
+    rom.header.prg_ram_size = 1 << 15;
+    MMC5::new(&rom)
+}
+
+/* writes to ExRAM as a nametable only land while the PPU is drawing a frame */
+fn start_frame(mapper: &mut MMC5) {
+    mapper.ppu_scanline_start(0, true);
+}
+
+fn unprotect_prg_ram(mapper: &mut MMC5) {
+    mapper.write_prg(0x5102, 2);
+    mapper.write_prg(0x5103, 1);
+}
+
+/* fetches a background tile as the PPU does, returning its nametable byte, attribute, and the
+ * low plane of the given pattern row
+ */
+fn fetch_tile(mapper: &mut MMC5, column: u8, nametable_address: u16, row: u16) -> [u8; 3] {
+    mapper.ppu_background_tile(10, column);
+    let tile = mapper.ppu_render_fetch(nametable_address, PPUFetch::Nametable, 0xaa);
+    let attribute = mapper.ppu_render_fetch(0x23c0, PPUFetch::Attribute, 0xbb);
+    let address = tile as u16 * 16 + row;
+    let pattern = mapper.ppu_render_fetch(address, PPUFetch::BackgroundPattern, 0xcc);
+    [tile, attribute, pattern]
+}
+
+#[test]
+fn starts_with_the_last_bank_at_the_reset_vector() {
+    let mapper = make_mmc5();
+    assert_eq!(mapper.read_prg(0xfffc), 31);
+}
+
+#[test]
+fn prg_mode_0_switches_32kb() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5100, 0);
+    mapper.write_prg(0x5117, 0x86);
+    assert_eq!(mapper.read_prg(0x8000), 4);
+    assert_eq!(mapper.read_prg(0xa000), 5);
+    assert_eq!(mapper.read_prg(0xc000), 6);
+    assert_eq!(mapper.read_prg(0xe000), 7);
+}
+
+#[test]
+fn prg_mode_1_switches_16kb() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5100, 1);
+    mapper.write_prg(0x5115, 0x83);
+    mapper.write_prg(0x5117, 0x8b);
+    assert_eq!(mapper.read_prg(0x8000), 2);
+    assert_eq!(mapper.read_prg(0xa000), 3);
+    assert_eq!(mapper.read_prg(0xc000), 10);
+    assert_eq!(mapper.read_prg(0xe000), 11);
+}
+
+#[test]
+fn prg_mode_2_switches_16kb_then_8kb() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5100, 2);
+    mapper.write_prg(0x5115, 0x84);
+    mapper.write_prg(0x5116, 0x89);
+    mapper.write_prg(0x5117, 0x8e);
+    assert_eq!(mapper.read_prg(0x8000), 4);
+    assert_eq!(mapper.read_prg(0xa000), 5);
+    assert_eq!(mapper.read_prg(0xc000), 9);
+    assert_eq!(mapper.read_prg(0xe000), 14);
+}
+
+#[test]
+fn prg_mode_3_switches_8kb() {
+    let mut mapper = make_mmc5();
+    for (i, bank) in [0x81, 0x97, 0x83, 0x05].into_iter().enumerate() {
+        mapper.write_prg(0x5114 + i as u16, bank);
+    }
+    assert_eq!(mapper.read_prg(0x8000), 1);
+    assert_eq!(mapper.read_prg(0xa000), 23);
+    assert_eq!(mapper.read_prg(0xc000), 3);
+    /* 0x5117 is always ROM, with or without bit 7 */
+    assert_eq!(mapper.read_prg(0xe000), 5);
+}
+
+#[test]
+fn prg_ram_banks_at_0x6000_and_in_the_rom_windows() {
+    let mut mapper = make_mmc5();
+    unprotect_prg_ram(&mut mapper);
+    mapper.write_prg(0x5113, 2);
+    mapper.write_prg(0x6000, 0x42);
+    /* the same bank through 0x8000, with bit 7 clear */
+    mapper.write_prg(0x5114, 2);
+    assert_eq!(mapper.read_prg(0x8000), 0x42);
+    mapper.write_prg(0x8001, 0x43);
+    assert_eq!(mapper.read_prg(0x6001), 0x43);
+
+    mapper.write_prg(0x5113, 3);
+    assert_eq!(mapper.read_prg(0x6000), 0);
+}
+
+#[test]
+fn prg_ram_is_only_writable_when_unprotected() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x6000, 0x42);
+    assert_eq!(mapper.read_prg(0x6000), 0);
+
+    unprotect_prg_ram(&mut mapper);
+    mapper.write_prg(0x6000, 0x42);
+    assert_eq!(mapper.read_prg(0x6000), 0x42);
+
+    mapper.write_prg(0x5103, 0);
+    mapper.write_prg(0x6000, 0x99);
+    assert_eq!(mapper.read_prg(0x6000), 0x42);
+}
+
+#[test]
+fn rom_ignores_writes() {
+    let mut mapper = make_mmc5();
+    unprotect_prg_ram(&mut mapper);
+    mapper.write_prg(0x8000, 0x42);
+    assert_eq!(mapper.read_prg(0x8000), 31);
+}
+
+#[test]
+fn chr_modes_use_the_last_register_of_each_group() {
+    let mut mapper = make_mmc5();
+    for i in 0..8 {
+        mapper.write_prg(0x5120 + i, 10 + i as u8);
+    }
+
+    /* 8kb: 0x5127 */
+    mapper.write_prg(0x5101, 0);
+    assert_eq!(mapper.read_chr(0x0000), 17 * 8);
+    assert_eq!(mapper.read_chr(0x1c00), 17 * 8 + 7);
+    /* 4kb: 0x5123 and 0x5127 */
+    mapper.write_prg(0x5101, 1);
+    assert_eq!(mapper.read_chr(0x0400), 13 * 4 + 1);
+    assert_eq!(mapper.read_chr(0x1000), 17 * 4);
+    /* 2kb: 0x5121, 0x5123, 0x5125, 0x5127 */
+    mapper.write_prg(0x5101, 2);
+    assert_eq!(mapper.read_chr(0x0000), 11 * 2);
+    assert_eq!(mapper.read_chr(0x1c00), 17 * 2 + 1);
+    /* 1kb: each register */
+    mapper.write_prg(0x5101, 3);
+    for i in 0..8 {
+        assert_eq!(mapper.read_chr(i * 0x400), 10 + i as u8);
+    }
+}
+
+#[test]
+fn chr_upper_bits_apply_to_registers_written_after() {
+    /* 1mb of CHR, each byte holding the top bits of its 1kb bank's number */
+    let mut rom = rom_with_numbered_banks(4, 0);
+    rom.chr_data = (0..1024usize)
+        .flat_map(|bank| vec![(bank >> 8) as u8; 1 << 10])
+        .collect();
+    let mut mapper = MMC5::new(&rom);
+    mapper.write_prg(0x5101, 3);
+    mapper.write_prg(0x5130, 2);
+    mapper.write_prg(0x5120, 5);
+    mapper.write_prg(0x5130, 0);
+    assert_eq!(mapper.read_chr(0x0000), 2);
+}
+
+#[test]
+fn tall_sprites_bank_sprites_and_background_separately() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5101, 3);
+    for i in 0..8 {
+        mapper.write_prg(0x5120 + i, 10 + i as u8);
+    }
+    for i in 0..4 {
+        mapper.write_prg(0x5128 + i, 30 + i as u8);
+    }
+    mapper.ppu_register_write(0x2000, 0x20);
+
+    let sprite = mapper.ppu_render_fetch(0x1400, PPUFetch::SpritePattern, 0);
+    assert_eq!(sprite, 15);
+    /* set B's 4kb appears in both halves */
+    let background = mapper.ppu_render_fetch(0x1400, PPUFetch::BackgroundPattern, 0);
+    assert_eq!(background, 31);
+    /* the CPU sees whichever was written last */
+    assert_eq!(mapper.read_chr(0x1400), 31);
+    mapper.write_prg(0x5120, 10);
+    assert_eq!(mapper.read_chr(0x1400), 15);
+}
+
+#[test]
+fn short_sprites_share_the_last_written_set() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5101, 3);
+    mapper.write_prg(0x5121, 7);
+    mapper.write_prg(0x5129, 9);
+    for fetch in [PPUFetch::SpritePattern, PPUFetch::BackgroundPattern] {
+        assert_eq!(mapper.ppu_render_fetch(0x0400, fetch, 0), 9);
+    }
+    mapper.write_prg(0x5121, 7);
+    for fetch in [PPUFetch::SpritePattern, PPUFetch::BackgroundPattern] {
+        assert_eq!(mapper.ppu_render_fetch(0x0400, fetch, 0), 7);
+    }
+}
+
+#[test]
+fn nametables_are_placed_by_0x5105() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5105, 0b11_10_01_00);
+    assert_eq!(mapper.nametable_source(0), NametableSource::Ciram(0));
+    assert_eq!(mapper.nametable_source(1), NametableSource::Ciram(1));
+    assert_eq!(mapper.nametable_source(2), NametableSource::Cartridge);
+    assert_eq!(mapper.nametable_source(3), NametableSource::Cartridge);
+}
+
+#[test]
+fn fill_mode_repeats_one_tile_and_color() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5105, 0b11_00_00_00);
+    mapper.write_prg(0x5106, 0x42);
+    mapper.write_prg(0x5107, 2);
+    assert_eq!(mapper.read_nametable(0x2c00), 0x42);
+    assert_eq!(mapper.read_nametable(0x2fbf), 0x42);
+    assert_eq!(mapper.read_nametable(0x2fc0), 0xaa);
+    /* writes go nowhere */
+    mapper.write_nametable(0x2c00, 0x11);
+    assert_eq!(mapper.read_nametable(0x2c00), 0x42);
+}
+
+#[test]
+fn exram_as_a_nametable() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5105, 0b00_00_10_00);
+    mapper.write_nametable(0x2405, 0x42);
+    assert_eq!(mapper.read_nametable(0x2405), 0x42);
+
+    /* the CPU can only write it while the PPU is drawing */
+    mapper.write_prg(0x5c05, 0x44);
+    assert_eq!(mapper.read_nametable(0x2405), 0);
+    start_frame(&mut mapper);
+    mapper.write_prg(0x5c05, 0x44);
+    assert_eq!(mapper.read_nametable(0x2405), 0x44);
+    /* and can't read it back */
+    assert_eq!(mapper.read_prg(0x5c05), 0);
+}
+
+#[test]
+fn exram_as_cpu_ram() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5105, 0b00_00_10_00);
+    mapper.write_prg(0x5104, 2);
+    mapper.write_prg(0x5fff, 0x42);
+    assert_eq!(mapper.read_prg(0x5fff), 0x42);
+    /* no longer a nametable */
+    assert_eq!(mapper.read_nametable(0x27ff), 0);
+
+    mapper.write_prg(0x5104, 3);
+    mapper.write_prg(0x5fff, 0x43);
+    assert_eq!(mapper.read_prg(0x5fff), 0x42);
+}
+
+#[test]
+fn extended_attributes_pick_each_tiles_palette_and_bank() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5104, 2);
+    mapper.write_prg(0x5c21, 0b10_000101);
+    mapper.write_prg(0x5104, 1);
+    mapper.write_prg(0x5130, 1);
+
+    /* 4kb bank 5 | 1 << 6 wraps around the 256kb of CHR to bank 5 */
+    let [tile, attribute, pattern] = fetch_tile(&mut mapper, 1, 0x2021, 0);
+    assert_eq!(tile, 0xaa);
+    assert_eq!(attribute, 0xaa);
+    assert_eq!(pattern, 5 * 4 + 2);
+
+    /* other tiles have their own */
+    let [_, attribute, _] = fetch_tile(&mut mapper, 2, 0x2022, 0);
+    assert_eq!(attribute, 0);
+}
+
+#[test]
+fn split_draws_exram_on_the_chosen_side() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5104, 2);
+    /* scanline 10 scrolled by 3 is row 13: tile row 1, fine y 5 */
+    mapper.write_prg(0x5c00 + 32 + 4, 0x40);
+    mapper.write_prg(0x5fc0 + 1, 0b00_00_00_11);
+    mapper.write_prg(0x5104, 0);
+    mapper.write_prg(0x5200, 0x80 | 8);
+    mapper.write_prg(0x5201, 3);
+    mapper.write_prg(0x5202, 2);
+
+    /* the split's 4kb bank 2 is 1kb banks 8-11; tile 0x40 is 1kb into it */
+    let [tile, attribute, pattern] = fetch_tile(&mut mapper, 4, 0x2000, 5);
+    assert_eq!(tile, 0x40);
+    assert_eq!(attribute, 0xff);
+    assert_eq!(pattern, 9);
+
+    /* past the threshold, it's the usual nametable */
+    let [tile, attribute, _] = fetch_tile(&mut mapper, 8, 0x2008, 5);
+    assert_eq!([tile, attribute], [0xaa, 0xbb]);
+
+    /* or the other way around */
+    mapper.write_prg(0x5200, 0xc0 | 8);
+    let [tile, _, _] = fetch_tile(&mut mapper, 4, 0x2004, 5);
+    assert_eq!(tile, 0xaa);
+    let [tile, _, _] = fetch_tile(&mut mapper, 8, 0x2008, 5);
+    assert_eq!(tile, 0);
+}
+
+#[test]
+fn split_leaves_sprites_alone() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5101, 3);
+    mapper.write_prg(0x5200, 0x80 | 31);
+    mapper.write_prg(0x5202, 2);
+    mapper.ppu_background_tile(0, 0);
+    assert_eq!(
+        mapper.ppu_render_fetch(0x0400, PPUFetch::SpritePattern, 0),
+        0
+    );
+}
+
+#[test]
+fn a_header_without_chr_gets_8kb_of_chr_ram() {
+    let mut rom = rom_with_numbered_banks(32, 0);
+    rom.header.chr_ram_size = 0;
+    let mut mapper = MMC5::new(&rom);
+    mapper.write_prg(0x5127, 9); // wraps around to the one 8kb bank
+    mapper.write_chr(0x1fff, 0x42);
+    assert_eq!(mapper.read_chr(0x1fff), 0x42);
+
+    /* extended attributes and the split pick their CHR banks their own way */
+    mapper.write_prg(0x5104, 1);
+    let [_, _, pattern] = fetch_tile(&mut mapper, 1, 0x2021, 0);
+    assert_eq!(pattern, 0);
+    mapper.write_prg(0x5104, 0);
+    mapper.write_prg(0x5200, 0x80 | 8);
+    mapper.write_prg(0x5202, 3);
+    let [_, _, pattern] = fetch_tile(&mut mapper, 4, 0x2000, 7);
+    assert_eq!(pattern, 0);
+}
+
+#[test]
+fn scanline_irq_fires_on_the_compare_line() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5203, 3);
+    mapper.write_prg(0x5204, 0x80);
+    for scanline in 0..3 {
+        mapper.ppu_scanline_start(scanline, true);
+        assert!(!mapper.irq_pending());
+    }
+    mapper.ppu_scanline_start(3, true);
+    assert!(mapper.irq_pending());
+
+    /* reading 0x5204 reports and acknowledges it */
+    assert_eq!(mapper.read_prg(0x5204), 0xc0);
+    assert!(!mapper.irq_pending());
+    assert_eq!(mapper.read_prg(0x5204), 0x40);
+}
+
+#[test]
+fn peeking_0x5204_leaves_the_irq_pending() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5203, 1);
+    mapper.write_prg(0x5204, 0x80);
+    mapper.ppu_scanline_start(0, true);
+    mapper.ppu_scanline_start(1, true);
+    assert!(mapper.irq_pending());
+
+    /* as the tracer and debugger read it, through the CPU's memory */
+    let memory = CoreMemory::new_from_mapper(Box::new(mapper));
+    assert_eq!(memory.peek(0x5204), 0xc0);
+    assert_eq!(memory.peek(0x5204), 0xc0);
+    assert!(memory.mapper.borrow().irq_pending());
+}
+
+#[test]
+fn scanline_irq_waits_to_be_enabled() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5203, 1);
+    mapper.ppu_scanline_start(0, true);
+    mapper.ppu_scanline_start(1, true);
+    assert!(!mapper.irq_pending());
+    /* still pending underneath */
+    mapper.write_prg(0x5204, 0x80);
+    assert!(mapper.irq_pending());
+}
+
+#[test]
+fn frames_end_at_vblank_or_when_rendering_stops() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5203, 2);
+    mapper.write_prg(0x5204, 0x80);
+    start_frame(&mut mapper);
+    mapper.ppu_scanline_start(240, true);
+    assert_eq!(mapper.read_prg(0x5204), 0);
+
+    /* rendering off partway restarts the count */
+    start_frame(&mut mapper);
+    mapper.ppu_scanline_start(1, true);
+    mapper.ppu_scanline_start(2, false);
+    mapper.ppu_scanline_start(3, true);
+    mapper.ppu_scanline_start(4, true);
+    assert!(!mapper.irq_pending());
+    mapper.ppu_scanline_start(5, true);
+    assert!(mapper.irq_pending());
+}
+
+#[test]
+fn multiplier() {
+    let mut mapper = make_mmc5();
+    assert_eq!(mapper.read_prg(0x5205), 0x01);
+    assert_eq!(mapper.read_prg(0x5206), 0xfe);
+    mapper.write_prg(0x5205, 200);
+    mapper.write_prg(0x5206, 123);
+    assert_eq!(mapper.read_prg(0x5205), (24600 & 0xff) as u8);
+    assert_eq!(mapper.read_prg(0x5206), (24600 >> 8) as u8);
+}
+
+#[test]
+fn pulses_play_at_full_volume() {
+    let mut mapper = make_mmc5();
+    assert_eq!(mapper.expansion_audio(), 0.0);
+    mapper.write_prg(0x5015, 0x03);
+    for base in [0x5000, 0x5004] {
+        mapper.write_prg(base, 0x3f); /* constant volume 15, halted */
+        mapper.write_prg(base + 2, 0x10);
+        mapper.write_prg(base + 3, 0x08);
+    }
+
+    let mut loudest: f32 = 0.0;
+    for _ in 0..1000 {
+        mapper.cpu_cycle();
+        loudest = loudest.max(mapper.expansion_audio());
+    }
+    assert_eq!(loudest, mix_pulses(30.0));
+}
+
+#[test]
+fn pulse_length_counters_run_at_240hz() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5015, 0x02);
+    mapper.write_prg(0x5004, 0x1f);
+    /* length 10 */
+    mapper.write_prg(0x5007, 0x00);
+    assert_eq!(mapper.read_prg(0x5015), 0x02);
+    for _ in 0..7457 * 10 - 1 {
+        mapper.cpu_cycle();
+    }
+    assert_eq!(mapper.read_prg(0x5015), 0x02);
+    mapper.cpu_cycle();
+    assert_eq!(mapper.read_prg(0x5015), 0);
+}
+
+#[test]
+fn pcm_plays_what_the_cpu_writes() {
+    let mut mapper = make_mmc5();
+    mapper.write_prg(0x5011, 0xff);
+    assert_eq!(mapper.expansion_audio(), mix_tnd(0.0, 0.0, 127.5));
+    /* 0 is ignored */
+    mapper.write_prg(0x5011, 0);
+    assert_eq!(mapper.expansion_audio(), mix_tnd(0.0, 0.0, 127.5));
+}
+
+#[test]
+fn save_state_round_trips() {
+    let mut mapper = make_mmc5();
+    unprotect_prg_ram(&mut mapper);
+    mapper.write_prg(0x5114, 0x87);
+    mapper.write_prg(0x6000, 0x42);
+    mapper.write_prg(0x5104, 2);
+    mapper.write_prg(0x5c00, 0x43);
+    mapper.write_prg(0x5101, 3);
+    mapper.write_prg(0x5120, 9);
+    mapper.write_prg(0x5203, 1);
+    mapper.write_prg(0x5011, 0x80);
+    start_frame(&mut mapper);
+
+    let mut writer = StateWriter::new();
+    mapper.save_state(&mut writer);
+    let state = writer.into_bytes();
+
+    let mut restored = make_mmc5();
+    restored
+        .load_state(&mut StateReader::new(&state).unwrap())
+        .unwrap();
+    assert_eq!(restored.read_prg(0x8000), 7);
+    assert_eq!(restored.read_prg(0x6000), 0x42);
+    assert_eq!(restored.read_prg(0x5c00), 0x43);
+    assert_eq!(restored.read_chr(0x0000), 9);
+    assert_eq!(restored.expansion_audio(), mapper.expansion_audio());
+    restored.ppu_scanline_start(1, true);
+    assert_eq!(restored.read_prg(0x5204), 0xc0);
+}
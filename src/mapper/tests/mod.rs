@@ -0,0 +1,48 @@
+use crate::mapper::{Mapper, PPUFetch};
+use crate::rom::{Rom, RomHeader};
+
+mod cnrom_tests;
+mod four_screen_tests;
+mod gxrom_tests;
+mod mmc2_tests;
+mod mmc3_tests;
+mod mmc5_tests;
+mod vrc4_tests;
+mod vrc6_tests;
+mod vrc7_tests;
+
+/* what an MMC3 sees of a scanline drawn with the background at 0x0000 and sprites at 0x1000:
+ * a long stretch of fetches with A12 low, then the sprites' with it high
+ */
+fn a12_rise<M: Mapper + ?Sized>(mapper: &mut M) {
+    for _ in 0..100 {
+        mapper.cpu_cycle();
+    }
+    mapper.ppu_render_fetch(0x1000, PPUFetch::SpritePattern, 0);
+}
+
+/* builds a ROM whose every byte holds the number of the 1kb (CHR) or 8kb (PRG) bank it's in,
+ * so tests can tell which bank a read landed in
+ */
+fn rom_with_numbered_banks(prg_8kb_banks: usize, chr_1kb_banks: usize) -> Rom {
+    let prg_data = (0..prg_8kb_banks)
+        .flat_map(|bank| vec![bank as u8; 1 << 13])
+        .collect();
+    let chr_data: Vec<u8> = (0..chr_1kb_banks)
+        .flat_map(|bank| vec![bank as u8; 1 << 10])
+        .collect();
+
+    /* 8kb of PRG-RAM, and 8kb of CHR-RAM if there's no CHR-ROM */
+    let header = RomHeader {
+        prg_ram_size: 1 << 13,
+        chr_ram_size: if chr_data.is_empty() { 1 << 13 } else { 0 },
+        ..RomHeader::default()
+    };
+
+    Rom {
+        header,
+        prg_data,
+        chr_data,
+        _trainer: vec![],
+    }
+}
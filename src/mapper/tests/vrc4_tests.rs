@@ -0,0 +1,246 @@
+use super::rom_with_numbered_banks;
+use crate::mapper::vrc4::VRC4;
+use crate::mapper::Mapper;
+use crate::ppu::NametableMirroring;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+/* 256kb PRG (32 8kb banks), 256kb CHR */
+fn make_vrc(mapper: u16, submapper: u8) -> VRC4 {
+    let mut rom = rom_with_numbered_banks(32, 256);
+    rom.header.submapper = submapper;
+    VRC4::new(&rom, mapper)
+}
+
+/* VRC4a, whose registers are selected by A1 and A2 */
+fn make_vrc4a() -> VRC4 {
+    make_vrc(21, 1)
+}
+
+fn run_cycles(mapper: &mut VRC4, cycles: usize) {
+    for _ in 0..cycles {
+        mapper.cpu_cycle();
+    }
+}
+
+#[test]
+fn each_board_decodes_its_own_address_lines() {
+    /* the high nibble of CHR bank 0, then the low nibble of CHR bank 1 */
+    let boards = [
+        (21, 1, 0xb002, 0xb004), /* VRC4a */
+        (21, 2, 0xb040, 0xb080), /* VRC4c */
+        (23, 1, 0xb001, 0xb002), /* VRC4f */
+        (23, 2, 0xb004, 0xb008), /* VRC4e */
+        (23, 3, 0xb001, 0xb002), /* VRC2b */
+        (25, 1, 0xb002, 0xb001), /* VRC4b */
+        (25, 2, 0xb008, 0xb004), /* VRC4d */
+        (25, 3, 0xb002, 0xb001), /* VRC2c */
+    ];
+    for (mapper_num, submapper, high_nibble, next_bank) in boards {
+        let mut mapper = make_vrc(mapper_num, submapper);
+        mapper.write_prg(0xb000, 5);
+        mapper.write_prg(high_nibble, 0);
+        mapper.write_prg(next_bank, 7);
+        assert_eq!(
+            (mapper.read_chr(0x0000), mapper.read_chr(0x0400)),
+            (5, 7),
+            "mapper {mapper_num}.{submapper}"
+        );
+        mapper.write_prg(high_nibble, 1);
+        assert_eq!(
+            mapper.read_chr(0x0000),
+            0x15,
+            "mapper {mapper_num}.{submapper}"
+        );
+    }
+}
+
+#[test]
+fn without_a_submapper_both_wirings_work() {
+    /* the high nibble of CHR bank 0, as each of the mapper's two boards wire it */
+    for (mapper_num, high_nibbles) in [(21, [0xb002, 0xb040]), (23, [0xb001, 0xb004])] {
+        for address in high_nibbles {
+            let mut mapper = make_vrc(mapper_num, 0);
+            mapper.write_prg(address, 1);
+            assert_eq!(
+                mapper.read_chr(0x0000),
+                0x10,
+                "mapper {mapper_num}, {address:x}"
+            );
+        }
+    }
+}
+
+#[test]
+fn vrc2a_ignores_the_low_bit_of_chr_banks() {
+    let mut mapper = make_vrc(22, 0);
+    mapper.write_prg(0xb000, 5);
+    mapper.write_prg(0xb002, 1);
+    mapper.write_prg(0xb001, 7);
+    assert_eq!(mapper.read_chr(0x0000), 10);
+    assert_eq!(mapper.read_chr(0x0400), 3);
+}
+
+#[test]
+fn prg_banks_and_swap_mode() {
+    let mut mapper = make_vrc4a();
+    mapper.write_prg(0x8000, 3);
+    mapper.write_prg(0xa000, 4);
+    assert_eq!(mapper.read_prg(0x8000), 3);
+    assert_eq!(mapper.read_prg(0xa000), 4);
+    assert_eq!(mapper.read_prg(0xc000), 30);
+    assert_eq!(mapper.read_prg(0xe000), 31);
+
+    mapper.write_prg(0x9004, 2);
+    assert_eq!(mapper.read_prg(0x8000), 30);
+    assert_eq!(mapper.read_prg(0xa000), 4);
+    assert_eq!(mapper.read_prg(0xc000), 3);
+    assert_eq!(mapper.read_prg(0xe000), 31);
+}
+
+#[test]
+fn vrc4_mirroring() {
+    let mut mapper = make_vrc4a();
+    mapper.write_prg(0x9000, 1);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::Vertical
+    ));
+    mapper.write_prg(0x9000, 2);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::SingleNametable0
+    ));
+    mapper.write_prg(0x9002, 3);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::SingleNametable1
+    ));
+    mapper.write_prg(0x9000, 0);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::Horizontal
+    ));
+}
+
+#[test]
+fn vrc2_has_one_bit_mirroring_and_no_swap_mode() {
+    let mut mapper = make_vrc(23, 3);
+    mapper.write_prg(0x8000, 3);
+    mapper.write_prg(0x9002, 3);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::Vertical
+    ));
+    assert_eq!(mapper.read_prg(0x8000), 3);
+}
+
+#[test]
+fn vrc2_latch_without_prg_ram() {
+    let mut rom = rom_with_numbered_banks(32, 256);
+    rom.header.prg_ram_size = 0;
+    let mut mapper = VRC4::new(&rom, 22);
+    mapper.write_prg(0x6000, 0xff);
+    assert_eq!(mapper.read_prg(0x6000), 1);
+    mapper.write_prg(0x6fff, 0xfe);
+    assert_eq!(mapper.read_prg(0x6000), 0);
+}
+
+#[test]
+fn prg_ram() {
+    let mut mapper = make_vrc4a();
+    mapper.write_prg(0x7fff, 0x42);
+    assert_eq!(mapper.read_prg(0x7fff), 0x42);
+    assert_eq!(mapper.get_save_data().unwrap()[0x1fff], 0x42);
+}
+
+#[test]
+fn irq_in_cycle_mode() {
+    let mut mapper = make_vrc4a();
+    /* latch 0xfd, written a nibble at a time */
+    mapper.write_prg(0xf000, 0xd);
+    mapper.write_prg(0xf002, 0xf);
+    mapper.write_prg(0xf004, 0b110);
+
+    run_cycles(&mut mapper, 2);
+    assert!(!mapper.irq_pending());
+    run_cycles(&mut mapper, 1);
+    assert!(mapper.irq_pending());
+
+    /* acknowledging disables it, since enable-after-acknowledge wasn't set */
+    mapper.write_prg(0xf006, 0);
+    assert!(!mapper.irq_pending());
+    run_cycles(&mut mapper, 1000);
+    assert!(!mapper.irq_pending());
+}
+
+#[test]
+fn irq_in_scanline_mode_counts_three_scanlines_every_341_cycles() {
+    let mut mapper = make_vrc4a();
+    /* latch 0xff, so every clock of the counter overflows it */
+    mapper.write_prg(0xf000, 0xf);
+    mapper.write_prg(0xf002, 0xf);
+    mapper.write_prg(0xf004, 0b011);
+
+    for cycles in [114, 114, 113] {
+        run_cycles(&mut mapper, cycles - 1);
+        assert!(!mapper.irq_pending());
+        run_cycles(&mut mapper, 1);
+        assert!(mapper.irq_pending());
+        mapper.write_prg(0xf006, 0);
+    }
+}
+
+#[test]
+fn irq_keeps_running_with_enable_after_acknowledge() {
+    let mut mapper = make_vrc4a();
+    mapper.write_prg(0xf000, 0xe);
+    mapper.write_prg(0xf002, 0xf);
+    mapper.write_prg(0xf004, 0b111);
+    run_cycles(&mut mapper, 2);
+    assert!(mapper.irq_pending());
+
+    mapper.write_prg(0xf006, 0);
+    assert!(!mapper.irq_pending());
+    run_cycles(&mut mapper, 2);
+    assert!(mapper.irq_pending());
+}
+
+#[test]
+fn vrc2_has_no_irq() {
+    let mut mapper = make_vrc(23, 3);
+    mapper.write_prg(0xf000, 0xf);
+    mapper.write_prg(0xf001, 0xf);
+    mapper.write_prg(0xf002, 0b110);
+    run_cycles(&mut mapper, 1000);
+    assert!(!mapper.irq_pending());
+}
+
+#[test]
+fn save_state_round_trips() {
+    let mut mapper = make_vrc4a();
+    mapper.write_prg(0x8000, 3);
+    mapper.write_prg(0x9004, 2);
+    mapper.write_prg(0xb004, 9);
+    mapper.write_prg(0xf000, 0xe);
+    mapper.write_prg(0xf002, 0xf);
+    mapper.write_prg(0xf004, 0b110);
+    run_cycles(&mut mapper, 1);
+
+    let mut writer = StateWriter::new();
+    mapper.save_state(&mut writer);
+    let state = writer.into_bytes();
+
+    let mut restored = make_vrc4a();
+    restored
+        .load_state(&mut StateReader::new(&state).unwrap())
+        .unwrap();
+    assert_eq!(restored.read_prg(0xc000), 3);
+    assert_eq!(restored.read_chr(0x0400), 9);
+    assert!(!restored.irq_pending());
+    run_cycles(&mut restored, 1);
+    assert!(restored.irq_pending());
+
+    /* the registers come back too, not just the banks they selected */
+    restored.write_prg(0x9004, 0);
+    assert_eq!(restored.read_prg(0x8000), 3);
+}
@@ -0,0 +1,205 @@
+use super::rom_with_numbered_banks;
+use crate::apu::mix_pulses;
+use crate::mapper::vrc6::VRC6;
+use crate::mapper::Mapper;
+use crate::ppu::NametableMirroring;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+/* 256kb PRG (32 8kb banks), 256kb CHR */
+fn make_vrc6(mapper: u16) -> VRC6 {
+    VRC6::new(&rom_with_numbered_banks(32, 256), mapper)
+}
+
+fn run_cycles(mapper: &mut VRC6, cycles: usize) {
+    for _ in 0..cycles {
+        mapper.cpu_cycle();
+    }
+}
+
+/* the sound channels' summed output, as a 4-bit pulse would count it */
+fn audio_units(mapper: &VRC6) -> u8 {
+    (mapper.expansion_audio() * 15.0 / mix_pulses(15.0)).round() as u8
+}
+
+#[test]
+fn prg_banks() {
+    let mut mapper = make_vrc6(24);
+    mapper.write_prg(0x8000, 3);
+    mapper.write_prg(0xc000, 9);
+    assert_eq!(mapper.read_prg(0x8000), 6);
+    assert_eq!(mapper.read_prg(0xa000), 7);
+    assert_eq!(mapper.read_prg(0xc000), 9);
+    assert_eq!(mapper.read_prg(0xe000), 31);
+}
+
+#[test]
+fn mapper_26_swaps_the_register_lines() {
+    let mut mapper = make_vrc6(24);
+    mapper.write_prg(0xd001, 5);
+    assert_eq!(mapper.read_chr(0x0400), 5);
+
+    let mut mapper = make_vrc6(26);
+    mapper.write_prg(0xd001, 5);
+    assert_eq!(mapper.read_chr(0x0400), 0);
+    assert_eq!(mapper.read_chr(0x0800), 5);
+}
+
+#[test]
+fn chr_modes() {
+    let mut mapper = make_vrc6(24);
+    for (i, address) in [
+        0xd000, 0xd001, 0xd002, 0xd003, 0xe000, 0xe001, 0xe002, 0xe003,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        mapper.write_prg(address, 10 + i as u8 * 2);
+    }
+    let banks = |mapper: &VRC6| {
+        (0..8)
+            .map(|i| mapper.read_chr(i * 0x400))
+            .collect::<Vec<_>>()
+    };
+
+    mapper.write_prg(0xb003, 0);
+    assert_eq!(banks(&mapper), [10, 12, 14, 16, 18, 20, 22, 24]);
+    mapper.write_prg(0xb003, 1);
+    assert_eq!(banks(&mapper), [10, 11, 12, 13, 14, 15, 16, 17]);
+    mapper.write_prg(0xb003, 2);
+    assert_eq!(banks(&mapper), [10, 12, 14, 16, 18, 19, 20, 21]);
+}
+
+#[test]
+fn mirroring() {
+    let mut mapper = make_vrc6(24);
+    mapper.write_prg(0xb003, 0x24);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::Vertical
+    ));
+    mapper.write_prg(0xb003, 0x2c);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::SingleNametable1
+    ));
+    mapper.write_prg(0xb003, 0x20);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::Horizontal
+    ));
+}
+
+#[test]
+fn prg_ram_needs_enabling() {
+    let mut mapper = make_vrc6(24);
+    mapper.write_prg(0x6000, 0x42);
+    assert_eq!(mapper.read_prg(0x6000), 0);
+
+    mapper.write_prg(0xb003, 0x80);
+    mapper.write_prg(0x6000, 0x42);
+    assert_eq!(mapper.read_prg(0x6000), 0x42);
+}
+
+#[test]
+fn irq() {
+    let mut mapper = make_vrc6(24);
+    mapper.write_prg(0xf000, 0xfe);
+    mapper.write_prg(0xf001, 0b110);
+    run_cycles(&mut mapper, 1);
+    assert!(!mapper.irq_pending());
+    run_cycles(&mut mapper, 1);
+    assert!(mapper.irq_pending());
+    mapper.write_prg(0xf002, 0);
+    assert!(!mapper.irq_pending());
+}
+
+#[test]
+fn pulse_plays_its_duty_out_of_16() {
+    let mut mapper = make_vrc6(24);
+    /* duty 3 at volume 10, stepping every cycle */
+    mapper.write_prg(0x9000, 0x3a);
+    mapper.write_prg(0x9001, 0);
+    mapper.write_prg(0x9002, 0x80);
+
+    let mut levels = vec![];
+    for _ in 0..16 {
+        levels.push(audio_units(&mapper));
+        run_cycles(&mut mapper, 1);
+    }
+    assert_eq!(levels.iter().filter(|&&level| level == 10).count(), 4);
+    assert_eq!(levels.iter().filter(|&&level| level == 0).count(), 12);
+}
+
+#[test]
+fn digitized_pulse_holds_its_volume() {
+    let mut mapper = make_vrc6(24);
+    mapper.write_prg(0xa000, 0x87);
+    mapper.write_prg(0xa002, 0x80);
+    run_cycles(&mut mapper, 100);
+    assert_eq!(audio_units(&mapper), 7);
+
+    /* disabling silences it */
+    mapper.write_prg(0xa002, 0);
+    assert_eq!(audio_units(&mapper), 0);
+}
+
+#[test]
+fn sawtooth_ramps_up_over_14_steps() {
+    let mut mapper = make_vrc6(24);
+    mapper.write_prg(0xb000, 42);
+    mapper.write_prg(0xb001, 0);
+    mapper.write_prg(0xb002, 0x80);
+
+    let mut levels = vec![];
+    for _ in 0..14 {
+        run_cycles(&mut mapper, 1);
+        levels.push(audio_units(&mapper));
+    }
+    /* the accumulator gains 42 every other step, and the top 5 of its 8 bits are output */
+    assert_eq!(levels, [0, 5, 5, 10, 10, 15, 15, 21, 21, 26, 26, 31, 31, 0]);
+}
+
+#[test]
+fn frequency_control_halts_and_speeds_up_the_channels() {
+    let mut mapper = make_vrc6(24);
+    mapper.write_prg(0xb000, 42);
+    mapper.write_prg(0xb001, 0x0f);
+    mapper.write_prg(0xb002, 0x80);
+
+    /* halted, nothing moves */
+    mapper.write_prg(0x9003, 1);
+    run_cycles(&mut mapper, 100);
+    assert_eq!(audio_units(&mapper), 0);
+
+    /* with the period shifted right 4 bits, it steps every cycle */
+    mapper.write_prg(0x9003, 2);
+    run_cycles(&mut mapper, 2);
+    assert_eq!(audio_units(&mapper), 5);
+}
+
+#[test]
+fn save_state_round_trips() {
+    let mut mapper = make_vrc6(26);
+    mapper.write_prg(0x8000, 3);
+    mapper.write_prg(0xd002, 9);
+    mapper.write_prg(0xb003, 0x80);
+    /* on mapper 26, the sawtooth's enable is at 0xb001 */
+    mapper.write_prg(0xb000, 42);
+    mapper.write_prg(0xb001, 0x80);
+    run_cycles(&mut mapper, 3);
+
+    let mut writer = StateWriter::new();
+    mapper.save_state(&mut writer);
+    let state = writer.into_bytes();
+
+    let mut restored = make_vrc6(26);
+    restored
+        .load_state(&mut StateReader::new(&state).unwrap())
+        .unwrap();
+    assert_eq!(restored.read_prg(0x8000), 6);
+    assert_eq!(restored.read_chr(0x0400), 9);
+    assert_eq!(audio_units(&restored), 5);
+    run_cycles(&mut mapper, 2);
+    run_cycles(&mut restored, 2);
+    assert_eq!(audio_units(&restored), audio_units(&mapper));
+}
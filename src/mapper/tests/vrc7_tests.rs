@@ -0,0 +1,195 @@
+use super::rom_with_numbered_banks;
+use crate::mapper::vrc7::VRC7;
+use crate::mapper::Mapper;
+use crate::ppu::NametableMirroring;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+
+/* 256kb PRG (32 8kb banks), 128kb CHR */
+fn make_vrc7(submapper: u8) -> VRC7 {
+    let mut rom = rom_with_numbered_banks(32, 128);
+    rom.header.submapper = submapper;
+    VRC7::new(&rom)
+}
+
+/* VRC7a, as on Lagrange Point, whose registers are selected by A4 */
+fn make_vrc7a() -> VRC7 {
+    make_vrc7(2)
+}
+
+fn run_cycles(mapper: &mut VRC7, cycles: usize) {
+    for _ in 0..cycles {
+        mapper.cpu_cycle();
+    }
+}
+
+fn write_audio(mapper: &mut VRC7, register: u8, value: u8) {
+    mapper.write_prg(0x9010, register);
+    mapper.write_prg(0x9030, value);
+}
+
+/* plays channel 0 with the flute at full volume, in the octave around middle C */
+fn key_on(mapper: &mut VRC7) {
+    write_audio(mapper, 0x30, 0x40);
+    write_audio(mapper, 0x10, 0xac);
+    write_audio(mapper, 0x20, 0x18);
+}
+
+/* the loudest the synth gets over some CPU cycles */
+fn peak_level(mapper: &mut VRC7, cycles: usize) -> f32 {
+    let mut peak: f32 = 0.0;
+    for _ in 0..cycles {
+        mapper.cpu_cycle();
+        peak = peak.max(mapper.expansion_audio().abs());
+    }
+    peak
+}
+
+#[test]
+fn prg_banks() {
+    let mut mapper = make_vrc7a();
+    mapper.write_prg(0x8000, 3);
+    mapper.write_prg(0x8010, 4);
+    mapper.write_prg(0x9000, 5);
+    assert_eq!(mapper.read_prg(0x8000), 3);
+    assert_eq!(mapper.read_prg(0xa000), 4);
+    assert_eq!(mapper.read_prg(0xc000), 5);
+    assert_eq!(mapper.read_prg(0xe000), 31);
+}
+
+#[test]
+fn chr_banks() {
+    let mut mapper = make_vrc7a();
+    for (i, address) in [
+        0xa000, 0xa010, 0xb000, 0xb010, 0xc000, 0xc010, 0xd000, 0xd010,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        mapper.write_prg(address, 20 + i as u8);
+    }
+    for i in 0..8 {
+        assert_eq!(mapper.read_chr(i * 0x400), 20 + i as u8);
+    }
+}
+
+#[test]
+fn vrc7b_selects_registers_with_a3() {
+    let mut mapper = make_vrc7(1);
+    mapper.write_prg(0x8008, 4);
+    mapper.write_prg(0xa008, 9);
+    assert_eq!(mapper.read_prg(0xa000), 4);
+    assert_eq!(mapper.read_chr(0x0400), 9);
+
+    /* A4 is just another address line to it */
+    mapper.write_prg(0x8010, 6);
+    assert_eq!(mapper.read_prg(0x8000), 6);
+}
+
+#[test]
+fn without_a_submapper_either_line_works() {
+    for address in [0x8008, 0x8010] {
+        let mut mapper = make_vrc7(0);
+        mapper.write_prg(address, 4);
+        assert_eq!(mapper.read_prg(0xa000), 4, "{address:x}");
+    }
+}
+
+#[test]
+fn mirroring_and_prg_ram() {
+    let mut mapper = make_vrc7a();
+    mapper.write_prg(0x6000, 0x42);
+    assert_eq!(mapper.read_prg(0x6000), 0);
+
+    mapper.write_prg(0xe000, 0x81);
+    assert!(matches!(
+        mapper.get_nametable_mirroring(),
+        NametableMirroring::Vertical
+    ));
+    mapper.write_prg(0x6000, 0x42);
+    assert_eq!(mapper.read_prg(0x6000), 0x42);
+}
+
+#[test]
+fn irq() {
+    let mut mapper = make_vrc7a();
+    mapper.write_prg(0xe010, 0xfe);
+    mapper.write_prg(0xf000, 0b110);
+    run_cycles(&mut mapper, 1);
+    assert!(!mapper.irq_pending());
+    run_cycles(&mut mapper, 1);
+    assert!(mapper.irq_pending());
+    mapper.write_prg(0xf010, 0);
+    assert!(!mapper.irq_pending());
+}
+
+#[test]
+fn synth_is_silent_until_a_channel_is_keyed() {
+    let mut mapper = make_vrc7a();
+    assert_eq!(peak_level(&mut mapper, 10_000), 0.0);
+
+    /* the flute takes a while to come in */
+    key_on(&mut mapper);
+    assert!(peak_level(&mut mapper, 200_000) > 0.01);
+}
+
+#[test]
+fn released_notes_fade_out() {
+    let mut mapper = make_vrc7a();
+    key_on(&mut mapper);
+    run_cycles(&mut mapper, 200_000);
+
+    write_audio(&mut mapper, 0x20, 0x08);
+    run_cycles(&mut mapper, 1_000_000);
+    assert!(peak_level(&mut mapper, 1000) < 0.001);
+}
+
+#[test]
+fn volume_attenuates() {
+    let mut loud = make_vrc7a();
+    key_on(&mut loud);
+    let mut quiet = make_vrc7a();
+    key_on(&mut quiet);
+    /* 15 steps of 3dB */
+    write_audio(&mut quiet, 0x30, 0x4f);
+
+    let ratio = peak_level(&mut quiet, 20_000) / peak_level(&mut loud, 20_000);
+    assert!(ratio < 0.01, "{ratio}");
+}
+
+#[test]
+fn silencing_resets_the_synth() {
+    let mut mapper = make_vrc7a();
+    key_on(&mut mapper);
+    run_cycles(&mut mapper, 10_000);
+
+    mapper.write_prg(0xe000, 0x40);
+    assert_eq!(peak_level(&mut mapper, 10_000), 0.0);
+    /* and the channel was keyed off, so it stays quiet once let go */
+    mapper.write_prg(0xe000, 0);
+    assert_eq!(peak_level(&mut mapper, 10_000), 0.0);
+}
+
+#[test]
+fn save_state_round_trips() {
+    let mut mapper = make_vrc7a();
+    mapper.write_prg(0x8000, 3);
+    mapper.write_prg(0xd010, 9);
+    key_on(&mut mapper);
+    run_cycles(&mut mapper, 5000);
+
+    let mut writer = StateWriter::new();
+    mapper.save_state(&mut writer);
+    let state = writer.into_bytes();
+
+    let mut restored = make_vrc7a();
+    restored
+        .load_state(&mut StateReader::new(&state).unwrap())
+        .unwrap();
+    assert_eq!(restored.read_prg(0x8000), 3);
+    assert_eq!(restored.read_chr(0x1c00), 9);
+    for _ in 0..1000 {
+        mapper.cpu_cycle();
+        restored.cpu_cycle();
+        assert_eq!(restored.expansion_audio(), mapper.expansion_audio());
+    }
+}
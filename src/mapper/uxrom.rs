@@ -2,6 +2,8 @@ use crate::mapper::bank_array::BankArray;
 use crate::mapper::{Mapper, SIZE_16_KB, SIZE_8_KB};
 use crate::ppu::NametableMirroring;
 use crate::rom::Rom;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
 
 pub struct UxROM {
     prg_banks: BankArray,
@@ -11,7 +13,7 @@ pub struct UxROM {
 
 impl UxROM {
     pub fn new(rom: &Rom) -> Self {
-        let mut chr_bank = BankArray::new(SIZE_8_KB, 0, rom.chr_data.clone());
+        let mut chr_bank = BankArray::new(SIZE_8_KB, 0, rom.chr_rom_or_ram());
         chr_bank.set_bank(0, 0);
 
         let mut prg_banks = BankArray::new(SIZE_16_KB, 0x8000, rom.prg_data.clone());
@@ -31,10 +33,6 @@ impl Mapper for UxROM {
         self.prg_banks.read(address)
     }
 
-    fn read_prg_slice(&self, address: u16, size: usize) -> &[u8] {
-        self.prg_banks.read_slice(address, size)
-    }
-
     fn write_prg(&mut self, address: u16, value: u8) {
         if address >= 0x8000 {
             /* TODO: should be 0x7 for some variants */
@@ -56,3 +54,17 @@ impl Mapper for UxROM {
         self.nametable_mirroring.clone()
     }
 }
+
+impl Savestate for UxROM {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.prg_banks.save_state(writer);
+        self.chr_bank.save_state(writer);
+        writer.write_bytes(self.chr_bank.data());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.prg_banks.load_state(reader)?;
+        self.chr_bank.load_state(reader)?;
+        reader.read_bytes_into(self.chr_bank.data_mut())
+    }
+}
@@ -0,0 +1,135 @@
+use crate::ppu::NametableMirroring;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
+/* the prescaler counts down by 3 from this each CPU cycle, so scanline mode clocks the counter
+ * every 113 2/3 CPU cycles, i.e. once per scanline
+ */
+const PRESCALER_PERIOD: i16 = 341;
+
+/* bits of the IRQ control register */
+const CONTROL_ENABLE_AFTER_ACK: u8 = 1;
+const CONTROL_ENABLE: u8 = 2;
+const CONTROL_CYCLE_MODE: u8 = 4;
+
+/* the VRC mirroring control shared by VRC4, VRC6 and VRC7; VRC2 has only the low bit. NB this
+ * crate names mirroring by the direction pages repeat
+ */
+pub fn vrc_mirroring(value: u8) -> NametableMirroring {
+    match value & 3 {
+        0 => NametableMirroring::Horizontal,
+        1 => NametableMirroring::Vertical,
+        2 => NametableMirroring::SingleNametable0,
+        _ => NametableMirroring::SingleNametable1,
+    }
+}
+
+/**
+ * The IRQ counter on VRC4, VRC6 and VRC7. Rather than watching the PPU, it counts CPU cycles:
+ * either every one, or through a prescaler that approximates scanlines. The 8-bit counter counts
+ * up, and when it overflows it's reloaded from the latch and raises an IRQ.
+ */
+pub struct IRQCounter {
+    latch: u8,
+    control: u8,
+    counter: u8,
+    prescaler: i16,
+    pending: bool,
+}
+
+impl IRQCounter {
+    pub fn new() -> IRQCounter {
+        IRQCounter {
+            latch: 0,
+            control: 0,
+            counter: 0,
+            prescaler: PRESCALER_PERIOD,
+            pending: false,
+        }
+    }
+
+    pub fn write_latch(&mut self, value: u8) {
+        self.latch = value;
+    }
+
+    /* VRC4 splits the latch between two registers */
+    pub fn write_latch_low(&mut self, value: u8) {
+        self.latch = (self.latch & 0xf0) | (value & 0xf);
+    }
+
+    pub fn write_latch_high(&mut self, value: u8) {
+        self.latch = (self.latch & 0xf) | (value & 0xf) << 4;
+    }
+
+    /* enabling reloads the counter and restarts the prescaler; either way, any pending
+     * interrupt is acknowledged
+     */
+    pub fn write_control(&mut self, value: u8) {
+        self.control = value & 7;
+        self.pending = false;
+        if self.enabled() {
+            self.counter = self.latch;
+            self.prescaler = PRESCALER_PERIOD;
+        }
+    }
+
+    /* acknowledges, and copies the enable-after-acknowledge bit into enable */
+    pub fn acknowledge(&mut self) {
+        self.pending = false;
+        self.control &= !CONTROL_ENABLE;
+        if self.control & CONTROL_ENABLE_AFTER_ACK != 0 {
+            self.control |= CONTROL_ENABLE;
+        }
+    }
+
+    pub fn cpu_cycle(&mut self) {
+        if !self.enabled() {
+            return;
+        }
+        if self.control & CONTROL_CYCLE_MODE != 0 {
+            self.clock();
+        } else {
+            self.prescaler -= 3;
+            if self.prescaler <= 0 {
+                self.prescaler += PRESCALER_PERIOD;
+                self.clock();
+            }
+        }
+    }
+
+    pub fn pending(&self) -> bool {
+        self.pending
+    }
+
+    fn enabled(&self) -> bool {
+        self.control & CONTROL_ENABLE != 0
+    }
+
+    fn clock(&mut self) {
+        if self.counter == 0xff {
+            self.counter = self.latch;
+            self.pending = true;
+        } else {
+            self.counter += 1;
+        }
+    }
+}
+
+impl Savestate for IRQCounter {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.latch);
+        writer.write_u8(self.control);
+        writer.write_u8(self.counter);
+        writer.write_u16(self.prescaler as u16);
+        writer.write_bool(self.pending);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.latch = reader.read_u8()?;
+        self.control = reader.read_u8()? & 7;
+        self.counter = reader.read_u8()?;
+        self.prescaler = (reader.read_u16()? as i16).clamp(1, PRESCALER_PERIOD);
+        self.pending = reader.read_bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,271 @@
+use crate::mapper::bank_array::BankArray;
+use crate::mapper::vrc::{vrc_mirroring, IRQCounter};
+use crate::mapper::{Mapper, SIZE_1_KB, SIZE_8_KB};
+use crate::ppu::NametableMirroring;
+use crate::rom::Rom;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
+/* address lines, as masks */
+const A0: u16 = 1 << 0;
+const A1: u16 = 1 << 1;
+const A2: u16 = 1 << 2;
+const A3: u16 = 1 << 3;
+const A6: u16 = 1 << 6;
+const A7: u16 = 1 << 7;
+
+/* for each of mappers 21, 22, 23 and 25 and its submappers: the address lines wired to the
+ * chip's two register select pins, and whether the chip is a VRC2 or a VRC4. With no
+ * submapper, both candidate pairs of lines are decoded at once, which works for every known
+ * game since each only writes the addresses of one of them
+ */
+struct Board {
+    register_lines: [u16; 2],
+    vrc4: bool,
+}
+
+fn board(mapper: u16, submapper: u8) -> Board {
+    let (register_lines, vrc4) = match (mapper, submapper) {
+        (21, 1) => ([A1, A2], true),           /* VRC4a */
+        (21, 2) => ([A6, A7], true),           /* VRC4c */
+        (21, _) => ([A1 | A6, A2 | A7], true), /* VRC4a or VRC4c */
+        (22, _) => ([A1, A0], false),          /* VRC2a */
+        (23, 1) => ([A0, A1], true),           /* VRC4f */
+        (23, 2) => ([A2, A3], true),           /* VRC4e */
+        (23, 3) => ([A0, A1], false),          /* VRC2b */
+        (23, _) => ([A0 | A2, A1 | A3], true), /* VRC2b or VRC4e */
+        (25, 1) => ([A1, A0], true),           /* VRC4b */
+        (25, 2) => ([A3, A2], true),           /* VRC4d */
+        (25, 3) => ([A1, A0], false),          /* VRC2c */
+        (_, _) => ([A1 | A3, A0 | A2], true),  /* VRC2c, VRC4b or VRC4d */
+    };
+    Board {
+        register_lines,
+        vrc4,
+    }
+}
+
+/**
+ * Konami's VRC2 and VRC4 (mappers 21, 22, 23 and 25; Contra and Ganbare Goemon 2 in Japan).
+ * Two switchable 8kb PRG banks and eight 1kb CHR banks, each CHR bank number written a nibble at
+ * a time. VRC4 adds a mode swapping the first switchable PRG bank with the fixed one at 0xc000,
+ * single-screen mirroring and the VRC IRQ counter. Boards wire different address lines to each
+ * register's select pins, so the same chip appears under several mapper numbers, told apart by
+ * the NES 2.0 submapper.
+ */
+pub struct VRC4 {
+    register_lines: [u16; 2],
+    vrc4: bool,
+    chr_shift: u8, /* VRC2a ignores the low bit of CHR bank numbers */
+    prg_ram: Vec<u8>,
+    latch: u8, /* 1 bit at 0x6000-0x6fff on boards with no PRG-RAM, as VRC2 games use */
+    prg_banks: BankArray,
+    chr_banks: BankArray,
+    prg_registers: [u8; 2],
+    prg_swap: bool,
+    chr_registers: [u16; 8],
+    nametable_mirroring: NametableMirroring,
+    irq: IRQCounter,
+}
+
+impl VRC4 {
+    pub fn new(rom: &Rom, mapper: u16) -> VRC4 {
+        let board = board(mapper, rom.header.submapper);
+        let mut prg_banks = BankArray::new(SIZE_8_KB, 0x8000, rom.prg_data.clone());
+        for i in 0..4 {
+            prg_banks.set_bank(i, 0);
+        }
+        let mut chr_banks = BankArray::new(SIZE_1_KB, 0, rom.chr_rom_or_ram());
+        for i in 0..8 {
+            chr_banks.set_bank(i, 0);
+        }
+
+        let mut result = VRC4 {
+            register_lines: board.register_lines,
+            vrc4: board.vrc4,
+            chr_shift: (mapper == 22) as u8,
+            prg_ram: vec![0; rom.header.work_ram_size()],
+            latch: 0,
+            prg_banks,
+            chr_banks,
+            prg_registers: [0; 2],
+            prg_swap: false,
+            chr_registers: [0; 8],
+            nametable_mirroring: rom.nametable_mirroring(),
+            irq: IRQCounter::new(),
+        };
+        result.update_prg_banks();
+        result
+    }
+
+    /* the register an address selects within its 4kb block, 0-3 */
+    fn register(&self, address: u16) -> u8 {
+        let [low, high] = self.register_lines;
+        (address & low != 0) as u8 | ((address & high != 0) as u8) << 1
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        let register = self.register(address);
+        match (address & 0xf000, register) {
+            (0x8000, _) => {
+                self.prg_registers[0] = value & 0x1f;
+                self.update_prg_banks();
+            }
+            (0x9000, 0) | (0x9000, 1) if self.vrc4 => {
+                self.nametable_mirroring = vrc_mirroring(value);
+            }
+            (0x9000, _) if self.vrc4 => {
+                self.prg_swap = value & 2 != 0;
+                self.update_prg_banks();
+            }
+            (0x9000, _) => self.nametable_mirroring = vrc_mirroring(value & 1),
+            (0xa000, _) => {
+                self.prg_registers[1] = value & 0x1f;
+                self.update_prg_banks();
+            }
+            /* each 1kb CHR bank takes a pair of registers, the low nibble then the high */
+            (0xb000..=0xe000, _) => {
+                let bank = ((address >> 12) as usize - 0xb) * 2 + (register >> 1) as usize;
+                let chr_register = &mut self.chr_registers[bank];
+                *chr_register = if register & 1 == 0 {
+                    (*chr_register & 0x1f0) | (value & 0xf) as u16
+                } else {
+                    (*chr_register & 0xf) | ((value & 0x1f) as u16) << 4
+                };
+                self.update_chr_bank(bank);
+            }
+            (0xf000, _) if !self.vrc4 => { /* VRC2 has no IRQ */ }
+            (0xf000, 0) => self.irq.write_latch_low(value),
+            (0xf000, 1) => self.irq.write_latch_high(value),
+            (0xf000, 2) => self.irq.write_control(value),
+            (0xf000, _) => self.irq.acknowledge(),
+            _ => unreachable!(),
+        }
+    }
+
+    /* 0xc000 is fixed to the second-to-last bank, and trades places with the first switchable
+     * bank in VRC4's swap mode; 0xe000 is always the last bank
+     */
+    fn update_prg_banks(&mut self) {
+        let bank_count = self.prg_banks.bank_count();
+        let r0 = (self.prg_registers[0] as usize % bank_count) as u8;
+        let r1 = (self.prg_registers[1] as usize % bank_count) as u8;
+        let second_to_last = (bank_count - 2) as u8;
+        if self.prg_swap {
+            self.prg_banks.set_bank(0, second_to_last);
+            self.prg_banks.set_bank(2, r0);
+        } else {
+            self.prg_banks.set_bank(0, r0);
+            self.prg_banks.set_bank(2, second_to_last);
+        }
+        self.prg_banks.set_bank(1, r1);
+        self.prg_banks.set_last_bank(3);
+    }
+
+    fn update_chr_bank(&mut self, index: usize) {
+        let bank = (self.chr_registers[index] >> self.chr_shift) as usize;
+        let bank_count = self.chr_banks.bank_count();
+        self.chr_banks
+            .set_bank(index as u8, (bank % bank_count) as u8);
+    }
+
+    fn prg_ram_index(&self, address: u16) -> usize {
+        address as usize - 0x6000
+    }
+}
+
+impl Mapper for VRC4 {
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        } else if address < 0x8000 {
+            match self.prg_ram.get(self.prg_ram_index(address)) {
+                Some(value) => *value,
+                None if address < 0x7000 => self.latch,
+                None => 0, /* TODO open bus */
+            }
+        } else {
+            self.prg_banks.read(address)
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x6000 {
+            /* nothing mapped here */
+        } else if address < 0x8000 {
+            let index = self.prg_ram_index(address);
+            if let Some(byte) = self.prg_ram.get_mut(index) {
+                *byte = value;
+            } else if address < 0x7000 {
+                self.latch = value & 1;
+            }
+        } else {
+            self.write_register(address, value);
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr_banks.read(address)
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr_banks.write(address, value);
+    }
+
+    fn get_nametable_mirroring(&self) -> NametableMirroring {
+        self.nametable_mirroring.clone()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn get_save_data(&self) -> Option<Vec<u8>> {
+        if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(self.prg_ram.clone())
+        }
+    }
+
+    fn set_save_data(&mut self, data: &Vec<u8>) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[0..len].copy_from_slice(&data[0..len]);
+    }
+}
+
+impl Savestate for VRC4 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        writer.write_u8(self.latch);
+        self.prg_banks.save_state(writer);
+        self.chr_banks.save_state(writer);
+        writer.write_bytes(self.chr_banks.data());
+        writer.write_bytes(&self.prg_registers);
+        writer.write_bool(self.prg_swap);
+        for register in self.chr_registers {
+            writer.write_u16(register);
+        }
+        self.nametable_mirroring.save_state(writer);
+        self.irq.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.prg_ram)?;
+        self.latch = reader.read_u8()? & 1;
+        self.prg_banks.load_state(reader)?;
+        self.chr_banks.load_state(reader)?;
+        reader.read_bytes_into(self.chr_banks.data_mut())?;
+        reader.read_bytes_into(&mut self.prg_registers)?;
+        self.prg_swap = reader.read_bool()?;
+        for register in &mut self.chr_registers {
+            *register = reader.read_u16()? & 0x1ff;
+        }
+        self.nametable_mirroring.load_state(reader)?;
+        self.irq.load_state(reader)
+    }
+}
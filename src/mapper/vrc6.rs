@@ -0,0 +1,487 @@
+use crate::apu::mix_pulses;
+use crate::mapper::bank_array::BankArray;
+use crate::mapper::vrc::{vrc_mirroring, IRQCounter};
+use crate::mapper::{Mapper, SIZE_1_KB, SIZE_8_KB};
+use crate::ppu::NametableMirroring;
+use crate::rom::Rom;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
+/* bits of 0xb003 */
+const CHR_MODE: u8 = 3;
+const PRG_RAM_ENABLE: u8 = 0x80;
+
+/* bits of 0x9003, which applies to all three sound channels */
+const AUDIO_HALT: u8 = 1;
+const AUDIO_SHIFT_4: u8 = 2;
+const AUDIO_SHIFT_8: u8 = 4;
+
+/**
+ * Konami's VRC6 (mappers 24 and 26; Akumajou Densetsu, Madara, Esper Dream 2). A switchable 16kb
+ * PRG bank, a switchable 8kb one and the last 8kb fixed, 1kb or 2kb CHR banks, the VRC IRQ
+ * counter, and three more sound channels: two pulses with 16 duty cycles and a sawtooth. The two
+ * mappers differ only in having the register select lines swapped.
+ */
+pub struct VRC6 {
+    swapped_lines: bool, /* mapper 26 wires A0 and A1 the other way round */
+    prg_ram: Vec<u8>,
+    prg_banks: BankArray,
+    chr_banks: BankArray,
+    chr_registers: [u8; 8],
+    banking_control: u8, /* 0xb003 */
+    nametable_mirroring: NametableMirroring,
+    irq: IRQCounter,
+    audio: VRC6Audio,
+}
+
+impl VRC6 {
+    pub fn new(rom: &Rom, mapper: u16) -> VRC6 {
+        let mut prg_banks = BankArray::new(SIZE_8_KB, 0x8000, rom.prg_data.clone());
+        for i in 0..3 {
+            prg_banks.set_bank(i, i);
+        }
+        prg_banks.set_last_bank(3);
+        let mut chr_banks = BankArray::new(SIZE_1_KB, 0, rom.chr_rom_or_ram());
+        for i in 0..8 {
+            chr_banks.set_bank(i, 0);
+        }
+
+        VRC6 {
+            swapped_lines: mapper == 26,
+            prg_ram: vec![0; rom.header.work_ram_size()],
+            prg_banks,
+            chr_banks,
+            chr_registers: [0; 8],
+            banking_control: 0,
+            nametable_mirroring: rom.nametable_mirroring(),
+            irq: IRQCounter::new(),
+            audio: VRC6Audio::new(),
+        }
+    }
+
+    /* the register an address selects within its 4kb block, 0-3 */
+    fn register(&self, address: u16) -> u8 {
+        let register = (address & 3) as u8;
+        if self.swapped_lines {
+            (register & 1) << 1 | register >> 1
+        } else {
+            register
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        let register = self.register(address);
+        match (address & 0xf000, register) {
+            /* a 16kb bank, as two 8kb ones */
+            (0x8000, _) => {
+                let bank_count = self.prg_banks.bank_count();
+                let bank = (value & 0xf) as usize * 2;
+                self.prg_banks.set_bank(0, (bank % bank_count) as u8);
+                self.prg_banks.set_bank(1, ((bank + 1) % bank_count) as u8);
+            }
+            (0xb000, 3) => {
+                self.banking_control = value;
+                /* TODO the settings putting CHR-ROM in the nametables, which no game uses */
+                self.nametable_mirroring = vrc_mirroring(value >> 2);
+                self.update_chr_banks();
+            }
+            (0x9000..=0xb000, _) => {
+                self.audio.write((address >> 12) as u8 - 9, register, value);
+            }
+            (0xc000, _) => {
+                let bank = (value & 0x1f) as usize % self.prg_banks.bank_count();
+                self.prg_banks.set_bank(2, bank as u8);
+            }
+            (0xd000 | 0xe000, _) => {
+                let index = ((address >> 12) as usize - 0xd) * 4 + register as usize;
+                self.chr_registers[index] = value;
+                self.update_chr_banks();
+            }
+            (0xf000, 0) => self.irq.write_latch(value),
+            (0xf000, 1) => self.irq.write_control(value),
+            (0xf000, 2) => self.irq.acknowledge(),
+            _ => {}
+        }
+    }
+
+    /* in mode 0, eight 1kb banks; in mode 1, R0-R3 select 2kb banks; in modes 2 and 3, the
+     * first half has 1kb banks R0-R3 and the second 2kb banks R4 and R5. A 2kb bank is a pair
+     * of 1kb ones, the register's low bit ignored
+     */
+    fn update_chr_banks(&mut self) {
+        let bank_count = self.chr_banks.bank_count();
+        for i in 0..8 {
+            /* which register, and whether it's half of a 2kb bank */
+            let (register, two_kb) = match self.banking_control & CHR_MODE {
+                0 => (i, false),
+                1 => (i / 2, true),
+                _ if i < 4 => (i, false),
+                _ => (2 + i / 2, true),
+            };
+            let mut bank = self.chr_registers[register];
+            if two_kb {
+                bank = (bank & !1) | (i & 1) as u8;
+            }
+            self.chr_banks
+                .set_bank(i as u8, (bank as usize % bank_count) as u8);
+        }
+    }
+
+    fn prg_ram_index(&self, address: u16) -> usize {
+        address as usize - 0x6000
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.banking_control & PRG_RAM_ENABLE != 0
+    }
+}
+
+impl Mapper for VRC6 {
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        } else if address < 0x8000 {
+            match self.prg_ram.get(self.prg_ram_index(address)) {
+                Some(value) if self.prg_ram_enabled() => *value,
+                _ => 0, /* TODO open bus */
+            }
+        } else {
+            self.prg_banks.read(address)
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x6000 {
+            /* nothing mapped here */
+        } else if address < 0x8000 {
+            let index = self.prg_ram_index(address);
+            if self.prg_ram_enabled() {
+                if let Some(byte) = self.prg_ram.get_mut(index) {
+                    *byte = value;
+                }
+            }
+        } else {
+            self.write_register(address, value);
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr_banks.read(address)
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr_banks.write(address, value);
+    }
+
+    fn get_nametable_mirroring(&self) -> NametableMirroring {
+        self.nametable_mirroring.clone()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+        self.audio.cycle();
+    }
+
+    fn expansion_audio(&self) -> f32 {
+        self.audio.level()
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn get_save_data(&self) -> Option<Vec<u8>> {
+        if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(self.prg_ram.clone())
+        }
+    }
+
+    fn set_save_data(&mut self, data: &Vec<u8>) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[0..len].copy_from_slice(&data[0..len]);
+    }
+}
+
+impl Savestate for VRC6 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        self.prg_banks.save_state(writer);
+        self.chr_banks.save_state(writer);
+        writer.write_bytes(self.chr_banks.data());
+        writer.write_bytes(&self.chr_registers);
+        writer.write_u8(self.banking_control);
+        self.nametable_mirroring.save_state(writer);
+        self.irq.save_state(writer);
+        self.audio.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.prg_ram)?;
+        self.prg_banks.load_state(reader)?;
+        self.chr_banks.load_state(reader)?;
+        reader.read_bytes_into(self.chr_banks.data_mut())?;
+        reader.read_bytes_into(&mut self.chr_registers)?;
+        self.banking_control = reader.read_u8()?;
+        self.nametable_mirroring.load_state(reader)?;
+        self.irq.load_state(reader)?;
+        self.audio.load_state(reader)
+    }
+}
+
+/**
+ * A 12-bit divider, clocked every CPU cycle, which VRC6's channels step their waveforms on. The
+ * period can be shortened by 4 or 8 bits for all channels at once through 0x9003.
+ */
+struct Divider {
+    period: u16,
+    counter: u16,
+}
+
+impl Divider {
+    fn new() -> Divider {
+        Divider {
+            period: 0,
+            counter: 0,
+        }
+    }
+
+    fn write_low(&mut self, value: u8) {
+        self.period = (self.period & 0xf00) | value as u16;
+    }
+
+    fn write_high(&mut self, value: u8) {
+        self.period = (self.period & 0xff) | ((value & 0xf) as u16) << 8;
+    }
+
+    /* whether the channel steps this cycle */
+    fn tick(&mut self, shift: u8) -> bool {
+        if self.counter == 0 {
+            self.counter = self.period >> shift;
+            true
+        } else {
+            self.counter -= 1;
+            false
+        }
+    }
+}
+
+impl Savestate for Divider {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.period);
+        writer.write_u16(self.counter);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.period = reader.read_u16()? & 0xfff;
+        self.counter = reader.read_u16()? & 0xfff;
+        Ok(())
+    }
+}
+
+/* output is the volume for the first duty + 1 of 16 steps, or always in digitized mode */
+struct VRC6Pulse {
+    control: u8, /* digitized mode, duty and volume */
+    enabled: bool,
+    divider: Divider,
+    step: u8,
+}
+
+impl VRC6Pulse {
+    fn new() -> VRC6Pulse {
+        VRC6Pulse {
+            control: 0,
+            enabled: false,
+            divider: Divider::new(),
+            step: 0,
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0 => self.control = value,
+            1 => self.divider.write_low(value),
+            _ => {
+                self.divider.write_high(value);
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, shift: u8) {
+        if self.enabled && self.divider.tick(shift) {
+            self.step = (self.step + 1) & 0xf;
+        }
+    }
+
+    fn amplitude(&self) -> u8 {
+        let digitized = self.control & 0x80 != 0;
+        let duty = (self.control >> 4) & 7;
+        if self.enabled && (digitized || self.step <= duty) {
+            self.control & 0xf
+        } else {
+            0
+        }
+    }
+}
+
+impl Savestate for VRC6Pulse {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.control);
+        writer.write_bool(self.enabled);
+        self.divider.save_state(writer);
+        writer.write_u8(self.step);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.control = reader.read_u8()?;
+        self.enabled = reader.read_bool()?;
+        self.divider.load_state(reader)?;
+        self.step = reader.read_u8()? & 0xf;
+        Ok(())
+    }
+}
+
+/* adds the rate to an accumulator every other step, and resets it after 14 steps; its output
+ * is the accumulator's top 5 bits
+ */
+struct Sawtooth {
+    rate: u8,
+    enabled: bool,
+    divider: Divider,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn new() -> Sawtooth {
+        Sawtooth {
+            rate: 0,
+            enabled: false,
+            divider: Divider::new(),
+            step: 0,
+            accumulator: 0,
+        }
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        match register {
+            0 => self.rate = value & 0x3f,
+            1 => self.divider.write_low(value),
+            _ => {
+                self.divider.write_high(value);
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn tick(&mut self, shift: u8) {
+        if !self.enabled || !self.divider.tick(shift) {
+            return;
+        }
+        self.step = (self.step + 1) % 14;
+        if self.step == 0 {
+            self.accumulator = 0;
+        } else if self.step & 1 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    fn amplitude(&self) -> u8 {
+        self.accumulator >> 3
+    }
+}
+
+impl Savestate for Sawtooth {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.rate);
+        writer.write_bool(self.enabled);
+        self.divider.save_state(writer);
+        writer.write_u8(self.step);
+        writer.write_u8(self.accumulator);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.rate = reader.read_u8()? & 0x3f;
+        self.enabled = reader.read_bool()?;
+        self.divider.load_state(reader)?;
+        self.step = reader.read_u8()? % 14;
+        self.accumulator = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+struct VRC6Audio {
+    pulses: [VRC6Pulse; 2],
+    sawtooth: Sawtooth,
+    control: u8, /* 0x9003 */
+}
+
+impl VRC6Audio {
+    fn new() -> VRC6Audio {
+        VRC6Audio {
+            pulses: [VRC6Pulse::new(), VRC6Pulse::new()],
+            sawtooth: Sawtooth::new(),
+            control: 0,
+        }
+    }
+
+    /* channel 0 and 1 are the pulses at 0x9000 and 0xa000, 2 the sawtooth at 0xb000 */
+    fn write(&mut self, channel: u8, register: u8, value: u8) {
+        match (channel, register) {
+            (0, 3) => self.control = value,
+            (_, 3) => {}
+            (2, _) => self.sawtooth.write(register, value),
+            _ => self.pulses[channel as usize].write(register, value),
+        }
+    }
+
+    fn cycle(&mut self) {
+        if self.control & AUDIO_HALT != 0 {
+            return;
+        }
+        let shift = if self.control & AUDIO_SHIFT_8 != 0 {
+            8
+        } else if self.control & AUDIO_SHIFT_4 != 0 {
+            4
+        } else {
+            0
+        };
+        for pulse in &mut self.pulses {
+            pulse.tick(shift);
+        }
+        self.sawtooth.tick(shift);
+    }
+
+    /* the chip mixes linearly, with a pulse at full volume about as loud as one of the APU's */
+    fn level(&self) -> f32 {
+        let sum =
+            self.pulses[0].amplitude() + self.pulses[1].amplitude() + self.sawtooth.amplitude();
+        sum as f32 * mix_pulses(15.0) / 15.0
+    }
+}
+
+impl Savestate for VRC6Audio {
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.pulses[0].save_state(writer);
+        self.pulses[1].save_state(writer);
+        self.sawtooth.save_state(writer);
+        writer.write_u8(self.control);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.pulses[0].load_state(reader)?;
+        self.pulses[1].load_state(reader)?;
+        self.sawtooth.load_state(reader)?;
+        self.control = reader.read_u8()?;
+        Ok(())
+    }
+}
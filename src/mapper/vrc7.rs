@@ -0,0 +1,199 @@
+use crate::mapper::bank_array::BankArray;
+use crate::mapper::vrc::{vrc_mirroring, IRQCounter};
+use crate::mapper::vrc7_audio::VRC7Audio;
+use crate::mapper::{Mapper, SIZE_1_KB, SIZE_8_KB};
+use crate::ppu::NametableMirroring;
+use crate::rom::Rom;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
+/* the synth's two registers, which decode A4 and A5 whichever line selects the others */
+const AUDIO_REGISTER_SELECT: u16 = 0x9010;
+const AUDIO_REGISTER_WRITE: u16 = 0x9030;
+
+/* bits of 0xe000, besides mirroring */
+const AUDIO_SILENCE: u8 = 0x40;
+const PRG_RAM_ENABLE: u8 = 0x80;
+
+/**
+ * Konami's VRC7 (mapper 85; Lagrange Point, Tiny Toon Adventures 2). Three switchable 8kb PRG
+ * banks with the last fixed, eight 1kb CHR banks, the VRC IRQ counter and, on Lagrange Point's
+ * board, a six-channel FM synthesizer. VRC7a boards (submapper 2) select each pair of registers
+ * with A4 and VRC7b (submapper 1) with A3; with no submapper, either does.
+ */
+pub struct VRC7 {
+    register_line: u16,
+    prg_ram: Vec<u8>,
+    prg_banks: BankArray,
+    chr_banks: BankArray,
+    control: u8, /* 0xe000 */
+    nametable_mirroring: NametableMirroring,
+    irq: IRQCounter,
+    audio: VRC7Audio,
+}
+
+impl VRC7 {
+    pub fn new(rom: &Rom) -> VRC7 {
+        let mut prg_banks = BankArray::new(SIZE_8_KB, 0x8000, rom.prg_data.clone());
+        for i in 0..3 {
+            prg_banks.set_bank(i, 0);
+        }
+        prg_banks.set_last_bank(3);
+        let mut chr_banks = BankArray::new(SIZE_1_KB, 0, rom.chr_rom_or_ram());
+        for i in 0..8 {
+            chr_banks.set_bank(i, 0);
+        }
+
+        VRC7 {
+            register_line: match rom.header.submapper {
+                1 => 1 << 3,
+                2 => 1 << 4,
+                _ => 1 << 3 | 1 << 4,
+            },
+            prg_ram: vec![0; rom.header.work_ram_size()],
+            prg_banks,
+            chr_banks,
+            control: 0,
+            nametable_mirroring: rom.nametable_mirroring(),
+            irq: IRQCounter::new(),
+            audio: VRC7Audio::new(),
+        }
+    }
+
+    fn write_register(&mut self, address: u16, value: u8) {
+        match address & 0xf030 {
+            AUDIO_REGISTER_SELECT => return self.audio.select_register(value),
+            AUDIO_REGISTER_WRITE => return self.audio.write(value),
+            _ => {}
+        }
+
+        let second = address & self.register_line != 0;
+        match (address & 0xf000, second) {
+            (0x8000, _) | (0x9000, false) => {
+                let slot = ((address >> 12) as u8 - 8) * 2 + second as u8;
+                let bank = (value & 0x3f) as usize % self.prg_banks.bank_count();
+                self.prg_banks.set_bank(slot, bank as u8);
+            }
+            (0x9000, true) => {}
+            (0xa000..=0xd000, _) => {
+                let slot = ((address >> 12) as u8 - 0xa) * 2 + second as u8;
+                let bank = value as usize % self.chr_banks.bank_count();
+                self.chr_banks.set_bank(slot, bank as u8);
+            }
+            (0xe000, false) => {
+                self.control = value;
+                self.nametable_mirroring = vrc_mirroring(value);
+                if value & AUDIO_SILENCE != 0 {
+                    self.audio.reset();
+                }
+            }
+            (0xe000, true) => self.irq.write_latch(value),
+            (0xf000, false) => self.irq.write_control(value),
+            (0xf000, true) => self.irq.acknowledge(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn prg_ram_index(&self, address: u16) -> usize {
+        address as usize - 0x6000
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        self.control & PRG_RAM_ENABLE != 0
+    }
+}
+
+impl Mapper for VRC7 {
+    fn read_prg(&self, address: u16) -> u8 {
+        if address < 0x6000 {
+            0
+        } else if address < 0x8000 {
+            match self.prg_ram.get(self.prg_ram_index(address)) {
+                Some(value) if self.prg_ram_enabled() => *value,
+                _ => 0, /* TODO open bus */
+            }
+        } else {
+            self.prg_banks.read(address)
+        }
+    }
+
+    fn write_prg(&mut self, address: u16, value: u8) {
+        if address < 0x6000 {
+            /* nothing mapped here */
+        } else if address < 0x8000 {
+            let index = self.prg_ram_index(address);
+            if self.prg_ram_enabled() {
+                if let Some(byte) = self.prg_ram.get_mut(index) {
+                    *byte = value;
+                }
+            }
+        } else {
+            self.write_register(address, value);
+        }
+    }
+
+    fn read_chr(&self, address: u16) -> u8 {
+        self.chr_banks.read(address)
+    }
+
+    fn write_chr(&mut self, address: u16, value: u8) {
+        self.chr_banks.write(address, value);
+    }
+
+    fn get_nametable_mirroring(&self) -> NametableMirroring {
+        self.nametable_mirroring.clone()
+    }
+
+    fn cpu_cycle(&mut self) {
+        self.irq.cpu_cycle();
+        /* held in reset while silenced */
+        if self.control & AUDIO_SILENCE == 0 {
+            self.audio.cycle();
+        }
+    }
+
+    fn expansion_audio(&self) -> f32 {
+        self.audio.level()
+    }
+
+    fn irq_pending(&self) -> bool {
+        self.irq.pending()
+    }
+
+    fn get_save_data(&self) -> Option<Vec<u8>> {
+        if self.prg_ram.is_empty() {
+            None
+        } else {
+            Some(self.prg_ram.clone())
+        }
+    }
+
+    fn set_save_data(&mut self, data: &Vec<u8>) {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram[0..len].copy_from_slice(&data[0..len]);
+    }
+}
+
+impl Savestate for VRC7 {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.prg_ram);
+        self.prg_banks.save_state(writer);
+        self.chr_banks.save_state(writer);
+        writer.write_bytes(self.chr_banks.data());
+        writer.write_u8(self.control);
+        self.nametable_mirroring.save_state(writer);
+        self.irq.save_state(writer);
+        self.audio.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.prg_ram)?;
+        self.prg_banks.load_state(reader)?;
+        self.chr_banks.load_state(reader)?;
+        reader.read_bytes_into(self.chr_banks.data_mut())?;
+        self.control = reader.read_u8()?;
+        self.nametable_mirroring.load_state(reader)?;
+        self.irq.load_state(reader)?;
+        self.audio.load_state(reader)
+    }
+}
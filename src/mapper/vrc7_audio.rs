@@ -0,0 +1,467 @@
+use crate::apu::mix_pulses;
+use crate::savestate::{invalid_data, Savestate, StateReader, StateWriter};
+use std::f32::consts::{LN_10, TAU};
+use std::io;
+
+const CHANNELS: usize = 6;
+
+/* the synth produces a sample every 36 CPU cycles, about 49.7kHz */
+const SAMPLE_PERIOD: u8 = 36;
+const SAMPLE_RATE: f32 = 1_789_773.0 / SAMPLE_PERIOD as f32;
+
+/* phase is kept in 19 bits, so an F-number shifted up by its block is the per-sample increment
+ * for a multiplier of 1
+ */
+const PHASE_BITS: u32 = 19;
+
+/* envelopes span 48dB, below which an operator is silent */
+const SILENT_DB: f32 = 48.0;
+
+/* the built-in instruments, from the die-shot dump on the nesdev wiki; 0 is the custom one */
+const PATCHES: [[u8; 8]; 16] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00],
+    [0x03, 0x21, 0x05, 0x06, 0xe8, 0x81, 0x42, 0x27], /* buzzy bell */
+    [0x13, 0x41, 0x14, 0x0d, 0xd8, 0xf6, 0x23, 0x12], /* guitar */
+    [0x11, 0x11, 0x08, 0x08, 0xfa, 0xb2, 0x20, 0x12], /* wurly */
+    [0x31, 0x61, 0x0c, 0x07, 0xa8, 0x64, 0x61, 0x27], /* flute */
+    [0x32, 0x21, 0x1e, 0x06, 0xe1, 0x76, 0x01, 0x28], /* clarinet */
+    [0x02, 0x01, 0x06, 0x00, 0xa3, 0xe2, 0xf4, 0xf4], /* synth */
+    [0x21, 0x61, 0x1d, 0x07, 0x82, 0x81, 0x11, 0x07], /* trumpet */
+    [0x23, 0x21, 0x22, 0x17, 0xa2, 0x72, 0x01, 0x17], /* organ */
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01], /* bells */
+    [0xb5, 0x01, 0x0f, 0x0f, 0xa8, 0xa5, 0x51, 0x02], /* vibes */
+    [0x17, 0xc1, 0x24, 0x07, 0xf8, 0xf8, 0x22, 0x12], /* vibraphone */
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16], /* tutti */
+    [0x01, 0x02, 0xd3, 0x05, 0xc9, 0x95, 0x03, 0x02], /* fretless */
+    [0x61, 0x63, 0x0c, 0x00, 0x94, 0xc0, 0x33, 0xf6], /* synth bass */
+    [0x21, 0x72, 0x0d, 0x00, 0xc1, 0xd5, 0x56, 0x06], /* sweep */
+];
+
+/* frequency multipliers, doubled so the first, 1/2, is whole */
+const MULTIPLIERS_X2: [u32; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+
+/* attenuation for key scaling at 6dB/octave, by the top 4 bits of the F-number in block 7 */
+const KEY_SCALE_DB: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25, 36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25,
+    42.0,
+];
+
+/* the envelope rates used on key-off in place of the patch's release rate */
+const SUSTAIN_RELEASE_RATE: u8 = 5;
+const PERCUSSIVE_RELEASE_RATE: u8 = 7;
+
+/* tremolo and vibrato */
+const AM_HZ: f32 = 3.7;
+const AM_DEPTH_DB: f32 = 4.8;
+const PM_HZ: f32 = 6.4;
+const PM_DEPTH: f32 = 0.004; /* about 7 cents each way */
+
+/* one operator's half of a patch */
+struct OperatorPatch {
+    am: bool,
+    vibrato: bool,
+    sustained: bool, /* hold at the sustain level while keyed, rather than decaying away */
+    key_scale_rate: bool,
+    multiplier: usize,
+    key_scale_level: u8,
+    rectified: bool, /* the negative half of the sine wave is flattened */
+    attack: u8,
+    decay: u8,
+    sustain_level: u8,
+    release: u8,
+}
+
+impl OperatorPatch {
+    fn new(patch: &[u8; 8], carrier: bool) -> OperatorPatch {
+        let i = carrier as usize;
+        OperatorPatch {
+            am: patch[i] & 0x80 != 0,
+            vibrato: patch[i] & 0x40 != 0,
+            sustained: patch[i] & 0x20 != 0,
+            key_scale_rate: patch[i] & 0x10 != 0,
+            multiplier: (patch[i] & 0xf) as usize,
+            key_scale_level: patch[2 + i] >> 6,
+            rectified: patch[3] & (0x08 << i) != 0,
+            attack: patch[4 + i] >> 4,
+            decay: patch[4 + i] & 0xf,
+            sustain_level: patch[6 + i] >> 4,
+            release: patch[6 + i] & 0xf,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum EnvelopeStage {
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/* a sine oscillator with an envelope; each channel has two, the modulator feeding the phase of
+ * the carrier
+ */
+struct Operator {
+    phase: u32,
+    stage: EnvelopeStage,
+    envelope_db: f32,
+    outputs: [f32; 2], /* the last two outputs, for the modulator's feedback */
+}
+
+impl Operator {
+    fn new() -> Operator {
+        Operator {
+            phase: 0,
+            stage: EnvelopeStage::Release,
+            envelope_db: SILENT_DB,
+            outputs: [0.0; 2],
+        }
+    }
+
+    fn key_on(&mut self) {
+        self.phase = 0;
+        self.stage = EnvelopeStage::Attack;
+    }
+
+    fn key_off(&mut self) {
+        self.stage = EnvelopeStage::Release;
+    }
+
+    /* rate_offset is the key scaling of envelope rates, from 0 to 15 */
+    fn update_envelope(&mut self, patch: &OperatorPatch, rate_offset: u8, release: u8) {
+        match self.stage {
+            EnvelopeStage::Attack => {
+                self.envelope_db -= self.envelope_db * attack_step(patch.attack, rate_offset);
+                if self.envelope_db < 0.1 {
+                    self.envelope_db = 0.0;
+                    self.stage = EnvelopeStage::Decay;
+                }
+            }
+            EnvelopeStage::Decay => {
+                let sustain_db = patch.sustain_level as f32 * 3.0;
+                self.envelope_db += decay_step(patch.decay, rate_offset);
+                if self.envelope_db >= sustain_db {
+                    self.envelope_db = sustain_db;
+                    self.stage = EnvelopeStage::Sustain;
+                }
+            }
+            EnvelopeStage::Sustain if patch.sustained => {}
+            EnvelopeStage::Sustain => {
+                self.envelope_db += decay_step(patch.release, rate_offset);
+            }
+            EnvelopeStage::Release => {
+                self.envelope_db += decay_step(release, rate_offset);
+            }
+        }
+        self.envelope_db = self.envelope_db.min(SILENT_DB);
+    }
+
+    /* advances the phase and returns the output, -1 to 1, with the phase offset by
+     * modulation (in cycles) and the envelope attenuated by a further attenuation_db
+     */
+    fn output(
+        &mut self,
+        increment: u32,
+        modulation: f32,
+        attenuation_db: f32,
+        rectified: bool,
+    ) -> f32 {
+        self.phase = (self.phase + increment) & ((1 << PHASE_BITS) - 1);
+        let db = self.envelope_db + attenuation_db;
+        let output = if db >= SILENT_DB {
+            0.0
+        } else {
+            let cycles = self.phase as f32 / (1 << PHASE_BITS) as f32 + modulation;
+            let wave = (cycles * TAU).sin();
+            let wave = if rectified { wave.max(0.0) } else { wave };
+            wave * (-db / 20.0 * LN_10).exp()
+        };
+        self.outputs = [self.outputs[1], output];
+        output
+    }
+}
+
+impl Savestate for Operator {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u32(self.phase);
+        writer.write_u8(self.stage as u8);
+        writer.write_u32(self.envelope_db.to_bits());
+        writer.write_u32(self.outputs[0].to_bits());
+        writer.write_u32(self.outputs[1].to_bits());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.phase = reader.read_u32()? & ((1 << PHASE_BITS) - 1);
+        self.stage = match reader.read_u8()? {
+            0 => EnvelopeStage::Attack,
+            1 => EnvelopeStage::Decay,
+            2 => EnvelopeStage::Sustain,
+            3 => EnvelopeStage::Release,
+            value => return Err(invalid_data(&format!("Invalid envelope stage {}", value))),
+        };
+        self.envelope_db = f32::from_bits(reader.read_u32()?).clamp(0.0, SILENT_DB);
+        self.outputs[0] = f32::from_bits(reader.read_u32()?);
+        self.outputs[1] = f32::from_bits(reader.read_u32()?);
+        Ok(())
+    }
+}
+
+/* the effective rate, 0-63, from a 4-bit rate and the key scaling; rate 0 never moves */
+fn effective_rate(rate: u8, rate_offset: u8) -> Option<i32> {
+    if rate == 0 {
+        None
+    } else {
+        Some((rate as i32 * 4 + rate_offset as i32).min(63))
+    }
+}
+
+/* the fraction of its remaining attenuation an attack removes each sample; a full attack
+ * takes 2.8s at the slowest rate, halving with every 4 steps of rate, and is instant at 60 up
+ */
+fn attack_step(rate: u8, rate_offset: u8) -> f32 {
+    match effective_rate(rate, rate_offset) {
+        None => 0.0,
+        Some(rate) if rate >= 60 => 1.0,
+        Some(rate) => {
+            let seconds = 2.826 * (-(rate - 4) as f32 / 4.0).exp2();
+            (480.0f32.ln() / (seconds * SAMPLE_RATE)).min(1.0)
+        }
+    }
+}
+
+/* dB per sample of a decay or release; 96dB takes 39s at the slowest rate, halving with
+ * every 4 steps of rate down to 2.4ms
+ */
+fn decay_step(rate: u8, rate_offset: u8) -> f32 {
+    match effective_rate(rate, rate_offset) {
+        None => 0.0,
+        Some(rate) => {
+            let seconds = 39.28 * (-(rate.min(60) - 4) as f32 / 4.0).exp2();
+            96.0 / (seconds * SAMPLE_RATE)
+        }
+    }
+}
+
+/**
+ * VRC7's FM synthesizer, a cut-down Yamaha YM2413 (OPLL): six channels, each a modulator
+ * operator feeding the phase of a carrier, playing one of 15 built-in instruments or a single
+ * custom one. This models the synthesis in floating point rather than reproducing the chip's
+ * log-sine and exponent tables bit for bit.
+ */
+pub struct VRC7Audio {
+    register_select: u8,
+    custom_patch: [u8; 8],
+    frequencies: [u16; CHANNELS], /* 9-bit F-numbers */
+    blocks: [u8; CHANNELS],
+    keyed: [bool; CHANNELS],
+    sustain: [bool; CHANNELS],
+    instruments: [u8; CHANNELS],
+    volumes: [u8; CHANNELS], /* attenuation in 3dB steps */
+    operators: [[Operator; 2]; CHANNELS],
+    sample_clock: u8,
+    am_phase: f32, /* the tremolo and vibrato oscillators, in cycles */
+    pm_phase: f32,
+    output: f32,
+}
+
+impl VRC7Audio {
+    pub fn new() -> VRC7Audio {
+        VRC7Audio {
+            register_select: 0,
+            custom_patch: [0; 8],
+            frequencies: [0; CHANNELS],
+            blocks: [0; CHANNELS],
+            keyed: [false; CHANNELS],
+            sustain: [false; CHANNELS],
+            instruments: [0; CHANNELS],
+            volumes: [0; CHANNELS],
+            operators: [(); CHANNELS].map(|_| [Operator::new(), Operator::new()]),
+            sample_clock: 0,
+            am_phase: 0.0,
+            pm_phase: 0.0,
+            output: 0.0,
+        }
+    }
+
+    /* silences every channel and clears all registers */
+    pub fn reset(&mut self) {
+        *self = VRC7Audio::new();
+    }
+
+    pub fn select_register(&mut self, value: u8) {
+        self.register_select = value;
+    }
+
+    pub fn write(&mut self, value: u8) {
+        let register = self.register_select;
+        let channel = (register & 0xf) as usize;
+        match register {
+            0x00..=0x07 => self.custom_patch[register as usize] = value,
+            0x10..=0x15 => {
+                self.frequencies[channel] = (self.frequencies[channel] & 0x100) | value as u16;
+            }
+            0x20..=0x25 => {
+                self.frequencies[channel] =
+                    (self.frequencies[channel] & 0xff) | ((value & 1) as u16) << 8;
+                self.blocks[channel] = (value >> 1) & 7;
+                self.sustain[channel] = value & 0x20 != 0;
+                let keyed = value & 0x10 != 0;
+                if keyed != self.keyed[channel] {
+                    for operator in &mut self.operators[channel] {
+                        if keyed {
+                            operator.key_on();
+                        } else {
+                            operator.key_off();
+                        }
+                    }
+                }
+                self.keyed[channel] = keyed;
+            }
+            0x30..=0x35 => {
+                self.instruments[channel] = value >> 4;
+                self.volumes[channel] = value & 0xf;
+            }
+            _ => {}
+        }
+    }
+
+    pub fn cycle(&mut self) {
+        self.sample_clock += 1;
+        if self.sample_clock == SAMPLE_PERIOD {
+            self.sample_clock = 0;
+            self.output = self.sample();
+        }
+    }
+
+    /* the six channels mixed, with one at full volume about as loud as an APU pulse */
+    pub fn level(&self) -> f32 {
+        self.output * mix_pulses(15.0) / 2.0
+    }
+
+    fn sample(&mut self) -> f32 {
+        self.am_phase = (self.am_phase + AM_HZ / SAMPLE_RATE).fract();
+        self.pm_phase = (self.pm_phase + PM_HZ / SAMPLE_RATE).fract();
+        let am_db = AM_DEPTH_DB * (1.0 - (self.am_phase * TAU).cos()) / 2.0;
+        let pm = 1.0 + PM_DEPTH * (self.pm_phase * TAU).sin();
+        (0..CHANNELS)
+            .map(|channel| self.channel_sample(channel, am_db, pm))
+            .sum()
+    }
+
+    fn channel_sample(&mut self, channel: usize, am_db: f32, pm: f32) -> f32 {
+        let patch = match self.instruments[channel] {
+            0 => self.custom_patch,
+            instrument => PATCHES[instrument as usize],
+        };
+        let frequency = self.frequencies[channel];
+        let block = self.blocks[channel];
+
+        /* higher notes have faster envelopes, and optionally quieter operators */
+        let key_code = block << 1 | (frequency >> 8) as u8;
+        let key_scale_db =
+            (KEY_SCALE_DB[(frequency >> 5) as usize] - 6.0 * (7 - block) as f32).max(0.0);
+        let release = |patch: &OperatorPatch| {
+            if self.sustain[channel] {
+                SUSTAIN_RELEASE_RATE
+            } else if patch.sustained {
+                patch.release
+            } else {
+                PERCUSSIVE_RELEASE_RATE
+            }
+        };
+
+        let modulator = OperatorPatch::new(&patch, false);
+        let carrier = OperatorPatch::new(&patch, true);
+        let releases = [release(&modulator), release(&carrier)];
+        let mut attenuations = [
+            (patch[2] & 0x3f) as f32 * 0.75,
+            self.volumes[channel] as f32 * 3.0,
+        ];
+        let mut increments = [0; 2];
+        for (i, operator_patch) in [&modulator, &carrier].into_iter().enumerate() {
+            let rate_offset = if operator_patch.key_scale_rate {
+                key_code
+            } else {
+                key_code >> 2
+            };
+            self.operators[channel][i].update_envelope(operator_patch, rate_offset, releases[i]);
+
+            if operator_patch.key_scale_level != 0 {
+                attenuations[i] +=
+                    key_scale_db / (1 << (3 - operator_patch.key_scale_level)) as f32;
+            }
+            if operator_patch.am {
+                attenuations[i] += am_db;
+            }
+            let increment =
+                ((frequency as u32) << block) * MULTIPLIERS_X2[operator_patch.multiplier] / 2;
+            increments[i] = if operator_patch.vibrato {
+                (increment as f32 * pm) as u32
+            } else {
+                increment
+            };
+        }
+
+        let [modulator_operator, carrier_operator] = &mut self.operators[channel];
+        let feedback = patch[3] & 7;
+        let feedback = if feedback == 0 {
+            0.0
+        } else {
+            (modulator_operator.outputs[0] + modulator_operator.outputs[1])
+                * ((feedback as i32 - 7) as f32).exp2()
+        };
+        let modulation = modulator_operator.output(
+            increments[0],
+            feedback,
+            attenuations[0],
+            modulator.rectified,
+        );
+        carrier_operator.output(
+            increments[1],
+            modulation * 2.0,
+            attenuations[1],
+            carrier.rectified,
+        )
+    }
+}
+
+impl Savestate for VRC7Audio {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.register_select);
+        writer.write_bytes(&self.custom_patch);
+        for channel in 0..CHANNELS {
+            writer.write_u16(self.frequencies[channel]);
+            writer.write_u8(self.blocks[channel]);
+            writer.write_bool(self.keyed[channel]);
+            writer.write_bool(self.sustain[channel]);
+            writer.write_u8(self.instruments[channel]);
+            writer.write_u8(self.volumes[channel]);
+            self.operators[channel][0].save_state(writer);
+            self.operators[channel][1].save_state(writer);
+        }
+        writer.write_u8(self.sample_clock);
+        writer.write_u32(self.am_phase.to_bits());
+        writer.write_u32(self.pm_phase.to_bits());
+        writer.write_u32(self.output.to_bits());
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.register_select = reader.read_u8()?;
+        reader.read_bytes_into(&mut self.custom_patch)?;
+        for channel in 0..CHANNELS {
+            self.frequencies[channel] = reader.read_u16()? & 0x1ff;
+            self.blocks[channel] = reader.read_u8()? & 7;
+            self.keyed[channel] = reader.read_bool()?;
+            self.sustain[channel] = reader.read_bool()?;
+            self.instruments[channel] = reader.read_u8()? & 0xf;
+            self.volumes[channel] = reader.read_u8()? & 0xf;
+            self.operators[channel][0].load_state(reader)?;
+            self.operators[channel][1].load_state(reader)?;
+        }
+        self.sample_clock = reader.read_u8()? % SAMPLE_PERIOD;
+        self.am_phase = f32::from_bits(reader.read_u32()?).fract();
+        self.pm_phase = f32::from_bits(reader.read_u32()?).fract();
+        self.output = f32::from_bits(reader.read_u32()?);
+        Ok(())
+    }
+}
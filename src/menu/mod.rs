@@ -6,33 +6,129 @@
 //! window, which lives in `window.rs` (`init_for_gtk_window` on Linux,
 //! `init_for_hwnd` on Windows, `init_for_nsapp` on macOS).
 
-use muda::accelerator::{Accelerator, Code, CMD_OR_CTRL};
-use muda::{Menu, MenuId, MenuItem, Submenu};
+use crate::config::Hotkey;
+use muda::accelerator::{Accelerator, Code, Modifiers, CMD_OR_CTRL};
+use muda::{Menu, MenuId, MenuItem, PredefinedMenuItem, Submenu};
+use patina::Speed;
 use tao::keyboard::Key;
 
 #[cfg(test)]
 mod tests;
 
 pub(crate) const MENU_ID_LOAD_ROM: &str = "load_rom";
+pub(crate) const MENU_ID_CONTROLS: &str = "controls";
+pub(crate) const MENU_ID_RECORD_AUDIO: &str = "record_audio";
+pub(crate) const MENU_ID_STOP_AUDIO: &str = "stop_audio";
 pub(crate) const MENU_ID_EXIT: &str = "exit";
+pub(crate) const MENU_ID_PAUSE: &str = "pause";
+pub(crate) const MENU_ID_FRAME_ADVANCE: &str = "frame_advance";
+pub(crate) const MENU_ID_RESET: &str = "reset";
+pub(crate) const MENU_ID_POWER_CYCLE: &str = "power_cycle";
+pub(crate) const MENU_ID_RECORD_MOVIE: &str = "record_movie";
+pub(crate) const MENU_ID_PLAY_MOVIE: &str = "play_movie";
+pub(crate) const MENU_ID_STOP_MOVIE: &str = "stop_movie";
+pub(crate) const MENU_ID_MOVIE_READ_ONLY: &str = "movie_read_only";
+/// Prefix for the speed items; the percentage, or `unthrottled`, is appended.
+pub(crate) const MENU_ID_SPEED_PREFIX: &str = "speed_";
+const UNTHROTTLED_ID_SUFFIX: &str = "unthrottled";
+/// Prefixes for the per-slot save state items; the slot number is appended.
+pub(crate) const MENU_ID_SAVE_STATE_PREFIX: &str = "save_state_";
+pub(crate) const MENU_ID_LOAD_STATE_PREFIX: &str = "load_state_";
+
+/// Number of save state slots, numbered from 1. Each slot has a function key.
+pub(crate) const SAVE_STATE_SLOTS: u8 = 4;
+const SLOT_KEYS: [(Key<'static>, Code); SAVE_STATE_SLOTS as usize] = [
+    (Key::F1, Code::F1),
+    (Key::F2, Code::F2),
+    (Key::F3, Code::F3),
+    (Key::F4, Code::F4),
+];
 
 /// A user-triggerable application action, however it was triggered (menu item,
 /// keyboard shortcut, window close, or signal).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum MenuAction {
     LoadRom,
+    /// Rebind the controls, one key press per button.
+    Controls,
+    /// Record the sound to a WAV file until told to stop.
+    RecordAudio,
+    StopAudioRecording,
+    /// Stop recording the sound if it's being recorded, or start.
+    ToggleAudioRecording,
     Exit,
+    /// Pause a running game, or resume a paused one.
+    TogglePause,
+    /// Run one frame, then pause.
+    FrameAdvance,
+    /// Press the console's reset button.
+    Reset,
+    /// Switch the console off and on again.
+    PowerCycle,
+    /// Run at the given speed until told otherwise.
+    SetSpeed(Speed),
+    /// Record an input movie from power-on, or play one back.
+    RecordMovie,
+    PlayMovie,
+    /// Stop the movie, saving it if anything was recorded.
+    StopMovie,
+    /// Switch between playing a movie back untouched and recording over it.
+    ToggleMovieReadOnly,
+    /// Save or load the whole machine state in the given slot (1-based).
+    SaveState(u8),
+    LoadState(u8),
 }
 
 /// Maps a triggered `muda` menu item id to its action. Pure.
 pub(crate) fn action_for_menu_id(id: &MenuId) -> Option<MenuAction> {
     match id.0.as_str() {
         MENU_ID_LOAD_ROM => Some(MenuAction::LoadRom),
+        MENU_ID_CONTROLS => Some(MenuAction::Controls),
+        MENU_ID_RECORD_AUDIO => Some(MenuAction::RecordAudio),
+        MENU_ID_STOP_AUDIO => Some(MenuAction::StopAudioRecording),
         MENU_ID_EXIT => Some(MenuAction::Exit),
-        _ => None,
+        MENU_ID_PAUSE => Some(MenuAction::TogglePause),
+        MENU_ID_FRAME_ADVANCE => Some(MenuAction::FrameAdvance),
+        MENU_ID_RESET => Some(MenuAction::Reset),
+        MENU_ID_POWER_CYCLE => Some(MenuAction::PowerCycle),
+        MENU_ID_RECORD_MOVIE => Some(MenuAction::RecordMovie),
+        MENU_ID_PLAY_MOVIE => Some(MenuAction::PlayMovie),
+        MENU_ID_STOP_MOVIE => Some(MenuAction::StopMovie),
+        MENU_ID_MOVIE_READ_ONLY => Some(MenuAction::ToggleMovieReadOnly),
+        other => {
+            if let Some(speed) = parse_speed(other) {
+                Some(MenuAction::SetSpeed(speed))
+            } else if let Some(slot) = parse_slot(other, MENU_ID_SAVE_STATE_PREFIX) {
+                Some(MenuAction::SaveState(slot))
+            } else {
+                parse_slot(other, MENU_ID_LOAD_STATE_PREFIX).map(MenuAction::LoadState)
+            }
+        }
     }
 }
 
+/// The menu item id for a speed, e.g. `speed_200`.
+pub(crate) fn speed_menu_id(speed: Speed) -> String {
+    match speed {
+        Speed::Percent(percent) => format!("{MENU_ID_SPEED_PREFIX}{percent}"),
+        Speed::Unthrottled => format!("{MENU_ID_SPEED_PREFIX}{UNTHROTTLED_ID_SUFFIX}"),
+    }
+}
+
+fn parse_speed(id: &str) -> Option<Speed> {
+    let suffix = id.strip_prefix(MENU_ID_SPEED_PREFIX)?;
+    if suffix == UNTHROTTLED_ID_SUFFIX {
+        return Some(Speed::Unthrottled);
+    }
+    let speed = Speed::Percent(suffix.parse().ok()?);
+    Speed::PRESETS.contains(&speed).then_some(speed)
+}
+
+fn parse_slot(id: &str, prefix: &str) -> Option<u8> {
+    let slot = id.strip_prefix(prefix)?.parse().ok()?;
+    (1..=SAVE_STATE_SLOTS).contains(&slot).then_some(slot)
+}
+
 /// Maps a `Ctrl`+`<key>` keyboard shortcut to its action. Pure.
 ///
 /// `ctrl` is whether the control modifier is held; `key` is the logical key of
@@ -48,8 +144,41 @@ pub(crate) fn action_for_shortcut(ctrl: bool, key: &Key) -> Option<MenuAction> {
     }
 }
 
-/// Builds the application's menu bar: a single `File` menu containing
-/// `Load ROM...` (Ctrl/Cmd+O) and `Exit` (Ctrl/Cmd+Q).
+/// Maps a function key to a save state action: `F<n>` loads slot `n` and
+/// `Shift`+`F<n>` saves to it. Pure.
+pub(crate) fn action_for_slot_key(shift: bool, key: &Key) -> Option<MenuAction> {
+    let index = SLOT_KEYS.iter().position(|(slot_key, _)| slot_key == key)?;
+    let slot = index as u8 + 1;
+    if shift {
+        Some(MenuAction::SaveState(slot))
+    } else {
+        Some(MenuAction::LoadState(slot))
+    }
+}
+
+/// Maps a configurable hotkey to its action, for the hotkeys that have one.
+/// Screenshots are handled by the key event handler, and fast-forward and
+/// rewind by the window, since they last for as long as the key is held. Pure.
+pub(crate) fn action_for_hotkey(hotkey: Hotkey) -> Option<MenuAction> {
+    match hotkey {
+        Hotkey::Screenshot => None,
+        Hotkey::Pause => Some(MenuAction::TogglePause),
+        Hotkey::FrameAdvance => Some(MenuAction::FrameAdvance),
+        Hotkey::Reset => Some(MenuAction::Reset),
+        Hotkey::PowerCycle => Some(MenuAction::PowerCycle),
+        Hotkey::FastForward | Hotkey::Rewind => None,
+        Hotkey::RecordAudio => Some(MenuAction::ToggleAudioRecording),
+    }
+}
+
+/// Builds the application's menu bar: a `File` menu containing `Load ROM...`
+/// (Ctrl/Cmd+O), `Controls...`, items to start and stop recording audio, and
+/// `Exit` (Ctrl/Cmd+Q), an `Emulation` menu
+/// to pause, frame advance, reset, power cycle and pick a speed from a `Speed`
+/// submenu, a `Movie` menu to record and play back input movies, and a `State`
+/// menu with an item to save (Shift+F1-F4) and load (F1-F4) each save state
+/// slot. The `Emulation` items have no accelerators, since their hotkeys are
+/// configurable.
 ///
 /// Not unit-tested: it constructs native menu objects (GTK/Win32/AppKit) that
 /// require a platform UI context.
@@ -61,13 +190,84 @@ pub(crate) fn build_menu() -> Result<Menu, muda::Error> {
         true,
         Some(Accelerator::new(Some(CMD_OR_CTRL), Code::KeyO)),
     );
+    let controls = MenuItem::with_id(MENU_ID_CONTROLS, "Controls...", true, None);
     let exit = MenuItem::with_id(
         MENU_ID_EXIT,
         "Exit",
         true,
         Some(Accelerator::new(Some(CMD_OR_CTRL), Code::KeyQ)),
     );
-    let file_menu = Submenu::with_items("File", true, &[&load_rom, &exit])?;
+    let file_menu = Submenu::with_items(
+        "File",
+        true,
+        &[
+            &load_rom,
+            &controls,
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id(MENU_ID_RECORD_AUDIO, "Record Audio...", true, None),
+            &MenuItem::with_id(MENU_ID_STOP_AUDIO, "Stop Recording Audio", true, None),
+            &PredefinedMenuItem::separator(),
+            &exit,
+        ],
+    )?;
     menu.append(&file_menu)?;
+
+    let speed_menu = Submenu::new("Speed", true);
+    for speed in Speed::PRESETS {
+        speed_menu.append(&MenuItem::with_id(
+            speed_menu_id(speed),
+            speed.label(),
+            true,
+            None,
+        ))?;
+    }
+    let emulation_menu = Submenu::with_items(
+        "Emulation",
+        true,
+        &[
+            &MenuItem::with_id(MENU_ID_PAUSE, "Pause/Resume", true, None),
+            &MenuItem::with_id(MENU_ID_FRAME_ADVANCE, "Frame Advance", true, None),
+            &speed_menu,
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id(MENU_ID_RESET, "Reset", true, None),
+            &MenuItem::with_id(MENU_ID_POWER_CYCLE, "Power Cycle", true, None),
+        ],
+    )?;
+    menu.append(&emulation_menu)?;
+
+    let movie_menu = Submenu::with_items(
+        "Movie",
+        true,
+        &[
+            &MenuItem::with_id(MENU_ID_RECORD_MOVIE, "Record Movie...", true, None),
+            &MenuItem::with_id(MENU_ID_PLAY_MOVIE, "Play Movie...", true, None),
+            &MenuItem::with_id(MENU_ID_STOP_MOVIE, "Stop Movie", true, None),
+            &PredefinedMenuItem::separator(),
+            &MenuItem::with_id(MENU_ID_MOVIE_READ_ONLY, "Toggle Read-Only", true, None),
+        ],
+    )?;
+    menu.append(&movie_menu)?;
+
+    let state_menu = Submenu::new("State", true);
+    for (index, (_, code)) in SLOT_KEYS.iter().enumerate() {
+        let slot = index + 1;
+        state_menu.append(&MenuItem::with_id(
+            format!("{MENU_ID_SAVE_STATE_PREFIX}{slot}"),
+            format!("Save State {slot}"),
+            true,
+            Some(Accelerator::new(Some(Modifiers::SHIFT), *code)),
+        ))?;
+    }
+    state_menu.append(&PredefinedMenuItem::separator())?;
+    for (index, (_, code)) in SLOT_KEYS.iter().enumerate() {
+        let slot = index + 1;
+        state_menu.append(&MenuItem::with_id(
+            format!("{MENU_ID_LOAD_STATE_PREFIX}{slot}"),
+            format!("Load State {slot}"),
+            true,
+            Some(Accelerator::new(None, *code)),
+        ))?;
+    }
+    menu.append(&state_menu)?;
     Ok(menu)
 }
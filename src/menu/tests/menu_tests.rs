@@ -1,7 +1,13 @@
+use crate::config::Hotkey;
 use crate::menu::{
-    action_for_menu_id, action_for_shortcut, MenuAction, MENU_ID_EXIT, MENU_ID_LOAD_ROM,
+    action_for_hotkey, action_for_menu_id, action_for_shortcut, action_for_slot_key, speed_menu_id,
+    MenuAction, MENU_ID_CONTROLS, MENU_ID_EXIT, MENU_ID_FRAME_ADVANCE, MENU_ID_LOAD_ROM,
+    MENU_ID_LOAD_STATE_PREFIX, MENU_ID_MOVIE_READ_ONLY, MENU_ID_PAUSE, MENU_ID_PLAY_MOVIE,
+    MENU_ID_POWER_CYCLE, MENU_ID_RECORD_AUDIO, MENU_ID_RECORD_MOVIE, MENU_ID_RESET,
+    MENU_ID_SAVE_STATE_PREFIX, MENU_ID_STOP_AUDIO, MENU_ID_STOP_MOVIE, SAVE_STATE_SLOTS,
 };
 use muda::MenuId;
+use patina::Speed;
 use tao::keyboard::Key;
 
 #[test]
@@ -16,6 +22,88 @@ fn menu_id_exit_maps_to_exit_action() {
     assert_eq!(action_for_menu_id(&id), Some(MenuAction::Exit));
 }
 
+#[test]
+fn menu_id_controls_maps_to_controls_action() {
+    let id = MenuId(MENU_ID_CONTROLS.to_string());
+    assert_eq!(action_for_menu_id(&id), Some(MenuAction::Controls));
+}
+
+#[test]
+fn emulation_menu_ids_map_to_their_actions() {
+    for (id, action) in [
+        (MENU_ID_PAUSE, MenuAction::TogglePause),
+        (MENU_ID_FRAME_ADVANCE, MenuAction::FrameAdvance),
+        (MENU_ID_RESET, MenuAction::Reset),
+        (MENU_ID_POWER_CYCLE, MenuAction::PowerCycle),
+    ] {
+        assert_eq!(action_for_menu_id(&MenuId(id.to_string())), Some(action));
+    }
+}
+
+#[test]
+fn movie_menu_ids_map_to_their_actions() {
+    for (id, action) in [
+        (MENU_ID_RECORD_MOVIE, MenuAction::RecordMovie),
+        (MENU_ID_PLAY_MOVIE, MenuAction::PlayMovie),
+        (MENU_ID_STOP_MOVIE, MenuAction::StopMovie),
+        (MENU_ID_MOVIE_READ_ONLY, MenuAction::ToggleMovieReadOnly),
+    ] {
+        assert_eq!(action_for_menu_id(&MenuId(id.to_string())), Some(action));
+    }
+}
+
+#[test]
+fn audio_menu_ids_map_to_their_actions() {
+    for (id, action) in [
+        (MENU_ID_RECORD_AUDIO, MenuAction::RecordAudio),
+        (MENU_ID_STOP_AUDIO, MenuAction::StopAudioRecording),
+    ] {
+        assert_eq!(action_for_menu_id(&MenuId(id.to_string())), Some(action));
+    }
+    /* the hotkey starts or stops, whichever makes sense */
+    assert_eq!(
+        action_for_hotkey(Hotkey::RecordAudio),
+        Some(MenuAction::ToggleAudioRecording)
+    );
+}
+
+#[test]
+fn hotkeys_map_to_emulation_actions() {
+    assert_eq!(
+        action_for_hotkey(Hotkey::Pause),
+        Some(MenuAction::TogglePause)
+    );
+    assert_eq!(
+        action_for_hotkey(Hotkey::FrameAdvance),
+        Some(MenuAction::FrameAdvance)
+    );
+    assert_eq!(action_for_hotkey(Hotkey::Reset), Some(MenuAction::Reset));
+    assert_eq!(
+        action_for_hotkey(Hotkey::PowerCycle),
+        Some(MenuAction::PowerCycle)
+    );
+    /* screenshots don't need the menu, and fast-forward lasts as long as it's held */
+    assert_eq!(action_for_hotkey(Hotkey::Screenshot), None);
+    assert_eq!(action_for_hotkey(Hotkey::FastForward), None);
+}
+
+#[test]
+fn speed_menu_ids_map_to_speeds() {
+    for speed in Speed::PRESETS {
+        assert_eq!(
+            action_for_menu_id(&MenuId::new(speed_menu_id(speed))),
+            Some(MenuAction::SetSpeed(speed))
+        );
+    }
+    assert_eq!(
+        action_for_menu_id(&MenuId::new("speed_unthrottled")),
+        Some(MenuAction::SetSpeed(Speed::Unthrottled))
+    );
+    /* only the speeds on the menu */
+    assert_eq!(action_for_menu_id(&MenuId::new("speed_0")), None);
+    assert_eq!(action_for_menu_id(&MenuId::new("speed_fast")), None);
+}
+
 #[test]
 fn unknown_menu_id_maps_to_no_action() {
     let id = MenuId("something_else".to_string());
@@ -54,3 +142,36 @@ fn ctrl_with_non_character_key_is_ignored() {
     assert_eq!(action_for_shortcut(true, &Key::Enter), None);
     assert_eq!(action_for_shortcut(true, &Key::ArrowUp), None);
 }
+
+#[test]
+fn save_state_menu_ids_map_to_slots() {
+    let id = MenuId(format!("{MENU_ID_SAVE_STATE_PREFIX}1"));
+    assert_eq!(action_for_menu_id(&id), Some(MenuAction::SaveState(1)));
+    let id = MenuId(format!("{MENU_ID_LOAD_STATE_PREFIX}{SAVE_STATE_SLOTS}"));
+    assert_eq!(
+        action_for_menu_id(&id),
+        Some(MenuAction::LoadState(SAVE_STATE_SLOTS))
+    );
+}
+
+#[test]
+fn save_state_menu_ids_outside_slot_range_are_ignored() {
+    for slot in ["0", "5", "x", ""] {
+        let id = MenuId(format!("{MENU_ID_SAVE_STATE_PREFIX}{slot}"));
+        assert_eq!(action_for_menu_id(&id), None);
+    }
+}
+
+#[test]
+fn function_keys_load_and_shift_function_keys_save() {
+    assert_eq!(
+        action_for_slot_key(false, &Key::F1),
+        Some(MenuAction::LoadState(1))
+    );
+    assert_eq!(
+        action_for_slot_key(true, &Key::F4),
+        Some(MenuAction::SaveState(4))
+    );
+    assert_eq!(action_for_slot_key(false, &Key::F5), None);
+    assert_eq!(action_for_slot_key(true, &Key::Character("s")), None);
+}
@@ -0,0 +1,263 @@
+mod session;
+
+#[cfg(test)]
+mod tests;
+
+pub use session::{MovieMode, MovieSession};
+
+use crate::rom::Rom;
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/* commands that can start a frame, as bits in the first field of an FM2 frame line */
+pub const COMMAND_RESET: u8 = 1;
+pub const COMMAND_POWER: u8 = 2;
+
+const FM2_VERSION: &str = "3";
+/* the FCEUX release whose format the movies follow */
+const EMU_VERSION: &str = "22020";
+/* each frame's buttons, from the highest bit down: Right Left Down Up Start Select B A */
+const BUTTON_CHARS: [char; 8] = ['R', 'L', 'D', 'U', 'T', 'S', 'B', 'A'];
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/* what happens at the start of a frame: any commands, then the buttons held on each port */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub buttons: [u8; 2], /* one bit per button in ControllerKeys order */
+}
+
+/**
+ * A recording of the buttons held on each controller, frame by frame from power-on, in the
+ * format of FCEUX's .fm2 files: a header of "key value" lines, then a line per frame like
+ * "|0|R..U...A|........||". Only standard controllers on the two ports are supported.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub rom_filename: String,
+    pub rom_checksum: String,
+    pub guid: String,
+    pub rerecord_count: u32,
+    pub comments: Vec<String>,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    /* an empty movie of the ROM with the given checksum, ready to record into */
+    pub fn new(rom_checksum: &str, rom_path: &str) -> Movie {
+        let rom_checksum = rom_checksum.to_string();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or_default();
+        let mut context = md5::Context::new();
+        context.consume(nanos.to_le_bytes());
+        context.consume(&rom_checksum);
+        Movie {
+            rom_filename: Path::new(rom_path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            rom_checksum,
+            guid: guid(&context.finalize().0),
+            rerecord_count: 0,
+            comments: Vec::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Movie> {
+        let text = std::fs::read_to_string(path)?;
+        Movie::parse(&text)
+            .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_fm2())
+    }
+
+    pub fn parse(text: &str) -> io::Result<Movie> {
+        let mut movie = Movie {
+            rom_filename: String::new(),
+            rom_checksum: String::new(),
+            guid: String::new(),
+            rerecord_count: 0,
+            comments: Vec::new(),
+            frames: Vec::new(),
+        };
+        let mut ports = [true, true];
+
+        for (index, line) in text.lines().enumerate() {
+            let error = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", index + 1, message),
+                )
+            };
+
+            let line = line.trim_end_matches('\r');
+            if line.starts_with('|') {
+                movie.frames.push(parse_frame(line, ports).map_err(error)?);
+                continue;
+            }
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let unsupported = |what: &str| Err(error(format!("{what} aren't supported")));
+            match (key, value) {
+                ("version", FM2_VERSION) => {}
+                ("version", _) => return Err(error(format!("unknown version {value}"))),
+                ("rerecordCount", _) => {
+                    movie.rerecord_count = value
+                        .parse()
+                        .map_err(|_| error(format!("bad rerecordCount \"{value}\"")))?;
+                }
+                ("romFilename", _) => movie.rom_filename = value.to_string(),
+                ("romChecksum", _) => movie.rom_checksum = value.to_string(),
+                ("guid", _) => movie.guid = value.to_string(),
+                ("comment", _) => movie.comments.push(value.to_string()),
+                ("port0" | "port1", "0" | "1") => {
+                    ports[(key == "port1") as usize] = value == "1";
+                }
+                ("port0" | "port1", _) => return unsupported("devices other than gamepads"),
+                ("port2" | "fourscore" | "microphone" | "FDS", "0") => {}
+                ("port2", _) => return unsupported("expansion port devices"),
+                ("fourscore", _) => return unsupported("Four Score movies"),
+                ("microphone", _) => return unsupported("microphone movies"),
+                ("FDS", _) => return unsupported("Famicom Disk System movies"),
+                ("palFlag", "0") => {}
+                ("palFlag", _) => return unsupported("PAL movies"),
+                ("binary", "1") => return unsupported("binary movies"),
+                ("savestate", _) => return unsupported("movies starting from a savestate"),
+                /* the rest, e.g. emuVersion and NewPPU, make no difference here */
+                _ => {}
+            }
+        }
+
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut text = format!(
+            "version {FM2_VERSION}\n\
+             emuVersion {EMU_VERSION}\n\
+             rerecordCount {}\n\
+             palFlag 0\n\
+             romFilename {}\n\
+             romChecksum {}\n\
+             guid {}\n\
+             fourscore 0\n\
+             microphone 0\n\
+             port0 1\n\
+             port1 1\n\
+             port2 0\n\
+             FDS 0\n\
+             NewPPU 0\n",
+            self.rerecord_count, self.rom_filename, self.rom_checksum, self.guid
+        );
+        for comment in &self.comments {
+            text.push_str(&format!("comment {comment}\n"));
+        }
+        for frame in &self.frames {
+            text.push_str(&format!(
+                "|{}|{}|{}||\n",
+                frame.commands,
+                button_string(frame.buttons[0]),
+                button_string(frame.buttons[1])
+            ));
+        }
+        text
+    }
+}
+
+/* a frame line; ports that have nothing plugged in have empty fields */
+fn parse_frame(line: &str, ports: [bool; 2]) -> Result<MovieFrame, String> {
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < 4 {
+        return Err(format!(
+            "expected |commands|port0|port1|port2|, found \"{line}\""
+        ));
+    }
+    let commands = fields[1]
+        .parse()
+        .map_err(|_| format!("bad commands \"{}\"", fields[1]))?;
+
+    let mut buttons = [0; 2];
+    for (port, plugged_in) in ports.into_iter().enumerate() {
+        let field = fields[port + 2];
+        if plugged_in {
+            buttons[port] = parse_buttons(field)?;
+        } else if !field.is_empty() {
+            return Err(format!("input on port{port}, which has nothing plugged in"));
+        }
+    }
+    Ok(MovieFrame { commands, buttons })
+}
+
+/* any character but a space or a dot means the button is held */
+fn parse_buttons(field: &str) -> Result<u8, String> {
+    if field.chars().count() != BUTTON_CHARS.len() {
+        return Err(format!("expected 8 buttons, found \"{field}\""));
+    }
+    Ok(field
+        .chars()
+        .enumerate()
+        .filter(|(_, ch)| *ch != '.' && *ch != ' ')
+        .fold(0, |buttons, (index, _)| buttons | 0x80 >> index))
+}
+
+fn button_string(buttons: u8) -> String {
+    BUTTON_CHARS
+        .iter()
+        .enumerate()
+        .map(|(index, ch)| {
+            if buttons & 0x80 >> index != 0 {
+                *ch
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+/* as FCEUX identifies a ROM: the MD5 of its PRG and CHR data, in base64 */
+pub fn rom_checksum(rom: &Rom) -> String {
+    let mut context = md5::Context::new();
+    context.consume(&rom.prg_data);
+    context.consume(&rom.chr_data);
+    format!("base64:{}", base64(&context.finalize().0))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let bits = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (bits >> (18 - 6 * index)) & 0x3f;
+                text.push(BASE64_ALPHABET[sextet as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn guid(bytes: &[u8; 16]) -> String {
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02X}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
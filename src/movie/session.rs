@@ -0,0 +1,146 @@
+use crate::movie::{Movie, MovieFrame};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    /* played to the end; the controllers are live again */
+    Finished,
+}
+
+/* what to do as a frame starts: run any commands, then hold these buttons if there are any */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStart {
+    pub commands: u8,
+    pub buttons: Option<[u8; 2]>,
+}
+
+/**
+ * A movie being recorded or played back, frame by frame. Frames are numbered from power-on, and
+ * the session is told about each as it starts and ends, and whenever a savestate takes the
+ * machine to some other frame. What loading a state does depends on the read-only flag, as in
+ * FCEUX: read-only, the movie plays on from there; read-write, everything after it is thrown
+ * away and recording carries on from there, counting as a rerecord.
+ */
+pub struct MovieSession {
+    movie: Movie,
+    mode: MovieMode,
+    read_only: bool,
+    pending_commands: u8, /* asked for while recording, to start the next frame with */
+    current_commands: u8, /* the commands that started the frame being recorded */
+    modified: bool,
+}
+
+impl MovieSession {
+    pub fn record(movie: Movie) -> MovieSession {
+        MovieSession {
+            movie,
+            mode: MovieMode::Recording,
+            read_only: false,
+            pending_commands: 0,
+            current_commands: 0,
+            modified: false,
+        }
+    }
+
+    pub fn play(movie: Movie, read_only: bool) -> MovieSession {
+        MovieSession {
+            movie,
+            mode: MovieMode::Playing,
+            read_only,
+            pending_commands: 0,
+            current_commands: 0,
+            modified: false,
+        }
+    }
+
+    pub fn begin_frame(&mut self, frame: usize) -> FrameStart {
+        match self.mode {
+            MovieMode::Recording => {
+                self.current_commands = std::mem::take(&mut self.pending_commands);
+                FrameStart {
+                    commands: self.current_commands,
+                    buttons: None,
+                }
+            }
+            MovieMode::Playing => match self.movie.frames.get(frame) {
+                Some(movie_frame) => FrameStart {
+                    commands: movie_frame.commands,
+                    buttons: Some(movie_frame.buttons),
+                },
+                None => {
+                    self.mode = MovieMode::Finished;
+                    FrameStart::default()
+                }
+            },
+            MovieMode::Finished => FrameStart::default(),
+        }
+    }
+
+    /* records the buttons the game read during a frame, replacing anything from it onward */
+    pub fn end_frame(&mut self, frame: usize, buttons: [u8; 2]) {
+        if self.mode == MovieMode::Recording {
+            self.modified = true;
+            self.movie.frames.resize(frame, MovieFrame::default());
+            self.movie.frames.push(MovieFrame {
+                commands: self.current_commands,
+                buttons,
+            });
+        }
+    }
+
+    /**
+     * Offers the movie a reset or power cycle the user asked for, returning whether it dealt with
+     * it. While recording, it's saved up to start the next frame with, so playback does it at the
+     * same point; while playing, the movie is in charge, so it's ignored.
+     */
+    pub fn command(&mut self, command: u8) -> bool {
+        match self.mode {
+            MovieMode::Recording => {
+                self.pending_commands |= command;
+                true
+            }
+            MovieMode::Playing => true,
+            MovieMode::Finished => false,
+        }
+    }
+
+    /* the machine has jumped to the start of the given frame, e.g. by loading a savestate */
+    pub fn state_loaded(&mut self, frame: usize) {
+        self.pending_commands = 0;
+        if self.read_only {
+            self.mode = if frame < self.movie.frames.len() {
+                MovieMode::Playing
+            } else {
+                MovieMode::Finished
+            };
+        } else {
+            self.modified = true;
+            self.movie.frames.truncate(frame);
+            self.movie.rerecord_count += 1;
+            self.mode = MovieMode::Recording;
+        }
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    pub fn mode(&self) -> MovieMode {
+        self.mode
+    }
+
+    /* whether anything has been recorded, so the movie needs saving */
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+
+    #[cfg(test)]
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn into_movie(self) -> Movie {
+        self.movie
+    }
+}
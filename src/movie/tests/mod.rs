@@ -0,0 +1,2 @@
+mod movie_tests;
+mod session_tests;
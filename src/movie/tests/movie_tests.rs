@@ -0,0 +1,137 @@
+use crate::movie::{rom_checksum, Movie, MovieFrame, COMMAND_POWER, COMMAND_RESET};
+use crate::rom::{Rom, RomHeader};
+
+const HEADER: &str = "version 3\n\
+                      emuVersion 22020\n\
+                      rerecordCount 7\n\
+                      palFlag 0\n\
+                      romFilename game\n\
+                      romChecksum base64:1B2M2Y8AsgTpgAmY7PhCfg==\n\
+                      guid 01234567-89AB-CDEF-0123-456789ABCDEF\n\
+                      fourscore 0\n\
+                      microphone 0\n\
+                      port0 1\n\
+                      port1 1\n\
+                      port2 0\n\
+                      FDS 0\n\
+                      NewPPU 0\n";
+
+#[test]
+fn parses_header_and_frames() {
+    let text = format!(
+        "{HEADER}comment author someone\n\
+         |0|........|........||\n\
+         |1|R......A|....T...||\n\
+         |2|RLDUTSBA|........||\n"
+    );
+    let movie = Movie::parse(&text).unwrap();
+
+    assert_eq!(movie.rom_filename, "game");
+    assert_eq!(movie.rom_checksum, "base64:1B2M2Y8AsgTpgAmY7PhCfg==");
+    assert_eq!(movie.guid, "01234567-89AB-CDEF-0123-456789ABCDEF");
+    assert_eq!(movie.rerecord_count, 7);
+    assert_eq!(movie.comments, vec!["author someone".to_string()]);
+    assert_eq!(
+        movie.frames,
+        vec![
+            MovieFrame::default(),
+            MovieFrame {
+                commands: COMMAND_RESET,
+                buttons: [0b1000_0001, 0b0000_1000],
+            },
+            MovieFrame {
+                commands: COMMAND_POWER,
+                buttons: [0xff, 0],
+            },
+        ]
+    );
+}
+
+#[test]
+fn written_movies_read_back_the_same() {
+    let mut movie = Movie::new("base64:1B2M2Y8AsgTpgAmY7PhCfg==", "/roms/game.nes");
+    movie.rerecord_count = 3;
+    movie.comments.push("a comment".to_string());
+    movie.frames = (0..=255u8)
+        .map(|n| MovieFrame {
+            commands: n & COMMAND_RESET,
+            buttons: [n, n.reverse_bits()],
+        })
+        .collect();
+
+    let text = movie.to_fm2();
+    assert!(text.contains("\nromFilename game\n"));
+    assert!(text.contains("\n|1|.......A|R.......||\n"));
+    assert_eq!(Movie::parse(&text).unwrap(), movie);
+}
+
+#[test]
+fn any_character_but_a_dot_or_space_holds_a_button() {
+    let movie = Movie::parse("|0|xx  ..ab|........||").unwrap();
+    assert_eq!(movie.frames[0].buttons, [0b1100_0011, 0]);
+}
+
+#[test]
+fn unplugged_ports_have_empty_fields() {
+    let movie = Movie::parse("port1 0\n|0|.......A|||").unwrap();
+    assert_eq!(movie.frames[0].buttons, [1, 0]);
+
+    let error = Movie::parse("port1 0\n|0|.......A|.......A||").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "line 2: input on port1, which has nothing plugged in"
+    );
+}
+
+#[test]
+fn unsupported_movies_are_rejected() {
+    for (line, message) in [
+        ("version 2", "unknown version 2"),
+        ("palFlag 1", "PAL movies aren't supported"),
+        ("fourscore 1", "Four Score movies aren't supported"),
+        ("port0 2", "devices other than gamepads aren't supported"),
+        ("binary 1", "binary movies aren't supported"),
+        (
+            "savestate base64:AAAA",
+            "movies starting from a savestate aren't supported",
+        ),
+    ] {
+        let error = Movie::parse(&format!("{HEADER}{line}\n")).unwrap_err();
+        assert_eq!(error.to_string(), format!("line 15: {message}"));
+    }
+}
+
+#[test]
+fn bad_frames_report_their_line() {
+    let error = Movie::parse(&format!(
+        "{HEADER}|0|........|........||\n|0|...|........||\n"
+    ))
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "line 16: expected 8 buttons, found \"...\""
+    );
+
+    let error = Movie::parse("|x|........|........||").unwrap_err();
+    assert_eq!(error.to_string(), "line 1: bad commands \"x\"");
+}
+
+#[test]
+fn checksum_is_base64_md5_of_prg_and_chr() {
+    let rom = Rom {
+        header: RomHeader::default(),
+        prg_data: b"ab".to_vec(),
+        chr_data: b"c".to_vec(),
+        _trainer: vec![],
+    };
+    /* md5("abc") = 900150983cd24fb0d6963f7d28e17f72 */
+    assert_eq!(rom_checksum(&rom), "base64:kAFQmDzST7DWlj99KOF/cg==");
+}
+
+#[test]
+fn new_movies_get_distinct_guids() {
+    let first = Movie::new("base64:AAAA", "game.nes");
+    let second = Movie::new("base64:AAAA", "game.nes");
+    assert_eq!(first.guid.len(), 36);
+    assert_ne!(first.guid, second.guid);
+}
@@ -0,0 +1,131 @@
+use crate::movie::session::FrameStart;
+use crate::movie::{Movie, MovieFrame, MovieMode, MovieSession, COMMAND_POWER, COMMAND_RESET};
+
+fn movie(frames: usize) -> Movie {
+    let mut movie = Movie::new("base64:AAAA", "game.nes");
+    movie.frames = (0..frames)
+        .map(|n| MovieFrame {
+            commands: 0,
+            buttons: [n as u8, 0],
+        })
+        .collect();
+    movie
+}
+
+/* records frames with the given player 1 buttons */
+fn record(session: &mut MovieSession, buttons: &[u8]) {
+    for (frame, buttons) in buttons.iter().enumerate() {
+        session.begin_frame(frame);
+        session.end_frame(frame, [*buttons, 0]);
+    }
+}
+
+#[test]
+fn recording_keeps_each_frames_buttons() {
+    let mut session = MovieSession::record(movie(0));
+    assert!(!session.is_modified());
+    record(&mut session, &[1, 2, 3]);
+
+    assert!(session.is_modified());
+    let frames: Vec<u8> = session
+        .movie()
+        .frames
+        .iter()
+        .map(|f| f.buttons[0])
+        .collect();
+    assert_eq!(frames, vec![1, 2, 3]);
+}
+
+#[test]
+fn commands_while_recording_start_the_next_frame() {
+    let mut session = MovieSession::record(movie(0));
+    session.begin_frame(0);
+    assert!(session.command(COMMAND_RESET));
+    session.end_frame(0, [0, 0]);
+
+    assert_eq!(
+        session.begin_frame(1),
+        FrameStart {
+            commands: COMMAND_RESET,
+            buttons: None,
+        }
+    );
+    session.end_frame(1, [0, 0]);
+    assert_eq!(session.begin_frame(2), FrameStart::default());
+    session.end_frame(2, [0, 0]);
+
+    let commands: Vec<u8> = session.movie().frames.iter().map(|f| f.commands).collect();
+    assert_eq!(commands, vec![0, COMMAND_RESET, 0]);
+}
+
+#[test]
+fn playing_holds_each_frames_buttons_until_the_end() {
+    let mut movie = movie(2);
+    movie.frames[1].commands = COMMAND_POWER;
+    let mut session = MovieSession::play(movie, true);
+
+    assert_eq!(session.begin_frame(0).buttons, Some([0, 0]));
+    session.end_frame(0, [0xff, 0xff]);
+    assert_eq!(
+        session.begin_frame(1),
+        FrameStart {
+            commands: COMMAND_POWER,
+            buttons: Some([1, 0]),
+        }
+    );
+    assert_eq!(session.mode(), MovieMode::Playing);
+    /* the movie decides when to reset */
+    assert!(session.command(COMMAND_RESET));
+
+    assert_eq!(session.begin_frame(2), FrameStart::default());
+    assert_eq!(session.mode(), MovieMode::Finished);
+    assert!(!session.command(COMMAND_RESET));
+    assert!(!session.is_modified());
+}
+
+#[test]
+fn read_only_loads_play_on_from_the_state() {
+    let mut session = MovieSession::play(movie(5), true);
+    for frame in 0..=5 {
+        session.begin_frame(frame);
+    }
+    assert_eq!(session.mode(), MovieMode::Finished);
+
+    session.state_loaded(2);
+    assert_eq!(session.mode(), MovieMode::Playing);
+    assert_eq!(session.begin_frame(2).buttons, Some([2, 0]));
+    session.state_loaded(7);
+    assert_eq!(session.mode(), MovieMode::Finished);
+    assert!(!session.is_modified());
+}
+
+#[test]
+fn read_write_loads_record_over_the_rest() {
+    let mut session = MovieSession::play(movie(5), false);
+    session.begin_frame(0);
+    session.state_loaded(3);
+
+    assert_eq!(session.mode(), MovieMode::Recording);
+    assert!(session.is_modified());
+    assert_eq!(session.movie().rerecord_count, 1);
+    assert_eq!(session.movie().frames.len(), 3);
+
+    session.begin_frame(3);
+    session.end_frame(3, [9, 0]);
+    let frames: Vec<u8> = session
+        .movie()
+        .frames
+        .iter()
+        .map(|f| f.buttons[0])
+        .collect();
+    assert_eq!(frames, vec![0, 1, 2, 9]);
+}
+
+#[test]
+fn read_only_can_be_turned_off_mid_movie() {
+    let mut session = MovieSession::play(movie(5), true);
+    session.set_read_only(false);
+    session.state_loaded(1);
+    assert_eq!(session.mode(), MovieMode::Recording);
+    assert_eq!(session.into_movie().frames.len(), 1);
+}
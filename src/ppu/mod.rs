@@ -16,8 +16,8 @@ pub use ppu_registers::PPURegister;
 pub use tile::Tile;
 
 const OAM_SIZE: usize = 256;
-/* 4kb VRAM covering the entirety of the nametable space; in reality only 2kb is used but this
- * makes addressing easier
+/* the console's 2kb of nametable RAM, enough for two nametables; cartridges that want more
+ * supply it themselves
  */
 const VRAM_SIZE: usize = 1 << 11;
 const PALETTE_MEMORY_SIZE: usize = 32;
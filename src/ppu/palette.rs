@@ -1,3 +1,6 @@
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
 const HUE_LOOKUP: [[u8; 4]; 64] = [
     [0x62, 0x62, 0x62, 0xff], /* dark gray */
     [0x00, 0x1c, 0x95, 0xff],
@@ -85,6 +88,16 @@ impl Palette {
     }
 }
 
+impl Savestate for Palette {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.data);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.data)
+    }
+}
+
 pub fn hue_lookup(hue: usize) -> &'static [u8; 4] {
     &HUE_LOOKUP[hue & 0x3F]
 }
@@ -1,5 +1,5 @@
 use crate::cpu::CPU;
-use crate::mapper::Mapper;
+use crate::mapper::{Mapper, NametableSource, PPUFetch};
 use crate::ppu::palette::Palette;
 use crate::ppu::sprite_info::SpriteInfo;
 use crate::ppu::{
@@ -7,8 +7,9 @@ use crate::ppu::{
     VRAM_SIZE, WRITE_BUFFER_SIZE,
 };
 use crate::processor::Processor;
+use crate::savestate::{invalid_data, Savestate, StateReader, StateWriter};
 use std::cell::RefCell;
-use std::mem::replace;
+use std::io;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
@@ -17,15 +18,16 @@ pub struct PPU {
     pub(super) oam: OAM,
     write_buffer: Arc<Mutex<WriteBuffer>>,
     internal_buffer: WriteBuffer,
-    vram: [u8; VRAM_SIZE],
+    pub(super) vram: [u8; VRAM_SIZE],
     palette_memory: [u8; PALETTE_MEMORY_SIZE],
-    scanline_sprites: Option<Vec<SpriteInfo>>,
+    scanline_sprites: Vec<SpriteInfo>, /* from dot 257 on, the next line's */
     current_tile: Option<Tile>,
     current_palette: Option<Palette>,
     next_tile: Option<Tile>,
     next_palette: Option<Palette>,
     tick_count: u32,
     is_even_frame: bool,
+    frame_count: u64, /* number of frames completed, counted at the start of vblank */
     /* shared registers */
     pub(super) ppu_ctrl: u8,
     pub(super) ppu_mask: u8,
@@ -33,6 +35,7 @@ pub struct PPU {
     pub(super) ppu_status: u8,
     pub(super) tall_sprites: bool, /* if true, sprites are 16 pixels tall instead of 8 */
     pub(super) internal_regs: PPUInternalRegisters,
+    pub(super) data_read_buffer: u8, /* PPUDATA reads return the previously read value */
 }
 
 #[derive(Clone, Debug)]
@@ -41,8 +44,31 @@ pub enum NametableMirroring {
     Vertical,         /* pages are mirrored vertically (appropriate for horizontal games) */
     SingleNametable0, /* first nametable mirrored four times */
     SingleNametable1, /* second nametable mirrored four times */
-    #[allow(dead_code)]
-    FourScreen, /* all four nametable/attribute tables available */
+    FourScreen,       /* all four nametables distinct, with the cartridge supplying the VRAM */
+}
+
+impl NametableMirroring {
+    /* which page of the console's nametable RAM each nametable is, or the cartridge for
+     * four-screen
+     */
+    pub fn nametable_source(&self, nametable: u8) -> NametableSource {
+        match self {
+            NametableMirroring::Horizontal => NametableSource::Ciram(nametable & 1),
+            NametableMirroring::Vertical => NametableSource::Ciram((nametable >> 1) & 1),
+            NametableMirroring::SingleNametable0 => NametableSource::Ciram(0),
+            NametableMirroring::SingleNametable1 => NametableSource::Ciram(1),
+            NametableMirroring::FourScreen => NametableSource::Cartridge,
+        }
+    }
+}
+
+/* where an address on the PPU's bus ends up */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum VramAddress {
+    Chr(u16),       /* the pattern tables, on the cartridge */
+    Ciram(usize),   /* an index into the console's nametable RAM */
+    Cartridge(u16), /* a nametable the cartridge supplies, at 0x2000-0x2fff */
+    Palette(usize), /* an index into palette memory */
 }
 
 impl Processor for PPU {
@@ -70,12 +96,14 @@ impl PPU {
             oam_addr: 0,
             internal_regs: PPUInternalRegisters::default(),
             tall_sprites: false,
-            scanline_sprites: None,
+            scanline_sprites: Vec::new(),
             current_tile: None,
             current_palette: None,
             next_tile: None,
             next_palette: None,
             is_even_frame: false,
+            frame_count: 0,
+            data_read_buffer: 0,
         }))
     }
 
@@ -89,6 +117,12 @@ impl PPU {
         let dot = (self.tick_count % 341) as u16;
         let rendering_on = self.ppu_mask & 0x18 != 0;
 
+        if dot == 1 {
+            self.mapper
+                .borrow_mut()
+                .ppu_scanline_start(scanline as u16, rendering_on);
+        }
+
         if scanline < 240 {
             self.render_scanline(scanline as u8, dot, rendering_on);
         } else if scanline == 240 {
@@ -111,20 +145,20 @@ impl PPU {
 
     fn render_scanline(&mut self, scanline: u8, dot: u16, rendering_on: bool) {
         if 0 < dot && dot < 257 {
-            if dot == 1 {
-                self.render_scanline_begin(scanline);
-            }
-            self.render_block(scanline, (dot - 1) as u8, rendering_on);
+            self.render_block(scanline, (dot - 1) as u8, 1, rendering_on);
             if dot == 256 && rendering_on {
                 self.internal_regs.y_increment();
             }
-        } else if dot == 257 && rendering_on {
-            self.internal_regs.copy_x_bits();
+        } else if dot > 256 && dot < 321 {
+            if dot == 257 && rendering_on {
+                self.internal_regs.copy_x_bits();
+            }
+            self.fetch_sprites(scanline.wrapping_add(1), dot, rendering_on);
         } else if dot > 320 && dot < 329 {
             //337 TODO fix: this is leading to incorrect renders where sprites haven't
             //been properly initialized. (scanline + 1) % 240 is probably at issue, but
             //that's to pass some specific tests
-            self.render_block((scanline + 1) % 240, (dot - 1 - 320) as u8, rendering_on);
+            self.render_block((scanline + 1) % 240, (dot - 1 - 320) as u8, 0, rendering_on);
         }
     }
 
@@ -145,12 +179,14 @@ impl PPU {
         self.render_scanline(0xff, dot, rendering_on);
     }
 
-    fn render_block(&mut self, scanline: u8, x: u8, rendering_on: bool) {
+    /* draws pixel x of the scanline, fetching a tile every 8; the tiles fetched are numbered
+     * from first_column, as the mapper sees them
+     */
+    fn render_block(&mut self, scanline: u8, x: u8, first_column: u8, rendering_on: bool) {
         let mod8 = x % 8;
         /* NB: these are offset by 1 from actual dot number */
         if mod8 == 0 {
-            self.load_tile();
-            self.load_palette();
+            self.load_tile(scanline, first_column + x / 8, rendering_on);
         }
         if rendering_on {
             self.render_pixel(scanline, x);
@@ -174,13 +210,49 @@ impl PPU {
             .lock()
             .unwrap()
             .copy_from_slice(&self.internal_buffer);
+        self.frame_count += 1;
+    }
+
+    /* reset clears the control registers and the write toggle; memory is untouched */
+    pub fn reset(&mut self) {
+        self.ppu_ctrl = 0;
+        self.ppu_mask = 0;
+        self.tall_sprites = false;
+        self.internal_regs.w = false;
+        self.data_read_buffer = 0;
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /* the scanline and dot that will be rendered next */
+    pub fn position(&self) -> (u16, u16) {
+        let scanline = self.tick_count / 341;
+        let dot = self.tick_count % 341;
+        (scanline as u16, dot as u16)
     }
 
-    pub fn render_scanline_begin(&mut self, scanline: u8) {
-        let sprite_data = self.sprite_evaluation(scanline);
-        self.scanline_sprites = Some(sprite_data);
-        self.current_tile = Some(self.get_current_tile());
-        self.current_palette = Some(self.palette_for_current_bg_tile());
+    /* the sprites on the next line are known by the end of this one, and their patterns are
+     * fetched over dots 257-320, 8 dots for each of the eight slots. Slots without a sprite are
+     * left filled with 0xff and fetch tile 0xff all the same, which mappers watching the fetches
+     * (e.g. MMC2's latches) see.
+     */
+    fn fetch_sprites(&mut self, scanline: u8, dot: u16, rendering_on: bool) {
+        if dot == 257 {
+            self.scanline_sprites = self.sprite_evaluation(scanline);
+        }
+        /* each slot's pattern is read on its fourth dot */
+        if (dot - 257) % 8 != 3 {
+            return;
+        }
+        let slot = ((dot - 257) / 8) as usize;
+        let mut sprites = std::mem::take(&mut self.scanline_sprites);
+        match sprites.get_mut(slot) {
+            Some(sprite) => sprite.fetch_pattern(self, scanline, rendering_on),
+            None => SpriteInfo::empty_slot().fetch_pattern(self, scanline, rendering_on),
+        }
+        self.scanline_sprites = sprites;
     }
 
     pub fn render_pixel(&mut self, scanline: u8, x: u8) {
@@ -192,9 +264,7 @@ impl PPU {
         let pixel = 'pixel: {
             let mut background_sprite_pixel = None;
             if render_sprites {
-                if let Some(pixel_data) =
-                    self.render_sprites(&self.scanline_sprites.as_ref().unwrap(), scanline, x)
-                {
+                if let Some(pixel_data) = self.render_sprites(&self.scanline_sprites, x) {
                     if render_background {
                         self.sprite0_hit_detection(x, &pixel_data.0);
                     }
@@ -224,19 +294,27 @@ impl PPU {
         }
     }
 
-    fn load_palette(&mut self) {
-        let new_palette = Some(self.get_bg_palette());
-        self.current_palette = replace(&mut self.next_palette, new_palette);
-    }
-
-    fn load_tile(&mut self) {
-        let new_tile = Some(
-            self.get_bg_tile(self.read_vram((0x2000 | (self.internal_regs.v & 0xfff)) as usize)),
-        );
-        self.current_tile = replace(&mut self.next_tile, new_tile);
+    /* fetches the next background tile in the order the PPU does: its nametable entry, then
+     * its attribute, then its pattern
+     */
+    fn load_tile(&mut self, scanline: u8, column: u8, rendering_on: bool) {
+        if rendering_on {
+            self.mapper
+                .borrow_mut()
+                .ppu_background_tile(scanline, column);
+        }
+        let nametable_address = 0x2000 | (self.internal_regs.v & 0xfff);
+        let tile_index = self.render_fetch(nametable_address, PPUFetch::Nametable, rendering_on);
+        let new_palette = self.get_bg_palette(rendering_on);
+        let mut new_tile = self.get_bg_tile(tile_index);
+        if rendering_on {
+            new_tile.fetch(self.internal_regs.get_fine_y());
+        }
+        self.current_tile = self.next_tile.replace(new_tile);
+        self.current_palette = self.next_palette.replace(new_palette);
     }
 
-    fn get_bg_palette(&self) -> Palette {
+    fn get_bg_palette(&self, rendering_on: bool) -> Palette {
         /* TODO comment */
         /* 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07) */
         let addr = 0x23c0
@@ -244,7 +322,7 @@ impl PPU {
             | (((self.internal_regs.get_coarse_y() as u16) & 0x1c) << 1)
             | ((self.internal_regs.get_coarse_x() as u16) >> 2);
         /* each address controls a 32x32 pixel block; 8 blocks per row */
-        let attr_table_value = self.read_vram(addr as usize);
+        let attr_table_value = self.render_fetch(addr, PPUFetch::Attribute, rendering_on);
         /* the attr_table_value stores information about 16x16 blocks as 2-bit palette references.
          * in order from the lowest bits they are: upper left, upper right, bottom left, bottom right
          */
@@ -293,15 +371,13 @@ impl PPU {
     fn render_sprites(
         &self,
         scanline_sprites: &Vec<SpriteInfo>,
-        scanline: u8,
         x: u8,
     ) -> Option<(SpriteInfo, &'static [u8; 4])> {
         for sprite in scanline_sprites {
             if !(x >= sprite.x && x - sprite.x < 8) {
                 continue;
             }
-            let brightness =
-                sprite.get_brightness_localized(self, x - sprite.x, scanline - sprite.get_y());
+            let brightness = sprite.pixel_intensity(x - sprite.x);
             if brightness > 0 {
                 return Some((
                     sprite.clone(),
@@ -342,40 +418,6 @@ impl PPU {
         }
     }
 
-    fn get_current_tile(&self) -> Tile {
-        self.get_bg_tile(self.read_vram((0x2000 | (self.internal_regs.v & 0xfff)) as usize))
-    }
-
-    fn palette_for_current_bg_tile(&self) -> Palette {
-        /* TODO comment */
-        /* 0x23C0 | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07) */
-        let addr = 0x23c0
-            | ((self.internal_regs.get_nametable() as u16) << 10)
-            | (((self.internal_regs.get_coarse_y() as u16) & 0x1c) << 1)
-            | ((self.internal_regs.get_coarse_x() as u16) >> 2);
-        /* each address controls a 32x32 pixel block; 8 blocks per row */
-        let attr_table_value = self.read_vram(addr as usize);
-        /* the attr_table_value stores information about 16x16 blocks as 2-bit palette references.
-         * in order from the lowest bits they are: upper left, upper right, bottom left, bottom right
-         */
-        let x_low = 8 * self.internal_regs.get_coarse_x() % 32 < 16;
-        let y_low = 8 * self.internal_regs.get_coarse_y() % 32 < 16;
-        let attr_table_offset = if x_low {
-            if y_low {
-                0
-            } else {
-                4
-            }
-        } else {
-            if y_low {
-                2
-            } else {
-                6
-            }
-        };
-        self.get_palette((attr_table_value >> attr_table_offset) & 3) /* only need two bits */
-    }
-
     /* optimized version of looking up the global background color that skips
      * constructing a palette
      */
@@ -413,13 +455,16 @@ impl PPU {
         SpriteInfo::from_memory(sprite_index, &sprite_data)
     }
 
-    pub(super) fn get_sprite_tile(&self, tile_index: u8) -> Tile {
+    /* where the given row of a sprite's low bit plane is; the high plane follows 8 bytes on */
+    pub(super) fn sprite_pattern_address(&self, tile_index: u8, row: u8) -> u16 {
         let (tile_index, pattern_table) = if self.tall_sprites {
             (tile_index & !1, tile_index & 1)
         } else {
             (tile_index, (self.ppu_ctrl & 0x8) >> 3)
         };
-        self.get_tile(tile_index, pattern_table)
+        /* the bottom half of a tall sprite is the next tile */
+        let row = if row > 7 { row + 8 } else { row };
+        0x1000 * pattern_table as u16 + tile_index as u16 * 16 + row as u16
     }
 
     fn get_bg_tile(&self, tile_index: u8) -> Tile {
@@ -440,70 +485,170 @@ impl PPU {
         Palette::new(palette_data)
     }
 
-    pub fn read_vram(&self, addr: usize) -> u8 {
-        let mapped_address = self.vram_address_mirror(addr);
-
-        /* pattern tables (CHR data) */
-        if mapped_address < 0x2000 {
-            self.mapper.borrow().read_chr(mapped_address as u16)
-        /* nametables and attribute tables */
-        } else if mapped_address < 0x3000 {
-            self.vram[mapped_address - 0x2000]
-        /* palettes */
+    /* reads a byte to draw; while rendering, the mapper sees the fetch and has the last word on
+     * what's drawn
+     */
+    pub(super) fn render_fetch(&self, address: u16, fetch: PPUFetch, rendering_on: bool) -> u8 {
+        let value = self.read_vram(address as usize);
+        if rendering_on {
+            self.mapper
+                .borrow_mut()
+                .ppu_render_fetch(address, fetch, value)
         } else {
-            self.palette_memory[mapped_address - 0x3f00]
+            value
         }
     }
 
-    pub fn write_vram(&mut self, addr: usize, val: u8) {
-        let mapped_address = self.vram_address_mirror(addr);
+    /* lets the mapper see a write to one of the registers at 0x2000-0x2007 */
+    pub(super) fn register_written(&self, address: u16, value: u8) {
+        self.mapper.borrow_mut().ppu_register_write(address, value);
+    }
 
-        /* pattern tables (CHR data) */
-        if mapped_address < 0x2000 {
-            self.mapper
-                .borrow_mut()
-                .write_chr(mapped_address as u16, val);
-        /* nametables and attribute tables */
-        } else if mapped_address < 0x3f00 {
-            self.vram[mapped_address - 0x2000] = val;
-            /* palettes */
-        } else {
-            self.palette_memory[mapped_address - 0x3f00] = val;
+    pub fn read_vram(&self, addr: usize) -> u8 {
+        match self.vram_address_mirror(addr) {
+            VramAddress::Chr(address) => self.mapper.borrow().read_chr(address),
+            VramAddress::Ciram(index) => self.vram[index],
+            VramAddress::Cartridge(address) => self.mapper.borrow().read_nametable(address),
+            VramAddress::Palette(index) => self.palette_memory[index],
+        }
+    }
+
+    pub fn write_vram(&mut self, addr: usize, val: u8) {
+        match self.vram_address_mirror(addr) {
+            VramAddress::Chr(address) => self.mapper.borrow_mut().write_chr(address, val),
+            VramAddress::Ciram(index) => self.vram[index] = val,
+            VramAddress::Cartridge(address) => {
+                self.mapper.borrow_mut().write_nametable(address, val)
+            }
+            VramAddress::Palette(index) => self.palette_memory[index] = val,
         }
     }
 
-    pub fn vram_address_mirror(&self, addr: usize) -> usize {
+    pub(super) fn vram_address_mirror(&self, addr: usize) -> VramAddress {
         let mut result = addr;
 
         if result < 0x2000 {
-            result
+            VramAddress::Chr(result as u16)
         } else if result < 0x3f00 {
+            /* 0x3000-0x3eff mirrors 0x2000-0x2eff */
             if result >= 0x3000 {
                 result -= 0x1000;
             }
 
-            /* TODO document */
-            match self.mapper.borrow().get_nametable_mirroring() {
-                NametableMirroring::Horizontal => result & !0x0800,
-                NametableMirroring::Vertical => (result & !0x0C00) | ((result & 0x0800) >> 1),
-                NametableMirroring::SingleNametable0 => result & !0x0c00,
-                NametableMirroring::SingleNametable1 => (result & !0x0c00) + 0x400,
-                NametableMirroring::FourScreen => result,
+            /* four 1kb nametables, each of which the mapper places in one of the console's two
+             * pages of RAM or on the cartridge
+             */
+            let nametable = ((result >> 10) & 3) as u8;
+            match self.mapper.borrow().nametable_source(nametable) {
+                NametableSource::Ciram(page) => {
+                    VramAddress::Ciram(((page as usize & 1) << 10) | (result & 0x3ff))
+                }
+                NametableSource::Cartridge => VramAddress::Cartridge(result as u16),
             }
         } else {
             /* palettes are repeated above 0x3f1f */
-            result = 0x3f00 | (result & 0x1f);
+            result &= 0x1f;
 
             /* the first color of corresponding background and sprite palettes are shared;
              * this doesn't have any real effect, except if the true background color is
              * written to 0x3f10
              */
-            if result == 0x3f10 {
+            if result == 0x10 {
                 result -= 0x10;
             }
 
-            result
+            VramAddress::Palette(result)
+        }
+    }
+}
+
+impl Savestate for NametableMirroring {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(match self {
+            NametableMirroring::Horizontal => 0,
+            NametableMirroring::Vertical => 1,
+            NametableMirroring::SingleNametable0 => 2,
+            NametableMirroring::SingleNametable1 => 3,
+            NametableMirroring::FourScreen => 4,
+        });
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        *self = match reader.read_u8()? {
+            0 => NametableMirroring::Horizontal,
+            1 => NametableMirroring::Vertical,
+            2 => NametableMirroring::SingleNametable0,
+            3 => NametableMirroring::SingleNametable1,
+            4 => NametableMirroring::FourScreen,
+            value => return Err(invalid_data(&format!("Invalid mirroring {}", value))),
+        };
+        Ok(())
+    }
+}
+
+/* the mapper is shared with the CPU, so it's saved along with CoreMemory rather than here;
+ * the in-progress frame is included so a state restored mid-frame finishes it identically
+ */
+impl Savestate for PPU {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.oam);
+        writer.write_bytes(&self.internal_buffer);
+        writer.write_bytes(&self.vram);
+        writer.write_bytes(&self.palette_memory);
+
+        writer.write_u8(self.scanline_sprites.len() as u8);
+        for sprite in &self.scanline_sprites {
+            sprite.save_state(writer);
+        }
+        writer.write_option(&self.current_tile);
+        writer.write_option(&self.current_palette);
+        writer.write_option(&self.next_tile);
+        writer.write_option(&self.next_palette);
+
+        writer.write_u32(self.tick_count);
+        writer.write_bool(self.is_even_frame);
+        writer.write_u64(self.frame_count);
+        writer.write_u8(self.ppu_ctrl);
+        writer.write_u8(self.ppu_mask);
+        writer.write_u8(self.oam_addr);
+        writer.write_u8(self.ppu_status);
+        writer.write_bool(self.tall_sprites);
+        self.internal_regs.save_state(writer);
+        writer.write_u8(self.data_read_buffer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.oam)?;
+        reader.read_bytes_into(&mut self.internal_buffer)?;
+        reader.read_bytes_into(&mut self.vram)?;
+        reader.read_bytes_into(&mut self.palette_memory)?;
+
+        self.scanline_sprites.clear();
+        for _i in 0..reader.read_u8()? {
+            let mut sprite = self.slice_as_sprite(0);
+            sprite.load_state(reader)?;
+            self.scanline_sprites.push(sprite);
+        }
+        let mapper = &self.mapper;
+        reader.read_option(&mut self.current_tile, || Tile::new(0, mapper.clone()))?;
+        reader.read_option(&mut self.current_palette, || Palette::new([0; 4]))?;
+        reader.read_option(&mut self.next_tile, || Tile::new(0, mapper.clone()))?;
+        reader.read_option(&mut self.next_palette, || Palette::new([0; 4]))?;
+
+        self.tick_count = reader.read_u32()?;
+        if self.tick_count >= 341 * 262 {
+            return Err(invalid_data("Invalid PPU tick count"));
         }
+        self.is_even_frame = reader.read_bool()?;
+        self.frame_count = reader.read_u64()?;
+        self.ppu_ctrl = reader.read_u8()?;
+        self.ppu_mask = reader.read_u8()?;
+        self.oam_addr = reader.read_u8()?;
+        self.ppu_status = reader.read_u8()?;
+        self.tall_sprites = reader.read_bool()?;
+        self.internal_regs.load_state(reader)?;
+        self.data_read_buffer = reader.read_u8()?;
+        Ok(())
     }
 }
 
@@ -1,3 +1,6 @@
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
+
 #[derive(Debug, Default, Clone)]
 pub struct PPUInternalRegisters {
     pub v: u16,
@@ -141,3 +144,20 @@ impl PPUInternalRegisters {
         self.set_nametable((self.get_nametable() & 0x1) | (self.get_nametable_t() & 0x2));
     }
 }
+
+impl Savestate for PPUInternalRegisters {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.v);
+        writer.write_u16(self.t);
+        writer.write_u8(self.x);
+        writer.write_bool(self.w);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.v = reader.read_u16()?;
+        self.t = reader.read_u16()?;
+        self.x = reader.read_u8()?;
+        self.w = reader.read_bool()?;
+        Ok(())
+    }
+}
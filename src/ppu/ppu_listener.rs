@@ -1,22 +1,18 @@
 use crate::cpu::CoreMemory;
 use crate::cpu::MemoryListener;
 use crate::ppu::PPURegister::*;
-use crate::ppu::{PPURegister, OAM_SIZE, PPU};
+use crate::ppu::{PPURegister, PPU};
 use std::cell::RefCell;
 use std::rc::Rc;
 
 #[derive(Clone)]
 pub struct PPUListener {
     ppu: Rc<RefCell<PPU>>,
-    read_buffer: u8,
 }
 
 impl PPUListener {
     pub fn new(ppu: Rc<RefCell<PPU>>) -> PPUListener {
-        PPUListener {
-            ppu,
-            read_buffer: 0,
-        }
+        PPUListener { ppu }
     }
 }
 
@@ -37,7 +33,7 @@ impl MemoryListener for PPUListener {
         addrs
     }
 
-    fn read(&mut self, _memory: &CoreMemory, address: u16) -> u8 {
+    fn read(&mut self, memory: &CoreMemory, address: u16) -> u8 {
         if let Some(updated_register) = PPURegister::from_addr(address) {
             let mut ppu = self.ppu.borrow_mut();
             match updated_register {
@@ -57,16 +53,15 @@ impl MemoryListener for PPUListener {
                     ppu.internal_regs.v as u8
                 }
                 PPUDATA => {
-                    let result = self.read_buffer;
-                    self.read_buffer = ppu.read_vram(ppu.internal_regs.v as usize);
+                    let result = ppu.data_read_buffer;
+                    ppu.data_read_buffer = ppu.read_vram(ppu.internal_regs.v as usize);
 
                     ppu.internal_regs.v += if ppu.ppu_ctrl & 0x4 != 0 { 32 } else { 1 };
 
                     result
                 }
-                _ => {
-                    panic!("unimplemented {:?}", updated_register)
-                }
+                /* write-only; dummy reads can land on these */
+                PPUSCROLL | OAMDMA => memory.open_bus(),
             }
         } else {
             panic!(
@@ -76,7 +71,7 @@ impl MemoryListener for PPUListener {
         }
     }
 
-    fn write(&mut self, memory: &CoreMemory, address: u16, value: u8) {
+    fn write(&mut self, _memory: &CoreMemory, address: u16, value: u8) {
         if let Some(updated_register) = PPURegister::from_addr(address) {
             let mut ppu = self.ppu.borrow_mut();
             match updated_register {
@@ -100,7 +95,7 @@ impl MemoryListener for PPUListener {
                 OAMDATA => {
                     let addr = ppu.oam_addr as usize;
                     ppu.oam[addr] = value;
-                    ppu.oam_addr += 1;
+                    ppu.oam_addr = ppu.oam_addr.wrapping_add(1);
                 }
                 PPUSCROLL => {
                     let coarse = (value >> 3) & 0x1f;
@@ -129,10 +124,10 @@ impl MemoryListener for PPUListener {
                     ppu.write_vram(addr, value);
                     ppu.internal_regs.v += if ppu.ppu_ctrl & 0x4 != 0 { 32 } else { 1 };
                 }
-                OAMDMA => {
-                    let base_addr = (value as u16) << 8;
-                    memory.copy_slice(base_addr, OAM_SIZE, &mut ppu.oam);
-                }
+                OAMDMA => { /* the CPU does the transfer, a byte at a time, through OAMDATA */ }
+            }
+            if address < 0x4000 {
+                ppu.register_written(address, value);
             }
         }
     }
@@ -1,5 +1,8 @@
+use crate::mapper::PPUFetch;
 use crate::ppu::palette::Palette;
 use crate::ppu::PPU;
+use crate::savestate::{Savestate, StateReader, StateWriter};
+use std::io;
 
 #[derive(Debug, Clone)]
 pub struct SpriteInfo {
@@ -9,6 +12,7 @@ pub struct SpriteInfo {
     attrs: u8,
     pub(super) x: u8,
     pub(super) sprite_index: usize,
+    pattern: [u8; 2], /* the low and high bit planes of the row drawn this scanline */
 }
 
 impl SpriteInfo {
@@ -21,19 +25,33 @@ impl SpriteInfo {
         self.y.saturating_add(1)
     }
 
-    pub(super) fn get_brightness_localized(&self, ppu: &PPU, x: u8, y: u8) -> u8 {
-        let mut tile = ppu.get_sprite_tile(self.tile_index);
-        let mut x_to_use = x;
-        if self.attrs & 0x40 != 0 {
-            /* flipped horizontally */
-            x_to_use = 7 - x_to_use;
-        }
-        let mut y_to_use = y;
+    /* reads the row of the pattern drawn on the given scanline, as the PPU does for each
+     * sprite on the line ahead of drawing it. Only the low bits of the distance from the top
+     * pick the row, which is all there is to go on for an empty slot.
+     */
+    pub(super) fn fetch_pattern(&mut self, ppu: &PPU, scanline: u8, rendering_on: bool) {
+        let mut row = scanline.wrapping_sub(self.y).wrapping_sub(1) & (ppu.sprite_height() - 1);
         if self.attrs & 0x80 != 0 {
             /* flipped vertically */
-            y_to_use = (ppu.sprite_height() - 1) - y_to_use;
+            row = (ppu.sprite_height() - 1) - row;
         }
-        tile.pixel_intensity(x_to_use, y_to_use)
+        let address = ppu.sprite_pattern_address(self.tile_index, row);
+        self.pattern = [
+            ppu.render_fetch(address, PPUFetch::SpritePattern, rendering_on),
+            ppu.render_fetch(address + 8, PPUFetch::SpritePattern, rendering_on),
+        ];
+    }
+
+    /* the 2-bit color of pixel x of the fetched row */
+    pub(super) fn pixel_intensity(&self, x: u8) -> u8 {
+        /* pattern bytes hold the leftmost pixel in their top bit */
+        let shift = if self.attrs & 0x40 != 0 {
+            /* flipped horizontally */
+            x
+        } else {
+            7 - x
+        };
+        (((self.pattern[1] >> shift) & 1) << 1) | ((self.pattern[0] >> shift) & 1)
     }
 
     pub(super) fn get_palette(&self, ppu: &PPU) -> Palette {
@@ -44,6 +62,11 @@ impl SpriteInfo {
         self.attrs & 0x20 == 0
     }
 
+    /* what a slot of secondary OAM without a sprite holds; it's cleared to 0xff */
+    pub(super) fn empty_slot() -> SpriteInfo {
+        SpriteInfo::from_memory(0xff, &[0xff; 4])
+    }
+
     /* create a SpriteInfo from memory */
     pub(super) fn from_memory(sprite_index: usize, src_slice: &[u8]) -> SpriteInfo {
         SpriteInfo {
@@ -52,6 +75,28 @@ impl SpriteInfo {
             attrs: src_slice[2],
             x: src_slice[3],
             sprite_index,
+            pattern: [0; 2],
         }
     }
 }
+
+impl Savestate for SpriteInfo {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.y);
+        writer.write_u8(self.tile_index);
+        writer.write_u8(self.attrs);
+        writer.write_u8(self.x);
+        writer.write_u8(self.sprite_index as u8);
+        writer.write_bytes(&self.pattern);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.y = reader.read_u8()?;
+        self.tile_index = reader.read_u8()?;
+        self.attrs = reader.read_u8()?;
+        self.x = reader.read_u8()?;
+        self.sprite_index = reader.read_u8()? as usize;
+        reader.read_bytes_into(&mut self.pattern)?;
+        Ok(())
+    }
+}
@@ -1,17 +1,26 @@
-use crate::mapper::Mapper;
+use crate::mapper::{Mapper, NametableSource};
 use crate::ppu::{NametableMirroring, PPU, WRITE_BUFFER_SIZE};
+use crate::savestate::{Savestate, StateReader, StateWriter};
 use std::cell::RefCell;
+use std::io;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
 
 pub struct MockMapper {
     pub chr: [u8; 0x2000],
     mirroring: NametableMirroring,
+    pub nametable_sources: Option<[NametableSource; 4]>, /* instead of following mirroring */
+    nametables: [u8; 0x1000],                            /* for NametableSource::Cartridge */
 }
 
 impl MockMapper {
     pub fn new(mirroring: NametableMirroring) -> Self {
-        MockMapper { chr: [0; 0x2000], mirroring }
+        MockMapper {
+            chr: [0; 0x2000],
+            mirroring,
+            nametable_sources: None,
+            nametables: [0; 0x1000],
+        }
     }
 }
 
@@ -20,10 +29,6 @@ impl Mapper for MockMapper {
         0
     }
 
-    fn read_prg_slice(&self, _address: u16, _size: usize) -> &[u8] {
-        panic!("MockMapper: PRG slice not supported")
-    }
-
     fn write_prg(&mut self, _address: u16, _value: u8) {}
 
     fn read_chr(&self, address: u16) -> u8 {
@@ -37,6 +42,31 @@ impl Mapper for MockMapper {
     fn get_nametable_mirroring(&self) -> NametableMirroring {
         self.mirroring.clone()
     }
+
+    fn nametable_source(&self, nametable: u8) -> NametableSource {
+        match self.nametable_sources {
+            Some(sources) => sources[nametable as usize],
+            None => self.mirroring.nametable_source(nametable),
+        }
+    }
+
+    fn read_nametable(&self, address: u16) -> u8 {
+        self.nametables[address as usize - 0x2000]
+    }
+
+    fn write_nametable(&mut self, address: u16, value: u8) {
+        self.nametables[address as usize - 0x2000] = value;
+    }
+}
+
+impl Savestate for MockMapper {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bytes(&self.chr);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        reader.read_bytes_into(&mut self.chr)
+    }
 }
 
 pub fn make_ppu_with_buffer(
@@ -49,6 +79,11 @@ pub fn make_ppu_with_buffer(
     (ppu, write_buffer)
 }
 
+pub fn make_ppu_with_mapper(mapper: MockMapper) -> Rc<RefCell<PPU>> {
+    let write_buffer = Arc::new(Mutex::new([0u8; WRITE_BUFFER_SIZE]));
+    PPU::new(write_buffer, Rc::new(RefCell::new(Box::new(mapper))))
+}
+
 pub fn make_ppu(mirroring: NametableMirroring) -> Rc<RefCell<PPU>> {
     make_ppu_with_buffer(mirroring).0
 }
@@ -1,7 +1,9 @@
 use super::mock_mapper::{make_ppu, MockMapper};
-use crate::cpu::{CoreMemory, MemoryListener};
+use crate::cpu::{CoreMemory, MemoryListener, CPU};
 use crate::ppu::ppu_listener::PPUListener;
 use crate::ppu::NametableMirroring;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 fn make_listener() -> (PPUListener, std::rc::Rc<std::cell::RefCell<crate::ppu::PPU>>, CoreMemory) {
     let ppu = make_ppu(NametableMirroring::Horizontal);
@@ -120,14 +122,24 @@ fn ppuscroll_second_write_sets_coarse_y_t_and_fine_y_t() {
 
 #[test]
 fn oamdma_copies_page_into_oam() {
-    let (mut listener, ppu, mut memory) = make_listener();
+    let (listener, ppu, mut memory) = make_listener();
+    memory.register_listener(Rc::new(RefCell::new(listener)));
     // Write sprite data to page 2 (0x0200-0x02FF) in CPU memory
     memory.write(0x0200, 0x10); // y
     memory.write(0x0201, 0x05); // tile
     memory.write(0x0202, 0x00); // attrs
     memory.write(0x0203, 0x40); // x
-    // Trigger DMA for page 0x02
-    listener.write(&memory, 0x4014, 0x02);
+
+    // The CPU does the transfer after the instruction that triggers it: LDA #$02; STA $4014
+    memory.write(0x0300, 0xa9);
+    memory.write(0x0301, 0x02);
+    memory.write(0x0302, 0x8d);
+    memory.write(0x0303, 0x14);
+    memory.write(0x0304, 0x40);
+    let mut cpu = CPU::new(Box::new(memory));
+    cpu.program_counter = 0x0300;
+    cpu.transition();
+    cpu.transition();
     let oam = &ppu.borrow().oam;
     assert_eq!(oam[0], 0x10);
     assert_eq!(oam[1], 0x05);
@@ -1,8 +1,13 @@
 use super::mock_mapper::{make_ppu, make_ppu_with_buffer};
 use crate::cpu::tests::test_mapper::TestMapper;
 use crate::cpu::{CoreMemory, CPU};
+use crate::mapper::Mapper;
 use crate::ppu::palette::Palette;
-use crate::ppu::NametableMirroring;
+use crate::ppu::{NametableMirroring, Tile, PPU, WRITE_BUFFER_SIZE};
+use crate::rom::{Rom, RomHeader};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
 // tick counts derived from: scanline * 341 + dot
 const TICKS_TO_VBLANK: usize = 82183; // scanline 241 dot 1 (end_of_screen_render)
@@ -141,3 +146,230 @@ fn tile_palette_color_used_for_nonzero_pixel_intensity() {
     let idx = 16 * 1024; // scanline 16, x=0
     assert_eq!(&buf[idx..idx + 4], Palette::hue_lookup(0x26));
 }
+
+// ── mapper pattern fetches ───────────────────────────────────────────────────
+//
+// MMC2 flips the left pattern table's bank after fetching row 0 of tile 0xfd or
+// 0xfe, so with bank 0 transparent and bank 1 solid, a row of tiles reading
+// "0 .. 0xfd .. 0xfe .. 0" is solid up to and including the 0xfd tile, clear up
+// to and including the 0xfe tile, then solid again. Only rows with fine_y=0
+// fetch row 0, so only those flip.
+
+#[test]
+fn mmc2_latches_flip_banks_partway_through_a_scanline() {
+    let rom = Rom {
+        header: RomHeader {
+            mapper: 9,
+            ..RomHeader::default()
+        },
+        prg_data: vec![0; 1 << 17],
+        chr_data: [vec![0; 0x1000], vec![0xff; 0x1000]].concat(),
+        _trainer: vec![],
+    };
+    let mapper: Rc<RefCell<Box<dyn Mapper>>> =
+        Rc::new(RefCell::new(rom.initialize_mapper().unwrap()));
+    mapper.borrow_mut().write_prg(0xb000, 0); // 0xfd: transparent
+    mapper.borrow_mut().write_prg(0xc000, 1); // 0xfe: solid
+    let write_buffer = Arc::new(Mutex::new([0u8; WRITE_BUFFER_SIZE]));
+    let ppu_rc = PPU::new(write_buffer.clone(), mapper);
+    let mut cpu = make_test_cpu();
+
+    {
+        let mut ppu = ppu_rc.borrow_mut();
+        for row in 0..30 {
+            ppu.write_vram(0x2000 + row * 32 + 10, 0xfd);
+            ppu.write_vram(0x2000 + row * 32 + 20, 0xfe);
+        }
+        ppu.write_vram(0x3f00, 0x16);
+        ppu.write_vram(0x3f03, 0x26);
+    }
+
+    // Frame 1: rendering disabled, so nothing is fetched for the mapper to see.
+    for _ in 0..TICKS_PER_FRAME {
+        ppu_rc.borrow_mut().tick(&mut cpu);
+    }
+    ppu_rc.borrow_mut().ppu_mask = 0x0A;
+    for _ in 0..TICKS_TO_VBLANK {
+        ppu_rc.borrow_mut().tick(&mut cpu);
+    }
+
+    let buf = write_buffer.lock().unwrap();
+    let pixel = |scanline: usize, x: usize| &buf[scanline * 1024 + x * 4..][..4];
+    for x in [0, 80, 87, 168, 255] {
+        assert_eq!(pixel(16, x), Palette::hue_lookup(0x26), "x={x}");
+    }
+    for x in [88, 120, 167] {
+        assert_eq!(pixel(16, x), Palette::hue_lookup(0x16), "x={x}");
+    }
+    // no flips on a row with fine_y=3; the latch is left on 0xfe from the row before
+    for x in [0, 88, 120, 167, 255] {
+        assert_eq!(pixel(19, x), Palette::hue_lookup(0x26), "x={x}");
+    }
+}
+
+// Sprite patterns for a line are fetched over dots 257-320 of the line before, a slot
+// every 8 dots, and the empty slots fetch tile 0xff. With 8x16 sprites that's the top
+// half, tile 0xfe, on some rows, so the right-hand latch flips to 0xfd for the one
+// sprite in slot 0 and straight back for slot 1.
+
+#[test]
+fn mmc2_sees_sprite_fetches_slot_by_slot_during_the_line_before() {
+    let rom = Rom {
+        header: RomHeader {
+            mapper: 9,
+            ..RomHeader::default()
+        },
+        prg_data: vec![0; 1 << 17],
+        chr_data: (0..4).flat_map(|bank| vec![bank; 0x1000]).collect(),
+        _trainer: vec![],
+    };
+    let mapper: Rc<RefCell<Box<dyn Mapper>>> =
+        Rc::new(RefCell::new(rom.initialize_mapper().unwrap()));
+    mapper.borrow_mut().write_prg(0xd000, 1); // right 0xfd: bank 1
+    mapper.borrow_mut().write_prg(0xe000, 2); // right 0xfe: bank 2
+    let write_buffer = Arc::new(Mutex::new([0u8; WRITE_BUFFER_SIZE]));
+    let ppu_rc = PPU::new(write_buffer, mapper.clone());
+    let mut cpu = make_test_cpu();
+
+    {
+        let mut ppu = ppu_rc.borrow_mut();
+        ppu.tall_sprites = true;
+        for sprite in 1..64 {
+            ppu.oam[sprite * 4] = 0xf0; // below the screen
+        }
+        // sprite 0 is tiles 0xfc and 0xfd from 0x1000, its bottom half on line 56
+        ppu.oam[..4].copy_from_slice(&[47, 0xfd, 0, 0]);
+    }
+    for _ in 0..TICKS_PER_FRAME {
+        ppu_rc.borrow_mut().tick(&mut cpu);
+    }
+    ppu_rc.borrow_mut().ppu_mask = 0x18;
+
+    let right_bank_after = |scanline: u16, dot: u16, cpu: &mut CPU| {
+        while ppu_rc.borrow().position() != (scanline, dot + 1) {
+            ppu_rc.borrow_mut().tick(cpu);
+        }
+        mapper.borrow().read_chr(0x1000)
+    };
+    assert_eq!(right_bank_after(55, 259, &mut cpu), 2);
+    assert_eq!(right_bank_after(55, 260, &mut cpu), 1);
+    assert_eq!(right_bank_after(55, 267, &mut cpu), 1);
+    assert_eq!(right_bank_after(55, 268, &mut cpu), 2);
+}
+
+// A background tile that wasn't fetched ahead of time, e.g. because rendering was
+// turned on partway through a line, is read when it's drawn; the mapper sees that
+// read like any other, so row 0 of tile 0xfd still flips MMC2's left latch.
+
+#[test]
+fn drawing_an_unfetched_tile_goes_through_the_mapper() {
+    let rom = Rom {
+        header: RomHeader {
+            mapper: 9,
+            ..RomHeader::default()
+        },
+        prg_data: vec![0; 1 << 17],
+        chr_data: (0..4).flat_map(|bank| vec![bank; 0x1000]).collect(),
+        _trainer: vec![],
+    };
+    let mapper: Rc<RefCell<Box<dyn Mapper>>> =
+        Rc::new(RefCell::new(rom.initialize_mapper().unwrap()));
+    mapper.borrow_mut().write_prg(0xb000, 1); // left 0xfd: bank 1
+    mapper.borrow_mut().write_prg(0xc000, 2); // left 0xfe: bank 2
+
+    let mut tile = Tile::new(0x0fd0, mapper.clone());
+    assert_eq!(mapper.borrow().read_chr(0), 2);
+    tile.pixel_intensity(0, 0);
+    assert_eq!(mapper.borrow().read_chr(0), 1);
+}
+
+// MMC3 counts lines by watching PPU A12 rise as the PPU fetches patterns. With the
+// background at 0x0000 and sprites at 0x1000 that's the first sprite fetch on each
+// line, so the first line reloads the counter and the IRQ fires latch lines later.
+
+#[test]
+fn mmc3_counts_lines_from_the_sprite_fetches() {
+    let rom = Rom {
+        header: RomHeader {
+            mapper: 4,
+            ..RomHeader::default()
+        },
+        prg_data: vec![0; 1 << 15],
+        chr_data: vec![0; 0x2000],
+        _trainer: vec![],
+    };
+    let mapper: Rc<RefCell<Box<dyn Mapper>>> =
+        Rc::new(RefCell::new(rom.initialize_mapper().unwrap()));
+    mapper.borrow_mut().write_prg(0xc000, 9); // latch
+    mapper.borrow_mut().write_prg(0xc001, 0); // reload
+    mapper.borrow_mut().write_prg(0xe001, 0); // enable
+    let write_buffer = Arc::new(Mutex::new([0u8; WRITE_BUFFER_SIZE]));
+    let ppu_rc = PPU::new(write_buffer, mapper.clone());
+    let mut cpu = make_test_cpu();
+
+    ppu_rc.borrow_mut().ppu_ctrl = 0x08; // sprites at 0x1000
+    for _ in 0..TICKS_PER_FRAME {
+        ppu_rc.borrow_mut().tick(&mut cpu);
+    }
+    ppu_rc.borrow_mut().ppu_mask = 0x18;
+
+    let irq_after = |scanline: u16, dot: u16, cpu: &mut CPU| {
+        while ppu_rc.borrow().position() <= (scanline, dot) {
+            for _ in 0..3 {
+                ppu_rc.borrow_mut().tick(cpu);
+            }
+            mapper.borrow_mut().cpu_cycle();
+        }
+        mapper.borrow().irq_pending()
+    };
+    assert!(!irq_after(9, 240, &mut cpu));
+    assert!(irq_after(9, 262, &mut cpu));
+}
+
+// MMC5's split screen draws the left 8 columns from ExRAM, through a CHR bank of
+// its own, whatever the nametable and pattern tables hold. ExRAM is all zeroes,
+// so that's tile 0 with palette 0 from the split's bank, which is solid.
+
+#[test]
+fn mmc5_split_replaces_the_left_columns() {
+    let rom = Rom {
+        header: RomHeader {
+            mapper: 5,
+            ..RomHeader::default()
+        },
+        prg_data: vec![0; 1 << 15],
+        chr_data: [vec![0; 0x1000], vec![0xff; 0x1000]].concat(),
+        _trainer: vec![],
+    };
+    let mapper: Rc<RefCell<Box<dyn Mapper>>> =
+        Rc::new(RefCell::new(rom.initialize_mapper().unwrap()));
+    mapper.borrow_mut().write_prg(0x5200, 0x80 | 8); // left side, 8 tiles
+    mapper.borrow_mut().write_prg(0x5202, 1); // the solid 4kb bank
+    let write_buffer = Arc::new(Mutex::new([0u8; WRITE_BUFFER_SIZE]));
+    let ppu_rc = PPU::new(write_buffer.clone(), mapper);
+    let mut cpu = make_test_cpu();
+
+    {
+        let mut ppu = ppu_rc.borrow_mut();
+        ppu.write_vram(0x3f00, 0x16);
+        ppu.write_vram(0x3f03, 0x26);
+    }
+    for _ in 0..TICKS_PER_FRAME {
+        ppu_rc.borrow_mut().tick(&mut cpu);
+    }
+    ppu_rc.borrow_mut().ppu_mask = 0x0A;
+    for _ in 0..TICKS_TO_VBLANK {
+        ppu_rc.borrow_mut().tick(&mut cpu);
+    }
+
+    let buf = write_buffer.lock().unwrap();
+    let pixel = |scanline: usize, x: usize| &buf[scanline * 1024 + x * 4..][..4];
+    for scanline in [16, 100] {
+        for x in [0, 32, 63] {
+            assert_eq!(pixel(scanline, x), Palette::hue_lookup(0x26), "x={x}");
+        }
+        for x in [64, 128, 255] {
+            assert_eq!(pixel(scanline, x), Palette::hue_lookup(0x16), "x={x}");
+        }
+    }
+}
@@ -1,4 +1,6 @@
-use super::mock_mapper::make_ppu;
+use super::mock_mapper::{make_ppu, make_ppu_with_mapper, MockMapper};
+use crate::mapper::NametableSource;
+use crate::ppu::ppu::VramAddress;
 use crate::ppu::NametableMirroring;
 
 #[test]
@@ -49,14 +51,65 @@ fn single_nametable_1_all_map_to_nametable_1() {
     assert_eq!(ppu.vram_address_mirror(0x2400), base);
     assert_eq!(ppu.vram_address_mirror(0x2800), base);
     assert_eq!(ppu.vram_address_mirror(0x2C00), base);
-    assert_eq!(base, 0x2400);
+    assert_eq!(base, VramAddress::Ciram(0x400));
+}
+
+#[test]
+fn four_screen_nametables_are_all_on_the_cartridge() {
+    let ppu = make_ppu(NametableMirroring::FourScreen);
+    let ppu = ppu.borrow();
+    for address in [0x2000, 0x2400, 0x2800, 0x2c00, 0x2fff] {
+        assert_eq!(
+            ppu.vram_address_mirror(address),
+            VramAddress::Cartridge(address as u16)
+        );
+    }
+    assert_eq!(
+        ppu.vram_address_mirror(0x3c00),
+        VramAddress::Cartridge(0x2c00)
+    );
+}
+
+#[test]
+fn four_screen_nametables_are_distinct() {
+    let ppu = make_ppu(NametableMirroring::FourScreen);
+    let mut ppu = ppu.borrow_mut();
+    for (i, address) in [0x2000, 0x2400, 0x2800, 0x2c00].into_iter().enumerate() {
+        ppu.write_vram(address + 5, i as u8 + 1);
+    }
+    for (i, address) in [0x2000, 0x2400, 0x2800, 0x2c00].into_iter().enumerate() {
+        assert_eq!(ppu.read_vram(address + 5), i as u8 + 1);
+    }
+    /* the console's own RAM is untouched */
+    assert!(ppu.vram.iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn mapper_can_place_each_nametable() {
+    let mut mapper = MockMapper::new(NametableMirroring::Horizontal);
+    mapper.nametable_sources = Some([
+        NametableSource::Ciram(1),
+        NametableSource::Cartridge,
+        NametableSource::Ciram(0),
+        NametableSource::Ciram(1),
+    ]);
+    let ppu = make_ppu_with_mapper(mapper);
+    let mut ppu = ppu.borrow_mut();
+
+    ppu.write_vram(0x2010, 0x11);
+    ppu.write_vram(0x2410, 0x22);
+    ppu.write_vram(0x2810, 0x33);
+    assert_eq!(ppu.read_vram(0x2c10), 0x11);
+    assert_eq!(ppu.read_vram(0x2410), 0x22);
+    assert_eq!(ppu.vram[0x010], 0x33);
+    assert_eq!(ppu.vram[0x410], 0x11);
 }
 
 #[test]
 fn palette_address_3f10_mirrors_to_3f00() {
     let ppu = make_ppu(NametableMirroring::Horizontal);
     let ppu = ppu.borrow();
-    assert_eq!(ppu.vram_address_mirror(0x3f10), 0x3f00);
+    assert_eq!(ppu.vram_address_mirror(0x3f10), VramAddress::Palette(0));
 }
 
 #[test]
@@ -79,7 +132,7 @@ fn nametable_range_3000_3eff_mirrors_2000_2eff() {
 fn chr_addresses_below_0x2000_pass_through_unchanged() {
     let ppu = make_ppu(NametableMirroring::Horizontal);
     let ppu = ppu.borrow();
-    assert_eq!(ppu.vram_address_mirror(0x0000), 0x0000);
-    assert_eq!(ppu.vram_address_mirror(0x1000), 0x1000);
-    assert_eq!(ppu.vram_address_mirror(0x1fff), 0x1fff);
+    assert_eq!(ppu.vram_address_mirror(0x0000), VramAddress::Chr(0x0000));
+    assert_eq!(ppu.vram_address_mirror(0x1000), VramAddress::Chr(0x1000));
+    assert_eq!(ppu.vram_address_mirror(0x1fff), VramAddress::Chr(0x1fff));
 }
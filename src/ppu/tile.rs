@@ -1,6 +1,8 @@
-use crate::mapper::Mapper;
+use crate::mapper::{Mapper, PPUFetch};
+use crate::savestate::{Savestate, StateReader, StateWriter};
 use bit_reverse::LookupReverse;
 use std::cell::RefCell;
+use std::io;
 use std::rc::Rc;
 
 pub struct Tile {
@@ -57,22 +59,58 @@ impl Tile {
         ((big & 1) << 1) | (small & 1)
     }
 
+    /* reads row y now, as the PPU's background fetches do, rather than when it's first drawn;
+     * the mapper sees both fetches, and can change what they read
+     */
+    pub fn fetch(&mut self, y: u8) {
+        let y_row = self.row_address(y);
+        let mut mapper = self.mapper.borrow_mut();
+        let small = mapper.read_chr(y_row);
+        let small = mapper.ppu_render_fetch(y_row, PPUFetch::BackgroundPattern, small);
+        let big = mapper.read_chr(y_row + 8);
+        let big = mapper.ppu_render_fetch(y_row + 8, PPUFetch::BackgroundPattern, big);
+        self.cached_y = y;
+        /* memory stores bits in the opposite order of x indexing; reversing them here
+         * to avoid a subtraction later */
+        self.cached_big = LookupReverse::swap_bits(big);
+        self.cached_small = LookupReverse::swap_bits(small);
+    }
+
+    /* double tall sprites are actually two regular 8x8 tiles glued together,
+     * so for the second half we need to increment values by 8 to index it correctly
+     */
+    fn row_address(&self, y: u8) -> u16 {
+        self.tile_addr + (if y > 7 { y + 8 } else { y } as u16)
+    }
+
+    /* a row that wasn't fetched ahead of time (e.g. rendering was turned on mid-line) is
+     * fetched when it's drawn, which the mapper sees all the same
+     */
     fn populate_cache(&mut self, y: u8) {
         if self.cached_y != y {
-            self.cached_y = y;
-            /* double tall sprites are actually two regular 8x8 tiles glued together,
-             * so for the second half we need to increment values by 8 to index it correctly
-             */
-            let y_row = self.tile_addr + (if y > 7 { y + 8 } else { y } as u16);
-            /* memory stores bits in the opposite order of x indexing; reversing them here
-             * to avoid a subtraction later */
-            let mapper = self.mapper.borrow();
-            self.cached_big = LookupReverse::swap_bits(mapper.read_chr(y_row + 8));
-            self.cached_small = LookupReverse::swap_bits(mapper.read_chr(y_row));
+            self.fetch(y);
         }
     }
 }
 
+/* the mapper isn't part of the state; the tile keeps whichever one it was created with */
+impl Savestate for Tile {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u16(self.tile_addr);
+        writer.write_u8(self.cached_y);
+        writer.write_u8(self.cached_big);
+        writer.write_u8(self.cached_small);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.tile_addr = reader.read_u16()?;
+        self.cached_y = reader.read_u8()?;
+        self.cached_big = reader.read_u8()?;
+        self.cached_small = reader.read_u8()?;
+        Ok(())
+    }
+}
+
 /* TODO comment */
 #[allow(dead_code)]
 pub fn index_to_pixel(width: usize, index: usize) -> (usize, usize) {
@@ -1,11 +1,11 @@
 //! Linux/BSD renderer: paints the NES framebuffer into a `gtk::DrawingArea`
 //! packed below muda's menubar, so GTK composites menu and frame correctly.
 
-use crate::ppu::{WriteBuffer, DISPLAY_HEIGHT, DISPLAY_WIDTH};
 use crate::renderer::{fit_rect, rgba_to_cairo_rgb24};
 use gtk::cairo::{Context, Filter, Format, ImageSurface};
 use gtk::glib;
 use gtk::prelude::*;
+use patina::{WriteBuffer, DISPLAY_HEIGHT, DISPLAY_WIDTH};
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
@@ -2,7 +2,7 @@
 //! area, so pixels' full-window wgpu surface is fine and gives free GPU
 //! scaling. This file is not compiled on Linux.
 
-use crate::ppu::{WriteBuffer, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use patina::{WriteBuffer, DISPLAY_HEIGHT, DISPLAY_WIDTH};
 use pixels::{Pixels, SurfaceTexture};
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
@@ -0,0 +1,92 @@
+mod rom_header;
+
+#[cfg(test)]
+mod tests;
+
+use crate::mapper::Mapper;
+use crate::ppu::NametableMirroring;
+use rom_header::{HEADER_SIZE, TRAINER_SIZE};
+use std::io::ErrorKind;
+use std::{fs, io};
+
+pub use rom_header::{ConsoleType, HeaderFormat, RomHeader, Timing};
+
+/* CHR-RAM for a cartridge whose header gives neither CHR-ROM nor CHR-RAM */
+const DEFAULT_CHR_RAM_SIZE: usize = 1 << 13; /* 8kb */
+
+pub struct Rom {
+    pub header: RomHeader,
+    pub prg_data: Vec<u8>,
+    pub chr_data: Vec<u8>,
+    pub(crate) _trainer: Vec<u8>,
+}
+
+impl Rom {
+    pub fn parse_file(file_ref: String) -> io::Result<Rom> {
+        let rom_data: Vec<u8> = fs::read(file_ref)?;
+        Rom::read_rom_data(&rom_data)
+    }
+
+    pub(crate) fn nametable_mirroring(&self) -> NametableMirroring {
+        self.header.nametable_mirroring.clone()
+    }
+
+    /* fails with ErrorKind::Unsupported if the ROM's mapper isn't one we emulate */
+    pub(crate) fn initialize_mapper(&self) -> io::Result<Box<dyn Mapper>> {
+        crate::mapper::load_mapper(self.header.mapper, self)
+    }
+
+    /* CHR-ROM if the cartridge has any, otherwise zeroed CHR-RAM of the size the header gives;
+     * a NES 2.0 header can claim no CHR at all, but the mappers need some to bank, so that gets
+     * the usual 8kb of RAM
+     */
+    pub(crate) fn chr_rom_or_ram(&self) -> Vec<u8> {
+        if self.chr_data.is_empty() {
+            match self.header.chr_ram_size + self.header.chr_nvram_size {
+                0 => vec![0; DEFAULT_CHR_RAM_SIZE],
+                size => vec![0; size],
+            }
+        } else {
+            self.chr_data.clone()
+        }
+    }
+
+    /* TODO: Result should probably be std Result, not io Result */
+    fn read_rom_data(rom_data: &[u8]) -> io::Result<Rom> {
+        let header = RomHeader::parse(rom_data)?;
+
+        /* the trainer, if present, sits between the header and PRG-ROM */
+        let trainer_start = HEADER_SIZE;
+        let prg_rom_start = trainer_start + if header.trainer { TRAINER_SIZE } else { 0 };
+        let Some((chr_rom_start, chr_rom_end)) = prg_rom_start
+            .checked_add(header.prg_rom_size)
+            .and_then(|start| Some((start, start.checked_add(header.chr_rom_size)?)))
+        else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "The ROM's header describes more data than can be loaded",
+            ));
+        };
+
+        if rom_data.len() < chr_rom_end {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The ROM's header describes {} bytes of data, but the file is only {} bytes",
+                    chr_rom_end,
+                    rom_data.len()
+                ),
+            ));
+        }
+
+        /* TODO: Would it be better to use Cow here? */
+        let rom = Rom {
+            prg_data: rom_data[prg_rom_start..chr_rom_start].to_vec(),
+            chr_data: rom_data[chr_rom_start..chr_rom_end].to_vec(),
+            _trainer: rom_data[trainer_start..prg_rom_start].to_vec(),
+            header,
+        };
+
+        Ok(rom)
+    }
+}
@@ -0,0 +1,255 @@
+use crate::ppu::NametableMirroring;
+use std::io;
+use std::io::ErrorKind;
+
+pub const HEADER_SIZE: usize = 16;
+pub const TRAINER_SIZE: usize = 512;
+
+const PRG_ROM_UNIT: usize = 1 << 14; /* 16kb */
+const CHR_ROM_UNIT: usize = 1 << 13; /* 8kb */
+const INES_PRG_RAM_UNIT: usize = 1 << 13; /* 8kb */
+
+/**
+ * Which kind of header a ROM file starts with, and so which of its fields can be trusted.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderFormat {
+    /* original iNES header; bytes 8-15 are mostly unreliable */
+    INes,
+    /* iNES header whose bytes 7-15 contain junk (e.g. "DiskDude!"); only byte 6 is trusted */
+    ArchaicINes,
+    Nes2,
+}
+
+/**
+ * The TV system, and so the CPU and PPU clock rates, the game was made for.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    MultiRegion, /* runs on either */
+    Dendy,
+}
+
+/**
+ * The machine the cartridge plugs into: a home NES, or one of the arcade boards built around it.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    Extended(u8), /* NES 2.0 extended console type, from byte 13 */
+}
+
+/**
+ * The parsed contents of the 16 byte header at the start of an iNES or NES 2.0 file. For iNES
+ * files, fields that only NES 2.0 can express (submapper, CHR-RAM size, etc.) are filled in with
+ * the conventional defaults.
+ */
+#[derive(Debug, Clone)]
+pub struct RomHeader {
+    pub format: HeaderFormat,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub prg_ram_size: usize,   /* volatile PRG-RAM */
+    pub prg_nvram_size: usize, /* battery-backed PRG-RAM/EEPROM */
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub(crate) nametable_mirroring: NametableMirroring,
+    pub four_screen: bool,
+    pub battery: bool,
+    pub trainer: bool,
+    pub timing: Timing,
+    pub console_type: ConsoleType,
+}
+
+impl Default for RomHeader {
+    /* an iNES NROM header: the bare minimum to describe a cartridge */
+    fn default() -> Self {
+        RomHeader {
+            format: HeaderFormat::INes,
+            mapper: 0,
+            submapper: 0,
+            prg_rom_size: 0,
+            chr_rom_size: 0,
+            prg_ram_size: 0,
+            prg_nvram_size: 0,
+            chr_ram_size: 0,
+            chr_nvram_size: 0,
+            nametable_mirroring: NametableMirroring::Vertical,
+            four_screen: false,
+            battery: false,
+            trainer: false,
+            timing: Timing::Ntsc,
+            console_type: ConsoleType::Nes,
+        }
+    }
+}
+
+impl RomHeader {
+    pub fn parse(header: &[u8]) -> io::Result<RomHeader> {
+        if header.len() < HEADER_SIZE {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "The ROM must be at least 16 bytes long.",
+            ));
+        }
+
+        if &header[0..4] != b"NES\x1A" {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The ROM's header must meet the NES ROM specification; however, it was: {:?}",
+                    &header[0..4]
+                ),
+            ));
+        }
+
+        let format = if header[7] & 0x0c == 0x08 {
+            HeaderFormat::Nes2
+        } else if header[7] & 0x0c == 0 && header[12..16].iter().all(|b| *b == 0) {
+            HeaderFormat::INes
+        } else {
+            HeaderFormat::ArchaicINes
+        };
+
+        match format {
+            HeaderFormat::Nes2 => Self::parse_nes2(header),
+            _ => Ok(Self::parse_ines(header, format)),
+        }
+    }
+
+    /* total PRG-RAM, battery-backed or not, that appears at 0x6000-0x7fff */
+    pub fn work_ram_size(&self) -> usize {
+        self.prg_ram_size + self.prg_nvram_size
+    }
+
+    /* byte 6 is laid out the same way in both formats */
+    fn parse_byte_6(&mut self, byte_6: u8) {
+        /* NB: this crate names mirroring by the direction pages repeat; see NametableMirroring */
+        self.nametable_mirroring = if byte_6 & 1 != 0 {
+            NametableMirroring::Horizontal
+        } else {
+            NametableMirroring::Vertical
+        };
+        self.battery = byte_6 & 0x2 != 0;
+        self.trainer = byte_6 & 0x4 != 0;
+        self.four_screen = byte_6 & 0x8 != 0;
+        self.mapper = (byte_6 >> 4) as u16;
+    }
+
+    fn parse_ines(header: &[u8], format: HeaderFormat) -> RomHeader {
+        let mut result = RomHeader {
+            format,
+            ..RomHeader::default()
+        };
+        result.parse_byte_6(header[6]);
+
+        result.prg_rom_size = header[4] as usize * PRG_ROM_UNIT;
+        result.chr_rom_size = header[5] as usize * CHR_ROM_UNIT;
+        /* iNES has no way to describe CHR-RAM; boards without CHR-ROM conventionally have 8kb */
+        if result.chr_rom_size == 0 {
+            result.chr_ram_size = CHR_ROM_UNIT;
+        }
+
+        /* the rest of the header is only trustworthy if it's been zero-padded */
+        if format == HeaderFormat::INes {
+            result.mapper |= (header[7] & 0xf0) as u16;
+            result.console_type = Self::console_type(header[7] & 0x3, 0);
+            result.timing = if header[9] & 1 != 0 {
+                Timing::Pal
+            } else {
+                Timing::Ntsc
+            };
+        }
+
+        /* byte 8 is PRG-RAM size in 8kb units, where 0 means 8kb for compatibility; which
+         * half it lands in depends on the battery flag
+         */
+        let prg_ram_units = if format == HeaderFormat::INes {
+            header[8].max(1) as usize
+        } else {
+            1
+        };
+        if result.battery {
+            result.prg_nvram_size = prg_ram_units * INES_PRG_RAM_UNIT;
+        } else {
+            result.prg_ram_size = prg_ram_units * INES_PRG_RAM_UNIT;
+        }
+
+        result
+    }
+
+    fn parse_nes2(header: &[u8]) -> io::Result<RomHeader> {
+        let mut result = RomHeader {
+            format: HeaderFormat::Nes2,
+            ..RomHeader::default()
+        };
+        result.parse_byte_6(header[6]);
+
+        result.mapper |= (header[7] & 0xf0) as u16 | (((header[8] & 0x0f) as u16) << 8);
+        result.submapper = header[8] >> 4;
+        result.console_type = Self::console_type(header[7] & 0x3, header[13] & 0x0f);
+
+        result.prg_rom_size = Self::rom_size(header[4], header[9] & 0x0f, PRG_ROM_UNIT)?;
+        result.chr_rom_size = Self::rom_size(header[5], header[9] >> 4, CHR_ROM_UNIT)?;
+
+        result.prg_ram_size = Self::ram_size(header[10] & 0x0f);
+        result.prg_nvram_size = Self::ram_size(header[10] >> 4);
+        result.chr_ram_size = Self::ram_size(header[11] & 0x0f);
+        result.chr_nvram_size = Self::ram_size(header[11] >> 4);
+
+        result.timing = match header[12] & 0x3 {
+            0 => Timing::Ntsc,
+            1 => Timing::Pal,
+            2 => Timing::MultiRegion,
+            _ => Timing::Dendy,
+        };
+
+        Ok(result)
+    }
+
+    /* ROM sizes are a 12 bit count of units, unless the MSB nybble is 0xf, in which case the
+     * LSB byte is in exponent-multiplier form: EEEEEEMM, giving 2^E * (MM * 2 + 1) bytes,
+     * which can be far more than fits in memory
+     */
+    fn rom_size(lsb: u8, msb_nybble: u8, unit: usize) -> io::Result<usize> {
+        let size = if msb_nybble == 0xf {
+            let exponent = (lsb >> 2) as u32;
+            let multiplier = (lsb & 0x3) as usize * 2 + 1;
+            1usize
+                .checked_shl(exponent)
+                .and_then(|power| power.checked_mul(multiplier))
+        } else {
+            Some((((msb_nybble as usize) << 8) | lsb as usize) * unit)
+        };
+        size.ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                "The ROM's header describes more data than can be loaded",
+            )
+        })
+    }
+
+    /* RAM sizes are stored as shift counts: 64 << shift bytes, with 0 meaning none at all */
+    fn ram_size(shift: u8) -> usize {
+        if shift == 0 {
+            0
+        } else {
+            64 << shift
+        }
+    }
+
+    fn console_type(console_bits: u8, extended_type: u8) -> ConsoleType {
+        match console_bits {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(extended_type),
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::ppu::NametableMirroring;
+use crate::rom::rom_header::{ConsoleType, HeaderFormat, Timing, HEADER_SIZE, TRAINER_SIZE};
+use crate::rom::{Rom, RomHeader};
+use std::io::ErrorKind;
+
+fn header_bytes(bytes_4_to_15: [u8; 12]) -> Vec<u8> {
+    let mut header = b"NES\x1A".to_vec();
+    header.extend_from_slice(&bytes_4_to_15);
+    header
+}
+
+#[test]
+fn parses_ines_header() {
+    /* 2x16kb PRG, 1x8kb CHR, mapper 0x14, horizontal arrangement, battery, PAL */
+    let header =
+        RomHeader::parse(&header_bytes([2, 1, 0x43, 0x10, 0, 1, 0, 0, 0, 0, 0, 0])).unwrap();
+
+    assert_eq!(header.format, HeaderFormat::INes);
+    assert_eq!(header.mapper, 0x14);
+    assert_eq!(header.prg_rom_size, 2 << 14);
+    assert_eq!(header.chr_rom_size, 1 << 13);
+    assert!(matches!(
+        header.nametable_mirroring,
+        NametableMirroring::Horizontal
+    ));
+    assert!(header.battery);
+    assert!(!header.trainer);
+    assert_eq!(header.timing, Timing::Pal);
+    /* a PRG-RAM size of 0 means 8kb, and it's battery backed */
+    assert_eq!(header.prg_ram_size, 0);
+    assert_eq!(header.prg_nvram_size, 1 << 13);
+    assert_eq!(header.chr_ram_size, 0);
+}
+
+#[test]
+fn ines_header_without_chr_rom_has_chr_ram() {
+    let header = RomHeader::parse(&header_bytes([1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+
+    assert_eq!(header.chr_rom_size, 0);
+    assert_eq!(header.chr_ram_size, 1 << 13);
+    assert_eq!(header.prg_ram_size, 2 << 13);
+    assert_eq!(header.work_ram_size(), 2 << 13);
+    assert!(matches!(
+        header.nametable_mirroring,
+        NametableMirroring::Vertical
+    ));
+}
+
+#[test]
+fn archaic_ines_header_ignores_junk_bytes() {
+    /* "DiskDude!" overwriting bytes 7-15 */
+    let mut bytes = header_bytes([1, 1, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    bytes[7..16].copy_from_slice(b"DiskDude!");
+    let header = RomHeader::parse(&bytes).unwrap();
+
+    assert_eq!(header.format, HeaderFormat::ArchaicINes);
+    assert_eq!(header.mapper, 1);
+    assert_eq!(header.console_type, ConsoleType::Nes);
+    assert_eq!(header.timing, Timing::Ntsc);
+    assert_eq!(header.prg_ram_size, 1 << 13);
+}
+
+#[test]
+fn parses_nes2_mapper_and_submapper() {
+    let header =
+        RomHeader::parse(&header_bytes([2, 0, 0x50, 0x48, 0x31, 0, 0, 0, 0, 0, 0, 0])).unwrap();
+
+    assert_eq!(header.format, HeaderFormat::Nes2);
+    assert_eq!(header.mapper, 0x145);
+    assert_eq!(header.submapper, 3);
+}
+
+#[test]
+fn parses_nes2_rom_sizes() {
+    /* PRG uses the MSB nybble; CHR uses exponent-multiplier form: 2^4 * (1 * 2 + 1) */
+    let header = RomHeader::parse(&header_bytes([
+        0x02, 0x11, 0, 0x08, 0, 0xf1, 0, 0, 0, 0, 0, 0,
+    ]))
+    .unwrap();
+
+    assert_eq!(header.prg_rom_size, 0x102 << 14);
+    assert_eq!(header.chr_rom_size, 16 * 3);
+}
+
+#[test]
+fn parses_nes2_ram_sizes() {
+    let header = RomHeader::parse(&header_bytes([
+        1, 0, 0x02, 0x08, 0, 0, 0x97, 0x07, 0, 0, 0, 0,
+    ]))
+    .unwrap();
+
+    assert_eq!(header.prg_ram_size, 64 << 7);
+    assert_eq!(header.prg_nvram_size, 64 << 9);
+    assert_eq!(header.work_ram_size(), (64 << 7) + (64 << 9));
+    assert_eq!(header.chr_ram_size, 64 << 7);
+    assert_eq!(header.chr_nvram_size, 0);
+    assert!(header.battery);
+}
+
+#[test]
+fn parses_nes2_timing_and_console_type() {
+    let header =
+        RomHeader::parse(&header_bytes([1, 1, 0, 0x0b, 0, 0, 0, 0, 3, 0x04, 0, 0])).unwrap();
+
+    assert_eq!(header.timing, Timing::Dendy);
+    assert_eq!(header.console_type, ConsoleType::Extended(4));
+
+    let header = RomHeader::parse(&header_bytes([1, 1, 0, 0x09, 0, 0, 0, 0, 2, 0, 0, 0])).unwrap();
+    assert_eq!(header.timing, Timing::MultiRegion);
+    assert_eq!(header.console_type, ConsoleType::VsSystem);
+}
+
+#[test]
+fn rom_data_skips_trainer() {
+    let mut data = header_bytes([1, 1, 0x04, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    data.extend(vec![0xaa; TRAINER_SIZE]);
+    data.extend(vec![0xbb; 1 << 14]);
+    data.extend(vec![0xcc; 1 << 13]);
+
+    let rom = Rom::read_rom_data(&data).unwrap();
+    assert!(rom.header.trainer);
+    assert_eq!(rom._trainer, vec![0xaa; TRAINER_SIZE]);
+    assert_eq!(rom.prg_data, vec![0xbb; 1 << 14]);
+    assert_eq!(rom.chr_data, vec![0xcc; 1 << 13]);
+}
+
+#[test]
+fn rejects_bad_magic() {
+    let mut data = header_bytes([1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    data[3] = 0;
+    let error = RomHeader::parse(&data).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn rejects_short_header() {
+    let error = RomHeader::parse(&[0; HEADER_SIZE - 1]).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn rejects_truncated_file() {
+    let mut data = header_bytes([2, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    data.extend(vec![0; 1 << 14]);
+    let error = Rom::read_rom_data(&data).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn rejects_rom_sizes_too_big_to_load() {
+    /* exponent 63, multiplier 3: 2^63 * 3 doesn't fit in a usize */
+    let data = header_bytes([0xfd, 0, 0, 0x08, 0, 0x0f, 0, 0, 0, 0, 0, 0]);
+    let error = RomHeader::parse(&data).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+
+    /* 2^63 bytes of PRG and of CHR each fit, but not together */
+    let data = header_bytes([0xfc, 0xfc, 0, 0x08, 0, 0xff, 0, 0, 0, 0, 0, 0]);
+    let error = Rom::read_rom_data(&data).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
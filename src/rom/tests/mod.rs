@@ -0,0 +1 @@
+mod header_tests;
@@ -0,0 +1,187 @@
+use std::io;
+use std::io::ErrorKind;
+
+#[cfg(test)]
+mod tests;
+
+/* "PATINAST" */
+const MAGIC: &[u8; 8] = b"PATINAST";
+/* bump whenever the layout of any component's state changes; old states are rejected */
+pub const SAVESTATE_VERSION: u32 = 12;
+
+/**
+ * A component of the machine whose state can be captured and later restored. Implementations
+ * write their fields in a fixed order and must read them back in exactly the same order; only
+ * state that changes while running belongs here, not configuration that comes from the ROM.
+ */
+pub trait Savestate {
+    fn save_state(&self, writer: &mut StateWriter);
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()>;
+}
+
+/**
+ * Serializes state as a flat, little-endian byte stream.
+ */
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        StateWriter::new()
+    }
+}
+
+impl StateWriter {
+    /* starts a new state, beginning with the magic number and version */
+    pub fn new() -> StateWriter {
+        let mut writer = StateWriter { data: Vec::new() };
+        writer.data.extend_from_slice(MAGIC);
+        writer.write_u32(SAVESTATE_VERSION);
+        writer
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_u8(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_option<T: Savestate>(&mut self, value: &Option<T>) {
+        self.write_bool(value.is_some());
+        if let Some(value) = value {
+            value.save_state(self);
+        }
+    }
+
+    /* length-prefixed, so variable-sized data can be read back without knowing its size */
+    pub fn write_bytes(&mut self, value: &[u8]) {
+        self.write_u32(value.len() as u32);
+        self.data.extend_from_slice(value);
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/**
+ * Reads back a byte stream produced by StateWriter. Every read fails with InvalidData rather
+ * than panicking if the data is truncated or malformed.
+ */
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    /* checks the magic number and version before anything else is read */
+    pub fn new(data: &'a [u8]) -> io::Result<StateReader<'a>> {
+        let mut reader = StateReader { data, position: 0 };
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(invalid_data("Not a save state"));
+        }
+        let version = reader.read_u32()?;
+        if version != SAVESTATE_VERSION {
+            return Err(invalid_data(&format!(
+                "Save state version {} is not supported (expected {})",
+                version, SAVESTATE_VERSION
+            )));
+        }
+
+        Ok(reader)
+    }
+
+    pub fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> io::Result<bool> {
+        match self.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(invalid_data(&format!("Invalid boolean {}", value))),
+        }
+    }
+
+    pub fn read_u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn read_u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn read_u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    /* make_default builds the value to load into if there's one in the state */
+    pub fn read_option<T: Savestate>(
+        &mut self,
+        dest: &mut Option<T>,
+        make_default: impl FnOnce() -> T,
+    ) -> io::Result<()> {
+        *dest = if self.read_bool()? {
+            let mut value = make_default();
+            value.load_state(self)?;
+            Some(value)
+        } else {
+            None
+        };
+        Ok(())
+    }
+
+    pub fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.read_u32()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /* reads length-prefixed bytes into a buffer that must already be exactly the right size */
+    pub fn read_bytes_into(&mut self, dest: &mut [u8]) -> io::Result<()> {
+        let len = self.read_u32()? as usize;
+        if len != dest.len() {
+            return Err(invalid_data(&format!(
+                "Expected {} bytes of state, found {}",
+                dest.len(),
+                len
+            )));
+        }
+        dest.copy_from_slice(self.take(len)?);
+        Ok(())
+    }
+
+    /* true once all of the data has been consumed */
+    pub fn is_finished(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() - self.position < len {
+            return Err(invalid_data("Save state is truncated"));
+        }
+        let result = &self.data[self.position..self.position + len];
+        self.position += len;
+        Ok(result)
+    }
+}
+
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
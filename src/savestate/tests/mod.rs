@@ -0,0 +1 @@
+mod state_stream_tests;
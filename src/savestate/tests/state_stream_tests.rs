@@ -0,0 +1,107 @@
+use crate::savestate::{Savestate, StateReader, StateWriter, SAVESTATE_VERSION};
+use std::io;
+use std::io::ErrorKind;
+
+#[derive(Debug, PartialEq)]
+struct Pair(u8, u16);
+
+impl Savestate for Pair {
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.0);
+        writer.write_u16(self.1);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> io::Result<()> {
+        self.0 = reader.read_u8()?;
+        self.1 = reader.read_u16()?;
+        Ok(())
+    }
+}
+
+#[test]
+fn values_round_trip() {
+    let mut writer = StateWriter::new();
+    writer.write_u8(0x12);
+    writer.write_bool(true);
+    writer.write_u16(0x3456);
+    writer.write_u32(0x789a_bcde);
+    writer.write_u64(0x0123_4567_89ab_cdef);
+    writer.write_bytes(&[1, 2, 3]);
+    writer.write_bytes(&[4, 5]);
+    writer.write_option(&Some(Pair(6, 0x0708)));
+    writer.write_option::<Pair>(&None);
+    let data = writer.into_bytes();
+
+    let mut reader = StateReader::new(&data).unwrap();
+    assert_eq!(reader.read_u8().unwrap(), 0x12);
+    assert!(reader.read_bool().unwrap());
+    assert_eq!(reader.read_u16().unwrap(), 0x3456);
+    assert_eq!(reader.read_u32().unwrap(), 0x789a_bcde);
+    assert_eq!(reader.read_u64().unwrap(), 0x0123_4567_89ab_cdef);
+    assert_eq!(reader.read_bytes().unwrap(), vec![1, 2, 3]);
+    let mut dest = [0; 2];
+    reader.read_bytes_into(&mut dest).unwrap();
+    assert_eq!(dest, [4, 5]);
+
+    let mut pair = None;
+    reader.read_option(&mut pair, || Pair(0, 0)).unwrap();
+    assert_eq!(pair, Some(Pair(6, 0x0708)));
+    reader.read_option(&mut pair, || Pair(0, 0)).unwrap();
+    assert_eq!(pair, None);
+
+    assert!(reader.is_finished());
+}
+
+#[test]
+fn rejects_data_without_magic() {
+    let error = StateReader::new(b"NES\x1a not a state").err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut data = StateWriter::new().into_bytes();
+    data[8..12].copy_from_slice(&(SAVESTATE_VERSION + 1).to_le_bytes());
+    let error = StateReader::new(&data).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn truncated_reads_fail() {
+    let mut writer = StateWriter::new();
+    writer.write_u8(1);
+    let data = writer.into_bytes();
+
+    let mut reader = StateReader::new(&data).unwrap();
+    assert_eq!(
+        reader.read_u16().unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn mismatched_lengths_fail() {
+    let mut writer = StateWriter::new();
+    writer.write_bytes(&[1, 2, 3]);
+    let data = writer.into_bytes();
+
+    let mut reader = StateReader::new(&data).unwrap();
+    let mut dest = [0; 4];
+    assert_eq!(
+        reader.read_bytes_into(&mut dest).unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn invalid_booleans_fail() {
+    let mut writer = StateWriter::new();
+    writer.write_u8(2);
+    let data = writer.into_bytes();
+
+    let mut reader = StateReader::new(&data).unwrap();
+    assert_eq!(
+        reader.read_bool().unwrap_err().kind(),
+        ErrorKind::InvalidData
+    );
+}
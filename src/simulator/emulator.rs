@@ -0,0 +1,306 @@
+use crate::apu::{AudioRecorder, APU, SAMPLE_RATE};
+use crate::cpu::{ButtonSource, CoreMemory, CycleListener, Tracer, UnstableOpcodePolicy, CPU};
+use crate::mapper::Mapper;
+use crate::ppu::ppu_listener::PPUListener;
+use crate::ppu::{WriteBuffer, PPU, WRITE_BUFFER_SIZE};
+use crate::rom::Rom;
+use crate::savestate::{invalid_data, Savestate, StateReader, StateWriter};
+use crate::simulator::Speed;
+use std::cell::RefCell;
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+/* master clock ticks per CPU cycle, for NTSC */
+const CPU_CYCLE_CLOCKS: u64 = 12;
+
+/* a finished frame: 256x240 pixels, row by row from the top left, 4 bytes (RGBA) each */
+pub type FrameBuffer = WriteBuffer;
+
+/* the buttons held on each controller, one bit per button */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Input {
+    pub buttons: [u8; 2],
+}
+
+impl Input {
+    pub const A: u8 = 0x01;
+    pub const B: u8 = 0x02;
+    pub const SELECT: u8 = 0x04;
+    pub const START: u8 = 0x08;
+    pub const UP: u8 = 0x10;
+    pub const DOWN: u8 = 0x20;
+    pub const LEFT: u8 = 0x40;
+    pub const RIGHT: u8 = 0x80;
+}
+
+/**
+ * Runs the PPU, APU, and mapper in lockstep with the CPU: three PPU dots per CPU cycle, and a
+ * cycle of the APU (whose channels run at half that rate) and of the mapper.
+ */
+struct Peripherals {
+    ppu: Rc<RefCell<PPU>>,
+    apu: Rc<RefCell<APU>>,
+    mapper: Rc<RefCell<Box<dyn Mapper>>>,
+}
+
+impl CycleListener for Peripherals {
+    fn cycle(&mut self, cpu: &mut CPU) {
+        let mut ppu = self.ppu.borrow_mut();
+        for _ in 0..3 {
+            ppu.tick(cpu);
+        }
+        self.mapper.borrow_mut().cpu_cycle();
+        self.apu.borrow_mut().tick(cpu);
+    }
+}
+
+/**
+ * The whole machine, run synchronously by whoever owns it. The CPU runs an instruction at a
+ * time, and since the PPU and APU advance with each of its bus accesses, the machine is always
+ * in step. Nothing here looks at the wall clock, so a given ROM and sequence of inputs always
+ * produces the same frames and sound; the windowed frontend's Scheduler wraps one of these to
+ * run it in real time.
+ */
+pub struct Emulator {
+    cpu: Box<CPU>,
+    ppu: Rc<RefCell<PPU>>,
+    apu: Rc<RefCell<APU>>,
+    write_buffer: Arc<Mutex<WriteBuffer>>,
+    frame: Box<FrameBuffer>, /* the last frame run_frame returned */
+    power_on_state: Vec<u8>,
+
+    clocks: u64, /* master clock ticks run since power-on */
+}
+
+impl Emulator {
+    pub const SAMPLE_RATE: u32 = SAMPLE_RATE;
+
+    /**
+     * A machine running the given ROM, with no audio device and nothing connected to its
+     * controllers besides the input given to run_frame. Fails with ErrorKind::Unsupported if
+     * the ROM's mapper isn't one we emulate.
+     */
+    pub fn new(rom: &Rom) -> io::Result<Emulator> {
+        let write_buffer = Arc::new(Mutex::new([0; WRITE_BUFFER_SIZE]));
+        let mapper = rom.initialize_mapper()?;
+        Ok(Self::with_mapper(mapper, write_buffer, APU::silent()))
+    }
+
+    /**
+     * Wires up the CPU, PPU, and the given APU around a mapper, with the PPU drawing into the
+     * given buffer. The parts share non-thread-safe references, so this must be called on the thread
+     * that will run the emulation.
+     */
+    pub(crate) fn with_mapper(
+        mapper: Box<dyn Mapper>,
+        write_buffer: Arc<Mutex<WriteBuffer>>,
+        apu: Rc<RefCell<APU>>,
+    ) -> Emulator {
+        let mut memory = Box::new(CoreMemory::new_from_mapper(mapper));
+
+        let ppu = PPU::new(write_buffer.clone(), memory.mapper.clone());
+
+        apu.borrow_mut().connect_cartridge(memory.mapper.clone());
+        memory.register_listener(apu.clone());
+
+        let ppu_listener = PPUListener::new(ppu.clone());
+        memory.register_listener(Rc::new(RefCell::new(ppu_listener)));
+
+        let mapper = memory.mapper.clone();
+        let mut cpu = CPU::new(memory);
+        cpu.set_cycle_listener(Some(Box::new(Peripherals {
+            ppu: ppu.clone(),
+            apu: apu.clone(),
+            mapper,
+        })));
+
+        let mut emulator = Emulator {
+            cpu,
+            ppu,
+            apu,
+            write_buffer,
+            frame: Box::new([0; WRITE_BUFFER_SIZE]),
+            power_on_state: Vec::new(),
+            clocks: 0,
+        };
+        emulator.power_on_state = emulator.save_state();
+        emulator
+    }
+
+    /**
+     * Runs until the PPU finishes the current frame, with the given buttons held throughout,
+     * and returns the finished frame.
+     */
+    pub fn run_frame(&mut self, input: Input) -> &FrameBuffer {
+        self.cpu.set_scripted_buttons(Some(input.buttons));
+        self.finish_frame();
+        self.frame
+            .copy_from_slice(&self.write_buffer.lock().unwrap()[..]);
+        &self.frame
+    }
+
+    /* runs until the PPU finishes the current frame, with the controllers left as they are */
+    pub(crate) fn finish_frame(&mut self) {
+        let frame = self.frame_count();
+        while self.frame_count() == frame {
+            self.step_instruction();
+        }
+    }
+
+    /* runs one CPU instruction, along with everything that happens alongside it, returning the
+     * CPU cycles it took
+     */
+    pub fn step_instruction(&mut self) -> u16 {
+        let cycles = self.cpu.transition();
+        self.clocks += cycles as u64 * CPU_CYCLE_CLOCKS;
+        cycles
+    }
+
+    /**
+     * Takes the mono samples, at SAMPLE_RATE, made since the last call. Only a few frames' worth
+     * are kept, so this should be called every frame or so.
+     */
+    pub fn audio_samples(&mut self) -> Vec<f32> {
+        self.apu.borrow_mut().take_samples()
+    }
+
+    /**
+     * Starts recording the sound, at SAMPLE_RATE, until stop_audio_recording. Any earlier
+     * recording is finished first, and the result is whether that went well.
+     */
+    pub fn start_audio_recording(&mut self, recorder: AudioRecorder) -> io::Result<()> {
+        self.apu.borrow_mut().start_recording(recorder)
+    }
+
+    /* completes the audio recording's files, if there is one */
+    pub fn stop_audio_recording(&mut self) -> io::Result<()> {
+        self.apu.borrow_mut().stop_recording()
+    }
+
+    /* presses the console's reset button */
+    pub fn reset(&mut self) {
+        self.cpu.reset();
+        self.ppu.borrow_mut().reset();
+        self.apu.borrow_mut().reset();
+    }
+
+    /**
+     * Turns the console off and on again: everything goes back to how it was when the emulator
+     * was created, except the battery-backed save RAM, which survives as it would on a cartridge.
+     */
+    pub fn power_cycle(&mut self) {
+        let save_data = self.cpu.get_save_data();
+        self.load_state_unchecked(&self.power_on_state.clone())
+            .expect("Should always be able to restore the power-on state");
+        self.clocks = 0;
+        if let Some(save_data) = save_data {
+            self.cpu.set_save_data(&save_data);
+        }
+    }
+
+    /* frames finished since power-on */
+    pub fn frame_count(&self) -> u64 {
+        self.ppu.borrow().frame_count()
+    }
+
+    /* master clock ticks run since power-on */
+    pub(crate) fn clocks(&self) -> u64 {
+        self.clocks
+    }
+
+    /* reads memory as the CPU would see it, without side effects, e.g. to inspect a test
+     * ROM's results
+     */
+    pub fn read_mem(&self, address: u16) -> u8 {
+        self.cpu.peek_mem(address)
+    }
+
+    /* the battery-backed save RAM, if the cartridge has any */
+    pub fn save_data(&self) -> Option<Vec<u8>> {
+        self.cpu.get_save_data()
+    }
+
+    pub fn set_save_data(&mut self, data: &Vec<u8>) {
+        self.cpu.set_save_data(data);
+    }
+
+    /* logs every CPU instruction to output in nestest.log format */
+    pub fn set_tracer(&mut self, output: Box<dyn Write>) {
+        let mut tracer = Tracer::new(output);
+        tracer.set_ppu(self.ppu.clone());
+        self.cpu.set_tracer(Some(tracer));
+    }
+
+    pub fn set_unstable_opcode_policy(&mut self, policy: UnstableOpcodePolicy) {
+        self.cpu.set_unstable_opcode_policy(policy);
+    }
+
+    pub(crate) fn set_key_source(&mut self, keys: ButtonSource) {
+        self.cpu.set_key_source(keys);
+    }
+
+    pub(crate) fn set_pad_source(&mut self, pads: ButtonSource) {
+        self.cpu.set_pad_source(pads);
+    }
+
+    /* buttons to hold instead of reading the key and pad sources, or None to go back to them */
+    pub(crate) fn set_scripted_buttons(&mut self, buttons: Option<[u8; 2]>) {
+        self.cpu.set_scripted_buttons(buttons);
+    }
+
+    pub(crate) fn take_frame_buttons(&mut self) -> [u8; 2] {
+        self.cpu.take_frame_buttons()
+    }
+
+    pub(crate) fn set_speed(&mut self, speed: Speed) {
+        self.apu.borrow_mut().set_speed(speed);
+    }
+
+    pub(crate) fn set_muted(&mut self, muted: bool) {
+        self.apu.borrow_mut().set_muted(muted);
+    }
+
+    /**
+     * Captures the state of the whole machine. Since states are only taken between steps, the
+     * CPU is always between instructions.
+     */
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+
+        self.cpu.save_state(&mut writer);
+        self.ppu.borrow().save_state(&mut writer);
+        self.apu.borrow().save_state(&mut writer);
+
+        writer.into_bytes()
+    }
+
+    /**
+     * Restores a state produced by save_state for the same ROM. If the state can't be loaded,
+     * the machine is left as it was.
+     */
+    pub fn load_state(&mut self, data: &[u8]) -> io::Result<()> {
+        let backup = self.save_state();
+        let result = self.load_state_unchecked(data);
+        if result.is_err() {
+            self.load_state_unchecked(&backup)
+                .expect("Should always be able to restore a state just saved");
+        }
+        result
+    }
+
+    fn load_state_unchecked(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut reader = StateReader::new(data)?;
+
+        self.cpu.load_state(&mut reader)?;
+        self.ppu.borrow_mut().load_state(&mut reader)?;
+        self.apu.borrow_mut().load_state(&mut reader)?;
+
+        if !reader.is_finished() {
+            return Err(invalid_data("Save state has unexpected trailing data"));
+        }
+
+        Ok(())
+    }
+}
@@ -1,10 +1,88 @@
-pub mod program_state;
+mod emulator;
+/* running in real time on its own thread, playing through the audio device */
+#[cfg(feature = "audio")]
+pub(crate) mod program_state;
 
+mod rewind;
+#[cfg(feature = "audio")]
 pub(crate) mod scheduler;
 
 #[cfg(test)]
 mod tests;
 
+use crate::apu::AudioRecorder;
+use crate::movie::Movie;
+use std::io;
+use std::sync::mpsc::Sender;
+
+pub use emulator::{Emulator, FrameBuffer, Input};
+pub use rewind::RewindSettings;
+
+/**
+ * How fast the emulation runs, as a percentage of a real NES, or as fast as the host allows.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Speed {
+    Percent(u16),
+    Unthrottled,
+}
+
+impl Speed {
+    pub const NORMAL: Speed = Speed::Percent(100);
+    pub const SLOWEST: Speed = Speed::Percent(25);
+    pub const FASTEST: Speed = Speed::Percent(800);
+    /* the speeds on offer in the menu */
+    pub const PRESETS: [Speed; 7] = [
+        Speed::SLOWEST,
+        Speed::Percent(50),
+        Speed::NORMAL,
+        Speed::Percent(200),
+        Speed::Percent(400),
+        Speed::FASTEST,
+        Speed::Unthrottled,
+    ];
+
+    /* how many times faster than a real NES, or None if unthrottled */
+    pub fn multiplier(self) -> Option<f64> {
+        match self {
+            Speed::Percent(percent) => Some(percent.max(1) as f64 / 100.0),
+            Speed::Unthrottled => None,
+        }
+    }
+
+    pub fn label(self) -> String {
+        match self {
+            Speed::Percent(percent) => format!("{percent}%"),
+            Speed::Unthrottled => "Unthrottled".to_string(),
+        }
+    }
+}
+
 pub(crate) enum SimulatorSignal {
     EndSimulation,
+    /* replies with a snapshot of the whole machine */
+    SaveState(Sender<Vec<u8>>),
+    /* replies with whether the snapshot could be restored; on failure the machine is untouched */
+    LoadState(Vec<u8>, Sender<io::Result<()>>),
+    Pause,
+    Resume,
+    /* runs one frame, then stays paused */
+    FrameAdvance,
+    /* the console's reset button: RAM and the cartridge keep their contents */
+    Reset,
+    /* as if the console were switched off and on */
+    PowerCycle,
+    SetSpeed(Speed),
+    /* play backwards, a state at a time, until told to stop */
+    StartRewind,
+    StopRewind,
+    SetRewindSettings(RewindSettings),
+    /* power cycles, then records or plays back from there */
+    RecordMovie(Box<Movie>),
+    PlayMovie(Box<Movie>, bool), /* and whether it starts read-only */
+    StopMovie(Sender<Option<Movie>>),
+    SetMovieReadOnly(bool),
+    RecordAudio(Box<AudioRecorder>),
+    /* replies with whether the recording's files were completed */
+    StopAudioRecording(Sender<io::Result<()>>),
 }
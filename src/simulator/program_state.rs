@@ -1,19 +1,18 @@
-use crate::apu::APU;
-use crate::cpu::{CoreMemory, CPU};
+use crate::apu::{AudioRecorder, APU};
+use crate::cpu::{ButtonSource, UnstableOpcodePolicy};
 use crate::mapper::Mapper;
-use crate::ppu::ppu_listener::PPUListener;
-use crate::ppu::{WriteBuffer, PPU, WRITE_BUFFER_SIZE};
+use crate::movie::Movie;
+use crate::ppu::{WriteBuffer, WRITE_BUFFER_SIZE};
 use crate::rom::Rom;
+use crate::simulator::emulator::Emulator;
 use crate::simulator::scheduler::Scheduler;
-use crate::simulator::SimulatorSignal;
-use std::cell::RefCell;
-use std::collections::HashSet;
-use std::rc::Rc;
+use crate::simulator::{RewindSettings, SimulatorSignal, Speed};
+use std::io;
+use std::io::Write;
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 use std::{fs, thread};
-use tao::keyboard::Key;
 
 /**
  * Provides an external view into a running emulator state. This packages up all relevant parts
@@ -23,7 +22,8 @@ use tao::keyboard::Key;
  */
 pub struct ProgramState {
     /* inputs */
-    pub key_source: Arc<Mutex<HashSet<Key<'static>>>>,
+    pub key_source: ButtonSource,
+    pub pad_source: ButtonSource,
 
     /* outputs */
     pub write_buffer: Arc<Mutex<WriteBuffer>>,
@@ -37,59 +37,84 @@ impl ProgramState {
     pub fn simulate_async(
         rom: &Rom,
         savefile: &Option<String>,
-        key_source: Arc<Mutex<HashSet<Key<'static>>>>,
-    ) -> ProgramState {
+        key_source: ButtonSource,
+        pad_source: ButtonSource,
+        trace: Option<Box<dyn Write + Send>>,
+        unstable_opcodes: UnstableOpcodePolicy,
+    ) -> io::Result<ProgramState> {
         let write_buffer = Arc::new(Mutex::new([0; WRITE_BUFFER_SIZE]));
-        let mapper = rom.initialize_mapper();
+        let mapper = rom.initialize_mapper()?;
 
         let (thread_sender, thread_receiver) = channel::<SimulatorSignal>();
 
         let mut result = ProgramState {
             key_source,
+            pad_source,
             write_buffer,
             thread_sender,
             thread_handle: None,
         };
 
-        result.simulate_async_internal(mapper, savefile, thread_receiver);
+        result.simulate_async_internal(mapper, savefile, trace, unstable_opcodes, thread_receiver);
 
-        result
+        Ok(result)
     }
 
     fn simulate_async_internal(
         &mut self,
         mapper: Box<dyn Mapper>,
         savefile: &Option<String>,
+        trace: Option<Box<dyn Write + Send>>,
+        unstable_opcodes: UnstableOpcodePolicy,
         thread_receiver: Receiver<SimulatorSignal>,
     ) {
         let write_buffer = self.write_buffer.clone();
         let key_source_clone = self.key_source.clone();
+        let pad_source = self.pad_source.clone();
         let savefile = savefile.clone();
 
         self.thread_handle = Some(thread::spawn(move || {
-            let mut memory = Box::new(CoreMemory::new_from_mapper(mapper));
-
-            let ppu = PPU::new(write_buffer, memory.mapper.clone());
-
-            let apu = APU::new();
-            memory.register_listener(apu.clone());
-
-            let ppu_listener = PPUListener::new(ppu.clone());
-            memory.register_listener(Rc::new(RefCell::new(ppu_listener)));
-
-            let mut cpu = CPU::new(memory);
-            cpu.set_key_source(key_source_clone);
-
+            let mut scheduler = Self::build_scheduler(
+                mapper,
+                write_buffer,
+                key_source_clone,
+                thread_receiver,
+                true,
+            );
+
+            scheduler.set_unstable_opcode_policy(unstable_opcodes);
+            scheduler.set_pad_source(pad_source);
+            if let Some(trace) = trace {
+                scheduler.set_tracer(trace);
+            }
             if let Some(save_data) = Self::load_save_data(&savefile) {
-                cpu.set_save_data(&save_data);
+                scheduler.set_save_data(&save_data);
             }
 
-            let mut scheduler = Scheduler::new(cpu, ppu, apu, thread_receiver);
-
             scheduler.simulate()
         }));
     }
 
+    /* builds the machine around a mapper, ready to run in real time; must be called on the
+     * thread that will run the emulation, since the parts share non-thread-safe references
+     */
+    pub(crate) fn build_scheduler(
+        mapper: Box<dyn Mapper>,
+        write_buffer: Arc<Mutex<WriteBuffer>>,
+        key_source: ButtonSource,
+        receiver: Receiver<SimulatorSignal>,
+        play_audio: bool,
+    ) -> Scheduler {
+        let apu = if play_audio {
+            APU::new()
+        } else {
+            APU::silent()
+        };
+        let mut emulator = Emulator::with_mapper(mapper, write_buffer, apu);
+        emulator.set_key_source(key_source);
+        Scheduler::new(emulator, receiver)
+    }
+
     pub fn cleanup(&mut self) -> Option<Vec<u8>> {
         if let Some(thread_handle) = self.thread_handle.take() {
             self.thread_sender
@@ -107,6 +132,104 @@ impl ProgramState {
         }
     }
 
+    /**
+     * Takes a snapshot of the running machine, or returns None if the emulation has stopped.
+     */
+    pub fn save_state(&self) -> Option<Vec<u8>> {
+        let (reply_sender, reply_receiver) = channel();
+        self.thread_sender
+            .send(SimulatorSignal::SaveState(reply_sender))
+            .ok()?;
+        reply_receiver.recv().ok()
+    }
+
+    /**
+     * Restores a snapshot taken by save_state. If it can't be restored, the emulation carries
+     * on unchanged.
+     */
+    pub fn load_state(&self, data: Vec<u8>) -> io::Result<()> {
+        let stopped = || io::Error::new(io::ErrorKind::BrokenPipe, "Emulation is not running");
+        let (reply_sender, reply_receiver) = channel();
+        self.thread_sender
+            .send(SimulatorSignal::LoadState(data, reply_sender))
+            .map_err(|_| stopped())?;
+        reply_receiver.recv().map_err(|_| stopped())?
+    }
+
+    pub fn pause(&self) {
+        self.send(SimulatorSignal::Pause);
+    }
+
+    pub fn resume(&self) {
+        self.send(SimulatorSignal::Resume);
+    }
+
+    /* runs a single frame and leaves the emulation paused */
+    pub fn frame_advance(&self) {
+        self.send(SimulatorSignal::FrameAdvance);
+    }
+
+    pub fn reset(&self) {
+        self.send(SimulatorSignal::Reset);
+    }
+
+    pub fn power_cycle(&self) {
+        self.send(SimulatorSignal::PowerCycle);
+    }
+
+    pub fn set_speed(&self, speed: Speed) {
+        self.send(SimulatorSignal::SetSpeed(speed));
+    }
+
+    pub fn start_rewind(&self) {
+        self.send(SimulatorSignal::StartRewind);
+    }
+
+    pub fn stop_rewind(&self) {
+        self.send(SimulatorSignal::StopRewind);
+    }
+
+    pub fn set_rewind_settings(&self, settings: RewindSettings) {
+        self.send(SimulatorSignal::SetRewindSettings(settings));
+    }
+
+    pub fn record_movie(&self, movie: Movie) {
+        self.send(SimulatorSignal::RecordMovie(Box::new(movie)));
+    }
+
+    pub fn play_movie(&self, movie: Movie, read_only: bool) {
+        self.send(SimulatorSignal::PlayMovie(Box::new(movie), read_only));
+    }
+
+    /* the movie that was recording or playing, if there was one */
+    pub fn stop_movie(&self) -> Option<Movie> {
+        let (reply_sender, reply_receiver) = channel();
+        self.thread_sender
+            .send(SimulatorSignal::StopMovie(reply_sender))
+            .ok()?;
+        reply_receiver.recv().ok()?
+    }
+
+    pub fn set_movie_read_only(&self, read_only: bool) {
+        self.send(SimulatorSignal::SetMovieReadOnly(read_only));
+    }
+
+    pub fn record_audio(&self, recorder: AudioRecorder) {
+        self.send(SimulatorSignal::RecordAudio(Box::new(recorder)));
+    }
+
+    /* completes the audio recording; if the emulation has stopped, it already has */
+    pub fn stop_audio_recording(&self) -> io::Result<()> {
+        let (reply_sender, reply_receiver) = channel();
+        self.send(SimulatorSignal::StopAudioRecording(reply_sender));
+        reply_receiver.recv().unwrap_or(Ok(()))
+    }
+
+    /* signals that don't need a reply are dropped if the emulation has stopped */
+    fn send(&self, signal: SimulatorSignal) {
+        let _ = self.thread_sender.send(signal);
+    }
+
     fn load_save_data(savefile: &Option<String>) -> Option<Vec<u8>> {
         match savefile {
             None => None,
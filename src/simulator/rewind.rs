@@ -0,0 +1,122 @@
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind};
+
+/* fast compression is plenty, since consecutive states are mostly the same few bytes */
+const COMPRESSION_LEVEL: u8 = 1;
+
+/**
+ * How much rewind history to keep: a state every `interval` frames, up to `depth` states or
+ * `memory_limit` bytes of compressed states, whichever is reached first. A depth of 0 turns
+ * rewinding off.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewindSettings {
+    pub interval: u32,
+    pub depth: usize,
+    pub memory_limit: usize,
+}
+
+impl Default for RewindSettings {
+    /* a minute of history, at a state every other frame */
+    fn default() -> RewindSettings {
+        RewindSettings {
+            interval: 2,
+            depth: 1800,
+            memory_limit: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/**
+ * A ring buffer of compressed save states, newest at the back. Once it's full, each new state
+ * pushes out the oldest.
+ */
+pub struct RewindBuffer {
+    settings: RewindSettings,
+    states: VecDeque<Vec<u8>>,
+    memory_used: usize,
+    frames_since_state: u32,
+}
+
+impl RewindBuffer {
+    pub fn new(settings: RewindSettings) -> RewindBuffer {
+        RewindBuffer {
+            settings,
+            states: VecDeque::new(),
+            memory_used: 0,
+            frames_since_state: 0,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: RewindSettings) {
+        self.settings = settings;
+        self.trim();
+    }
+
+    /* counts a finished frame, returning whether it's time to push a state */
+    pub fn frame_finished(&mut self) -> bool {
+        if self.settings.depth == 0 {
+            return false;
+        }
+        self.frames_since_state += 1;
+        if self.frames_since_state < self.settings.interval.max(1) {
+            return false;
+        }
+        self.frames_since_state = 0;
+        true
+    }
+
+    pub fn push(&mut self, state: &[u8]) {
+        let compressed = compress_to_vec(state, COMPRESSION_LEVEL);
+        self.memory_used += compressed.len();
+        self.states.push_back(compressed);
+        self.trim();
+    }
+
+    /* drops the newest state */
+    pub fn pop(&mut self) {
+        if let Some(state) = self.states.pop_back() {
+            self.memory_used -= state.len();
+        }
+        self.frames_since_state = 0;
+    }
+
+    /* the newest state, uncompressed */
+    pub fn latest(&self) -> Option<io::Result<Vec<u8>>> {
+        self.states.back().map(|state| {
+            decompress_to_vec(state).map_err(|e| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Couldn't inflate a rewind state: {e}"),
+                )
+            })
+        })
+    }
+
+    /* drops every state */
+    pub fn clear(&mut self) {
+        self.states.clear();
+        self.memory_used = 0;
+        self.frames_since_state = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    #[cfg(test)]
+    pub fn memory_used(&self) -> usize {
+        self.memory_used
+    }
+
+    fn trim(&mut self) {
+        while self.states.len() > self.settings.depth
+            || (self.memory_used > self.settings.memory_limit && !self.states.is_empty())
+        {
+            let oldest = self.states.pop_front().unwrap();
+            self.memory_used -= oldest.len();
+        }
+    }
+}
@@ -1,109 +1,440 @@
-use crate::apu::APU;
-use crate::cpu::CPU;
-use crate::ppu::PPU;
-use crate::simulator::scheduler::TaskType::*;
-use crate::simulator::SimulatorSignal;
-use std::cell::RefCell;
+use crate::cpu::{ButtonSource, UnstableOpcodePolicy};
+use crate::movie::{Movie, MovieMode, MovieSession, COMMAND_POWER, COMMAND_RESET};
+use crate::simulator::emulator::Emulator;
+use crate::simulator::rewind::{RewindBuffer, RewindSettings};
+use crate::simulator::{SimulatorSignal, Speed};
+use std::io;
+use std::io::Write;
 use std::ops::Add;
-use std::rc::Rc;
 use std::sync::mpsc::Receiver;
 use std::thread;
 use std::time::{Duration, Instant};
-#[derive(Clone)]
-enum TaskType {
-    CPU,
-    PPU,
-    APU,
-}
 
+/**
+ * Runs an Emulator in real time for the windowed frontend, taking its orders from signals sent
+ * by the UI thread, and keeping up the extras that live play needs: pausing, speed control,
+ * rewinding, and movies.
+ */
 pub struct Scheduler {
-    cpu: Box<CPU>,
-    ppu: Rc<RefCell<PPU>>,
-    apu: Rc<RefCell<APU>>,
+    emulator: Emulator,
     receiver: Receiver<SimulatorSignal>,
-
-    next_cpu_time: u64,
-    next_ppu_time: u64,
-    next_apu_time: u64,
+    paused: bool,
+    speed: Speed,
+    rewind: RewindBuffer,
+    rewinding: bool,
+    movie: Option<MovieSession>,
+    movie_frame_offset: u64, /* movie frames before the PPU's count last started from 0 */
 }
 
 impl Scheduler {
-    pub fn new(
-        cpu: Box<CPU>,
-        ppu: Rc<RefCell<PPU>>,
-        apu: Rc<RefCell<APU>>,
-        receiver: Receiver<SimulatorSignal>,
-    ) -> Self {
+    pub fn new(emulator: Emulator, receiver: Receiver<SimulatorSignal>) -> Self {
         Scheduler {
-            cpu,
-            ppu,
-            apu,
+            emulator,
             receiver,
-            next_cpu_time: 0,
-            next_ppu_time: 0,
-            next_apu_time: 0,
+            paused: false,
+            speed: Speed::NORMAL,
+            rewind: RewindBuffer::new(RewindSettings::default()),
+            rewinding: false,
+            movie: None,
+            movie_frame_offset: 0,
         }
     }
-    pub fn simulate(&mut self) -> Option<Vec<u8>> {
-        let start_time = Instant::now();
 
-        let quantum = Duration::from_millis(10);
-
-        let mut most_recent_now = start_time;
-        let mut check_time_clocks = duration_to_clocks(quantum);
+    /**
+     * Runs the machine in real time until told to stop, returning the battery-backed save data.
+     * While paused, or rewinding with nothing left to rewind, it just waits for the next signal.
+     */
+    pub fn simulate(&mut self) -> Option<Vec<u8>> {
+        let mut pacer = Pacer::new(self.emulator.clocks(), self.speed);
 
         loop {
-            if let Ok(signal) = self.receiver.try_recv() {
+            let signal = if self.is_idle() {
+                match self.receiver.recv() {
+                    Ok(signal) => Some(signal),
+                    /* nobody is left to unpause us */
+                    Err(_) => return self.end_simulation(),
+                }
+            } else {
+                self.receiver.try_recv().ok()
+            };
+
+            if let Some(signal) = signal {
                 match signal {
-                    SimulatorSignal::EndSimulation => {
-                        return self.cpu.get_save_data();
+                    SimulatorSignal::EndSimulation => return self.end_simulation(),
+                    /* the requester may have given up waiting; nothing to do if so */
+                    SimulatorSignal::SaveState(reply) => {
+                        let _ = reply.send(self.save_state());
+                    }
+                    SimulatorSignal::LoadState(data, reply) => {
+                        let _ = reply.send(self.load_state(&data));
+                    }
+                    SimulatorSignal::Pause => self.paused = true,
+                    SimulatorSignal::Resume => {
+                        /* time spent paused shouldn't be made up for by running flat out */
+                        if self.paused {
+                            pacer = Pacer::new(self.emulator.clocks(), self.speed);
+                        }
+                        self.paused = false;
+                    }
+                    SimulatorSignal::FrameAdvance => {
+                        self.paused = true;
+                        self.run_frame();
+                        self.record_frame();
+                    }
+                    SimulatorSignal::Reset => self.user_command(COMMAND_RESET),
+                    SimulatorSignal::PowerCycle => self.user_command(COMMAND_POWER),
+                    SimulatorSignal::SetSpeed(speed) => {
+                        self.set_speed(speed);
+                        pacer = Pacer::new(self.emulator.clocks(), speed);
+                    }
+                    SimulatorSignal::StartRewind => self.set_rewinding(true),
+                    SimulatorSignal::StopRewind => self.set_rewinding(false),
+                    SimulatorSignal::SetRewindSettings(settings) => {
+                        self.rewind.set_settings(settings);
+                    }
+                    SimulatorSignal::RecordMovie(movie) => self.record_movie(*movie),
+                    SimulatorSignal::PlayMovie(movie, read_only) => {
+                        self.play_movie(*movie, read_only);
+                    }
+                    SimulatorSignal::StopMovie(reply) => {
+                        let _ = reply.send(self.stop_movie());
+                    }
+                    SimulatorSignal::SetMovieReadOnly(read_only) => {
+                        if let Some(session) = &mut self.movie {
+                            session.set_read_only(read_only);
+                        }
+                    }
+                    SimulatorSignal::RecordAudio(recorder) => {
+                        if let Err(e) = self.emulator.start_audio_recording(*recorder) {
+                            eprintln!("Failed to finish the earlier audio recording: {e}");
+                        }
+                    }
+                    SimulatorSignal::StopAudioRecording(reply) => {
+                        let _ = reply.send(self.emulator.stop_audio_recording());
                     }
                 }
             }
 
-            let next_task = self.next_task();
-            if next_task.1 > check_time_clocks {
-                thread::sleep(
-                    clocks_to_time(start_time, next_task.1)
-                        .saturating_duration_since(most_recent_now),
-                );
-                most_recent_now = Instant::now();
-                check_time_clocks =
-                    duration_to_clocks(most_recent_now.add(quantum).duration_since(start_time));
+            if self.is_idle() {
+                continue;
             }
 
-            match next_task {
-                (CPU, time) => self.next_cpu_time = time + (self.cpu.transition() as u64) * 12,
-                (PPU, time) => {
-                    self.ppu.borrow_mut().tick(&mut self.cpu);
-                    self.next_ppu_time = time + 4;
-                }
-                (APU, time) => {
-                    let mut apu = self.apu.borrow_mut();
-                    apu.apu_tick();
-                    self.next_apu_time = time + 1 * 24;
-                }
+            pacer.throttle(self.emulator.clocks());
+            if self.rewinding {
+                self.rewind_frame();
+            } else {
+                self.step_live();
             }
         }
     }
 
-    fn next_task(&self) -> (TaskType, u64) {
-        let mut best_time = self.next_cpu_time;
-        let mut best = CPU;
+    /* finishes off any audio recording, since nobody will be left to, and returns the
+     * battery-backed save data
+     */
+    fn end_simulation(&mut self) -> Option<Vec<u8>> {
+        if let Err(e) = self.emulator.stop_audio_recording() {
+            eprintln!("Failed to finish audio recording: {e}");
+        }
+        self.emulator.save_data()
+    }
 
-        if best_time > self.next_ppu_time {
-            best_time = self.next_ppu_time;
-            best = PPU;
+    /* runs an instruction of live play, keeping the movie and rewind buffer up as frames end */
+    fn step_live(&mut self) {
+        let frame = self.frame_count();
+        self.step();
+        if self.frame_count() != frame {
+            self.frame_started();
+            self.record_frame();
         }
+    }
 
-        if best_time > self.next_apu_time {
-            best_time = self.next_apu_time;
-            best = APU;
+    fn is_idle(&self) -> bool {
+        if self.rewinding {
+            self.rewind.len() < 2
+        } else {
+            self.paused
         }
+    }
 
-        (best, best_time)
+    /* the game is silent while it runs backwards */
+    fn set_rewinding(&mut self, rewinding: bool) {
+        self.rewinding = rewinding;
+        self.emulator.set_muted(rewinding);
+    }
+
+    /* keeps a state for rewinding to, every so many frames; called as each frame starts */
+    fn record_frame(&mut self) {
+        if self.rewind.frame_finished() {
+            let state = self.save_state();
+            self.rewind.push(&state);
+        }
+    }
+
+    /**
+     * Steps back a state. The picture from when the newest state was taken is only drawn by
+     * running up to it, so this goes back to the state before that and runs a frame from there.
+     * If a state can't be restored, the history is dropped and play carries on from here.
+     */
+    fn rewind_frame(&mut self) {
+        self.rewind.pop();
+        let Some(state) = self.rewind.latest() else {
+            return;
+        };
+        match state.and_then(|state| self.emulator.load_state(&state)) {
+            Ok(()) => {
+                self.movie_state_loaded();
+                self.run_frame();
+            }
+            Err(e) => {
+                eprintln!("Failed to rewind, dropping the rewind history: {e}");
+                self.rewind.clear();
+            }
+        }
+    }
+
+    /* starts recording a movie from power-on */
+    pub fn record_movie(&mut self, movie: Movie) {
+        self.start_movie(MovieSession::record(movie));
+    }
+
+    /* plays a movie from power-on; once it ends, the controllers are live again */
+    pub fn play_movie(&mut self, movie: Movie, read_only: bool) {
+        self.start_movie(MovieSession::play(movie, read_only));
+    }
+
+    fn start_movie(&mut self, session: MovieSession) {
+        self.stop_movie();
+        self.power_cycle();
+        self.movie_frame_offset = 0;
+        self.emulator.take_frame_buttons();
+        self.movie = Some(session);
+        self.begin_movie_frame(0);
+    }
+
+    /* stops recording or playing, returning the movie if anything was recorded into it */
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.emulator.set_scripted_buttons(None);
+        self.movie
+            .take()
+            .filter(MovieSession::is_modified)
+            .map(MovieSession::into_movie)
+    }
+
+    #[cfg(test)]
+    pub fn movie_mode(&self) -> Option<MovieMode> {
+        self.movie.as_ref().map(MovieSession::mode)
+    }
+
+    /* the frame being run, counting from when the movie started */
+    fn movie_frame(&self) -> usize {
+        (self.frame_count() + self.movie_frame_offset) as usize
+    }
+
+    /**
+     * Called as each frame starts while a movie is on: records the buttons the game read in the
+     * frame just finished, then starts the new one with its commands and, when playing, buttons.
+     */
+    fn frame_started(&mut self) {
+        if self.movie.is_none() {
+            return;
+        }
+        let frame = self.movie_frame();
+        let buttons = self.emulator.take_frame_buttons();
+        if let (Some(session), Some(finished)) = (&mut self.movie, frame.checked_sub(1)) {
+            session.end_frame(finished, buttons);
+        }
+        self.begin_movie_frame(frame);
+    }
+
+    fn begin_movie_frame(&mut self, frame: usize) {
+        let Some(session) = &mut self.movie else {
+            return;
+        };
+        let was_playing = session.mode() == MovieMode::Playing;
+        let start = session.begin_frame(frame);
+        if was_playing && session.mode() == MovieMode::Finished {
+            println!("Movie finished after {frame} frames");
+        }
+
+        if start.commands & COMMAND_POWER != 0 {
+            self.power_cycle();
+            /* the PPU starts counting frames from 0 again, but the movie carries on */
+            self.movie_frame_offset = frame as u64;
+        } else if start.commands & COMMAND_RESET != 0 {
+            self.reset();
+        }
+        self.emulator.set_scripted_buttons(start.buttons);
+    }
+
+    /* after a savestate is loaded, a movie picks up from the frame it was saved in */
+    fn movie_state_loaded(&mut self) {
+        if self.movie.is_none() {
+            return;
+        }
+        let frame = self.movie_frame();
+        self.emulator.take_frame_buttons();
+        if let Some(session) = &mut self.movie {
+            session.state_loaded(frame);
+        }
+        self.begin_movie_frame(frame);
+    }
+
+    /* a reset or power cycle, unless a movie says otherwise */
+    fn user_command(&mut self, command: u8) {
+        let handled = match &mut self.movie {
+            Some(session) => session.command(command),
+            None => false,
+        };
+        if handled {
+            return;
+        }
+        if command == COMMAND_POWER {
+            self.power_cycle();
+        } else {
+            self.reset();
+        }
+    }
+
+    #[cfg(test)]
+    pub fn rewind_len(&self) -> usize {
+        self.rewind.len()
+    }
+
+    /* the APU adjusts its sampling to match, so audio keeps pace without piling up */
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.emulator.set_speed(speed);
+    }
+
+    #[cfg(test)]
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    #[cfg(test)]
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.emulator.frame_count()
+    }
+
+    /* runs until the PPU finishes the current frame, without any real-time throttling */
+    pub fn run_frame(&mut self) {
+        self.emulator.finish_frame();
+        self.frame_started();
+    }
+
+    /* runs one CPU instruction, along with everything that happens alongside it */
+    pub fn step(&mut self) {
+        self.emulator.step_instruction();
+    }
+
+    pub fn reset(&mut self) {
+        self.emulator.reset();
+    }
+
+    pub fn power_cycle(&mut self) {
+        self.emulator.power_cycle();
+    }
+
+    #[cfg(test)]
+    pub fn read_mem(&self, address: u16) -> u8 {
+        self.emulator.read_mem(address)
+    }
+
+    pub fn set_tracer(&mut self, output: Box<dyn Write>) {
+        self.emulator.set_tracer(output);
+    }
+
+    pub fn set_unstable_opcode_policy(&mut self, policy: UnstableOpcodePolicy) {
+        self.emulator.set_unstable_opcode_policy(policy);
+    }
+
+    pub fn set_pad_source(&mut self, pads: ButtonSource) {
+        self.emulator.set_pad_source(pads);
+    }
+
+    pub fn set_save_data(&mut self, data: &Vec<u8>) {
+        self.emulator.set_save_data(data);
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        self.emulator.save_state()
+    }
+
+    /* as Emulator::load_state, with any movie picking up from the loaded frame */
+    pub fn load_state(&mut self, data: &[u8]) -> io::Result<()> {
+        self.emulator.load_state(data)?;
+        self.movie_state_loaded();
+        Ok(())
+    }
+}
+/**
+ * Keeps the emulation to real time, or a multiple of it, by sleeping whenever it gets more than
+ * a short quantum ahead of the wall clock. Time is measured from when the pacer was made, so a
+ * new one is needed after any pause or change of speed. A power cycle starts the machine's clock
+ * over, and the pacer starts over with it.
+ */
+struct Pacer {
+    start_time: Instant,
+    start_clocks: u64,
+    multiplier: Option<f64>, /* None never sleeps */
+    most_recent_now: Instant,
+    check_time_clocks: u64, /* clocks since the start after which to check the time again */
+}
+
+impl Pacer {
+    const QUANTUM: Duration = Duration::from_millis(10);
+
+    fn new(clocks: u64, speed: Speed) -> Pacer {
+        Self::with_multiplier(clocks, speed.multiplier())
+    }
+
+    fn with_multiplier(clocks: u64, multiplier: Option<f64>) -> Pacer {
+        let start_time = Instant::now();
+        Pacer {
+            start_time,
+            start_clocks: clocks,
+            multiplier,
+            most_recent_now: start_time,
+            check_time_clocks: Self::scale(duration_to_clocks(Self::QUANTUM), multiplier),
+        }
+    }
+
+    /* converts real-time clocks into clocks at this speed */
+    fn scale(clocks: u64, multiplier: Option<f64>) -> u64 {
+        (clocks as f64 * multiplier.unwrap_or(1.0)) as u64
+    }
+
+    fn throttle(&mut self, clocks: u64) {
+        let Some(multiplier) = self.multiplier else {
+            return;
+        };
+        if clocks < self.start_clocks {
+            *self = Self::with_multiplier(clocks, self.multiplier);
+        }
+        let clocks = clocks - self.start_clocks;
+        if clocks > self.check_time_clocks {
+            let real_clocks = (clocks as f64 / multiplier) as u64;
+            thread::sleep(
+                clocks_to_time(self.start_time, real_clocks)
+                    .saturating_duration_since(self.most_recent_now),
+            );
+            self.most_recent_now = Instant::now();
+            self.check_time_clocks = Self::scale(
+                duration_to_clocks(
+                    self.most_recent_now
+                        .add(Self::QUANTUM)
+                        .duration_since(self.start_time),
+                ),
+                self.multiplier,
+            );
+        }
     }
 }
+
 /**
  * Given a starting time and a number of master clock ticks, returns the time at which that many
  * master clock ticks have passed since the start time. A master clock tick is defined, for an
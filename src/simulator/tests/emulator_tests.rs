@@ -0,0 +1,186 @@
+use crate::apu::{AudioRecorder, WavFormat, STEM_NAMES};
+use crate::rom::{Rom, RomHeader};
+use crate::simulator::{Emulator, Input};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+/* plays a tone, and each frame reads player 1's buttons into $12 and shows them as the
+ * background colour
+ */
+fn input_rom() -> Rom {
+    let mut prg_data = vec![0u8; 0x4000];
+    let reset: &[u8] = &[
+        0x78, 0xd8, 0xa2, 0xff, 0x9a, // SEI; CLD; LDX #$ff; TXS
+        0xa9, 0x0f, 0x8d, 0x15, 0x40, // enable APU channels
+        0xa9, 0xbf, 0x8d, 0x00, 0x40, // pulse 1: constant volume
+        0xa9, 0x40, 0x8d, 0x02, 0x40, // pulse 1: timer
+        0xa9, 0x08, 0x8d, 0x03, 0x40, // pulse 1: length counter
+        0xa9, 0x80, 0x8d, 0x00, 0x20, // enable NMI
+        0xa9, 0x1e, 0x8d, 0x01, 0x20, // show background and sprites
+        0x4c, 0x23, 0x80, // loop: JMP loop
+    ];
+    let nmi: &[u8] = &[
+        0xa9, 0x01, 0x8d, 0x16, 0x40, 0xa9, 0x00, 0x8d, 0x16, 0x40, // strobe the controllers
+        0xa2, 0x08, // LDX #8
+        0xad, 0x16, 0x40, 0x4a, 0x26, 0x12, 0xca, 0xd0, 0xf7, // read: LDA $4016; LSR; ROL $12
+        0xa9, 0x3f, 0x8d, 0x06, 0x20, 0xa9, 0x00, 0x8d, 0x06, 0x20, // PPUADDR = $3f00
+        0xa5, 0x12, 0x29, 0x3f, 0x8d, 0x07, 0x20, // backdrop = $12 & $3f
+        0xa9, 0x00, 0x8d, 0x05, 0x20, 0x8d, 0x05, 0x20, // reset scroll
+        0x40, // RTI
+    ];
+    prg_data[..reset.len()].copy_from_slice(reset);
+    prg_data[0x40..0x40 + nmi.len()].copy_from_slice(nmi);
+    prg_data[0x3ffa..].copy_from_slice(&[0x40, 0x80, 0x00, 0x80, 0x00, 0x80]);
+
+    Rom {
+        header: RomHeader::default(),
+        prg_data,
+        chr_data: vec![0; 0x2000],
+        _trainer: vec![],
+    }
+}
+
+fn player_1(buttons: u8) -> Input {
+    Input {
+        buttons: [buttons, 0],
+    }
+}
+
+/* a few seconds of mashing buttons, the same every time */
+fn inputs() -> Vec<Input> {
+    (0..180u32)
+        .map(|frame| player_1((frame * 37 % 251) as u8))
+        .collect()
+}
+
+#[test]
+fn unsupported_mapper_is_an_error() {
+    let mut rom = input_rom();
+    rom.header.mapper = 255;
+    let error = Emulator::new(&rom).err().unwrap();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn same_inputs_give_same_frames_and_sound() {
+    let rom = input_rom();
+    let mut first = Emulator::new(&rom).unwrap();
+    let mut second = Emulator::new(&rom).unwrap();
+
+    for input in inputs() {
+        let frame = first.run_frame(input).to_vec();
+        assert!(frame == second.run_frame(input).to_vec());
+        let samples = first.audio_samples();
+        assert!(!samples.is_empty());
+        assert_eq!(samples, second.audio_samples());
+    }
+    assert_eq!(first.save_state(), second.save_state());
+}
+
+#[test]
+fn input_is_held_through_the_frame() {
+    let mut emulator = Emulator::new(&input_rom()).unwrap();
+    emulator.run_frame(Input::default());
+
+    /* the game reads the buttons A first, so they come out reversed */
+    emulator.run_frame(player_1(Input::START | Input::RIGHT));
+    assert_eq!(emulator.read_mem(0x12), 0b0001_0001);
+    let start_right = emulator
+        .run_frame(player_1(Input::START | Input::RIGHT))
+        .to_vec();
+    let a = emulator.run_frame(player_1(Input::A)).to_vec();
+    assert_eq!(emulator.read_mem(0x12), 0b1000_0000);
+    assert!(start_right != a);
+}
+
+#[test]
+fn audio_samples_are_taken_once() {
+    let mut emulator = Emulator::new(&input_rom()).unwrap();
+    /* the first frame is short, since the machine starts partway through one */
+    emulator.run_frame(Input::default());
+    emulator.audio_samples();
+    emulator.run_frame(Input::default());
+
+    /* a frame is about 29780 CPU cycles, and there's a sample every 40 */
+    let samples = emulator.audio_samples().len();
+    assert!((700..800).contains(&samples), "{samples} samples");
+    assert!(emulator.audio_samples().is_empty());
+    assert_eq!(Emulator::SAMPLE_RATE, 44744);
+}
+
+/* the samples in a float WAV file, which has a 58-byte header */
+fn read_float_wav(path: &Path) -> Vec<f32> {
+    fs::read(path).unwrap()[58..]
+        .chunks(4)
+        .map(|sample| f32::from_le_bytes(sample.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn recording_holds_what_is_played() {
+    let dir = std::env::temp_dir().join(format!("patina-emulator-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("tone.wav");
+    let mut emulator = Emulator::new(&input_rom()).unwrap();
+
+    let recorder = AudioRecorder::create(&path, WavFormat::Float, true).unwrap();
+    emulator.start_audio_recording(recorder).unwrap();
+    let mut played = Vec::new();
+    for input in inputs().into_iter().take(10) {
+        emulator.run_frame(input);
+        played.extend(emulator.audio_samples());
+    }
+    emulator.stop_audio_recording().unwrap();
+
+    let recorded = read_float_wav(&path);
+    assert!(recorded.iter().any(|sample| *sample != 0.0));
+    assert_eq!(recorded, played);
+    for name in STEM_NAMES {
+        let stem = read_float_wav(&AudioRecorder::stem_path(&path, name));
+        assert_eq!(stem.len(), recorded.len(), "{name}");
+    }
+    /* the tone is on pulse 1 */
+    let pulse1 = read_float_wav(&AudioRecorder::stem_path(&path, "pulse1"));
+    assert!(pulse1.iter().any(|sample| *sample != 0.0));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn step_instruction_runs_one_instruction() {
+    let mut emulator = Emulator::new(&input_rom()).unwrap();
+    /* SEI, then CLD, each 2 cycles */
+    assert_eq!(emulator.step_instruction(), 2);
+    assert_eq!(emulator.step_instruction(), 2);
+    assert_eq!(emulator.frame_count(), 0);
+}
+
+#[test]
+fn reset_and_power_cycle_restart_the_game() {
+    let rom = input_rom();
+    let mut emulator = Emulator::new(&rom).unwrap();
+    let power_on = emulator.save_state();
+    for _i in 0..3 {
+        emulator.run_frame(player_1(Input::B));
+    }
+    assert_eq!(emulator.read_mem(0x12), 0b0100_0000);
+
+    /* reset keeps RAM */
+    emulator.reset();
+    assert_eq!(emulator.read_mem(0x12), 0b0100_0000);
+    emulator.power_cycle();
+    assert_eq!(emulator.save_state(), power_on);
+}
+
+#[test]
+fn power_cycle_starts_the_clock_over() {
+    let mut emulator = Emulator::new(&input_rom()).unwrap();
+    emulator.run_frame(player_1(0));
+    let one_frame = emulator.clocks();
+    assert!(one_frame > 0);
+
+    emulator.power_cycle();
+    assert_eq!(emulator.clocks(), 0);
+    emulator.run_frame(player_1(0));
+    assert_eq!(emulator.clocks(), one_frame);
+}
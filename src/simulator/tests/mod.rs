@@ -1,2 +1,6 @@
+mod emulator_tests;
+#[cfg(feature = "audio")]
 mod program_state_tests;
+mod rewind_tests;
+#[cfg(feature = "audio")]
 mod scheduler_tests;
@@ -1,25 +1,48 @@
-use crate::rom::Rom;
+use crate::cpu::{ButtonSource, UnstableOpcodePolicy};
+use crate::rom::{Rom, RomHeader};
 use crate::simulator::program_state::ProgramState;
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex};
 
 fn make_test_rom() -> Rom {
     Rom {
         prg_data: vec![0u8; 16384],
         chr_data: vec![0u8; 8192],
-        byte_6_flags: 0,
-        byte_7_flags: 0,
+        header: RomHeader::default(),
         _trainer: vec![],
-        _prg_ram: vec![],
-        _tv_system: 0,
     }
 }
 
 #[test]
 fn simulate_async_starts_thread_and_cleanup_stops_it() {
-    let keys = Arc::new(Mutex::new(HashSet::new()));
-    let mut state = ProgramState::simulate_async(&make_test_rom(), &None, keys);
+    let mut state = ProgramState::simulate_async(
+        &make_test_rom(),
+        &None,
+        ButtonSource::default(),
+        ButtonSource::default(),
+        None,
+        UnstableOpcodePolicy::default(),
+    )
+    .unwrap();
     assert!(state.thread_handle.is_some());
     assert!(state.cleanup().is_none());
     assert!(state.thread_handle.is_none());
 }
+
+#[test]
+fn save_and_load_state_through_running_thread() {
+    let mut state = ProgramState::simulate_async(
+        &make_test_rom(),
+        &None,
+        ButtonSource::default(),
+        ButtonSource::default(),
+        None,
+        UnstableOpcodePolicy::default(),
+    )
+    .unwrap();
+    let snapshot = state.save_state().expect("emulation should be running");
+    assert!(state.load_state(snapshot).is_ok());
+    assert!(state.load_state(vec![1, 2, 3]).is_err());
+    state.cleanup();
+
+    assert!(state.save_state().is_none());
+    assert!(state.load_state(vec![]).is_err());
+}
@@ -0,0 +1,114 @@
+use crate::simulator::rewind::{RewindBuffer, RewindSettings};
+
+fn settings(interval: u32, depth: usize, memory_limit: usize) -> RewindSettings {
+    RewindSettings {
+        interval,
+        depth,
+        memory_limit,
+    }
+}
+
+/* a distinct state for each n, big enough to be worth compressing */
+fn state(n: u8) -> Vec<u8> {
+    let mut state = vec![0; 0x1000];
+    state[0] = n;
+    state[0x800] = n.wrapping_mul(3);
+    state
+}
+
+#[test]
+fn states_come_back_newest_first() {
+    let mut buffer = RewindBuffer::new(RewindSettings::default());
+    assert!(buffer.latest().is_none());
+    for n in 0..3 {
+        buffer.push(&state(n));
+    }
+
+    assert_eq!(buffer.latest().unwrap().unwrap(), state(2));
+    buffer.pop();
+    assert_eq!(buffer.latest().unwrap().unwrap(), state(1));
+    buffer.pop();
+    buffer.pop();
+    assert!(buffer.latest().is_none());
+    assert_eq!(buffer.len(), 0);
+    assert_eq!(buffer.memory_used(), 0);
+
+    /* popping when empty does nothing */
+    buffer.pop();
+    assert_eq!(buffer.len(), 0);
+}
+
+#[test]
+fn clearing_drops_every_state() {
+    let mut buffer = RewindBuffer::new(settings(2, 10, usize::MAX));
+    buffer.push(&state(1));
+    buffer.push(&state(2));
+    buffer.frame_finished();
+
+    buffer.clear();
+    assert!(buffer.latest().is_none());
+    assert_eq!(buffer.len(), 0);
+    assert_eq!(buffer.memory_used(), 0);
+    /* the interval starts over too */
+    assert!(!buffer.frame_finished());
+    assert!(buffer.frame_finished());
+}
+
+#[test]
+fn states_are_compressed() {
+    let mut buffer = RewindBuffer::new(RewindSettings::default());
+    buffer.push(&state(1));
+    assert!(buffer.memory_used() < state(1).len() / 10);
+}
+
+#[test]
+fn a_state_is_taken_every_interval_frames() {
+    let mut buffer = RewindBuffer::new(settings(3, 10, usize::MAX));
+    let taken: Vec<bool> = (0..7).map(|_| buffer.frame_finished()).collect();
+    assert_eq!(taken, [false, false, true, false, false, true, false]);
+}
+
+#[test]
+fn depth_0_turns_rewind_off() {
+    let mut buffer = RewindBuffer::new(settings(1, 0, usize::MAX));
+    assert!(!buffer.frame_finished());
+}
+
+#[test]
+fn oldest_states_are_dropped_beyond_the_depth() {
+    let mut buffer = RewindBuffer::new(settings(1, 3, usize::MAX));
+    for n in 0..5 {
+        buffer.push(&state(n));
+    }
+    assert_eq!(buffer.len(), 3);
+    buffer.pop();
+    buffer.pop();
+    assert_eq!(buffer.latest().unwrap().unwrap(), state(2));
+}
+
+#[test]
+fn oldest_states_are_dropped_beyond_the_memory_limit() {
+    let mut buffer = RewindBuffer::new(RewindSettings::default());
+    buffer.push(&state(1));
+    let state_size = buffer.memory_used();
+    let limit = state_size * 5 / 2;
+
+    buffer.set_settings(settings(1, 100, limit));
+    for n in 2..5 {
+        buffer.push(&state(n));
+    }
+    assert_eq!(buffer.len(), 2);
+    assert!(buffer.memory_used() <= limit);
+    assert_eq!(buffer.latest().unwrap().unwrap(), state(4));
+}
+
+#[test]
+fn shrinking_the_settings_trims_the_buffer() {
+    let mut buffer = RewindBuffer::new(RewindSettings::default());
+    for n in 0..5 {
+        buffer.push(&state(n));
+    }
+    buffer.set_settings(settings(1, 2, usize::MAX));
+    assert_eq!(buffer.len(), 2);
+    assert_eq!(buffer.latest().unwrap().unwrap(), state(4));
+}
@@ -1,20 +1,20 @@
 use crate::apu::APU;
 use crate::cpu::tests::test_mapper::TestMapper;
-use crate::cpu::{CoreMemory, CPU};
-use crate::ppu::{WriteBuffer, WRITE_BUFFER_SIZE, PPU};
+use crate::cpu::ButtonSource;
+use crate::movie::{Movie, MovieMode, COMMAND_RESET};
+use crate::ppu::{WriteBuffer, WRITE_BUFFER_SIZE};
+use crate::rom::{Rom, RomHeader};
+use crate::simulator::program_state::ProgramState;
 use crate::simulator::scheduler::Scheduler;
-use crate::simulator::SimulatorSignal;
+use crate::simulator::{Emulator, RewindSettings, SimulatorSignal, Speed};
 use std::sync::mpsc::{channel, Sender};
 use std::sync::{Arc, Mutex};
 
 fn make_scheduler(mapper: TestMapper) -> (Scheduler, Sender<SimulatorSignal>) {
     let write_buffer: Arc<Mutex<WriteBuffer>> = Arc::new(Mutex::new([0; WRITE_BUFFER_SIZE]));
-    let memory = Box::new(CoreMemory::new_from_mapper(Box::new(mapper)));
-    let ppu = PPU::new(write_buffer, memory.mapper.clone());
-    let apu = APU::new();
-    let cpu = CPU::new(memory);
+    let emulator = Emulator::with_mapper(Box::new(mapper), write_buffer, APU::silent());
     let (tx, rx) = channel();
-    (Scheduler::new(cpu, ppu, apu, rx), tx)
+    (Scheduler::new(emulator, rx), tx)
 }
 
 #[test]
@@ -31,3 +31,403 @@ fn simulate_returns_save_data_on_end_simulation() {
     tx.send(SimulatorSignal::EndSimulation).unwrap();
     assert_eq!(scheduler.simulate(), Some(expected));
 }
+
+/* an NROM program that keeps the CPU, PPU, and APU busy: the main loop counts in RAM, and the
+ * NMI handler changes the palette and a nametable entry every frame, so every frame differs
+ */
+fn animated_rom() -> Rom {
+    let mut prg_data = vec![0u8; 0x4000];
+    let reset: &[u8] = &[
+        0x78, 0xd8, 0xa2, 0xff, 0x9a, // SEI; CLD; LDX #$ff; TXS
+        0xa9, 0x0f, 0x8d, 0x15, 0x40, // enable APU channels
+        0xa9, 0xbf, 0x8d, 0x00, 0x40, // pulse 1: constant volume
+        0xa9, 0x40, 0x8d, 0x02, 0x40, // pulse 1: timer
+        0xa9, 0x08, 0x8d, 0x03, 0x40, // pulse 1: length counter
+        0xa9, 0x80, 0x8d, 0x00, 0x20, // enable NMI
+        0xa9, 0x1e, 0x8d, 0x01, 0x20, // show background and sprites
+        0xe6, 0x10, 0x4c, 0x23, 0x80, // loop: INC $10; JMP loop
+        0x40, // IRQ handler: RTI
+    ];
+    let nmi: &[u8] = &[
+        0xa9, 0x3f, 0x8d, 0x06, 0x20, 0xa9, 0x00, 0x8d, 0x06, 0x20, // PPUADDR = $3f00
+        0xa6, 0x11, 0x8e, 0x07, 0x20, 0xe8, 0x8e, 0x07, 0x20, // palette = $11, $11 + 1, ...
+        0xe8, 0x8e, 0x07, 0x20, 0xe8, 0x8e, 0x07, 0x20, //
+        0xa9, 0x20, 0x8d, 0x06, 0x20, 0xa5, 0x11, 0x8d, 0x06, 0x20, // PPUADDR = $2000 + $11
+        0x8d, 0x07, 0x20, 0xe6, 0x11, // write tile $11, INC $11
+        0xa9, 0x00, 0x8d, 0x05, 0x20, 0x8d, 0x05, 0x20, // reset scroll
+        0x40, // RTI
+    ];
+    prg_data[..reset.len()].copy_from_slice(reset);
+    prg_data[0x40..0x40 + nmi.len()].copy_from_slice(nmi);
+    prg_data[0x3ffa..].copy_from_slice(&[0x40, 0x80, 0x00, 0x80, 0x28, 0x80]);
+
+    Rom {
+        header: RomHeader::default(),
+        prg_data,
+        chr_data: (0..0x2000).map(|i| (i * 7) as u8).collect(),
+        _trainer: vec![],
+    }
+}
+
+fn make_machine(rom: &Rom) -> (Scheduler, Arc<Mutex<WriteBuffer>>) {
+    let (scheduler, write_buffer, _tx) = make_controlled_machine(rom);
+    (scheduler, write_buffer)
+}
+
+fn make_controlled_machine(
+    rom: &Rom,
+) -> (Scheduler, Arc<Mutex<WriteBuffer>>, Sender<SimulatorSignal>) {
+    let write_buffer: Arc<Mutex<WriteBuffer>> = Arc::new(Mutex::new([0; WRITE_BUFFER_SIZE]));
+    let keys = ButtonSource::default();
+    let (tx, rx) = channel();
+    let mapper = rom.initialize_mapper().unwrap();
+    let scheduler = ProgramState::build_scheduler(mapper, write_buffer.clone(), keys, rx, false);
+    (scheduler, write_buffer, tx)
+}
+
+/* queues up signals, then runs the scheduler until it reaches the EndSimulation after them */
+fn simulate_signals(
+    scheduler: &mut Scheduler,
+    tx: &Sender<SimulatorSignal>,
+    signals: Vec<SimulatorSignal>,
+) {
+    for signal in signals {
+        tx.send(signal).unwrap();
+    }
+    tx.send(SimulatorSignal::EndSimulation).unwrap();
+    scheduler.simulate();
+}
+
+#[test]
+fn restored_machine_produces_identical_frames() {
+    let rom = animated_rom();
+    let (mut original, original_frame) = make_machine(&rom);
+    for _i in 0..5 {
+        original.run_frame();
+    }
+    /* save partway through a frame */
+    for _i in 0..10_000 {
+        original.step();
+    }
+    let state = original.save_state();
+
+    let (mut restored, restored_frame) = make_machine(&rom);
+    restored.run_frame();
+    restored.load_state(&state).unwrap();
+
+    let mut previous_frame = original_frame.lock().unwrap().to_vec();
+    for _i in 0..5 {
+        original.run_frame();
+        restored.run_frame();
+        let frame = original_frame.lock().unwrap().to_vec();
+        assert_ne!(frame, previous_frame);
+        assert!(frame == restored_frame.lock().unwrap().to_vec());
+        previous_frame = frame;
+    }
+    assert_eq!(original.save_state(), restored.save_state());
+}
+
+#[test]
+fn failed_load_leaves_machine_untouched() {
+    let rom = animated_rom();
+    let (mut scheduler, _frame) = make_machine(&rom);
+    scheduler.run_frame();
+    let before = scheduler.save_state();
+
+    /* a valid header, cut off partway through the CPU's state */
+    let truncated = &before[..before.len() / 2];
+    assert!(scheduler.load_state(truncated).is_err());
+    assert!(scheduler.load_state(b"garbage").is_err());
+    assert_eq!(scheduler.save_state(), before);
+}
+
+#[test]
+fn frame_advance_runs_one_frame_at_a_time_while_paused() {
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&animated_rom());
+    simulate_signals(
+        &mut scheduler,
+        &tx,
+        vec![SimulatorSignal::Pause, SimulatorSignal::FrameAdvance],
+    );
+    assert!(scheduler.is_paused());
+    assert_eq!(scheduler.frame_count(), 1);
+    let state = scheduler.save_state();
+
+    /* paused, nothing runs between signals */
+    simulate_signals(&mut scheduler, &tx, vec![]);
+    assert_eq!(scheduler.save_state(), state);
+
+    simulate_signals(
+        &mut scheduler,
+        &tx,
+        vec![SimulatorSignal::FrameAdvance, SimulatorSignal::FrameAdvance],
+    );
+    assert_eq!(scheduler.frame_count(), 3);
+}
+
+#[test]
+fn frame_advance_pauses_a_running_machine() {
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&animated_rom());
+    assert!(!scheduler.is_paused());
+    simulate_signals(&mut scheduler, &tx, vec![SimulatorSignal::FrameAdvance]);
+    assert!(scheduler.is_paused());
+    assert_eq!(scheduler.frame_count(), 1);
+}
+
+#[test]
+fn resume_unpauses() {
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&animated_rom());
+    simulate_signals(&mut scheduler, &tx, vec![SimulatorSignal::Pause]);
+    assert!(scheduler.is_paused());
+    simulate_signals(&mut scheduler, &tx, vec![SimulatorSignal::Resume]);
+    assert!(!scheduler.is_paused());
+
+    /* resuming when not paused is harmless */
+    simulate_signals(&mut scheduler, &tx, vec![SimulatorSignal::Resume]);
+    assert!(!scheduler.is_paused());
+}
+
+#[test]
+fn paused_scheduler_stops_when_its_sender_is_dropped() {
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&animated_rom());
+    tx.send(SimulatorSignal::Pause).unwrap();
+    drop(tx);
+    assert_eq!(scheduler.simulate(), None);
+}
+
+#[test]
+fn reset_keeps_ram() {
+    let rom = animated_rom();
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&rom);
+    let (mut expected, _expected_frame) = make_machine(&rom);
+    for _i in 0..3 {
+        scheduler.run_frame();
+        expected.run_frame();
+    }
+    let counter = scheduler.read_mem(0x10);
+    assert_ne!(counter, 0);
+
+    simulate_signals(
+        &mut scheduler,
+        &tx,
+        vec![SimulatorSignal::Pause, SimulatorSignal::Reset],
+    );
+    expected.reset();
+    assert_eq!(scheduler.save_state(), expected.save_state());
+    assert_eq!(scheduler.read_mem(0x10), counter);
+}
+
+#[test]
+fn power_cycle_restores_power_on_state() {
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&animated_rom());
+    let power_on = scheduler.save_state();
+    for _i in 0..3 {
+        scheduler.run_frame();
+    }
+    assert_ne!(scheduler.read_mem(0x10), 0);
+
+    simulate_signals(
+        &mut scheduler,
+        &tx,
+        vec![SimulatorSignal::Pause, SimulatorSignal::PowerCycle],
+    );
+    assert_eq!(scheduler.save_state(), power_on);
+    assert_eq!(scheduler.read_mem(0x10), 0);
+}
+
+#[test]
+fn power_cycle_keeps_battery_backed_ram() {
+    let mut rom = animated_rom();
+    rom.header.battery = true;
+    rom.header.prg_nvram_size = 0x2000;
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&rom);
+    let save_data: Vec<u8> = (0..0x2000).map(|i| i as u8).collect();
+    scheduler.set_save_data(&save_data);
+    scheduler.run_frame();
+
+    tx.send(SimulatorSignal::PowerCycle).unwrap();
+    tx.send(SimulatorSignal::EndSimulation).unwrap();
+    assert_eq!(scheduler.simulate(), Some(save_data));
+    assert_eq!(scheduler.read_mem(0x6001), 1);
+}
+
+#[test]
+fn set_speed_changes_speed_but_not_the_machine() {
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&animated_rom());
+    assert_eq!(scheduler.speed(), Speed::NORMAL);
+    simulate_signals(&mut scheduler, &tx, vec![SimulatorSignal::Pause]);
+    let state = scheduler.save_state();
+
+    simulate_signals(
+        &mut scheduler,
+        &tx,
+        vec![SimulatorSignal::SetSpeed(Speed::Percent(200))],
+    );
+    assert_eq!(scheduler.speed(), Speed::Percent(200));
+    assert!(scheduler.is_paused());
+    assert_eq!(scheduler.save_state(), state);
+
+    simulate_signals(
+        &mut scheduler,
+        &tx,
+        vec![
+            SimulatorSignal::SetSpeed(Speed::Unthrottled),
+            SimulatorSignal::FrameAdvance,
+        ],
+    );
+    assert_eq!(scheduler.speed(), Speed::Unthrottled);
+    assert_eq!(scheduler.frame_count(), 1);
+}
+
+#[test]
+fn rewind_plays_back_recorded_frames() {
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&animated_rom());
+    let every_frame = RewindSettings {
+        interval: 1,
+        ..RewindSettings::default()
+    };
+    let advance = |count| (0..count).map(|_| SimulatorSignal::FrameAdvance).collect();
+
+    simulate_signals(
+        &mut scheduler,
+        &tx,
+        vec![SimulatorSignal::SetRewindSettings(every_frame)],
+    );
+    simulate_signals(&mut scheduler, &tx, advance(5));
+    let fifth_frame = scheduler.save_state();
+    simulate_signals(&mut scheduler, &tx, advance(1));
+    assert_eq!(scheduler.rewind_len(), 6);
+
+    /* each signal is followed by a step back; the first replays the newest frame, the next
+     * goes back one */
+    simulate_signals(
+        &mut scheduler,
+        &tx,
+        vec![
+            SimulatorSignal::StartRewind,
+            SimulatorSignal::StartRewind,
+            SimulatorSignal::StopRewind,
+        ],
+    );
+    assert_eq!(scheduler.rewind_len(), 4);
+    assert_eq!(scheduler.save_state(), fifth_frame);
+    assert_eq!(scheduler.frame_count(), 5);
+
+    /* playing on from there records over the rewound frames */
+    simulate_signals(&mut scheduler, &tx, advance(1));
+    assert_eq!(scheduler.rewind_len(), 5);
+}
+
+#[test]
+fn rewind_stops_at_the_oldest_state() {
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&animated_rom());
+    let every_frame = RewindSettings {
+        interval: 1,
+        ..RewindSettings::default()
+    };
+    simulate_signals(
+        &mut scheduler,
+        &tx,
+        vec![
+            SimulatorSignal::SetRewindSettings(every_frame),
+            SimulatorSignal::FrameAdvance,
+            SimulatorSignal::FrameAdvance,
+        ],
+    );
+
+    let mut signals: Vec<_> = (0..5).map(|_| SimulatorSignal::StartRewind).collect();
+    signals.push(SimulatorSignal::StopRewind);
+    simulate_signals(&mut scheduler, &tx, signals);
+    /* the oldest state is kept to run the picture from, so that's as far back as it goes */
+    assert_eq!(scheduler.rewind_len(), 1);
+    assert_eq!(scheduler.frame_count(), 2);
+}
+
+/* pauses, records a movie of some frames with a reset partway, and returns it */
+fn record_movie(rom: &Rom) -> (Movie, Vec<u8>) {
+    let (mut scheduler, _frame, tx) = make_controlled_machine(rom);
+    let (reply, movie) = channel();
+    let mut signals = vec![
+        SimulatorSignal::Pause,
+        SimulatorSignal::RecordMovie(Box::new(Movie::new("base64:AAAA", "game.nes"))),
+    ];
+    signals.extend((0..3).map(|_| SimulatorSignal::FrameAdvance));
+    signals.push(SimulatorSignal::Reset);
+    signals.extend((0..3).map(|_| SimulatorSignal::FrameAdvance));
+    simulate_signals(&mut scheduler, &tx, signals);
+    let state = scheduler.save_state();
+
+    simulate_signals(&mut scheduler, &tx, vec![SimulatorSignal::StopMovie(reply)]);
+    (movie.recv().unwrap().unwrap(), state)
+}
+
+#[test]
+fn recorded_movie_plays_back_to_the_same_state() {
+    let rom = animated_rom();
+    let (movie, recorded_state) = record_movie(&rom);
+    assert_eq!(movie.frames.len(), 6);
+    /* the reset came partway through a frame, so it's done as the next one starts */
+    let commands: Vec<u8> = movie.frames.iter().map(|frame| frame.commands).collect();
+    assert_eq!(commands, vec![0, 0, 0, 0, COMMAND_RESET, 0]);
+
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&rom);
+    scheduler.run_frame();
+    let mut signals = vec![
+        SimulatorSignal::Pause,
+        SimulatorSignal::PlayMovie(Box::new(movie), true),
+    ];
+    signals.extend((0..5).map(|_| SimulatorSignal::FrameAdvance));
+    simulate_signals(&mut scheduler, &tx, signals);
+    assert_eq!(scheduler.movie_mode(), Some(MovieMode::Playing));
+
+    /* it's over as soon as the frame after the last one starts */
+    simulate_signals(&mut scheduler, &tx, vec![SimulatorSignal::FrameAdvance]);
+    assert_eq!(scheduler.movie_mode(), Some(MovieMode::Finished));
+    assert_eq!(scheduler.save_state(), recorded_state);
+}
+
+#[test]
+fn resets_during_playback_are_ignored() {
+    let rom = animated_rom();
+    let (movie, recorded_state) = record_movie(&rom);
+
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&rom);
+    let mut signals = vec![
+        SimulatorSignal::Pause,
+        SimulatorSignal::PlayMovie(Box::new(movie), true),
+        SimulatorSignal::FrameAdvance,
+        SimulatorSignal::PowerCycle,
+    ];
+    signals.extend((0..5).map(|_| SimulatorSignal::FrameAdvance));
+    simulate_signals(&mut scheduler, &tx, signals);
+    assert_eq!(scheduler.save_state(), recorded_state);
+}
+
+#[test]
+fn loading_a_state_read_write_records_over_the_rest() {
+    let rom = animated_rom();
+    let (movie, _recorded_state) = record_movie(&rom);
+
+    let (mut scheduler, _frame, tx) = make_controlled_machine(&rom);
+    let signals = vec![
+        SimulatorSignal::Pause,
+        SimulatorSignal::PlayMovie(Box::new(movie), false),
+        SimulatorSignal::FrameAdvance,
+        SimulatorSignal::FrameAdvance,
+    ];
+    simulate_signals(&mut scheduler, &tx, signals);
+    let second_frame = scheduler.save_state();
+    simulate_signals(
+        &mut scheduler,
+        &tx,
+        vec![SimulatorSignal::FrameAdvance, SimulatorSignal::FrameAdvance],
+    );
+    scheduler.load_state(&second_frame).unwrap();
+    assert_eq!(scheduler.movie_mode(), Some(MovieMode::Recording));
+
+    let (reply, rerecorded) = channel();
+    simulate_signals(&mut scheduler, &tx, vec![SimulatorSignal::StopMovie(reply)]);
+    let rerecorded = rerecorded.recv().unwrap().unwrap();
+    assert_eq!(rerecorded.frames.len(), 2);
+    assert_eq!(rerecorded.rerecord_count, 1);
+}
@@ -1,24 +1,30 @@
+use crate::config::{Config, Hotkey, RebindProgress, Rebinder};
+use crate::gamepad::GamepadInput;
 use crate::key_event_handler::KeyEventHandler;
 use crate::menu::{self, MenuAction};
 use crate::renderer::Renderer;
-use crate::rom::Rom;
-use crate::simulator::program_state::ProgramState;
 use muda::{Menu, MenuEvent, MenuId};
+use patina::movie::{self, Movie, MovieMode};
+use patina::{AudioRecorder, ProgramState, Rom, Speed, UnstableOpcodePolicy};
 use std::error::Error;
 use std::fs;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 use tao::dpi::LogicalSize;
-use tao::event::{ElementState, Event, WindowEvent};
+use tao::event::{ElementState, Event, KeyEvent, WindowEvent};
 use tao::event_loop::{ControlFlow, EventLoopBuilder};
 use tao::keyboard::ModifiersState;
 use tao::window::{Window, WindowBuilder};
 
 const WINDOW_START_WIDTH: u16 = 420;
 const WINDOW_START_HEIGHT: u16 = 380;
+const WINDOW_TITLE: &str = "Patina";
 /// Target redraw cadence (~60 fps). The emulator runs on its own thread; the UI
 /// just samples its framebuffer at this rate.
 const FRAME_INTERVAL: Duration = Duration::from_millis(16);
+/// How fast the game runs while the fast-forward hotkey is held.
+const FAST_FORWARD_SPEED: Speed = Speed::Percent(400);
 
 /// Events delivered to the event loop from sources other than window events:
 /// the OS signal handler (Ctrl+C) and `muda` menu activations.
@@ -30,9 +36,35 @@ pub(crate) enum AppEvent {
 struct WindowApp {
     renderer: Renderer,
     key_event_handler: KeyEventHandler,
+    gamepads: Option<GamepadInput>,
     program_state: ProgramState,
     savefile: Option<String>,
+    /// Path of the running ROM; save state slots are stored alongside it.
+    rom_path: String,
+    /// Identifies the running ROM in movies.
+    rom_checksum: String,
+    /// The movie being recorded or played, as far as the window knows: a
+    /// movie that plays to the end still counts until it's stopped.
+    movie: Option<MovieMode>,
+    /// Where the movie is saved when it's stopped, if anything was recorded.
+    movie_path: Option<PathBuf>,
+    movie_read_only: bool,
+    /// Where the sound is being recorded, if it is.
+    audio_path: Option<PathBuf>,
     modifiers: ModifiersState,
+    config: Config,
+    /// Where rebound controls are saved, if there's anywhere to save them.
+    config_path: Option<PathBuf>,
+    /// Set while the user is rebinding the controls; key presses go to it
+    /// instead of the game.
+    rebinder: Option<Rebinder>,
+    /// Whether the emulation has been paused, to toggle it and show it.
+    paused: bool,
+    /// The speed picked from the menu, which fast-forward returns to.
+    speed: Speed,
+    fast_forwarding: bool,
+    rewinding: bool,
+    window: Arc<Window>,
     /// The native menu bar. Kept alive for the lifetime of the app: dropping it
     /// removes the menu from the window.
     _menu: Menu,
@@ -40,6 +72,9 @@ struct WindowApp {
 
 impl WindowApp {
     fn render(&mut self) {
+        if let Some(gamepads) = &mut self.gamepads {
+            gamepads.poll();
+        }
         self.renderer.render();
     }
 
@@ -48,11 +83,280 @@ impl WindowApp {
     fn handle_action(&mut self, action: MenuAction, control_flow: &mut ControlFlow) {
         match action {
             MenuAction::LoadRom => self.load_rom(),
+            MenuAction::Controls => self.start_rebinding(),
+            MenuAction::RecordAudio => self.record_audio(),
+            MenuAction::StopAudioRecording => {
+                self.stop_audio_recording();
+                self.update_title();
+            }
+            MenuAction::ToggleAudioRecording => {
+                if self.audio_path.is_some() {
+                    self.stop_audio_recording();
+                    self.update_title();
+                } else {
+                    self.record_audio();
+                }
+            }
+            MenuAction::TogglePause => {
+                if self.paused {
+                    self.program_state.resume();
+                } else {
+                    self.program_state.pause();
+                }
+                self.paused = !self.paused;
+                self.update_title();
+            }
+            MenuAction::FrameAdvance => {
+                self.program_state.frame_advance();
+                self.paused = true;
+                self.update_title();
+            }
+            MenuAction::Reset => self.program_state.reset(),
+            MenuAction::PowerCycle => self.program_state.power_cycle(),
+            MenuAction::SetSpeed(speed) => {
+                self.speed = speed;
+                if !self.fast_forwarding {
+                    self.program_state.set_speed(speed);
+                }
+                self.update_title();
+            }
+            MenuAction::RecordMovie => self.record_movie(),
+            MenuAction::PlayMovie => {
+                if let Some(path) = movie_file_dialog().pick_file() {
+                    self.play_movie(path);
+                }
+            }
+            MenuAction::StopMovie => {
+                self.stop_movie();
+                self.update_title();
+            }
+            MenuAction::ToggleMovieReadOnly => {
+                self.movie_read_only = !self.movie_read_only;
+                self.program_state.set_movie_read_only(self.movie_read_only);
+                self.update_title();
+            }
             MenuAction::Exit => self.do_exit(control_flow),
+            MenuAction::SaveState(slot) => self.save_state(slot),
+            MenuAction::LoadState(slot) => self.load_state(slot),
+        }
+    }
+
+    fn save_state_path(&self, slot: u8) -> String {
+        format!("{}.ss{slot}", self.rom_path)
+    }
+
+    fn save_state(&mut self, slot: u8) {
+        let path = self.save_state_path(slot);
+        let Some(state) = self.program_state.save_state() else {
+            eprintln!("Failed to save state: emulation is not running");
+            return;
+        };
+        match fs::write(&path, state) {
+            Ok(()) => println!("Saved state to {path}"),
+            Err(e) => eprintln!("Failed to write save state {path}: {e}"),
+        }
+    }
+
+    fn load_state(&mut self, slot: u8) {
+        let path = self.save_state_path(slot);
+        let result = fs::read(&path).and_then(|state| self.program_state.load_state(state));
+        match result {
+            Ok(()) => println!("Loaded state from {path}"),
+            Err(e) => eprintln!("Failed to load save state {path}: {e}"),
         }
     }
 
+    fn record_movie(&mut self) {
+        let Some(path) = movie_file_dialog().save_file() else {
+            return;
+        };
+        self.stop_movie();
+        self.program_state
+            .record_movie(Movie::new(&self.rom_checksum, &self.rom_path));
+        println!("Recording movie to {}", path.display());
+        self.movie = Some(MovieMode::Recording);
+        self.movie_path = Some(path);
+        self.update_title();
+    }
+
+    fn play_movie(&mut self, path: PathBuf) {
+        let movie = match Movie::load(&path) {
+            Ok(movie) => movie,
+            Err(e) => {
+                eprintln!("Failed to load movie: {e}");
+                return;
+            }
+        };
+        if movie.rom_checksum != self.rom_checksum {
+            eprintln!("Warning: the movie was recorded with a different ROM");
+        }
+        self.stop_movie();
+        self.program_state.play_movie(movie, self.movie_read_only);
+        self.movie = Some(MovieMode::Playing);
+        self.movie_path = Some(path);
+        self.update_title();
+    }
+
+    /// Stops any movie, saving it if anything was recorded into it.
+    fn stop_movie(&mut self) {
+        self.movie = None;
+        let Some(path) = self.movie_path.take() else {
+            return;
+        };
+        if let Some(movie) = self.program_state.stop_movie() {
+            match movie.save(&path) {
+                Ok(()) => println!("Saved movie to {}", path.display()),
+                Err(e) => eprintln!("Failed to write movie {}: {e}", path.display()),
+            }
+        }
+    }
+
+    /// Asks where to record the sound, then records it there, with a stem for
+    /// each channel if the config asks for them.
+    fn record_audio(&mut self) {
+        let Some(path) = rfd::FileDialog::new()
+            .add_filter("WAV audio", &["wav"])
+            .save_file()
+        else {
+            return;
+        };
+        self.stop_audio_recording();
+        match AudioRecorder::create(&path, self.config.wav_format, self.config.wav_stems) {
+            Ok(recorder) => {
+                self.program_state.record_audio(recorder);
+                println!("Recording audio to {}", path.display());
+                self.audio_path = Some(path);
+            }
+            Err(e) => eprintln!("Failed to create audio file {}: {e}", path.display()),
+        }
+        self.update_title();
+    }
+
+    /// Stops any audio recording, completing its files.
+    fn stop_audio_recording(&mut self) {
+        let Some(path) = self.audio_path.take() else {
+            return;
+        };
+        match self.program_state.stop_audio_recording() {
+            Ok(()) => println!("Saved audio to {}", path.display()),
+            Err(e) => eprintln!("Failed to write audio file {}: {e}", path.display()),
+        }
+    }
+
+    fn start_rebinding(&mut self) {
+        self.rebinder = Some(Rebinder::new(&self.config));
+        self.update_title();
+    }
+
+    /// Runs at the fast-forward speed while the hotkey is held, then goes back
+    /// to the speed from the menu.
+    fn set_fast_forward(&mut self, held: bool) {
+        if held == self.fast_forwarding {
+            return;
+        }
+        self.fast_forwarding = held;
+        self.program_state.set_speed(self.current_speed());
+        self.update_title();
+    }
+
+    /// Plays the game backwards while the hotkey is held, then carries on
+    /// from wherever it got to.
+    fn set_rewinding(&mut self, held: bool) {
+        if held == self.rewinding {
+            return;
+        }
+        self.rewinding = held;
+        if held {
+            self.program_state.start_rewind();
+        } else {
+            self.program_state.stop_rewind();
+        }
+        self.update_title();
+    }
+
+    fn current_speed(&self) -> Speed {
+        if self.fast_forwarding {
+            FAST_FORWARD_SPEED
+        } else {
+            self.speed
+        }
+    }
+
+    /// The title bar doubles as a status line: it prompts for keys while
+    /// rebinding, and otherwise says when the game is rewinding, paused, or
+    /// not at normal speed, what any movie is doing, and whether the sound is
+    /// being recorded.
+    fn update_title(&self) {
+        if let Some(rebinder) = &self.rebinder {
+            self.window
+                .set_title(&format!("{WINDOW_TITLE} - {}", rebinder.prompt()));
+            return;
+        }
+
+        let mut status = Vec::new();
+        if self.rewinding {
+            status.push("Rewinding".to_string());
+        } else if self.paused {
+            status.push("Paused".to_string());
+        } else if self.current_speed() != Speed::NORMAL {
+            status.push(self.current_speed().label());
+        }
+        let access = match self.movie_read_only {
+            true => "read-only",
+            false => "read-write",
+        };
+        match self.movie {
+            Some(MovieMode::Recording) => status.push(format!("Recording movie, {access}")),
+            Some(_) => status.push(format!("Playing movie, {access}")),
+            None => {}
+        }
+        if self.audio_path.is_some() {
+            status.push("Recording audio".to_string());
+        }
+
+        let title = match status.is_empty() {
+            true => WINDOW_TITLE.to_string(),
+            false => format!("{WINDOW_TITLE} - {}", status.join(", ")),
+        };
+        self.window.set_title(&title);
+    }
+
+    fn rebind_key_press(&mut self, input: &KeyEvent) {
+        let Some(rebinder) = &mut self.rebinder else {
+            return;
+        };
+        match rebinder.press(&input.logical_key) {
+            RebindProgress::Next => self.update_title(),
+            RebindProgress::Rejected => eprintln!("That key can't be bound"),
+            RebindProgress::Done(config) => {
+                self.rebinder = None;
+                self.update_title();
+                self.apply_config(*config);
+            }
+            RebindProgress::Cancelled => {
+                self.rebinder = None;
+                self.update_title();
+            }
+        }
+    }
+
+    fn apply_config(&mut self, config: Config) {
+        self.key_event_handler.set_config(config.clone());
+        if let Some(gamepads) = &mut self.gamepads {
+            gamepads.set_config(&config);
+        }
+        if let Some(path) = &self.config_path {
+            match config.save(path) {
+                Ok(()) => println!("Saved controls to {}", path.display()),
+                Err(e) => eprintln!("Failed to write config file {}: {e}", path.display()),
+            }
+        }
+        self.config = config;
+    }
+
     fn do_exit(&mut self, control_flow: &mut ControlFlow) {
+        self.stop_movie();
+        self.stop_audio_recording();
         let save_data = self.program_state.cleanup();
         if let (Some(path), Some(data)) = (&self.savefile, save_data) {
             if let Err(e) = fs::write(path, data) {
@@ -77,13 +381,37 @@ impl WindowApp {
             }
         };
 
+        self.stop_movie();
+        self.stop_audio_recording();
+        self.rebinder = None;
+        self.paused = false;
+        self.rewinding = false;
+        self.update_title();
         let key_source = self.program_state.key_source.clone();
+        let pad_source = self.program_state.pad_source.clone();
         self.program_state.cleanup();
-        let new_state = ProgramState::simulate_async(&rom, &None, key_source);
+        let new_state = match ProgramState::simulate_async(
+            &rom,
+            &None,
+            key_source,
+            pad_source,
+            None,
+            UnstableOpcodePolicy::default(),
+        ) {
+            Ok(state) => state,
+            Err(e) => {
+                eprintln!("Failed to load ROM: {e}");
+                return;
+            }
+        };
+        new_state.set_speed(self.current_speed());
+        new_state.set_rewind_settings(self.config.rewind);
         self.renderer.set_write_buffer(new_state.write_buffer.clone());
         self.key_event_handler
             .set_write_buffer(new_state.write_buffer.clone());
         self.program_state = new_state;
+        self.rom_path = path.to_string_lossy().to_string();
+        self.rom_checksum = movie::rom_checksum(&rom);
     }
 
     fn window_event(&mut self, event: WindowEvent, control_flow: &mut ControlFlow) {
@@ -98,10 +426,29 @@ impl WindowApp {
                 self.modifiers = new_modifiers;
             }
             WindowEvent::KeyboardInput { event: input, .. } => {
-                if input.state == ElementState::Pressed {
-                    if let Some(action) =
-                        menu::action_for_shortcut(self.modifiers.control_key(), &input.logical_key)
-                    {
+                /* releases still go through, so nothing held beforehand gets stuck down */
+                if self.rebinder.is_some() && input.state == ElementState::Pressed {
+                    if !input.repeat {
+                        self.rebind_key_press(&input);
+                    }
+                    return;
+                }
+                let held = input.state == ElementState::Pressed;
+                match self.config.hotkey_for(&input.logical_key) {
+                    Some(Hotkey::FastForward) => return self.set_fast_forward(held),
+                    Some(Hotkey::Rewind) => return self.set_rewinding(held),
+                    _ => (),
+                }
+                if input.state == ElementState::Pressed && !input.repeat {
+                    let key = &input.logical_key;
+                    let action = menu::action_for_shortcut(self.modifiers.control_key(), key)
+                        .or_else(|| menu::action_for_slot_key(self.modifiers.shift_key(), key))
+                        .or_else(|| {
+                            self.config
+                                .hotkey_for(key)
+                                .and_then(menu::action_for_hotkey)
+                        });
+                    if let Some(action) = action {
                         self.handle_action(action, control_flow);
                         return;
                     }
@@ -124,6 +471,10 @@ impl WindowApp {
     }
 }
 
+fn movie_file_dialog() -> rfd::FileDialog {
+    rfd::FileDialog::new().add_filter("FCEUX movie", &["fm2"])
+}
+
 /// Attaches the menu bar to the window. This is the only platform-divergent
 /// part of the menu implementation; the menu itself is defined once in
 /// [`crate::menu`].
@@ -154,10 +505,22 @@ fn attach_menu(menu: &Menu, _window: &Window) {
     menu.init_for_nsapp();
 }
 
+/// The game the window starts out running.
+pub struct Game {
+    pub rom_path: String,
+    pub rom_checksum: String,
+    pub savefile: Option<String>,
+    /// A movie to play back from the start.
+    pub movie: Option<PathBuf>,
+}
+
 pub fn initialize_ui(
     program_state: ProgramState,
     key_event_handler: KeyEventHandler,
-    savefile: Option<String>,
+    gamepads: Option<GamepadInput>,
+    config: Config,
+    config_path: Option<PathBuf>,
+    game: Game,
 ) -> Result<(), Box<dyn Error>> {
     let event_loop = EventLoopBuilder::<AppEvent>::with_user_event().build();
 
@@ -176,7 +539,7 @@ pub fn initialize_ui(
 
     let window = Arc::new(
         WindowBuilder::new()
-            .with_title("Patina")
+            .with_title(WINDOW_TITLE)
             .with_inner_size(LogicalSize::new(WINDOW_START_WIDTH, WINDOW_START_HEIGHT))
             .build(&event_loop)?,
     );
@@ -191,11 +554,29 @@ pub fn initialize_ui(
     let mut app = WindowApp {
         renderer,
         key_event_handler,
+        gamepads,
         program_state,
-        savefile,
+        savefile: game.savefile,
+        rom_path: game.rom_path,
+        rom_checksum: game.rom_checksum,
+        movie: None,
+        movie_path: None,
+        movie_read_only: true,
+        audio_path: None,
         modifiers: ModifiersState::empty(),
+        config,
+        config_path,
+        rebinder: None,
+        paused: false,
+        speed: Speed::NORMAL,
+        fast_forwarding: false,
+        rewinding: false,
+        window: window.clone(),
         _menu: menu,
     };
+    if let Some(path) = game.movie {
+        app.play_movie(path);
+    }
 
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::WaitUntil(Instant::now() + FRAME_INTERVAL);